@@ -0,0 +1,69 @@
+//! Parsing, encoding and DOL handling over what the mock cards give when read,
+//! to keep an eye on the cost of refactoring the TLV code. Run with
+//! `cargo bench --bench tlv`.
+
+use std::convert::TryFrom;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use emv_term::tlv::{TagID, TagList, DOL};
+
+/// Every FCI, GPO response and record read from the mock cards, as the card
+/// sent it
+fn card_objects() -> Vec<Vec<u8>> {
+    let terminal = emv_term::terminal::TerminalData::new(emv_term::card::Interface::Contact);
+    [emv_term::mock::visa_card(), emv_term::mock::cda_card(), emv_term::mock::sda_card()].iter()
+        .map(|card| emv_term::dump::read_card(card, &terminal, &emv_term::capk::CapkStore::new()).unwrap())
+        .flat_map(|dump| dump.card_objects().into_iter().map(|o| o.to_vec()).collect::<Vec<_>>())
+        .collect()
+}
+
+fn tlv(c: &mut Criterion) {
+    let objects = card_objects();
+    let parsed = objects.iter().map(|o| TagList::try_from(o.as_slice()).unwrap()).collect::<Vec<_>>();
+
+    c.bench_function("parse card objects", |b| b.iter(|| {
+        for object in &objects {
+            black_box(TagList::try_from(black_box(object.as_slice())).unwrap());
+        }
+    }));
+    c.bench_function("encode card objects", |b| b.iter(|| {
+        for tags in &parsed {
+            black_box(Vec::<u8>::from(black_box(tags)));
+        }
+    }));
+    c.bench_function("find tags in card objects", |b| b.iter(|| {
+        for tags in &parsed {
+            for tag in tags.tags() {
+                black_box(tag.get_tag(black_box(TagID::CardRiskManagementDOL1)));
+            }
+        }
+    }));
+}
+
+fn dol(c: &mut Criterion) {
+    let cdol1 = card_objects().iter()
+        .filter_map(|o| TagList::try_from(o.as_slice()).ok())
+        .find_map(|tags| {
+            let record = tags.get_tag(TagID::ReadRecordResponseMessageTemplate)?;
+            Some(Vec::<u8>::from(record.get_tag(TagID::CardRiskManagementDOL1)?.contents()))
+        })
+        .unwrap();
+    let mut terminal = emv_term::terminal::TerminalData::new(emv_term::card::Interface::Contact);
+    terminal.amount_authorised = 1000;
+
+    c.bench_function("parse CDOL1", |b| b.iter(|| DOL::try_from(black_box(cdol1.as_slice())).unwrap()));
+    c.bench_function("fill and encode CDOL1", |b| b.iter(|| {
+        let mut dol = DOL::try_from(cdol1.as_slice()).unwrap();
+        dol.fill(black_box(&terminal));
+        Vec::<u8>::from(dol)
+    }));
+    let filled = {
+        let mut dol = DOL::try_from(cdol1.as_slice()).unwrap();
+        dol.fill(&terminal);
+        Vec::<u8>::from(dol)
+    };
+    let dol = DOL::try_from(cdol1.as_slice()).unwrap();
+    c.bench_function("split CDOL1 data", |b| b.iter(|| dol.split(black_box(&filled)).unwrap()));
+}
+
+criterion_group!(benches, tlv, dol);
+criterion_main!(benches);
@@ -0,0 +1,563 @@
+pub use emv_term::card::ReaderSelector;
+
+fn parse_share_mode(value: &str) -> Result<pcsc::ShareMode, String> {
+    match value.to_lowercase().as_str() {
+        "exclusive" => Ok(pcsc::ShareMode::Exclusive),
+        "shared" => Ok(pcsc::ShareMode::Shared),
+        "direct" => Ok(pcsc::ShareMode::Direct),
+        v => Err(format!("Unknown share mode: {}", v))
+    }
+}
+
+fn parse_protocols(value: &str) -> Result<pcsc::Protocols, String> {
+    let mut protocols = pcsc::Protocols::empty();
+    for p in value.to_lowercase().split(',') {
+        protocols |= match p {
+            "t0" | "t=0" => pcsc::Protocols::T0,
+            "t1" | "t=1" => pcsc::Protocols::T1,
+            "raw" => pcsc::Protocols::RAW,
+            "any" => pcsc::Protocols::ANY,
+            v => return Err(format!("Unknown protocol: {}", v))
+        };
+    }
+    Ok(protocols)
+}
+
+fn parse_disposition(value: &str) -> Result<pcsc::Disposition, String> {
+    match value.to_lowercase().as_str() {
+        "leave" => Ok(pcsc::Disposition::LeaveCard),
+        "reset" => Ok(pcsc::Disposition::ResetCard),
+        "unpower" => Ok(pcsc::Disposition::UnpowerCard),
+        "eject" => Ok(pcsc::Disposition::EjectCard),
+        v => Err(format!("Unknown disposition: {}", v))
+    }
+}
+
+fn parse_dump_format(value: &str) -> Result<emv_term::dump::DumpFormat, String> {
+    match value.to_lowercase().as_str() {
+        "json" => Ok(emv_term::dump::DumpFormat::Json),
+        "tlv" => Ok(emv_term::dump::DumpFormat::Tlv),
+        "ber" => Ok(emv_term::dump::DumpFormat::Ber),
+        "hex" => Ok(emv_term::dump::DumpFormat::Hex),
+        "tags" => Ok(emv_term::dump::DumpFormat::TagValue),
+        v => Err(format!("Unknown dump format: {}", v))
+    }
+}
+
+fn parse_interface(value: &str) -> Result<emv_term::card::Interface, String> {
+    match value.to_lowercase().as_str() {
+        "contact" => Ok(emv_term::card::Interface::Contact),
+        "contactless" => Ok(emv_term::card::Interface::Contactless),
+        v => Err(format!("Unknown interface: {}", v))
+    }
+}
+
+/// In the terminal's currency, which has two decimal places unless set
+/// otherwise
+fn parse_amount(value: &str, currency: Option<&emv_term::iso::Currency>) -> Result<u64, String> {
+    emv_term::util::parse_amount(value, currency.map_or(2, |c| c.exponent)).ok_or_else(|| format!("Invalid amount: {}", value))
+}
+
+fn parse_merchant_category_code(value: &str) -> Result<u16, String> {
+    match value.len() == 4 && value.chars().all(|c| c.is_ascii_digit()) {
+        true => Ok(value.parse().unwrap()),
+        false => Err(format!("Merchant category code must be four digits: {}", value))
+    }
+}
+
+/// Printable ASCII of at most `max` characters, as the card and host expect
+fn parse_merchant_text(name: &str, value: &str, max: usize) -> Result<String, String> {
+    match !value.is_empty() && value.len() <= max && value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        true => Ok(value.to_string()),
+        false => Err(format!("{} must be 1 to {} printable ASCII characters", name, max))
+    }
+}
+
+fn parse_language(value: &str) -> Result<emv_term::language::Language, String> {
+    emv_term::language::Language::from_code(value).ok_or_else(|| format!("Unsupported language: {}", value))
+}
+
+fn parse_currency(value: &str) -> Result<&'static emv_term::iso::Currency, String> {
+    emv_term::iso::parse_currency(value).ok_or_else(|| format!("Unknown currency: {}", value))
+}
+
+fn parse_country(value: &str) -> Result<&'static emv_term::iso::Country, String> {
+    emv_term::iso::parse_country(value).ok_or_else(|| format!("Unknown country: {}", value))
+}
+
+fn parse_transaction_type(value: &str) -> Result<u8, String> {
+    emv_term::terminal::transaction_type_from_name(value).ok_or_else(|| format!("Unknown transaction type: {}", value))
+}
+
+fn parse_terminal_type(value: &str) -> Result<u8, String> {
+    match emv_term::hex::decode(value).as_deref() {
+        Some(&[t]) if emv_term::terminal::valid_terminal_type(t) => Ok(t),
+        _ => Err(format!("Invalid terminal type: {}", value))
+    }
+}
+
+fn parse_timeout(value: &str) -> Result<std::time::Duration, String> {
+    match value.parse::<u64>() {
+        Ok(s) if s > 0 => Ok(std::time::Duration::from_secs(s)),
+        _ => Err(format!("Invalid timeout: {}", value))
+    }
+}
+
+fn parse_attempts(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Invalid number of attempts: {}", value))
+    }
+}
+
+fn parse_issuer_key(value: &str) -> Result<emv_term::hsm::TdesKey, String> {
+    match value.parse() {
+        Ok(emv_term::hsm::TdesKey::Software(k)) if k.len() != 16 => Err("Issuer master key must be 16 bytes of hex".to_string()),
+        Ok(k) => Ok(k),
+        Err(e) => Err(format!("Invalid issuer master key: {}", e))
+    }
+}
+
+fn parse_date(value: &str) -> Result<chrono::NaiveDate, String> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("Invalid date: {}", value))
+}
+
+fn parse_log_level(value: &str) -> Result<tracing_subscriber::filter::LevelFilter, String> {
+    value.parse().map_err(|_| format!("Unknown log level: {}", value))
+}
+
+fn parse_log_format(value: &str) -> Result<LogFormat, String> {
+    match value.to_lowercase().as_str() {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        v => Err(format!("Unknown log format: {}", v))
+    }
+}
+
+/// An AID, matching any card AID starting with it unless followed by `:exact`
+fn parse_aid(value: &str) -> Result<emv_term::selection::TerminalApplication, String> {
+    let (aid, exact) = match value.split_once(':') {
+        Some((aid, "exact")) => (aid, true),
+        Some(_) => return Err(format!("Unknown AID match, only exact is allowed: {}", value)),
+        None => (value, false)
+    };
+    match emv_term::hex::decode(aid) {
+        Some(aid) if (5..=16).contains(&aid.len()) => {
+            let mut application = emv_term::selection::TerminalApplication::new(&aid);
+            application.partial_selection = !exact;
+            Ok(application)
+        }
+        _ => Err(format!("AID must be 5 to 16 bytes of hex: {}", value))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per event, with the spans it happened in
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Run a transaction with the card
+    Pay,
+    /// Read everything from the card without running a transaction
+    Read,
+    /// Explore the card, reading all it will give and printing it
+    Dump,
+    /// Print the card's log of past transactions
+    Logs,
+    /// Print the card's payment system directory
+    Pse,
+    /// Read cards in every reader side by side as they're presented
+    Monitor,
+    /// Explore cards full screen
+    Tui,
+    /// Total up the batch of completed transactions and clear it
+    Settle,
+    /// Take requests from a point of sale over a Unix socket
+    Serve,
+    /// Take commands as JSON lines on stdin and give events on stdout, for
+    /// running under an ECR
+    Stdio,
+    /// Run the transaction in a transcript or card dump again without the card
+    Analyze(String),
+}
+
+/// An EMV terminal for PC/SC card readers
+#[derive(clap::Parser)]
+#[command(name = "emv-term", version)]
+struct Cli {
+    /// What to do, running a transaction if not given
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+    #[command(flatten)]
+    options: Options,
+}
+
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Run a transaction with the card
+    Pay,
+    /// Read everything from the card without running a transaction
+    Read {
+        /// Where to write everything read from the card
+        #[arg(long, value_name = "FILE")]
+        dump: Option<String>,
+        #[arg(long, visible_alias = "dump-format", value_parser = parse_dump_format, default_value = "json", value_name = "json|tlv|ber|hex|tags")]
+        format: emv_term::dump::DumpFormat,
+    },
+    /// Explore the card: try every application, read every record and all
+    /// the common GET DATA objects, and print the lot
+    Dump {
+        /// Where to write everything read from the card
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<String>,
+        #[arg(long, visible_alias = "dump-format", value_parser = parse_dump_format, default_value = "json", value_name = "json|tlv|ber|hex|tags")]
+        format: emv_term::dump::DumpFormat,
+    },
+    /// Print the card's log of past transactions, without running one
+    Logs,
+    /// Print every entry in the PSE, or PPSE when contactless, and any
+    /// directories it leads to, without selecting an application
+    Pse,
+    /// Watch every reader at once, reading each card presented and printing
+    /// what's on it against its reader, until Ctrl-C
+    Monitor,
+    /// Explore cards full screen: readers, applications, every APDU and the
+    /// tags in it, and transactions as they run
+    Tui,
+    /// Total up the batch of completed transactions and clear it
+    Settle,
+    /// Take requests from a point of sale over a Unix socket
+    Serve {
+        #[arg(long, default_value = emv_term::serve::DEFAULT_SOCKET, value_name = "PATH")]
+        socket: String,
+    },
+    /// Take commands as JSON lines on stdin and give events on stdout
+    Stdio,
+    /// Run the transaction in a transcript or card dump again without the card
+    #[command(visible_alias = "analyse")]
+    Analyze {
+        /// Transcript or card dump to run the transaction against
+        #[arg(value_name = "TRANSCRIPT|DUMP")]
+        path: String,
+    },
+}
+
+#[derive(clap::Args)]
+struct Options {
+    /// Reader to use, by name or by its index in the list
+    #[arg(long, short, global = true, value_name = "NAME|INDEX")]
+    reader: Option<String>,
+    /// Whether other applications can use the card at the same time
+    #[arg(long, global = true, value_parser = parse_share_mode, value_name = "exclusive|shared|direct")]
+    share: Option<pcsc::ShareMode>,
+    /// Protocols to accept when connecting to the card
+    #[arg(long, global = true, value_parser = parse_protocols, value_name = "t0,t1,raw|any")]
+    protocol: Option<pcsc::Protocols>,
+    /// What to do with the card when finished with it
+    #[arg(long, global = true, value_parser = parse_disposition, value_name = "leave|reset|unpower|eject")]
+    disposition: Option<pcsc::Disposition>,
+    /// Interface to use in place of working it out from the ATR
+    #[arg(long, global = true, value_parser = parse_interface, value_name = "contact|contactless")]
+    interface: Option<emv_term::card::Interface>,
+    /// Only select applications with these AIDs, or AIDs starting with them
+    /// unless `:exact`
+    #[arg(long, global = true, value_parser = parse_aid, value_name = "HEX[:exact]")]
+    aid: Vec<emv_term::selection::TerminalApplication>,
+    /// Table of applications the terminal supports, with the parameters for
+    /// each, in place of the built-in ones
+    #[arg(long = "aids", global = true, value_name = "FILE.JSON|FILE.CSV")]
+    application_file: Option<String>,
+    /// CA public key file to load on top of the built-in keys
+    #[arg(long = "capk", global = true, value_name = "FILE")]
+    capk_files: Vec<String>,
+    /// BIN range file to load on top of the schemes' own ranges
+    #[arg(long = "bins", global = true, value_name = "FILE.CSV")]
+    bin_files: Vec<String>,
+    /// Key to encrypt online PIN blocks under
+    #[arg(long, global = true, value_parser = |v: &str| v.parse::<emv_term::pinblock::PinKey>(),
+          value_name = "tdes|aes:HEX|pkcs11:MODULE:SLOT:LABEL|dukpt:[bdk:]HEX:KSN")]
+    pin_key: Option<emv_term::pinblock::PinKey>,
+    /// Run transactions as if on this date rather than today
+    #[arg(long, global = true, value_parser = parse_date, value_name = "YYYY-MM-DD")]
+    date: Option<chrono::NaiveDate>,
+    /// Where to write the transaction outcome as JSON, `-` for stdout
+    #[arg(long, global = true, value_name = "FILE|-")]
+    outcome: Option<String>,
+    /// Amount of goods or services, in the terminal's currency
+    #[arg(long, global = true, value_name = "12.34")]
+    amount: Option<String>,
+    /// Cash given back on top of the amount
+    #[arg(long, global = true, value_name = "12.34")]
+    cashback: Option<String>,
+    #[arg(long = "type", global = true, value_parser = parse_transaction_type, value_name = "purchase|cash|cashback|refund")]
+    transaction_type: Option<u8>,
+    #[arg(long, global = true, value_parser = parse_currency, value_name = "GBP|826")]
+    currency: Option<&'static emv_term::iso::Currency>,
+    #[arg(long, global = true, value_parser = parse_country, value_name = "GB|GBR|826")]
+    country: Option<&'static emv_term::iso::Country>,
+    /// Merchant category code
+    #[arg(long, global = true, value_parser = parse_merchant_category_code, value_name = "5411")]
+    mcc: Option<u16>,
+    #[arg(long, global = true, value_parser = |v: &str| parse_merchant_text("Merchant name and location", v, 40), value_name = "NAME AND LOCATION")]
+    merchant_name: Option<String>,
+    #[arg(long, global = true, value_parser = |v: &str| parse_merchant_text("Merchant ID", v, 15), value_name = "ID")]
+    merchant_id: Option<String>,
+    #[arg(long, global = true, value_parser = |v: &str| parse_merchant_text("Terminal ID", v, 8), value_name = "ID")]
+    terminal_id: Option<String>,
+    /// Language to prompt the cardholder in when their card has no
+    /// preference, rather than the locale's
+    #[arg(long, global = true, value_parser = parse_language, value_name = "en|cy|fr|de|es|it")]
+    language: Option<emv_term::language::Language>,
+    /// Terminal Type, such as 22 for an attended merchant terminal
+    #[arg(long, global = true, value_parser = parse_terminal_type, value_name = "HEX")]
+    terminal_type: Option<u8>,
+    /// Where to keep the terminal's state between runs
+    #[arg(long = "state", global = true, default_value = emv_term::state::DEFAULT_STATE_FILE, value_name = "FILE")]
+    state_file: String,
+    /// Where completed transactions are kept until settlement
+    #[arg(long = "batch", global = true, default_value = emv_term::batch::DEFAULT_BATCH_FILE, value_name = "FILE")]
+    batch_file: String,
+    /// The acquirer's host to send authorisations to
+    #[arg(long, global = true, value_name = "HOST:PORT")]
+    host: Option<String>,
+    #[arg(long, global = true)]
+    host_tls: bool,
+    /// CA certificates to trust for the host, in place of the web roots
+    #[arg(long, global = true, value_name = "FILE.PEM")]
+    host_ca: Option<String>,
+    #[arg(long, global = true, value_parser = parse_timeout, value_name = "SECONDS")]
+    host_timeout: Option<std::time::Duration>,
+    /// Sign on with the host before the transaction
+    #[arg(long, global = true)]
+    sign_on: bool,
+    /// Approve online without a host
+    #[arg(long, global = true)]
+    stand_in: bool,
+    #[arg(long, global = true, value_name = "12.34")]
+    stand_in_limit: Option<String>,
+    /// Issuer master key for the stand-in to generate ARPCs with
+    #[arg(long, global = true, value_parser = parse_issuer_key, value_name = "HEX|pkcs11:MODULE:SLOT:LABEL")]
+    issuer_key: Option<emv_term::hsm::TdesKey>,
+    /// Where to POST each transaction outcome
+    #[arg(long, global = true, value_name = "URL")]
+    webhook: Option<String>,
+    #[arg(long, global = true, value_name = "SECRET")]
+    webhook_secret: Option<String>,
+    #[arg(long, global = true, value_parser = parse_attempts, value_name = "N")]
+    webhook_attempts: Option<u32>,
+    /// Never prompt, skipping anything that needs the cardholder's say
+    #[arg(long, short = 'y', visible_alias = "yes", global = true)]
+    non_interactive: bool,
+    /// Print card numbers in full rather than masked
+    #[arg(long, global = true)]
+    show_pan: bool,
+    /// Where to record every exchange with the card, for `analyze`
+    #[arg(long = "transcript", global = true, value_name = "FILE")]
+    transcript_file: Option<String>,
+    /// The least severe events to log, in place of RUST_LOG
+    #[arg(long, global = true, value_parser = parse_log_level, value_name = "error|warn|info|debug|trace")]
+    log_level: Option<tracing_subscriber::filter::LevelFilter>,
+    /// JSON by default when serving
+    #[arg(long, global = true, value_parser = parse_log_format, value_name = "text|json")]
+    log_format: Option<LogFormat>,
+}
+
+pub struct Args {
+    pub command: Command,
+    pub reader: Option<ReaderSelector>,
+    pub connect: emv_term::card::ConnectOptions,
+    pub dump: Option<String>,
+    pub dump_format: emv_term::dump::DumpFormat,
+    pub interface: Option<emv_term::card::Interface>,
+    /// The only applications to select, taking their parameters from
+    /// `applications`
+    pub aid: Vec<emv_term::selection::TerminalApplication>,
+    /// Where to load the terminal's applications from
+    pub application_file: Option<String>,
+    /// The applications the terminal supports, built in until
+    /// `application_file` is loaded
+    pub applications: Vec<emv_term::selection::TerminalApplication>,
+    /// CA public key files to load on top of the built-in keys
+    pub capk_files: Vec<String>,
+    /// BIN range files to load on top of the schemes' own ranges
+    pub bin_files: Vec<String>,
+    /// Key to encrypt online PIN blocks under
+    pub pin_key: Option<emv_term::pinblock::PinKey>,
+    /// Run transactions as if on this date rather than today
+    pub transaction_date: Option<chrono::NaiveDate>,
+    /// Where to write the transaction outcome as JSON, `-` for stdout
+    pub outcome_file: Option<String>,
+    /// Amount of goods or services, in minor units
+    pub amount: Option<u64>,
+    /// Cash given back on top of `amount`, in minor units
+    pub cashback: Option<u64>,
+    pub transaction_type: Option<u8>,
+    /// The terminal's currency, which amounts are in
+    pub currency: Option<&'static emv_term::iso::Currency>,
+    /// Where the terminal is
+    pub country: Option<&'static emv_term::iso::Country>,
+    pub merchant: emv_term::terminal::MerchantData,
+    /// The terminal's own language, used unless the card prefers another
+    pub language: Option<emv_term::language::Language>,
+    /// Terminal Type, such as 22 for an attended merchant terminal or 14 for
+    /// an ATM
+    pub terminal_type: Option<u8>,
+    /// Where to keep the terminal's state between runs
+    pub state_file: String,
+    /// Where completed transactions are kept until settlement
+    pub batch_file: String,
+    /// The acquirer's host to send authorisations to
+    pub host: Option<emv_term::host::HostConfig>,
+    /// Sign on with the host before the transaction
+    pub sign_on: bool,
+    /// Approve online without a host
+    pub stand_in: Option<emv_term::acquirer::StandIn>,
+    /// Where to POST the transaction outcome
+    pub webhook: Option<emv_term::webhook::WebhookConfig>,
+    /// Never prompt, skipping anything that needs the cardholder's say
+    pub non_interactive: bool,
+    /// Print card numbers in full rather than masked
+    pub show_pan: bool,
+    /// Where to listen when serving
+    pub socket: String,
+    /// Where to record every exchange with the card, for `analyze`
+    pub transcript_file: Option<String>,
+    /// The least severe events to log, in place of `RUST_LOG`
+    pub log_level: Option<tracing_subscriber::filter::LevelFilter>,
+    pub log_format: LogFormat,
+}
+
+/// An error in the arguments as a whole, rather than in any one of them
+fn invalid(message: &str) -> clap::Error {
+    <Cli as clap::CommandFactory>::command().error(clap::error::ErrorKind::ArgumentConflict, message)
+}
+
+impl Args {
+    /// Parses the arguments after the program name. Errors include those
+    /// for `--help` and `--version`, which `exit` prints as it should.
+    pub fn parse<I: Iterator<Item=String>>(args: I) -> Result<Self, clap::Error> {
+        let cli = <Cli as clap::Parser>::try_parse_from(std::iter::once("emv-term".to_string()).chain(args))?;
+        let options = cli.options;
+        let mut dump = None;
+        let mut dump_format = emv_term::dump::DumpFormat::Json;
+        let mut socket = emv_term::serve::DEFAULT_SOCKET.to_string();
+        let command = match cli.command {
+            None | Some(Subcommand::Pay) => Command::Pay,
+            Some(Subcommand::Read { dump: d, format }) => {
+                dump = d;
+                dump_format = format;
+                Command::Read
+            }
+            Some(Subcommand::Dump { output, format }) => {
+                dump = output;
+                dump_format = format;
+                Command::Dump
+            }
+            Some(Subcommand::Logs) => Command::Logs,
+            Some(Subcommand::Pse) => Command::Pse,
+            Some(Subcommand::Monitor) => Command::Monitor,
+            Some(Subcommand::Tui) => Command::Tui,
+            Some(Subcommand::Settle) => Command::Settle,
+            Some(Subcommand::Serve { socket: s }) => {
+                socket = s;
+                Command::Serve
+            }
+            Some(Subcommand::Stdio) => Command::Stdio,
+            Some(Subcommand::Analyze { path }) => Command::Analyze(path),
+        };
+        let serving = matches!(command, Command::Serve | Command::Stdio);
+
+        let mut connect = emv_term::card::ConnectOptions::default();
+        connect.share_mode = options.share.unwrap_or(connect.share_mode);
+        connect.protocols = options.protocol.unwrap_or(connect.protocols);
+        connect.disposition = options.disposition.unwrap_or(connect.disposition);
+
+        let currency = options.currency;
+        let parse_amount = |a: Option<String>| a.map(|a| parse_amount(&a, currency)).transpose().map_err(|e| invalid(&e));
+        let amount = parse_amount(options.amount)?;
+        let cashback = parse_amount(options.cashback)?;
+        let stand_in_limit = parse_amount(options.stand_in_limit)?;
+        if serving && (amount.is_some() || cashback.is_some() || options.transaction_type.is_some() || options.outcome.is_some()) {
+            return Err(invalid("Transaction details come with each request when serving"));
+        }
+
+        let host = match options.host {
+            Some(address) => {
+                let mut host = emv_term::host::HostConfig::new(&address);
+                host.tls = options.host_tls || options.host_ca.is_some();
+                host.ca_file = options.host_ca;
+                host.timeout = options.host_timeout.unwrap_or(host.timeout);
+                Some(host)
+            }
+            None if options.host_tls || options.host_ca.is_some() || options.host_timeout.is_some() || options.sign_on => return Err(invalid("Host options need --host")),
+            None => None
+        };
+        let stand_in = match options.stand_in {
+            true if host.is_some() => return Err(invalid("--stand-in can't be used with --host")),
+            true => Some(emv_term::acquirer::StandIn {
+                limit: stand_in_limit,
+                issuer_master_key: options.issuer_key,
+            }),
+            false if stand_in_limit.is_some() || options.issuer_key.is_some() => return Err(invalid("Stand-in options need --stand-in")),
+            false => None
+        };
+        let webhook = match options.webhook {
+            Some(url) => {
+                let mut webhook = emv_term::webhook::WebhookConfig::new(&url);
+                webhook.secret = options.webhook_secret.map(String::into_bytes);
+                webhook.attempts = options.webhook_attempts.unwrap_or(webhook.attempts);
+                Some(webhook)
+            }
+            None if options.webhook_secret.is_some() || options.webhook_attempts.is_some() => return Err(invalid("Webhook options need --webhook")),
+            None => None
+        };
+
+        Ok(Args {
+            command,
+            reader: options.reader.as_deref().map(ReaderSelector::from),
+            connect,
+            dump,
+            dump_format,
+            interface: options.interface,
+            aid: options.aid,
+            application_file: options.application_file,
+            applications: emv_term::selection::default_terminal_applications(),
+            capk_files: options.capk_files,
+            bin_files: options.bin_files,
+            pin_key: options.pin_key,
+            transaction_date: options.date,
+            outcome_file: options.outcome,
+            amount,
+            cashback,
+            transaction_type: options.transaction_type,
+            currency,
+            country: options.country,
+            merchant: emv_term::terminal::MerchantData {
+                category_code: options.mcc,
+                identifier: options.merchant_id,
+                terminal_identification: options.terminal_id,
+                name_and_location: options.merchant_name,
+            },
+            language: options.language,
+            terminal_type: options.terminal_type,
+            state_file: options.state_file,
+            batch_file: options.batch_file,
+            host,
+            sign_on: options.sign_on,
+            stand_in,
+            webhook,
+            // There's no one at the terminal to ask when serving
+            non_interactive: options.non_interactive || serving,
+            show_pan: options.show_pan,
+            socket,
+            transcript_file: options.transcript_file,
+            log_level: options.log_level,
+            // Logs from a service go somewhere to be searched, not read
+            log_format: options.log_format.unwrap_or(if serving { LogFormat::Json } else { LogFormat::Text }),
+        })
+    }
+}
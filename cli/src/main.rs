@@ -0,0 +1,898 @@
+mod args;
+mod tui;
+
+use emv_term::{acquirer, batch, bins, capk, card, dump, events, hex, history, host, language, pinblock, pinpad, replay, run, selection, serve, state, stdio, terminal, transaction, transport, util, webhook};
+use transport::CardTransport;
+
+
+/// Fills in the transaction type and amounts from the command line, asking for
+/// whatever wasn't given when there's someone to ask. Amount, Authorised
+/// includes any cashback.
+fn enter_transaction_details(terminal: &mut terminal::TerminalData, args: &args::Args) {
+    let interactive = !args.non_interactive;
+    terminal.transaction_type = match args.transaction_type {
+        Some(t) => t,
+        None if args.cashback.is_some() => terminal::TRANSACTION_TYPE_CASHBACK,
+        None if interactive && args.amount.is_none() => loop {
+            let name: String = util::get_input("Transaction type [purchase/cash/cashback/refund]: ");
+            if let Some(t) = terminal::transaction_type_from_name(name.trim()) {
+                break t;
+            }
+        },
+        None => terminal::TRANSACTION_TYPE_PURCHASE
+    };
+    let amount = match args.amount {
+        Some(a) => a,
+        None if interactive => util::get_amount("Amount: ", terminal.currency_exponent),
+        None => 0
+    };
+    let cashback = match args.cashback {
+        Some(c) => c,
+        None if interactive && terminal.transaction_type == terminal::TRANSACTION_TYPE_CASHBACK => util::get_amount("Cashback amount: ", terminal.currency_exponent),
+        None => 0
+    };
+    terminal.amount_other = cashback;
+    terminal.amount_authorised = amount + cashback;
+}
+
+/// The terminal data to start from for the card, as set up on the command line
+fn terminal_for<T: CardTransport + ?Sized>(card: &T, args: &args::Args) -> terminal::TerminalData {
+    let interface = args.interface.unwrap_or_else(|| card::detect_interface(card));
+    println!("Interface: {}", interface);
+    terminal_data(interface, args)
+}
+
+/// The terminal data for the interface, as set up on the command line
+fn terminal_data(interface: card::Interface, args: &args::Args) -> terminal::TerminalData {
+    let mut terminal = terminal::TerminalData::new(interface);
+    terminal.date_override = args.transaction_date;
+    if let Some(terminal_type) = args.terminal_type {
+        terminal.terminal_type = terminal_type;
+    }
+    if let Some(currency) = args.currency {
+        terminal.currency_code = currency.numeric;
+        terminal.currency_exponent = currency.exponent;
+    }
+    if let Some(country) = args.country {
+        terminal.country_code = country.numeric;
+    }
+    terminal.merchant = args.merchant.clone();
+    terminal.language = args.language.or_else(language::Language::from_environment).unwrap_or_default();
+    terminal.applications = match args.aid.is_empty() {
+        true => args.applications.clone(),
+        false => selection::restrict_applications(&args.applications, &args.aid)
+    };
+    terminal
+}
+
+/// Has the operator call the issuer, giving the authorisation code they're read
+fn ask_for_referral(request: &transaction::ReferralRequest) -> Option<String> {
+    let question = format!("Call the issuer for authorisation of {}. Did the issuer approve?", request.masked_pan.as_deref().unwrap_or_default());
+    if util::get_input_bool(&question) {
+        Some(util::get_input::<String>("Authorisation code: "))
+    } else {
+        None
+    }
+}
+
+/// Runs the transaction with the card, with whichever host or stand-in the
+/// command line set up, then adds it to the batch and sends it to the webhook.
+/// Errors are for the terminal being unable to start a transaction at all.
+fn transact(card: &mut card::Session, terminal: &mut terminal::TerminalData, args: &args::Args, ca_keys: &capk::CapkStore) -> Result<transaction::TransactionOutcome, String> {
+    // Saved straight away so a counter is never used twice, even if this run fails
+    let state_path = std::path::Path::new(&args.state_file);
+    let mut terminal_state = state::TerminalState::load(state_path).map_err(|e| format!("Unable to read terminal state: {}", e))?;
+    // Without the host the transaction carries on as unable to go online
+    let host = match &args.host {
+        Some(config) => match host::HostConnection::connect(config) {
+            Ok(mut h) => match args.sign_on {
+                true => match h.sign_on(terminal_state.next_sequence_counter()) {
+                    Ok(()) => Some(h),
+                    Err(e) => {
+                        println!("Unable to sign on with the host: {}", e);
+                        None
+                    }
+                }
+                false => Some(h)
+            },
+            Err(e) => {
+                println!("Unable to connect to the host: {}", e);
+                None
+            }
+        },
+        None => None
+    };
+    let mut acquirer: Option<Box<dyn acquirer::Acquirer>> = match (host, &args.stand_in) {
+        (Some(h), _) => Some(Box::new(h)),
+        (None, Some(s)) => Some(Box::new(s.clone())),
+        (None, None) => None
+    };
+    terminal.transaction_sequence_counter = terminal_state.next_sequence_counter();
+    let mut pin_key = args.pin_key.clone();
+    if let Some(pinblock::PinKey::Dukpt(key)) = &mut pin_key {
+        key.ksn = terminal_state.next_ksn(&key.ksn).ok_or("DUKPT key used up, a new one needs injecting")?;
+    }
+    if let Err(e) = terminal_state.save(state_path) {
+        println!("Unable to save terminal state: {}", e);
+    }
+    if !args.non_interactive {
+        terminal.referral = Some(transaction::ReferralHandler::new(ask_for_referral));
+    }
+    // Messages for the cardholder come as events from part way through, so are
+    // shown as they arrive
+    let (sender, messages) = std::sync::mpsc::channel();
+    let printer = std::thread::spawn(move || {
+        for event in messages {
+            if let events::Event::CardholderMessage { message } = event {
+                println!("{}", message);
+            }
+        }
+    });
+    terminal.events = Some(sender);
+    let mut recording = transport::Recording::new(card);
+    let result = run(&mut recording, terminal, ca_keys, pin_key.as_ref(), acquirer.as_mut().map(|a| &mut **a as &mut dyn acquirer::Acquirer), !args.non_interactive);
+    terminal.events = None;
+    let _ = printer.join();
+    let atr = recording.atr().ok().map(|a| a.raw().to_vec());
+    let exchanges = recording.into_exchanges();
+    let outcome = result.unwrap_or_else(|e| transaction::TransactionOutcome::card_error(&e, terminal.interface));
+    println!("{}", outcome.cardholder_message());
+    if outcome.outcome.approved() && outcome.signature_required {
+        println!("{}", outcome.language.message(language::Message::SignatureRequired));
+    }
+    println!("{}", outcome);
+    if !outcome.outcome.approved() {
+        println!("{}", outcome.explain());
+    }
+    if let Some(path) = &args.transcript_file {
+        match replay::Transcript::new(terminal, &outcome, atr, exchanges).save(std::path::Path::new(path)) {
+            Ok(()) => println!("Wrote transcript to {}", path),
+            Err(e) => println!("Unable to write transcript: {}", e)
+        }
+    }
+    if let Some(record) = batch::BatchRecord::new(terminal, &outcome) {
+        match batch::append(std::path::Path::new(&args.batch_file), &record) {
+            Ok(()) => println!("Added to batch {}", args.batch_file),
+            Err(e) => println!("Unable to add transaction to batch: {}", e)
+        }
+    }
+    if let Some(webhook) = &args.webhook {
+        match webhook::send(webhook, &outcome) {
+            Ok(()) => println!("Sent transaction outcome to {}", webhook.url),
+            Err(e) => println!("Unable to send transaction outcome to webhook: {}", e)
+        }
+    }
+    Ok(outcome)
+}
+
+/// Writes the outcome as JSON where `--outcome` asked for it
+fn write_outcome(outcome: &transaction::TransactionOutcome, args: &args::Args) {
+    match args.outcome_file.as_deref() {
+        Some("-") => match serde_json::to_string_pretty(outcome) {
+            Ok(json) => println!("{}", json),
+            Err(e) => println!("Unable to write transaction outcome: {}", e)
+        },
+        Some(path) => {
+            let written = serde_json::to_vec_pretty(outcome).map_err(std::io::Error::from)
+                .and_then(|data| std::fs::write(path, data));
+            match written {
+                Ok(()) => println!("Wrote transaction outcome to {}", path),
+                Err(e) => println!("Unable to write transaction outcome: {}", e)
+            }
+        }
+        None => {}
+    }
+}
+
+/// The scheme and issuer of each application, its PAN, masked unless
+/// `show_pan`, the cardholder's name, whether it's expired by `today`, its
+/// service code, its issuer's details, and what the card says about where
+/// it's from
+fn print_card_summary(card_dump: &dump::CardDump, show_pan: bool, today: chrono::NaiveDate) {
+    for application in &card_dump.applications {
+        match (&application.scheme, &application.issuer) {
+            (Some(scheme), Some(issuer)) => println!("{}: {} card issued by {}", hex::Hex(&application.aid), scheme, issuer),
+            (Some(scheme), None) => println!("{}: {} card", hex::Hex(&application.aid), scheme),
+            _ => {}
+        }
+        let data = application.application_data();
+        match &data.pan {
+            Some(pan) if show_pan => println!("  PAN: {}", pan.reveal()),
+            Some(pan) => println!("  PAN: {}", pan),
+            None => {}
+        }
+        if let Some(name) = &data.cardholder_name {
+            println!("  Cardholder: {}", name);
+        }
+        if let Some(expiry) = data.expiry_date {
+            match data.is_expired(today) {
+                true => println!("  Expired {}", expiry),
+                false => println!("  Expires {}", expiry)
+            }
+        }
+        if let Some(service_code) = data.service_code {
+            println!("  Service code {}", service_code);
+        }
+        for (name, value) in application.card_info().fields() {
+            println!("  {}: {}", name, value);
+        }
+        for (tag, description) in application.codes() {
+            println!("  {:?}: {}", tag, description);
+        }
+        if let Some(key) = &application.icc_public_key {
+            println!("ICC public key for {}: {}", hex::Hex(&application.aid), hex::Hex(&key.modulus));
+        }
+    }
+}
+
+/// Prints a transaction log as a table, amounts in their own currency
+fn print_transaction_log(log: &history::TransactionLog) {
+    println!("Transaction log for {}", hex::Hex(&log.aid));
+    if log.entries.is_empty() {
+        println!("  No transactions");
+        return;
+    }
+    println!("  {:<10}  {:<8}  {:<8}  {:>16}  {:<5}  Country", "Date", "Time", "Type", "Amount", "ATC");
+    for entry in &log.entries {
+        let date = entry.date().map(|d| d.to_string());
+        let time = entry.time().map(|t| t.to_string());
+        let transaction_type = entry.transaction_type()
+            .map(|t| terminal::transaction_type_name(t).map(|n| n.to_string()).unwrap_or_else(|| format!("{:02X}", t)));
+        let amount = entry.amount().map(|a| match entry.currency() {
+            Some(c) => format!("{} {}", c.code, util::format_amount(a, c.exponent)),
+            None => match entry.currency_code() {
+                Some(c) => format!("{:03} {}", c, a),
+                None => a.to_string()
+            }
+        });
+        let country = entry.country().map(|c| c.to_string())
+            .or_else(|| entry.country_code().map(|c| format!("{:03}", c)));
+        println!(
+            "  {:<10}  {:<8}  {:<8}  {:>16}  {:<5}  {}",
+            date.as_deref().unwrap_or("-"),
+            time.as_deref().unwrap_or("-"),
+            transaction_type.as_deref().unwrap_or("-"),
+            amount.as_deref().unwrap_or("-"),
+            entry.application_transaction_counter().map(|c| c.to_string()).as_deref().unwrap_or("-"),
+            country.as_deref().unwrap_or("-"),
+        );
+        if let Some(merchant) = entry.merchant_name() {
+            println!("  {:<10}  {}", "", merchant);
+        }
+    }
+}
+
+/// Runs a recorded transaction again without the card, or a transaction
+/// against a card dump, with the terminal's current CA keys and logic
+fn analyze(path: &str, args: &args::Args, ca_keys: &capk::CapkStore) -> Result<transaction::TransactionOutcome, String> {
+    let recording = replay::Recording::load(std::path::Path::new(path)).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let outcome = match recording {
+        replay::Recording::Transcript(transcript) => {
+            let mut card = replay::Replay::new(&transcript);
+            let outcome = run(&mut card, &transcript.terminal(), ca_keys, None, None, false);
+            match card.divergence() {
+                Some(d) => println!("Replay diverged from the recording: {}", d),
+                None if card.remaining() > 0 => println!("{} recorded commands weren't replayed", card.remaining()),
+                None => println!("Replay matched the recording")
+            }
+            outcome
+        }
+        replay::Recording::Dump(dump) => {
+            let mut card = replay::DumpCard::new(&dump);
+            let mut terminal = terminal_for(&card, args);
+            enter_transaction_details(&mut terminal, args);
+            let outcome = run(&mut card, &terminal, ca_keys, None, None, false);
+            if let Some(command) = card.unanswered() {
+                println!("Stopped at {}, which the dump has no answer to", hex::Hex(&command));
+            }
+            outcome
+        }
+    };
+    Ok(outcome.unwrap_or_else(|e| transaction::TransactionOutcome::without_transaction(transaction::Outcome::Error, Some(format!("Card communication failed: {}", e)))))
+}
+
+/// Connects to the card in the reader asked for, or the one picked from the
+/// list, waiting for a card to be put in it. Without a reader to use, waits
+/// for a card in any of them. Cancelling stops the wait, and any exchange with
+/// the card after.
+fn connect(ctx: &pcsc::Context, args: &args::Args, canceller: &card::Canceller) -> Result<card::Session, String> {
+    let reader = match &args.reader {
+        Some(s) => Some(card::resolve_reader(ctx, s).map_err(|e| match e {
+            pcsc::Error::UnknownReader => "No reader matches the one asked for".to_string(),
+            e => format!("Unable to find the reader: {}", e)
+        })?),
+        None if args.non_interactive => None,
+        None => card::choose_reader(ctx).map_err(|e| format!("Unable to list readers: {}", e))?
+    };
+    let reader = match reader {
+        Some(r) => {
+            card::wait_for_card(ctx, &r).map_err(|e| format!("Unable to wait for a card: {}", e))?;
+            r
+        }
+        None => card::find_reader(ctx).map_err(|e| format!("Unable to wait for a card: {}", e))?
+    };
+    let mut card = card::Session::connect(ctx, &reader, args.connect).map_err(|e| format!("Unable to connect to the card: {}", e))?;
+    card.cancel_with(canceller);
+    Ok(card)
+}
+
+/// Reads each card presented to any reader, several at once, printing what's
+/// on each under the reader it's in
+fn monitor(ctx: &pcsc::Context, args: &args::Args, ca_keys: &capk::CapkStore, bins: &bins::BinTable, canceller: &card::Canceller) -> Result<(), String> {
+    println!("Watching every reader, present cards now or press Ctrl-C to stop...");
+    card::monitor_readers(ctx, args.connect, canceller, |card| {
+        let reader = card.reader().to_string_lossy().into_owned();
+        let interface = args.interface.unwrap_or_else(|| card::detect_interface(&card));
+        let terminal = terminal_data(interface, args);
+        let read = dump::read_card(&card, &terminal, ca_keys);
+        let disconnected = card.disconnect();
+
+        // Each card's output kept together, whatever other readers are doing
+        let _stdout = std::io::stdout().lock();
+        println!("{} ({}):", reader, interface);
+        if let Err(e) = disconnected {
+            println!("Unable to disconnect from card: {}", e);
+        }
+        match read {
+            Ok(mut card_dump) => {
+                card_dump.annotate(bins);
+                print_card_summary(&card_dump, args.show_pan, terminal.transaction_date());
+            }
+            Err(e) if card::is_card_removed(&e) => println!("Card removed before it could be read"),
+            Err(e) if card::is_cancelled(&e) => {}
+            Err(e) => println!("Card communication failed: {}", e)
+        }
+    }).map_err(|e| format!("Unable to watch the readers: {}", e))
+}
+
+/// Connects to the card without waiting for one, in the reader asked for or
+/// else the first with a card in it
+fn connect_present(ctx: &pcsc::Context, args: &args::Args) -> Result<Option<card::Session>, pcsc::Error> {
+    let reader = match &args.reader {
+        Some(s) => Some(card::resolve_reader(ctx, s)?),
+        None => card::list_readers(ctx)?.into_iter().find(|r| r.card_present()).map(|r| r.name().to_owned())
+    };
+    match reader.map(|r| card::Session::connect(ctx, &r, args.connect)) {
+        Some(Ok(card)) => Ok(Some(card)),
+        Some(Err(pcsc::Error::NoSmartcard)) | Some(Err(pcsc::Error::RemovedCard)) | None => Ok(None),
+        Some(Err(e)) => Err(e)
+    }
+}
+
+fn handle_request(ctx: &pcsc::Context, args: &args::Args, ca_keys: &capk::CapkStore, bins: &bins::BinTable, request: serve::Request) -> serve::Response {
+    let card = match connect_present(ctx, args) {
+        Ok(c) => c,
+        Err(e) => return serve::Response::error(format!("Unable to connect to card: {}", e))
+    };
+    if request == serve::Request::DetectCard {
+        let response = serve::Response::Card {
+            present: card.is_some(),
+            reader: card.as_ref().map(|c| c.reader().to_string_lossy().into_owned()),
+            atr: card.as_ref().and_then(|c| c.atr().ok()).map(|a| a.raw().to_vec()),
+            interface: card.as_ref().map(|c| args.interface.unwrap_or_else(|| card::detect_interface(c)).to_string()),
+        };
+        if let Some(Err(e)) = card.map(|c| c.disconnect_with(pcsc::Disposition::LeaveCard)) {
+            println!("Unable to disconnect from card: {}", e);
+        }
+        return response;
+    }
+    let mut card = match card {
+        Some(c) => c,
+        None => return serve::Response::error("No card present")
+    };
+
+    let mut terminal = terminal_for(&card, args);
+    let mut card_removed = false;
+    let response = match request {
+        serve::Request::DetectCard => unreachable!(),
+        serve::Request::ReadCard => match dump::read_card(&card, &terminal, ca_keys) {
+            Ok(mut d) => {
+                d.annotate(bins);
+                serve::Response::CardData(d)
+            }
+            Err(e) => {
+                card_removed = card::is_card_removed(&e);
+                serve::Response::error(format!("Card communication failed: {}", e))
+            }
+        },
+        serve::Request::Transaction { amount, cashback, transaction_type } => {
+            let transaction_type = match transaction_type.as_deref().map(|n| (n, terminal::transaction_type_from_name(n))) {
+                Some((_, Some(t))) => Some(t),
+                Some((name, None)) => return serve::Response::error(format!("Unknown transaction type: {}", name)),
+                None => None
+            };
+            terminal::TransactionRequest::new(transaction_type, amount, cashback).apply(&mut terminal);
+            match transact(&mut card, &mut terminal, args, ca_keys) {
+                Ok(o) => {
+                    card_removed = o.outcome.card_removed();
+                    serve::Response::Outcome(Box::new(o))
+                }
+                Err(e) => serve::Response::error(e)
+            }
+        }
+    };
+    if !card_removed {
+        if let Err(e) = card.disconnect() {
+            println!("Unable to disconnect from card: {}", e);
+        }
+    }
+    response
+}
+
+/// Runs transactions as the ECR on stdin asks, waiting for a card between
+/// checks for a cancel, until stdin closes
+fn run_stdio(ctx: &pcsc::Context, args: &args::Args, ca_keys: &capk::CapkStore) -> std::io::Result<()> {
+    let mut events = stdio::take_stdout()?;
+    let commands = stdio::read_commands(std::io::BufReader::new(std::io::stdin()));
+    let mut session = stdio::Session::new();
+    stdio::emit(&mut events, &stdio::Event::Ready)?;
+    loop {
+        let received = match session.awaiting_card() {
+            Some(_) => commands.recv_timeout(stdio::POLL_INTERVAL),
+            None => commands.recv().map_err(|_| std::sync::mpsc::RecvTimeoutError::Disconnected)
+        };
+        let request = match received {
+            Ok(Ok(command)) => {
+                for event in session.command(command) {
+                    stdio::emit(&mut events, &event)?;
+                }
+                continue;
+            }
+            Ok(Err(e)) => {
+                stdio::emit(&mut events, &stdio::Event::error(e))?;
+                continue;
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => match session.awaiting_card() {
+                Some(r) => r.clone(),
+                None => continue
+            }
+        };
+
+        let mut card = match connect_present(ctx, args) {
+            Ok(Some(c)) => c,
+            Ok(None) => continue,
+            Err(e) => {
+                session.finish();
+                stdio::emit(&mut events, &stdio::Event::error(format!("Unable to connect to card: {}", e)))?;
+                continue;
+            }
+        };
+        let mut terminal = terminal_for(&card, args);
+        stdio::emit(&mut events, &stdio::Event::CardDetected {
+            reader: card.reader().to_string_lossy().into_owned(),
+            atr: card.atr().ok().map(|a| a.raw().to_vec()),
+            interface: terminal.interface.to_string(),
+        })?;
+        request.apply(&mut terminal);
+        let result = transact(&mut card, &mut terminal, args, ca_keys);
+        session.finish();
+        let card_removed = matches!(&result, Ok(o) if o.outcome.card_removed());
+        if !card_removed {
+            if let Err(e) = card.disconnect() {
+                println!("Unable to disconnect from card: {}", e);
+            }
+        }
+        stdio::emit(&mut events, &match result {
+            Ok(o) => stdio::Event::Outcome(Box::new(o)),
+            Err(e) => stdio::Event::error(e)
+        })?;
+    }
+}
+
+/// Sends logs to stderr, leaving stdout for the outcome and anything asked
+/// of the cardholder
+fn init_logging(args: &args::Args) {
+    let filter = match args.log_level {
+        Some(level) => tracing_subscriber::EnvFilter::default().add_directive(level.into()),
+        None => tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"))
+    };
+    let logs = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    match args.log_format {
+        args::LogFormat::Text => logs.without_time().with_target(false).init(),
+        args::LogFormat::Json => logs.json().init(),
+    }
+}
+
+fn main() {
+    let mut args = match args::Args::parse(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => e.exit()
+    };
+    // Logs would draw over the interface
+    if args.command != args::Command::Tui {
+        init_logging(&args);
+    }
+
+    if args.command == args::Command::Settle {
+        match batch::settle(std::path::Path::new(&args.batch_file)) {
+            Ok(settlement) => println!("{}", settlement),
+            Err(e) => {
+                println!("Unable to settle batch: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut ca_keys = capk::CapkStore::with_defaults();
+    for path in &args.capk_files {
+        if let Err(e) = ca_keys.load_file(std::path::Path::new(path)) {
+            println!("Unable to load CA public keys: {}", e);
+            std::process::exit(1);
+        }
+    }
+    println!("Loaded {} CA public keys", ca_keys.len());
+
+    let mut bins = bins::BinTable::with_defaults();
+    for path in &args.bin_files {
+        if let Err(e) = bins.load_file(std::path::Path::new(path)) {
+            println!("Unable to load BIN ranges: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(path) = &args.application_file {
+        match selection::load_applications(std::path::Path::new(path)) {
+            Ok(a) => args.applications = a,
+            Err(e) => {
+                println!("Unable to load the terminal's applications: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let args::Command::Analyze(path) = &args.command {
+        match analyze(path, &args, &ca_keys) {
+            Ok(outcome) => {
+                println!("{}", outcome);
+                println!("{}", outcome.explain());
+                write_outcome(&outcome, &args);
+            }
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let ctx = match pcsc::Context::establish(pcsc::Scope::User) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Unable to open PC/SC context, is the PC/SC daemon running? {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if args.command == args::Command::Stdio {
+        if let Err(e) = run_stdio(&ctx, &args, &ca_keys) {
+            println!("Unable to write events: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.command == args::Command::Tui {
+        if let Err(e) = tui::run_tui(&ctx, &args, &ca_keys) {
+            println!("Unable to run the interface: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.command == args::Command::Serve {
+        if let Err(e) = serve::serve(std::path::Path::new(&args.socket), |r| handle_request(&ctx, &args, &ca_keys, &bins, r)) {
+            println!("Unable to serve: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Ctrl-C cancels waiting for the card or the transaction with it, and a
+    // second gives up on the card altogether
+    let canceller = card::Canceller::new();
+    canceller.watch(&ctx);
+    let interrupted = canceller.clone();
+    if let Err(e) = ctrlc::set_handler(move || match interrupted.is_cancelled() {
+        true => std::process::exit(130),
+        false => interrupted.cancel()
+    }) {
+        println!("Unable to handle Ctrl-C: {}", e);
+    }
+
+    if args.command == args::Command::Monitor {
+        if let Err(e) = monitor(&ctx, &args, &ca_keys, &bins, &canceller) {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut card = match connect(&ctx, &args, &canceller) {
+        Ok(c) => c,
+        Err(_) if canceller.is_cancelled() => {
+            let outcome = transaction::TransactionOutcome::without_transaction(transaction::Outcome::Aborted, None);
+            println!("{}", outcome);
+            write_outcome(&outcome, &args);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match card.atr() {
+        Ok(atr) => println!("{}", atr),
+        Err(e) => println!("Unable to read ATR: {}", e)
+    }
+    if pinpad::find_verify_pin_direct(&card).is_some() {
+        println!("Reader supports secure PIN entry");
+    }
+    let mut terminal = terminal_for(&card, &args);
+
+    if args.command == args::Command::Pse {
+        match selection::read_directory_tree(&card, terminal.interface) {
+            Ok(Some(directory)) => print!("{}", directory),
+            Ok(None) => println!("The card has no {}", match terminal.interface {
+                card::Interface::Contact => "PSE",
+                card::Interface::Contactless => "PPSE"
+            }),
+            Err(e) if card::is_card_removed(&e) => {
+                println!("Outcome: {}", transaction::Outcome::TornTransaction);
+                std::process::exit(1);
+            }
+            Err(e) if card::is_cancelled(&e) => {
+                println!("Outcome: {}", transaction::Outcome::Aborted);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                println!("Card communication failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        if let Err(e) = card.disconnect() {
+            println!("Unable to disconnect from card: {}", e);
+        }
+        return;
+    }
+
+    if args.command == args::Command::Logs {
+        let logs = match history::read_logs(&card, &terminal) {
+            Ok(l) => l,
+            Err(e) if card::is_card_removed(&e) => {
+                println!("Outcome: {}", transaction::Outcome::TornTransaction);
+                std::process::exit(1);
+            }
+            Err(e) if card::is_cancelled(&e) => {
+                println!("Outcome: {}", transaction::Outcome::Aborted);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                println!("Card communication failed: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if logs.is_empty() {
+            println!("The card has no transaction log");
+        }
+        for log in &logs {
+            print_transaction_log(log);
+        }
+        if let Err(e) = card.disconnect() {
+            println!("Unable to disconnect from card: {}", e);
+        }
+        return;
+    }
+
+    if matches!(args.command, args::Command::Read | args::Command::Dump) {
+        let read = match args.command {
+            args::Command::Dump => dump::explore_card(&card, &terminal, &ca_keys),
+            _ => dump::read_card(&card, &terminal, &ca_keys)
+        };
+        let mut card_dump = match read {
+            Ok(d) => d,
+            Err(e) if card::is_card_removed(&e) => {
+                println!("Outcome: {}", transaction::Outcome::TornTransaction);
+                std::process::exit(1);
+            }
+            Err(e) if card::is_cancelled(&e) => {
+                println!("Outcome: {}", transaction::Outcome::Aborted);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                println!("Card communication failed: {}", e);
+                std::process::exit(1);
+            }
+        };
+        card_dump.annotate(&bins);
+        match args.command {
+            args::Command::Dump => print!("{}", card_dump),
+            _ => print_card_summary(&card_dump, args.show_pan, terminal.transaction_date())
+        }
+        if let Some(path) = &args.dump {
+            if let Err(e) = dump::write_dump(&card_dump, path, args.dump_format) {
+                println!("Unable to write card dump to {}: {}", path, e);
+                std::process::exit(1);
+            }
+            println!("Wrote card dump to {}", path);
+        }
+        if let Err(e) = card.disconnect() {
+            println!("Unable to disconnect from card: {}", e);
+        }
+        return;
+    }
+
+    enter_transaction_details(&mut terminal, &args);
+    let outcome = match transact(&mut card, &mut terminal, &args, &ca_keys) {
+        Ok(o) => o,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    write_outcome(&outcome, &args);
+
+    let disconnected = match outcome.outcome {
+        o if o.card_removed() => Ok(()),
+        // The card would otherwise be left part way through the transaction
+        transaction::Outcome::Aborted => card.disconnect_with(pcsc::Disposition::ResetCard),
+        _ => card.disconnect()
+    };
+    if let Err(e) = disconnected {
+        println!("Unable to disconnect from card: {}", e);
+    }
+    if matches!(outcome.outcome, transaction::Outcome::Error | transaction::Outcome::Aborted) {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn parse(args: &[&str]) -> Result<crate::args::Args, clap::Error> {
+        crate::args::Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_transaction_details() {
+        let args = parse(&["--amount", "20.00", "--cashback", "5", "--non-interactive"]).unwrap();
+        let mut terminal = emv_term::terminal::TerminalData::new(emv_term::card::Interface::Contact);
+        crate::enter_transaction_details(&mut terminal, &args);
+        assert_eq!(terminal.transaction_type, emv_term::terminal::TRANSACTION_TYPE_CASHBACK);
+        assert_eq!(terminal.value(emv_term::tlv::TagID::AmountAuthorised).unwrap(), vec![0, 0, 0, 0, 0x25, 0x00]);
+        assert_eq!(terminal.value(emv_term::tlv::TagID::AmountOther).unwrap(), vec![0, 0, 0, 0, 0x05, 0x00]);
+        assert_eq!(terminal.value(emv_term::tlv::TagID::TransactionType).unwrap(), vec![0x09]);
+
+        let args = parse(&["--type", "refund", "--amount", "1"]).unwrap();
+        assert_eq!(args.transaction_type, Some(emv_term::terminal::TRANSACTION_TYPE_REFUND));
+        assert!(parse(&["--type", "transfer"]).is_err());
+        assert!(parse(&["--terminal-type", "27"]).is_err());
+        assert!(parse(&["--terminal-type", "2"]).is_err());
+        assert_eq!(parse(&["--terminal-type", "25"]).unwrap().terminal_type, Some(0x25));
+        assert_eq!(parse(&["--language", "cy"]).unwrap().language, Some(emv_term::language::Language::Welsh));
+        assert!(parse(&["--language", "xx"]).is_err());
+    }
+
+    #[test]
+    fn summarises_exchanges() {
+        let exchange = |command: &[u8], response: &[u8]| emv_term::transport::Exchange {
+            command: command.to_vec(),
+            response: response.to_vec(),
+        };
+        let exchanges = vec![
+            exchange(&[0x00, 0xa4, 0x04, 0x00, 0x02, 0xa0, 0x00, 0x00], &[0x6f, 0x03, 0x84, 0x01, 0xa0, 0x90, 0x00]),
+            exchange(&[0x00, 0xb2, 0x01, 0x0c, 0x00], &[0x6a, 0x83]),
+            exchange(&[0x00, 0xb2, 0x02, 0x0c, 0x00], &[0x6a, 0x83]),
+            exchange(&[0x80, 0x99, 0x00, 0x00, 0x00], &[0x01, 0x02, 0x90, 0x00]),
+        ];
+        assert_eq!(crate::tui::progress(&exchanges), vec![
+            ("SELECT".to_string(), 1),
+            ("READ RECORD".to_string(), 2),
+            ("INS 99".to_string(), 1),
+        ]);
+        assert_eq!(crate::tui::response_tags(&exchanges[0]), vec!["Status 9000", "6F FileControlInformationTemplate", "  84 DedicatedFileName: A0"]);
+        assert_eq!(crate::tui::response_tags(&exchanges[1]), vec!["Status 6A83"]);
+        assert_eq!(crate::tui::response_tags(&exchanges[3]), vec!["Status 9000", "0102"]);
+    }
+
+    #[test]
+    fn parses_commands() {
+        let args = parse(&["analyse", "card.json"]).unwrap();
+        assert_eq!(args.command, crate::args::Command::Analyze("card.json".to_string()));
+        assert!(parse(&["analyze"]).is_err());
+
+        let args = parse(&["settle", "--batch", "batch.jsonl"]).unwrap();
+        assert_eq!(args.command, crate::args::Command::Settle);
+        assert_eq!(args.batch_file, "batch.jsonl");
+
+        let args = parse(&["stdio"]).unwrap();
+        assert_eq!(args.command, crate::args::Command::Stdio);
+        assert!(args.non_interactive);
+        assert!(parse(&["stdio", "--socket", "a.sock"]).is_err());
+        assert!(parse(&["stdio", "--amount", "1"]).is_err());
+
+        let args = parse(&["dump", "--output", "card.json"]).unwrap();
+        assert_eq!(args.command, crate::args::Command::Dump);
+        assert_eq!(args.dump.as_deref(), Some("card.json"));
+
+        assert_eq!(parse(&["logs", "--aid", "A0000000031010"]).unwrap().command, crate::args::Command::Logs);
+        assert!(parse(&["logs", "--dump", "card.txt"]).is_err());
+        assert_eq!(parse(&["pse", "--interface", "contactless"]).unwrap().command, crate::args::Command::Pse);
+        assert_eq!(parse(&["monitor", "--interface", "contact"]).unwrap().command, crate::args::Command::Monitor);
+        assert_eq!(parse(&["tui", "--amount", "1"]).unwrap().command, crate::args::Command::Tui);
+
+        let args = parse(&["read", "--dump", "card.txt", "--format", "tags"]).unwrap();
+        assert_eq!(args.command, crate::args::Command::Read);
+        assert_eq!(args.dump_format, emv_term::dump::DumpFormat::TagValue);
+        assert!(parse(&["pay", "--dump", "card.txt"]).is_err());
+
+        // Options can come before or after the subcommand
+        let args = parse(&["--reader", "1", "pay", "--amount", "1.50", "--yes"]).unwrap();
+        assert_eq!(args.command, crate::args::Command::Pay);
+        assert_eq!(args.amount, Some(150));
+        assert!(args.non_interactive);
+        assert_eq!(parse(&["--amount", "1"]).unwrap().command, crate::args::Command::Pay);
+        assert!(parse(&["-y"]).unwrap().non_interactive);
+        assert!(parse(&["read", "--show-pan"]).unwrap().show_pan);
+        assert!(!parse(&["read"]).unwrap().show_pan);
+
+        let args = parse(&["pay", "--aid", "A0000000031010"]).unwrap();
+        assert_eq!(args.aid.len(), 1);
+        assert_eq!(args.aid[0].aid, vec![0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10]);
+        assert!(args.aid[0].partial_selection);
+        assert!(parse(&["pay", "--aid", "A000"]).is_err());
+        assert!(parse(&["pay", "--aid", "A0000000031010:prefix"]).is_err());
+        let args = parse(&["pay", "--aid", "A0000000031010:exact", "--aid", "A000000004", "--aids", "aids.csv"]).unwrap();
+        assert!(!args.aid[0].partial_selection);
+        assert_eq!(args.aid[1].aid, vec![0xa0, 0x00, 0x00, 0x00, 0x04]);
+        assert_eq!(args.application_file.as_deref(), Some("aids.csv"));
+
+        let args = parse(&["serve", "--socket", "a.sock"]).unwrap();
+        assert_eq!(args.socket, "a.sock");
+    }
+
+    #[test]
+    fn parses_keys() {
+        let args = parse(&["--stand-in", "--issuer-key", "pkcs11:C:\\pkcs11.dll:0:imk"]).unwrap();
+        match args.stand_in.and_then(|s| s.issuer_master_key) {
+            Some(emv_term::hsm::TdesKey::Pkcs11(k)) => assert_eq!((k.module.as_str(), k.slot, k.label.as_str()), ("C:\\pkcs11.dll", 0, "imk")),
+            k => panic!("Wrong issuer key: {:?}", k)
+        }
+        assert!(parse(&["--stand-in", "--issuer-key", "0011"]).is_err());
+    }
+
+    #[test]
+    fn parses_merchant_data() {
+        let args = parse(&["--mcc", "5411", "--merchant-id", "123456789", "--terminal-id", "T1",
+                           "--merchant-name", "Corner Shop, Cardiff"]).unwrap();
+        assert_eq!(args.merchant.category_code, Some(5411));
+        assert_eq!(args.merchant.identifier.as_deref(), Some("123456789"));
+        assert_eq!(args.merchant.terminal_identification.as_deref(), Some("T1"));
+        assert_eq!(args.merchant.name_and_location.as_deref(), Some("Corner Shop, Cardiff"));
+        assert!(parse(&["--mcc", "541"]).is_err());
+        assert!(parse(&["--terminal-id", "TERMINAL1"]).is_err());
+        assert!(parse(&["--merchant-name", "Café"]).is_err());
+    }
+
+    #[test]
+    fn parses_currencies_and_countries() {
+        // Amounts are in the currency's own minor units
+        let args = parse(&["--currency", "JPY", "--country", "JP", "--amount", "1500"]).unwrap();
+        assert_eq!(args.amount, Some(1500));
+        assert_eq!(args.currency.unwrap().numeric, 392);
+        assert_eq!(args.country.unwrap().alpha3, "JPN");
+        assert!(parse(&["--amount", "15.00", "--currency", "JPY"]).is_err());
+        assert_eq!(parse(&["--amount", "15.00"]).unwrap().amount, Some(1500));
+        assert!(parse(&["--currency", "Pounds"]).is_err());
+    }
+    #[test]
+    fn parses_logging() {
+        let args = parse(&["--log-level", "debug"]).unwrap();
+        assert_eq!(args.log_level, Some(tracing_subscriber::filter::LevelFilter::DEBUG));
+        assert_eq!(args.log_format, crate::args::LogFormat::Text);
+        assert_eq!(parse(&["serve"]).unwrap().log_format, crate::args::LogFormat::Json);
+        assert_eq!(parse(&["stdio", "--log-format", "text"]).unwrap().log_format, crate::args::LogFormat::Text);
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["--log-format", "xml"]).is_err());
+    }
+}
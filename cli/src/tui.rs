@@ -0,0 +1,437 @@
+//! A full screen interface for exploring cards: the readers and what's in
+//! them, the applications a card offers, every exchange with the card as it
+//! happens with the tags in each response, and how far a transaction has got.
+
+use std::convert::TryFrom;
+use emv_term::{acquirer, apdu, capk, card, data, hex, run, selection, state, terminal, tlv, transaction, transport};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+
+/// How long to wait for a key before checking on the card again
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// How often to look for readers and cards coming and going
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// What a job with the card tells the interface as it goes along
+enum Update {
+    Connected {
+        reader: String,
+        atr: Option<Vec<u8>>,
+        interface: card::Interface,
+    },
+    Exchange(transport::Exchange),
+    Applications(Vec<data::Application>),
+    Outcome(Box<transaction::TransactionOutcome>),
+    Error(String),
+    Finished,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Pane {
+    Readers,
+    Applications,
+    Exchanges,
+}
+
+impl Pane {
+    fn next(self) -> Self {
+        match self {
+            Pane::Readers => Pane::Applications,
+            Pane::Applications => Pane::Exchanges,
+            Pane::Exchanges => Pane::Readers,
+        }
+    }
+
+    fn previous(self) -> Self {
+        self.next().next()
+    }
+}
+
+/// Each step of the exchanges with the card so far, by the commands sent,
+/// with how many times in a row each was sent
+pub(crate) fn progress(exchanges: &[transport::Exchange]) -> Vec<(String, usize)> {
+    let mut steps: Vec<(String, usize)> = vec![];
+    for exchange in exchanges {
+        let name = match exchange.command.get(1) {
+            Some(i) => apdu::instruction_name(*i).map(|n| n.to_string()).unwrap_or_else(|| format!("INS {:02X}", i)),
+            None => continue
+        };
+        match steps.last_mut() {
+            Some((last, count)) if *last == name => *count += 1,
+            _ => steps.push((name, 1))
+        }
+    }
+    steps
+}
+
+/// What the card gave back, as a tree of tags where it's BER-TLV
+pub(crate) fn response_tags(exchange: &transport::Exchange) -> Vec<String> {
+    let (data, status) = exchange.response.split_at(exchange.response.len().saturating_sub(2));
+    let mut lines = vec![format!("Status {}", hex::Hex(status))];
+    if data.is_empty() {
+        return lines;
+    }
+    match tlv::TagList::try_from(data) {
+        Ok(tags) if !tags.tags().is_empty() => lines.extend(tags.pretty(0).lines().map(str::to_string)),
+        _ => lines.push(hex::encode(data))
+    }
+    lines
+}
+
+/// Moves a list's selection up or down, keeping it in the list
+fn step(state: &mut ListState, len: usize, forward: bool) {
+    if len == 0 {
+        state.select(None);
+        return;
+    }
+    state.select(Some(match (state.selected(), forward) {
+        (None, _) => 0,
+        (Some(i), true) => (i + 1).min(len - 1),
+        (Some(i), false) => i.saturating_sub(1),
+    }));
+}
+
+struct App {
+    readers: Vec<(String, &'static str)>,
+    reader_state: ListState,
+    applications: Vec<data::Application>,
+    application_state: ListState,
+    exchanges: Vec<transport::Exchange>,
+    exchange_state: ListState,
+    focus: Pane,
+    /// The reader, ATR and interface of the card the last job was with
+    card: Vec<String>,
+    outcome: Option<Box<transaction::TransactionOutcome>>,
+    error: Option<String>,
+    busy: bool,
+}
+
+impl App {
+    fn new() -> Self {
+        Self {
+            readers: vec![],
+            reader_state: ListState::default(),
+            applications: vec![],
+            application_state: ListState::default(),
+            exchanges: vec![],
+            exchange_state: ListState::default(),
+            focus: Pane::Readers,
+            card: vec![],
+            outcome: None,
+            error: None,
+            busy: false,
+        }
+    }
+
+    fn set_readers(&mut self, readers: Result<Vec<card::ReaderInfo>, pcsc::Error>) {
+        match readers {
+            Ok(r) => self.readers = r.iter().map(|r| (r.name().to_string_lossy().into_owned(), r.describe_state())).collect(),
+            Err(e) => {
+                self.readers.clear();
+                self.error = Some(format!("Unable to list readers: {}", e));
+            }
+        }
+        if self.reader_state.selected().is_none_or(|i| i >= self.readers.len()) {
+            // Start from the first reader with a card in it
+            let present = self.readers.iter().position(|(_, s)| *s == "card present");
+            self.reader_state.select(present.or(if self.readers.is_empty() { None } else { Some(0) }));
+        }
+    }
+
+    fn selected_reader(&self) -> Option<std::ffi::CString> {
+        let (name, _) = self.readers.get(self.reader_state.selected()?)?;
+        std::ffi::CString::new(name.as_str()).ok()
+    }
+
+    fn selected_application(&self) -> Option<&data::Application> {
+        self.applications.get(self.application_state.selected()?)
+    }
+
+    /// Clears out what the last job left, for a new one
+    fn start(&mut self) {
+        self.busy = true;
+        self.exchanges.clear();
+        self.exchange_state.select(None);
+        self.card.clear();
+        self.outcome = None;
+        self.error = None;
+    }
+
+    /// Takes in an update from the job, giving whether it's finished
+    fn update(&mut self, update: Update) -> bool {
+        match update {
+            Update::Connected { reader, atr, interface } => {
+                self.card = vec![format!("Reader: {}", reader), format!("Interface: {}", interface)];
+                if let Some(atr) = atr {
+                    self.card.push(format!("ATR: {}", hex::Hex(&atr)));
+                }
+            }
+            Update::Exchange(exchange) => {
+                // Follow the log as it grows, unless looking back through it
+                let following = self.exchange_state.selected().is_none_or(|i| i + 1 == self.exchanges.len());
+                self.exchanges.push(exchange);
+                if following {
+                    self.exchange_state.select(Some(self.exchanges.len() - 1));
+                }
+            }
+            Update::Applications(applications) => {
+                self.application_state.select(if applications.is_empty() { None } else { Some(0) });
+                self.applications = applications;
+                if self.applications.is_empty() {
+                    self.error = Some("The card has no applications the terminal supports".to_string());
+                }
+            }
+            Update::Outcome(outcome) => self.outcome = Some(outcome),
+            Update::Error(e) => self.error = Some(e),
+            Update::Finished => {
+                self.busy = false;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn draw(&mut self, frame: &mut ratatui::Frame) {
+        let [main, footer] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [top, bottom] = Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(main);
+        let [left, status] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(top);
+        let [readers, applications] = Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(left);
+        let [exchanges, tags] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(bottom);
+
+        let focus = self.focus;
+        let block = |title: &'static str, pane: Option<Pane>| {
+            let block = Block::bordered().title(title);
+            match pane == Some(focus) {
+                true => block.border_style(Style::new().fg(Color::Yellow)),
+                false => block
+            }
+        };
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+        let items = self.readers.iter().map(|(name, state)| ListItem::new(format!("{} ({})", name, state)));
+        let list = List::new(items).block(block(" Readers ", Some(Pane::Readers))).highlight_style(highlight);
+        frame.render_stateful_widget(list, readers, &mut self.reader_state);
+
+        let items = self.applications.iter().map(|a| ListItem::new(format!("{} {}", hex::Hex(a.aid()), a.name())));
+        let list = List::new(items).block(block(" Applications ", Some(Pane::Applications))).highlight_style(highlight);
+        frame.render_stateful_widget(list, applications, &mut self.application_state);
+
+        let mut lines: Vec<Line> = self.card.iter().map(|l| Line::from(l.as_str())).collect();
+        for (name, count) in progress(&self.exchanges) {
+            lines.push(Line::from(match count {
+                1 => name,
+                n => format!("{} ×{}", name, n)
+            }));
+        }
+        if self.busy {
+            lines.push(Line::styled("Working…", Style::new().add_modifier(Modifier::DIM)));
+        }
+        if let Some(outcome) = &self.outcome {
+            let colour = if outcome.outcome.approved() { Color::Green } else { Color::Red };
+            lines.push(Line::styled(outcome.cardholder_message(), Style::new().fg(colour).add_modifier(Modifier::BOLD)));
+            lines.extend(outcome.to_string().lines().map(|l| Line::from(l.to_string())));
+        }
+        if let Some(e) = &self.error {
+            lines.push(Line::styled(e.as_str(), Style::new().fg(Color::Red)));
+        }
+        frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }).block(block(" Transaction ", None)), status);
+
+        let items = self.exchanges.iter().map(|e| {
+            let name = e.command.get(1).and_then(|i| apdu::instruction_name(*i)).unwrap_or("");
+            ListItem::new(Text::from(vec![
+                Line::from(format!("> {} {}", apdu::loggable_command(&e.command), name)),
+                Line::styled(format!("< {}", hex::Hex(&e.response)), Style::new().fg(Color::Cyan)),
+            ]))
+        });
+        let list = List::new(items).block(block(" APDUs ", Some(Pane::Exchanges))).highlight_style(highlight);
+        frame.render_stateful_widget(list, exchanges, &mut self.exchange_state);
+
+        let lines = match self.exchange_state.selected().and_then(|i| self.exchanges.get(i)) {
+            Some(e) => response_tags(e).into_iter().map(Line::from).collect(),
+            None => vec![]
+        };
+        frame.render_widget(Paragraph::new(lines).block(block(" Tags ", None)), tags);
+
+        frame.render_widget(Paragraph::new("Tab: next pane  ↑↓: move  r: read card  Enter: pay with application  c: clear  q: quit")
+            .style(Style::new().add_modifier(Modifier::DIM)), footer);
+    }
+}
+
+/// What a job needs besides the card
+struct Job<'a> {
+    ctx: &'a pcsc::Context,
+    args: &'a crate::args::Args,
+    ca_keys: &'a capk::CapkStore,
+    canceller: &'a card::Canceller,
+    updates: std::sync::mpsc::Sender<Update>,
+}
+
+/// Connects to the card in the reader, telling the interface about it
+fn connect(job: &Job, reader: &std::ffi::CStr) -> Result<(card::Session, card::Interface), String> {
+    let (args, updates) = (job.args, &job.updates);
+    let mut session = card::Session::connect(job.ctx, reader, args.connect).map_err(|e| format!("Unable to connect to the card: {}", e))?;
+    session.cancel_with(job.canceller);
+    let interface = args.interface.unwrap_or_else(|| card::detect_interface(&session));
+    let _ = updates.send(Update::Connected {
+        reader: reader.to_string_lossy().into_owned(),
+        atr: transport::CardTransport::atr(&session).ok().map(|a| a.raw().to_vec()),
+        interface,
+    });
+    Ok((session, interface))
+}
+
+/// Finds the applications the card and terminal have in common
+fn read_applications(job: &Job, reader: &std::ffi::CStr) -> Result<(), String> {
+    let (args, updates) = (job.args, &job.updates);
+    let (mut session, interface) = connect(job, reader)?;
+    let terminal = crate::terminal_data(interface, args);
+    let watched = transport::Watching::new(&mut session, |e: &transport::Exchange| {
+        let _ = updates.send(Update::Exchange(e.clone()));
+    });
+    let mut candidates = selection::build_candidate_list(&watched, interface, &terminal.applications)
+        .map_err(|e| format!("Card communication failed: {}", e))?;
+    selection::sort_candidates(&mut candidates);
+    let _ = updates.send(Update::Applications(candidates));
+    session.disconnect().map_err(|e| format!("Unable to disconnect from card: {}", e))
+}
+
+/// Runs a transaction for the amount on the command line, with only the
+/// application picked if there is one. Without anyone to ask at the card
+/// the transaction runs as if non-interactive.
+fn pay(job: &Job, reader: &std::ffi::CStr, aid: Option<Vec<u8>>) -> Result<(), String> {
+    let (args, updates) = (job.args, &job.updates);
+    let (mut session, interface) = connect(job, reader)?;
+    let mut terminal = crate::terminal_data(interface, args);
+    if let Some(aid) = aid {
+        let mut only = selection::TerminalApplication::new(&aid);
+        only.partial_selection = false;
+        terminal.applications = selection::restrict_applications(&terminal.applications, &[only]);
+    }
+    terminal::TransactionRequest::new(args.transaction_type, args.amount.unwrap_or(0), args.cashback.unwrap_or(0)).apply(&mut terminal);
+
+    let state_path = std::path::Path::new(&args.state_file);
+    let mut terminal_state = state::TerminalState::load(state_path).map_err(|e| format!("Unable to read terminal state: {}", e))?;
+    terminal.transaction_sequence_counter = terminal_state.next_sequence_counter();
+    terminal_state.save(state_path).map_err(|e| format!("Unable to save terminal state: {}", e))?;
+
+    let mut stand_in = args.stand_in.clone();
+    let mut watched = transport::Watching::new(&mut session, |e: &transport::Exchange| {
+        let _ = updates.send(Update::Exchange(e.clone()));
+    });
+    let outcome = run(&mut watched, &terminal, job.ca_keys, None, stand_in.as_mut().map(|s| s as &mut dyn acquirer::Acquirer), false)
+        .unwrap_or_else(|e| transaction::TransactionOutcome::card_error(&e, interface));
+    let disconnect = match outcome.outcome {
+        o if o.card_removed() => None,
+        transaction::Outcome::Aborted => Some(pcsc::Disposition::ResetCard),
+        _ => Some(args.connect.disposition)
+    };
+    let _ = updates.send(Update::Outcome(Box::new(outcome)));
+    match disconnect {
+        Some(d) => session.disconnect_with(d).map_err(|e| format!("Unable to disconnect from card: {}", e)),
+        None => Ok(())
+    }
+}
+
+/// Runs the interface until quit, with jobs on the card in their own thread
+/// so the screen keeps up with them. Quitting cancels whatever job is running.
+pub fn run_tui(ctx: &pcsc::Context, args: &crate::args::Args, ca_keys: &capk::CapkStore) -> std::io::Result<()> {
+    let canceller = card::Canceller::new();
+    canceller.watch(ctx);
+    let mut screen = ratatui::init();
+    let result = std::thread::scope(|scope| {
+        let (sender, updates) = std::sync::mpsc::channel();
+        let mut app = App::new();
+        let mut refreshed: Option<std::time::Instant> = None;
+        loop {
+            // Listing readers alongside a job would get in the way of its card
+            if !app.busy && refreshed.is_none_or(|r| r.elapsed() >= REFRESH_INTERVAL) {
+                app.set_readers(card::list_readers(ctx));
+                refreshed = Some(std::time::Instant::now());
+            }
+            let mut finished = false;
+            for update in updates.try_iter() {
+                finished |= app.update(update);
+            }
+            // Anything the library printed while it ran is cleared away
+            if finished {
+                screen.clear()?;
+            }
+            screen.draw(|f| app.draw(f))?;
+
+            if !event::poll(POLL_INTERVAL)? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(k) if k.kind == KeyEventKind::Press => k,
+                _ => continue
+            };
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    canceller.cancel();
+                    return Ok(());
+                }
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    canceller.cancel();
+                    return Ok(());
+                }
+                KeyCode::Tab => app.focus = app.focus.next(),
+                KeyCode::BackTab => app.focus = app.focus.previous(),
+                KeyCode::Up | KeyCode::Down => {
+                    let forward = key.code == KeyCode::Down;
+                    match app.focus {
+                        Pane::Readers => step(&mut app.reader_state, app.readers.len(), forward),
+                        Pane::Applications => step(&mut app.application_state, app.applications.len(), forward),
+                        Pane::Exchanges => step(&mut app.exchange_state, app.exchanges.len(), forward),
+                    }
+                }
+                KeyCode::Char('c') => {
+                    app.exchanges.clear();
+                    app.exchange_state.select(None);
+                }
+                KeyCode::Char('r') | KeyCode::Enter if !app.busy => {
+                    let reader = match app.selected_reader() {
+                        Some(r) => r,
+                        None => {
+                            app.error = Some("No reader to use".to_string());
+                            continue;
+                        }
+                    };
+                    let aid = match (key.code, app.focus) {
+                        (KeyCode::Enter, Pane::Applications) => app.selected_application().map(|a| a.aid().to_vec()),
+                        _ => None
+                    };
+                    let paying = key.code == KeyCode::Enter;
+                    app.start();
+                    if !paying {
+                        app.applications.clear();
+                        app.application_state.select(None);
+                    }
+                    canceller.reset();
+                    let job = Job {
+                        ctx,
+                        args,
+                        ca_keys,
+                        canceller: &canceller,
+                        updates: sender.clone(),
+                    };
+                    scope.spawn(move || {
+                        let result = match paying {
+                            true => pay(&job, &reader, aid),
+                            false => read_applications(&job, &reader)
+                        };
+                        if let Err(e) = result {
+                            let _ = job.updates.send(Update::Error(e));
+                        }
+                        let _ = job.updates.send(Update::Finished);
+                    });
+                }
+                _ => {}
+            }
+        }
+    });
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,14 @@
+//! Parses arbitrary bytes as a DOL, then fills it in and splits the input by it
+#![no_main]
+
+use std::convert::TryFrom;
+use libfuzzer_sys::fuzz_target;
+use emv_term::tlv::DOL;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut dol) = DOL::try_from(data) {
+        let _ = dol.split(data);
+        dol.fill(&emv_term::terminal::TerminalData::new(emv_term::card::Interface::Contact));
+        let _ = Vec::<u8>::from(dol);
+    }
+});
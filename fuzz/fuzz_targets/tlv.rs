@@ -0,0 +1,21 @@
+//! Parses arbitrary bytes as BER-TLV, then does everything else the terminal
+//! would with what it got back. Whatever parses has to encode to something
+//! that parses back the same.
+#![no_main]
+
+use std::convert::TryFrom;
+use libfuzzer_sys::fuzz_target;
+use emv_term::tlv::{TagID, TagList};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = emv_term::tlv::template_value(data, TagID::FileControlInformationTemplate);
+    if let Ok(tags) = TagList::try_from(data) {
+        let encoded = Vec::<u8>::from(&tags);
+        let reparsed = TagList::try_from(encoded.as_slice()).expect("Encoded TLV doesn't parse");
+        assert_eq!(Vec::<u8>::from(&reparsed), encoded);
+        let _ = tags.pretty(0);
+        for tag in tags.tags() {
+            let _ = tag.get_tags(TagID::ApplicationTemplate);
+        }
+    }
+});
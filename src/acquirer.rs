@@ -0,0 +1,177 @@
+use std::convert::TryFrom;
+use crate::transport::CardTransport;
+
+/// Everything about a transaction an acquirer's host might need, whatever the
+/// protocol it's sent in
+#[derive(Debug, Clone)]
+pub struct AuthorisationRequest {
+    pub pan: Option<String>,
+    /// Track 2 with `=` as the separator, when it was read from the card
+    pub track2: Option<String>,
+    /// YYMM
+    pub expiry: Option<String>,
+    pub pan_sequence_number: Option<u64>,
+    pub transaction_type: u8,
+    /// Amount, Authorised in minor units, including any cashback
+    pub amount_authorised: u64,
+    pub amount_other: u64,
+    pub currency_code: u16,
+    pub transaction_sequence_counter: u32,
+    pub transaction_date: chrono::NaiveDate,
+    pub transaction_time: chrono::NaiveTime,
+    /// ISO 8583 POS entry mode, as `online::pos_entry_mode`
+    pub pos_entry_mode: String,
+    /// The encrypted online PIN block
+    pub pin_block: Option<Vec<u8>>,
+    /// DUKPT Key Serial Number the PIN block and track data are encrypted
+    /// under
+    pub ksn: Option<Vec<u8>>,
+    /// Track 2 enciphered under the DUKPT data key, to send in place of the
+    /// clear track
+    pub encrypted_track2: Option<Vec<u8>>,
+    /// TLV of `online::CHIP_DATA_TAGS`, None for magnetic stripe reads
+    pub chip_data: Option<Vec<u8>>,
+    pub merchant: crate::terminal::MerchantData,
+}
+
+impl AuthorisationRequest {
+    /// The request as things stand, so after the final GENERATE AC it carries
+    /// that cryptogram rather than the ARQC
+    pub fn new<T: CardTransport + ?Sized>(transaction: &crate::transaction::Transaction<T>) -> Self {
+        let terminal = &transaction.terminal;
+        let track2 = transaction.tag_value(crate::tlv::TagID::Track2EquivalentData)
+            .map(|t| crate::hex::encode(&t).trim_end_matches('F').replace('D', "="));
+        let pan = transaction.tag_value(crate::tlv::TagID::ApplicationPrimaryAccountNumber)
+            .map(|p| crate::hex::encode(&p).trim_end_matches('F').to_string())
+            .or_else(|| track2.as_ref().and_then(|t| t.split('=').next()).map(|p| p.to_string()));
+        let dukpt = match &transaction.online_pin_key {
+            Some(crate::pinblock::PinKey::Dukpt(k)) => Some(k),
+            _ => None
+        };
+        Self {
+            pan,
+            ksn: dukpt.map(|k| k.ksn.to_vec()),
+            encrypted_track2: dukpt.zip(track2.as_ref()).map(|(k, t)| k.encrypt_data(t.as_bytes())),
+            track2,
+            // YYMMDD on the card
+            expiry: transaction.tag_value(crate::tlv::TagID::ApplicationExpirationDate)
+                .filter(|e| e.len() == 3)
+                .map(|e| crate::hex::encode(&e[..2])),
+            pan_sequence_number: transaction.tag_value(crate::tlv::TagID::ApplicationPrimaryAccountNumberSequenceNumber)
+                .and_then(|s| crate::util::from_bcd(&s)),
+            transaction_type: terminal.transaction_type,
+            amount_authorised: terminal.amount_authorised,
+            amount_other: terminal.amount_other,
+            currency_code: terminal.currency_code,
+            transaction_sequence_counter: terminal.transaction_sequence_counter,
+            transaction_date: terminal.transaction_date(),
+            transaction_time: terminal.transaction_time.time(),
+            pos_entry_mode: crate::online::pos_entry_mode(terminal, transaction.mag_stripe_mode()),
+            pin_block: transaction.online_pin_block().map(|b| b.to_vec()),
+            chip_data: if transaction.mag_stripe_mode() { None } else { Some(crate::online::chip_data(transaction)) },
+            merchant: terminal.merchant.clone(),
+        }
+    }
+}
+
+/// Where transactions go online to. Implement this to use a host protocol
+/// other than the ISO 8583 one `host::HostConnection` speaks.
+pub trait Acquirer {
+    /// Asks the issuer to authorise the transaction
+    fn authorise(&mut self, request: &AuthorisationRequest) -> std::io::Result<crate::transaction::OnlineResponse>;
+
+    /// Undoes an authorisation the issuer gave, when the card declined the
+    /// transaction anyway
+    fn reverse(&mut self, request: &AuthorisationRequest) -> std::io::Result<()>;
+
+    /// Tells the issuer about a transaction it didn't get to authorise, when
+    /// the card asks for that
+    fn advice(&mut self, request: &AuthorisationRequest) -> std::io::Result<()>;
+}
+
+/// Answers for the issuer without a host, approving anything within its
+/// limit. Given the issuer master key it also generates an ARPC, so that
+/// issuer authentication can be tried.
+#[derive(Debug, Clone, Default)]
+pub struct StandIn {
+    /// Amounts above this, in minor units, are declined as insufficient funds
+    pub limit: Option<u64>,
+    /// Double length issuer master key for application cryptograms
+    pub issuer_master_key: Option<crate::hsm::TdesKey>,
+}
+
+fn xor_block(a: &[u8], b: &[u8]) -> [u8; 8] {
+    let mut out = [0; 8];
+    for (o, (a, b)) in out.iter_mut().zip(a.iter().zip(b)) {
+        *o = a ^ b;
+    }
+    out
+}
+
+/// Book 2 A1.4.1 option A: the card's master key, from the rightmost 16
+/// digits of the PAN and PAN sequence number. A key on a token stays there.
+fn icc_master_key(issuer_master_key: &crate::hsm::OpenTdesKey, pan: &str, pan_sequence_number: u64) -> Result<crate::hsm::OpenTdesKey, String> {
+    let digits = format!("{:0>16}", format!("{}{:02}", pan, pan_sequence_number % 100));
+    let y = crate::hex::decode(&digits[digits.len() - 16..]).unwrap_or_default();
+    issuer_master_key.derive(&[xor_block(&y, &[0; 8]), xor_block(&y, &[0xff; 8])])
+}
+
+/// Book 2 A1.3: the common session key for this ATC
+fn session_key(icc_master_key: &crate::hsm::OpenTdesKey, atc: &[u8]) -> Result<crate::hsm::OpenTdesKey, String> {
+    let mut blocks = [[0; 8]; 2];
+    for (block, diversifier) in blocks.iter_mut().zip([0xf0, 0x0f]) {
+        block[..2].copy_from_slice(atc);
+        block[2] = diversifier;
+    }
+    icc_master_key.derive(&blocks)
+}
+
+impl StandIn {
+    fn authorisation_response_code(&self, request: &AuthorisationRequest) -> [u8; 2] {
+        match self.limit {
+            Some(l) if request.amount_authorised > l => *b"51",
+            _ => *b"00"
+        }
+    }
+
+    /// Book 2 A1.2.1: ARPC method 1 over the ARQC and response code, giving
+    /// the Issuer Authentication Data to send back
+    pub fn issuer_authentication_data(&self, request: &AuthorisationRequest, authorisation_response_code: [u8; 2]) -> Option<Vec<u8>> {
+        let issuer_master_key = self.issuer_master_key.as_ref()?;
+        let chip_data = crate::tlv::TagList::try_from(request.chip_data.as_deref()?).ok()?;
+        let value = |tag| chip_data.get_tag(tag).map(|t| Vec::<u8>::from(t.contents()));
+        let arqc = value(crate::tlv::TagID::ApplicationCryptogram).filter(|c| c.len() == 8)?;
+        let atc = value(crate::tlv::TagID::ApplicationTransactionCounter).filter(|a| a.len() == 2)?;
+
+        let pan = request.pan.as_deref()?;
+        let mut arc = [0; 8];
+        arc[..2].copy_from_slice(&authorisation_response_code);
+        let mut iad = issuer_master_key.open()
+            .and_then(|k| icc_master_key(&k, pan, request.pan_sequence_number.unwrap_or(0)))
+            .and_then(|k| session_key(&k, &atc))
+            .and_then(|k| k.encrypt_blocks(&[xor_block(&arqc, &arc)]))
+            .map_err(|e| tracing::warn!("Can't generate the ARPC: {}", e))
+            .ok()?;
+        iad.extend(authorisation_response_code);
+        Some(iad)
+    }
+}
+
+impl Acquirer for StandIn {
+    fn authorise(&mut self, request: &AuthorisationRequest) -> std::io::Result<crate::transaction::OnlineResponse> {
+        let authorisation_response_code = self.authorisation_response_code(request);
+        Ok(crate::transaction::OnlineResponse {
+            authorisation_response_code,
+            issuer_authentication_data: self.issuer_authentication_data(request, authorisation_response_code),
+            issuer_scripts: vec![],
+        })
+    }
+
+    fn reverse(&mut self, _request: &AuthorisationRequest) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn advice(&mut self, _request: &AuthorisationRequest) -> std::io::Result<()> {
+        Ok(())
+    }
+}
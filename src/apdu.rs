@@ -1,3 +1,20 @@
+/// Why a command didn't get a successful response from the card
+#[derive(Debug, thiserror::Error)]
+pub enum ApduError {
+    #[error(transparent)]
+    Transport(#[from] crate::transport::TransportError),
+    #[error("Response of {0} bytes has no status word")]
+    NoStatusWord(usize),
+    #[error("Function not supported (6A81)")]
+    FunctionNotSupported,
+    #[error("File or application not found (6A82)")]
+    FileNotFound,
+    #[error("Record not found (6A83)")]
+    RecordNotFound,
+    #[error("Card returned status {0:02X}{1:02X}")]
+    Status(u8, u8),
+}
+
 #[derive(Debug)]
 pub struct ApduCommand {
     class: u8,
@@ -19,6 +36,13 @@ impl ApduCommand {
             length_expected
         }
     }
+
+    /// Changes P1 and P2, for sending the same command again about something
+    /// else, such as the next record
+    pub fn set_params(&mut self, param1: u8, param2: u8) {
+        self.param1 = param1;
+        self.param2 = param2;
+    }
 }
 
 pub struct ApduResponse {
@@ -35,77 +59,151 @@ impl ApduResponse {
     pub fn status(&self) -> (&u8, &u8) {
         (&self.sw1, &self.sw2)
     }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// The response if the command succeeded, or why it didn't
+    pub fn check(self) -> Result<Self, ApduError> {
+        match (self.sw1, self.sw2) {
+            (0x90, 0x00) => Ok(self),
+            (0x6A, 0x81) => Err(ApduError::FunctionNotSupported),
+            (0x6A, 0x82) => Err(ApduError::FileNotFound),
+            (0x6A, 0x83) => Err(ApduError::RecordNotFound),
+            (sw1, sw2) => Err(ApduError::Status(sw1, sw2))
+        }
+    }
 }
 
 impl std::fmt::Debug for ApduResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ApduResponse")
-            .field("data", &format_args!("{:02x?}", self.data))
+            .field("data", &crate::hex::Hex(&self.data))
             .field("sw1", &format_args!("{:02x}", self.sw1))
             .field("sw2", &format_args!("{:02x}", self.sw2))
             .finish()
     }
 }
 
-pub fn send_apdu(card: &pcsc::Card, apdu_command: &ApduCommand) -> Result<ApduResponse, pcsc::Error> {
-    let mut apdu_out = vec![apdu_command.class, apdu_command.instruction, apdu_command.param1, apdu_command.param2];
+pub fn send_apdu<T: crate::transport::CardTransport + ?Sized>(card: &T, apdu_command: &ApduCommand) -> Result<ApduResponse, ApduError> {
+    exchange_apdu(card, apdu_command)?.check()
+}
+
+/// The C-APDU as it can go in logs, with the PIN block of a VERIFY left out
+pub fn loggable_command(command: &[u8]) -> String {
+    match command {
+        [_, 0x20, ..] if command.len() > 5 => format!("{}{:02X}<PIN block>", crate::hex::Hex(&command[..4]), command[4]),
+        _ => crate::hex::encode(command)
+    }
+}
+
+/// The name of a command by its instruction byte, for those a terminal sends
+pub fn instruction_name(instruction: u8) -> Option<&'static str> {
+    Some(match instruction {
+        0x1e => "APPLICATION BLOCK",
+        0x18 => "APPLICATION UNBLOCK",
+        0x16 => "CARD BLOCK",
+        0x20 => "VERIFY",
+        0x24 => "PIN CHANGE/UNBLOCK",
+        0x2a => "COMPUTE CRYPTOGRAPHIC CHECKSUM",
+        0x82 => "EXTERNAL AUTHENTICATE",
+        0x84 => "GET CHALLENGE",
+        0x88 => "INTERNAL AUTHENTICATE",
+        0xa4 => "SELECT",
+        0xa8 => "GET PROCESSING OPTIONS",
+        0xae => "GENERATE AC",
+        0xb2 => "READ RECORD",
+        0xc0 => "GET RESPONSE",
+        0xca => "GET DATA",
+        0xda => "PUT DATA",
+        0xdc => "UPDATE RECORD",
+        _ => return None
+    })
+}
+
+/// Space for building commands and receiving responses in, kept by a
+/// transport so every command sent over it reuses the same buffers
+#[derive(Debug, Default)]
+pub struct ApduBuffers {
+    send: Vec<u8>,
+    receive: Vec<u8>,
+}
 
-    if apdu_command.data.len() > 0 {
-        apdu_out.push(apdu_command.data.len() as u8);
-        apdu_out.extend(&apdu_command.data);
+/// Sends one C-APDU, appending the response data to `data` and giving back the
+/// status word
+fn transmit_into<T: crate::transport::CardTransport + ?Sized>(
+    card: &T, buffers: &mut ApduBuffers, header: [u8; 4], body: &[u8], length_expected: u8, data: &mut Vec<u8>,
+) -> Result<(u8, u8), ApduError> {
+    let apdu_out = &mut buffers.send;
+    apdu_out.clear();
+    apdu_out.extend_from_slice(&header);
+
+    if !body.is_empty() {
+        apdu_out.push(body.len() as u8);
+        apdu_out.extend_from_slice(body);
     }
 
-    apdu_out.push(apdu_command.length_expected);
+    apdu_out.push(length_expected);
 
-    let len_expected = if apdu_command.length_expected == 0 {
+    let len_expected = if length_expected == 0 {
         256
     } else {
-        apdu_command.length_expected as usize
+        length_expected as usize
     };
 
-    let mut apdu_in = vec![0; len_expected + 2];
-    let data = card.transmit(&apdu_out, &mut apdu_in)?;
+    if buffers.receive.len() < len_expected + 2 {
+        buffers.receive.resize(len_expected + 2, 0);
+    }
+    let response = card.transmit(apdu_out, &mut buffers.receive[..len_expected + 2])?;
 
-    let response_len = data.len();
-    let mut response = ApduResponse {
-        data: data[0..response_len - 2].to_vec(),
-        sw1: data[response_len - 2],
-        sw2: data[response_len - 1],
-    };
+    tracing::debug!(command = %loggable_command(apdu_out), response = %crate::hex::Hex(response), "APDU");
 
-    while response.sw1 == 0x61 {
-        let new_apdu_command = ApduCommand {
-            class: apdu_command.class,
-            instruction: 0xC0,
-            param1: 0x00,
-            param2: 0x00,
-            data: vec![],
-            length_expected: response.sw2,
-        };
-
-        let new_response = send_apdu(card, &new_apdu_command)?;
-        response.sw1 = new_response.sw1;
-        response.sw2 = new_response.sw2;
-        response.data.extend(new_response.data)
+    let response_len = response.len();
+    if response_len < 2 {
+        return Err(ApduError::NoStatusWord(response_len));
     }
+    data.extend_from_slice(&response[..response_len - 2]);
+    Ok((response[response_len - 2], response[response_len - 1]))
+}
 
-    if response.sw1 == 0x6c {
-        let new_apdu_command = ApduCommand {
-            class: apdu_command.class,
-            instruction: apdu_command.instruction,
-            param1: apdu_command.param1,
-            param2: apdu_command.param2,
-            data: apdu_command.data.to_vec(),
-            length_expected: response.sw2,
-        };
-
-        return send_apdu(card, &new_apdu_command);
-    }
+/// Like `send_apdu` but hands back whatever status the card finished with, for
+/// commands where a non-9000 status carries meaning
+pub fn exchange_apdu<T: crate::transport::CardTransport + ?Sized>(card: &T, apdu_command: &ApduCommand) -> Result<ApduResponse, ApduError> {
+    // Transports without buffers of their own, or whose buffers are already in
+    // use further up, get some just for this command
+    let mut own_buffers = ApduBuffers::default();
+    let mut card_buffers = card.buffers();
+    let buffers = match &mut card_buffers {
+        Some(b) => &mut **b,
+        None => &mut own_buffers
+    };
 
-    match (response.sw1, response.sw2) {
-        (0x90, 0x00) => Ok(response),
-        (0x6A, 0x81) => Err(pcsc::Error::UnsupportedFeature),
-        (0x6A, 0x82) | (0x6A, 0x83) => Err(pcsc::Error::FileNotFound),
-        _ => Err(pcsc::Error::UnknownError)
+    let mut header = [apdu_command.class, apdu_command.instruction, apdu_command.param1, apdu_command.param2];
+    let mut body = apdu_command.data.as_slice();
+    let mut length_expected = apdu_command.length_expected;
+    let mut data = vec![];
+
+    loop {
+        let start = data.len();
+        match transmit_into(card, buffers, header, body, length_expected, &mut data)? {
+            // More data to come, fetched with GET RESPONSE and added to what's
+            // come so far
+            (0x61, available) => {
+                header = [apdu_command.class, 0xC0, 0x00, 0x00];
+                body = &[];
+                length_expected = available;
+            }
+            // Wrong Le, so the same command again asking for the right length
+            (0x6c, exact) => {
+                data.truncate(start);
+                length_expected = exact;
+            }
+            (sw1, sw2) => return Ok(ApduResponse {
+                data,
+                sw1,
+                sw2,
+            })
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,305 @@
+use std::convert::TryFrom;
+
+const FI_TABLE: [Option<(u16, f32)>; 16] = [
+    Some((372, 4.0)), Some((372, 5.0)), Some((558, 6.0)), Some((744, 8.0)),
+    Some((1116, 12.0)), Some((1488, 16.0)), Some((1860, 20.0)), None,
+    None, Some((512, 5.0)), Some((768, 7.5)), Some((1024, 10.0)),
+    Some((1536, 15.0)), Some((2048, 20.0)), None, None,
+];
+
+const DI_TABLE: [Option<u8>; 16] = [
+    None, Some(1), Some(2), Some(4), Some(8), Some(16), Some(32), Some(64),
+    Some(12), Some(20), None, None, None, None, None, None,
+];
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Convention {
+    Direct,
+    Inverse,
+}
+
+#[derive(Default, Clone)]
+pub struct InterfaceBytes {
+    pub ta: Option<u8>,
+    pub tb: Option<u8>,
+    pub tc: Option<u8>,
+    pub td: Option<u8>,
+}
+
+impl InterfaceBytes {
+    fn protocol(&self) -> Option<u8> {
+        self.td.map(|td| td & 0x0f)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum HistoricalObject {
+    CompactTlv(u8, Vec<u8>),
+    Status(Vec<u8>),
+    Proprietary(Vec<u8>),
+}
+
+#[derive(Clone)]
+pub struct Atr {
+    raw: Vec<u8>,
+    convention: Convention,
+    interface: Vec<InterfaceBytes>,
+    historical: Vec<u8>,
+    tck: Option<u8>,
+}
+
+impl Atr {
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn convention(&self) -> Convention {
+        self.convention
+    }
+
+    /// Interface bytes grouped by index, so `interface()[0]` holds TA1/TB1/TC1/TD1
+    pub fn interface(&self) -> &[InterfaceBytes] {
+        &self.interface
+    }
+
+    pub fn historical_bytes(&self) -> &[u8] {
+        &self.historical
+    }
+
+    pub fn tck(&self) -> Option<u8> {
+        self.tck
+    }
+
+    /// Protocols offered by the card, T=0 being implied if none are indicated
+    pub fn protocols(&self) -> Vec<u8> {
+        let mut protocols: Vec<u8> = vec![];
+        for i in &self.interface {
+            if let Some(p) = i.protocol() {
+                if p != 15 && !protocols.contains(&p) {
+                    protocols.push(p);
+                }
+            }
+        }
+        if protocols.is_empty() {
+            protocols.push(0);
+        }
+        protocols
+    }
+
+    /// Clock rate conversion integer and maximum clock frequency (MHz) from TA1
+    pub fn fi(&self) -> Option<(u16, f32)> {
+        let ta1 = self.interface.first()?.ta.unwrap_or(0x11);
+        FI_TABLE[(ta1 >> 4) as usize]
+    }
+
+    /// Baud rate adjustment integer from TA1
+    pub fn di(&self) -> Option<u8> {
+        let ta1 = self.interface.first().and_then(|i| i.ta).unwrap_or(0x11);
+        DI_TABLE[(ta1 & 0x0f) as usize]
+    }
+
+    /// Extra guard time in ETUs from TC1
+    pub fn extra_guard_time(&self) -> u8 {
+        self.interface.first().and_then(|i| i.tc).unwrap_or(0)
+    }
+
+    /// Whether TA2 is present, meaning the card runs in specific rather than negotiable mode
+    pub fn specific_mode(&self) -> Option<u8> {
+        let ta2 = self.interface.get(1)?.ta?;
+        Some(ta2 & 0x0f)
+    }
+
+    /// T=1 information field size and block/character waiting times from the
+    /// first interface bytes following a T=1 indication
+    pub fn t1_parameters(&self) -> Option<(u8, u8, u8)> {
+        let position = self.interface.iter().position(|i| i.protocol() == Some(1))?;
+        let t1 = self.interface.get(position + 1)?;
+        let ifsc = t1.ta.unwrap_or(32);
+        let bwi = t1.tb.map(|b| b >> 4).unwrap_or(4);
+        let cwi = t1.tb.map(|b| b & 0x0f).unwrap_or(13);
+        Some((ifsc, bwi, cwi))
+    }
+
+    /// Whether this is the synthesised ATR a PC/SC reader reports for a
+    /// contactless card, which always starts 3B 8x 80 01
+    pub fn is_contactless(&self) -> bool {
+        self.convention == Convention::Direct
+            && self.interface.len() == 3
+            && self.interface[0].td == Some(0x80) && self.interface[0].ta.is_none()
+            && self.interface[1].td == Some(0x01) && self.interface[1].ta.is_none()
+    }
+
+    /// Splits the historical bytes into ISO 7816-4 compact-TLV objects where
+    /// they follow the standard format
+    pub fn historical_objects(&self) -> Vec<HistoricalObject> {
+        let (category, data) = match self.historical.split_first() {
+            Some(h) => h,
+            None => return vec![]
+        };
+        match category {
+            0x00 => {
+                if data.len() < 3 {
+                    return vec![HistoricalObject::Proprietary(data.to_vec())];
+                }
+                let (objects, status) = data.split_at(data.len() - 3);
+                let mut out = Self::decode_compact_tlv(objects);
+                out.push(HistoricalObject::Status(status.to_vec()));
+                out
+            }
+            0x80 => Self::decode_compact_tlv(data),
+            _ => vec![HistoricalObject::Proprietary(self.historical.clone())]
+        }
+    }
+
+    fn decode_compact_tlv(data: &[u8]) -> Vec<HistoricalObject> {
+        let mut out = vec![];
+        let mut i = 0;
+        while i < data.len() {
+            let tag = data[i] >> 4;
+            let len = (data[i] & 0x0f) as usize;
+            i += 1;
+            let end = std::cmp::min(i + len, data.len());
+            if tag == 0x8 && end == data.len() && len == 3 {
+                out.push(HistoricalObject::Status(data[i..end].to_vec()));
+            } else {
+                out.push(HistoricalObject::CompactTlv(tag, data[i..end].to_vec()));
+            }
+            i = end;
+        }
+        out
+    }
+}
+
+impl TryFrom<&[u8]> for Atr {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let mut bytes = value.iter();
+        let convention = match bytes.next() {
+            Some(0x3b) => Convention::Direct,
+            Some(0x3f) => Convention::Inverse,
+            Some(_) => return Err("Invalid TS byte"),
+            None => return Err("Empty ATR")
+        };
+        let t0 = match bytes.next() {
+            Some(t) => *t,
+            None => return Err("No T0 byte")
+        };
+
+        let mut interface = vec![];
+        let mut y = t0 >> 4;
+        let mut tck_required = false;
+        loop {
+            let mut i = InterfaceBytes::default();
+            if y & 0b0001 != 0 {
+                i.ta = Some(*bytes.next().ok_or("ATR truncated in TA")?);
+            }
+            if y & 0b0010 != 0 {
+                i.tb = Some(*bytes.next().ok_or("ATR truncated in TB")?);
+            }
+            if y & 0b0100 != 0 {
+                i.tc = Some(*bytes.next().ok_or("ATR truncated in TC")?);
+            }
+            if y & 0b1000 != 0 {
+                i.td = Some(*bytes.next().ok_or("ATR truncated in TD")?);
+            }
+            let td = i.td;
+            if let Some(p) = i.protocol() {
+                if p != 0 {
+                    tck_required = true;
+                }
+            }
+            interface.push(i);
+            match td {
+                Some(td) => y = td >> 4,
+                None => break
+            }
+        }
+
+        let historical_len = (t0 & 0x0f) as usize;
+        let historical: Vec<u8> = bytes.by_ref().take(historical_len).cloned().collect();
+        if historical.len() != historical_len {
+            return Err("ATR truncated in historical bytes");
+        }
+
+        let tck = bytes.next().cloned();
+        if tck_required {
+            let tck = tck.ok_or("Missing TCK")?;
+            let check = value[1..value.len() - 1].iter().fold(tck, |acc, b| acc ^ b);
+            if check != 0 {
+                return Err("Invalid TCK");
+            }
+        }
+
+        Ok(Self {
+            raw: value.to_vec(),
+            convention,
+            interface,
+            historical,
+            tck,
+        })
+    }
+}
+
+/// An optional byte in hex, for Debug
+fn optional_byte(byte: &Option<u8>) -> Option<crate::hex::Hex<'_>> {
+    byte.as_ref().map(|b| crate::hex::Hex(std::slice::from_ref(b)))
+}
+
+impl std::fmt::Debug for InterfaceBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InterfaceBytes")
+            .field("ta", &optional_byte(&self.ta))
+            .field("tb", &optional_byte(&self.tb))
+            .field("tc", &optional_byte(&self.tc))
+            .field("td", &optional_byte(&self.td))
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for Atr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Atr")
+            .field("raw", &crate::hex::Hex(&self.raw))
+            .field("convention", &self.convention)
+            .field("interface", &self.interface)
+            .field("historical", &crate::hex::Hex(&self.historical))
+            .field("tck", &optional_byte(&self.tck))
+            .finish()
+    }
+}
+
+impl std::fmt::Display for Atr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "ATR: {}", crate::hex::Hex(&self.raw))?;
+        writeln!(f, "  Convention: {:?}", self.convention)?;
+        writeln!(f, "  Protocols: {}", self.protocols().iter().map(|p| format!("T={}", p)).collect::<Vec<_>>().join(", "))?;
+        match (self.fi(), self.di()) {
+            (Some((fi, fmax)), Some(di)) => writeln!(f, "  Fi={} (fmax {} MHz), Di={}", fi, fmax, di)?,
+            _ => writeln!(f, "  Fi/Di: RFU")?
+        }
+        writeln!(f, "  Extra guard time: {} etu", self.extra_guard_time())?;
+        if let Some(p) = self.specific_mode() {
+            writeln!(f, "  Specific mode: T={}", p)?;
+        }
+        if let Some((ifsc, bwi, cwi)) = self.t1_parameters() {
+            writeln!(f, "  T=1: IFSC={}, BWI={}, CWI={}", ifsc, bwi, cwi)?;
+        }
+        for (n, i) in self.interface.iter().enumerate() {
+            let n = n + 1;
+            if let Some(b) = i.ta { writeln!(f, "  TA{}: {:02x}", n, b)?; }
+            if let Some(b) = i.tb { writeln!(f, "  TB{}: {:02x}", n, b)?; }
+            if let Some(b) = i.tc { writeln!(f, "  TC{}: {:02x}", n, b)?; }
+            if let Some(b) = i.td { writeln!(f, "  TD{}: {:02x}", n, b)?; }
+        }
+        write!(f, "  Historical bytes: {}", crate::hex::Hex(&self.historical))?;
+        for o in self.historical_objects() {
+            match o {
+                HistoricalObject::CompactTlv(t, d) => write!(f, "\n    Tag {:x}: {}", t, crate::hex::Hex(&d))?,
+                HistoricalObject::Status(d) => write!(f, "\n    Status: {}", crate::hex::Hex(&d))?,
+                HistoricalObject::Proprietary(d) => write!(f, "\n    Proprietary: {}", crate::hex::Hex(&d))?,
+            }
+        }
+        Ok(())
+    }
+}
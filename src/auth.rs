@@ -0,0 +1,294 @@
+use chrono::Datelike;
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+use crate::tlv::TagID;
+
+#[derive(Debug, Clone)]
+pub struct IssuerPublicKey {
+    pub modulus: Vec<u8>,
+    pub exponent: Vec<u8>,
+    /// Leftmost 3 to 8 digits of the PANs the key is for, padded with F
+    pub issuer_identifier: [u8; 4],
+    /// Certificate expiry, MMYY
+    pub expiry: [u8; 2],
+    pub serial_number: [u8; 3],
+}
+
+#[derive(Debug, Clone)]
+pub struct IccPublicKey {
+    pub modulus: Vec<u8>,
+    pub exponent: Vec<u8>,
+    /// Certificate expiry, MMYY
+    pub expiry: [u8; 2],
+    pub serial_number: [u8; 3],
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, thiserror::Error)]
+pub enum OdaError {
+    /// A data object needed for authentication wasn't read from the card
+    #[error("ICC data missing")]
+    IccDataMissing,
+    #[error("{0}")]
+    Failed(&'static str),
+}
+
+/// The RSA public key operation, with the result padded out to the modulus length
+fn recover(data: &[u8], modulus: &[u8], exponent: &[u8]) -> Result<Vec<u8>, OdaError> {
+    if data.len() != modulus.len() {
+        return Err(OdaError::Failed("Signature length doesn't match key"));
+    }
+    let out = BigUint::from_bytes_be(data)
+        .modpow(&BigUint::from_bytes_be(exponent), &BigUint::from_bytes_be(modulus))
+        .to_bytes_be();
+    let mut padded = vec![0; modulus.len() - out.len()];
+    padded.extend(out);
+    Ok(padded)
+}
+
+fn sha1(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    for p in parts {
+        hasher.update(p);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Whether an MMYY date is this month or later
+fn not_expired(mmyy: &[u8], today: chrono::NaiveDate) -> bool {
+    match (crate::util::from_bcd(&mmyy[..1]), crate::util::from_bcd(&mmyy[1..2])) {
+        (Some(month), Some(year)) => (2000 + year as i32, month as u32) >= (today.year(), today.month()),
+        _ => false
+    }
+}
+
+/// Whether the 3 to 8 digit issuer identifier from a certificate is the start of the PAN
+fn pan_matches(identifier: &[u8], pan: &[u8]) -> bool {
+    let identifier = crate::hex::encode(identifier);
+    let identifier = identifier.trim_end_matches('F');
+    identifier.len() >= 3 && crate::hex::encode(pan).starts_with(identifier)
+}
+
+/// Whether the PAN in an ICC public key certificate, padded with F, is exactly the card's PAN
+fn pan_equal(certificate_pan: &[u8], pan: &[u8]) -> bool {
+    crate::hex::encode(certificate_pan).trim_end_matches('F') == crate::hex::encode(pan).trim_end_matches('F')
+}
+
+/// Book 2 5.3: recovers the issuer public key from its certificate, checking
+/// the certificate's format and hash, that it's for this PAN and hasn't expired
+pub fn recover_issuer_public_key(ca_key: &crate::capk::CaPublicKey, certificate: &[u8], remainder: &[u8], exponent: &[u8], pan: &[u8], today: chrono::NaiveDate) -> Result<IssuerPublicKey, OdaError> {
+    let n = ca_key.modulus.len();
+    let x = recover(certificate, &ca_key.modulus, &ca_key.exponent)?;
+    if x[0] != 0x6a || x[n - 1] != 0xbc || x[1] != 0x02 {
+        return Err(OdaError::Failed("Invalid issuer public key certificate"));
+    }
+    if sha1(&[&x[1..n - 21], remainder, exponent]) != x[n - 21..n - 1] {
+        return Err(OdaError::Failed("Issuer public key certificate hash mismatch"));
+    }
+    if !pan_matches(&x[2..6], pan) {
+        return Err(OdaError::Failed("Issuer identifier doesn't match PAN"));
+    }
+    if !not_expired(&x[6..8], today) {
+        return Err(OdaError::Failed("Issuer public key certificate expired"));
+    }
+    if x[11] != 0x01 || x[12] != 0x01 {
+        return Err(OdaError::Failed("Unsupported issuer public key algorithm"));
+    }
+
+    let length = x[13] as usize;
+    let leftmost = &x[15..n - 21];
+    let mut modulus = leftmost[..std::cmp::min(length, leftmost.len())].to_vec();
+    if length > leftmost.len() {
+        modulus.extend(remainder);
+    }
+    if modulus.len() != length {
+        return Err(OdaError::Failed("Issuer public key remainder wrong length"));
+    }
+
+    Ok(IssuerPublicKey {
+        modulus,
+        exponent: exponent.to_vec(),
+        issuer_identifier: [x[2], x[3], x[4], x[5]],
+        expiry: [x[6], x[7]],
+        serial_number: [x[8], x[9], x[10]],
+    })
+}
+
+/// Finds the CA key the card names and recovers the issuer public key with it
+fn issuer_key(ca_keys: &crate::capk::CapkStore, aid: &[u8], records: &crate::data::ApplicationRecords, today: chrono::NaiveDate) -> Result<IssuerPublicKey, OdaError> {
+    let required = |tag| records.get_bytes(tag).ok_or(OdaError::IccDataMissing);
+    let index = required(TagID::CertificationAuthorityPublicKeyIndex)?;
+    let certificate = required(TagID::IssuerPublicKeyCertificate)?;
+    let exponent = required(TagID::IssuerPublicKeyExponent)?;
+    let pan = required(TagID::ApplicationPrimaryAccountNumber)?;
+    let remainder = records.get_bytes(TagID::IssuerPublicKeyRemainder).unwrap_or_default();
+
+    let ca_key = match (aid.get(..5), index.first()) {
+        (Some(rid), Some(index)) => ca_keys.get(rid, *index),
+        _ => None
+    }.ok_or(OdaError::Failed("Unknown CA public key"))?;
+    if ca_key.expired(today) {
+        return Err(OdaError::Failed("CA public key expired"));
+    }
+    recover_issuer_public_key(ca_key, &certificate, &remainder, &exponent, &pan, today)
+}
+
+/// The static data to be authenticated: the records marked for ODA, followed
+/// by the AIP if the card's SDA Tag List asks for it
+fn static_data(records: &crate::data::ApplicationRecords, aip: [u8; 2]) -> Result<Vec<u8>, OdaError> {
+    if records.oda_record_invalid {
+        return Err(OdaError::Failed("Record for ODA isn't a 70 template"));
+    }
+    let mut static_data = records.oda_data.clone();
+    if let Some(tag_list) = records.get_bytes(TagID::StaticDataAuthenticationTagList) {
+        if tag_list != [0x82] {
+            return Err(OdaError::Failed("SDA tag list names something other than the AIP"));
+        }
+        static_data.extend(&aip);
+    }
+    Ok(static_data)
+}
+
+/// Book 2 5.4: Static Data Authentication. Checks the Signed Static Application
+/// Data against the static data to be authenticated, giving back the Data
+/// Authentication Code on success.
+pub fn verify_sda(ca_keys: &crate::capk::CapkStore, aid: &[u8], records: &crate::data::ApplicationRecords, aip: [u8; 2], today: chrono::NaiveDate) -> Result<[u8; 2], OdaError> {
+    let signed_data = records.get_bytes(TagID::SignedStaticApplicationData).ok_or(OdaError::IccDataMissing)?;
+    let issuer_key = issuer_key(ca_keys, aid, records, today)?;
+
+    let n = issuer_key.modulus.len();
+    let x = recover(&signed_data, &issuer_key.modulus, &issuer_key.exponent)?;
+    if x[0] != 0x6a || x[n - 1] != 0xbc || x[1] != 0x03 {
+        return Err(OdaError::Failed("Invalid signed static application data"));
+    }
+    if sha1(&[&x[1..n - 21], &static_data(records, aip)?]) != x[n - 21..n - 1] {
+        return Err(OdaError::Failed("Signed static application data hash mismatch"));
+    }
+
+    Ok([x[3], x[4]])
+}
+
+/// Book 2 6.4: recovers the ICC public key from its certificate, checking the
+/// certificate's format, that its hash covers `static_data`, and that it's for
+/// this PAN and hasn't expired
+pub fn recover_icc_public_key(issuer_key: &IssuerPublicKey, certificate: &[u8], remainder: &[u8], exponent: &[u8], pan: &[u8], static_data: &[u8], today: chrono::NaiveDate) -> Result<IccPublicKey, OdaError> {
+    let n = issuer_key.modulus.len();
+    let x = recover(certificate, &issuer_key.modulus, &issuer_key.exponent)?;
+    if x[0] != 0x6a || x[n - 1] != 0xbc || x[1] != 0x04 {
+        return Err(OdaError::Failed("Invalid ICC public key certificate"));
+    }
+    if sha1(&[&x[1..n - 21], remainder, exponent, static_data]) != x[n - 21..n - 1] {
+        return Err(OdaError::Failed("ICC public key certificate hash mismatch"));
+    }
+    if !pan_equal(&x[2..12], pan) {
+        return Err(OdaError::Failed("ICC public key certificate is for another PAN"));
+    }
+    if !not_expired(&x[12..14], today) {
+        return Err(OdaError::Failed("ICC public key certificate expired"));
+    }
+    if x[17] != 0x01 || x[18] != 0x01 {
+        return Err(OdaError::Failed("Unsupported ICC public key algorithm"));
+    }
+
+    let length = x[19] as usize;
+    let leftmost = &x[21..n - 21];
+    let mut modulus = leftmost[..std::cmp::min(length, leftmost.len())].to_vec();
+    if length > leftmost.len() {
+        modulus.extend(remainder);
+    }
+    if modulus.len() != length {
+        return Err(OdaError::Failed("ICC public key remainder wrong length"));
+    }
+
+    Ok(IccPublicKey {
+        modulus,
+        exponent: exponent.to_vec(),
+        expiry: [x[12], x[13]],
+        serial_number: [x[14], x[15], x[16]],
+    })
+}
+
+/// Recovers the ICC public key from 9F46, 9F47 and 9F48 in the card's records,
+/// going through the issuer key certified by one of `ca_keys`
+pub fn recover_icc_key(ca_keys: &crate::capk::CapkStore, aid: &[u8], records: &crate::data::ApplicationRecords, aip: [u8; 2], today: chrono::NaiveDate) -> Result<IccPublicKey, OdaError> {
+    let required = |tag| records.get_bytes(tag).ok_or(OdaError::IccDataMissing);
+    let certificate = required(TagID::IccPublicKeyCertificate)?;
+    let exponent = required(TagID::IccPublicKeyExponent)?;
+    let pan = required(TagID::ApplicationPrimaryAccountNumber)?;
+    let remainder = records.get_bytes(TagID::IccPublicKeyRemainder).unwrap_or_default();
+    let issuer_key = issuer_key(ca_keys, aid, records, today)?;
+
+    recover_icc_public_key(&issuer_key, &certificate, &remainder, &exponent, &pan, &static_data(records, aip)?, today)
+}
+
+/// Book 2 7.2: enciphers a format 2 PIN block for offline verification, along
+/// with the challenge the card just gave and random padding
+pub fn encipher_pin(key: &IccPublicKey, pin_block: &[u8], challenge: &[u8]) -> Result<Vec<u8>, OdaError> {
+    let n = key.modulus.len();
+    if pin_block.len() != 8 || challenge.len() != 8 || n < 17 {
+        return Err(OdaError::Failed("Can't encipher PIN under this key"));
+    }
+    let mut data = vec![0x7f];
+    data.extend(pin_block);
+    data.extend(challenge);
+    let mut padding = vec![0; n - 17];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut padding);
+    data.extend(padding);
+    recover(&data, &key.modulus, &key.exponent)
+}
+
+/// Recovers Signed Dynamic Application Data and checks its hash over the
+/// terminal's dynamic data, giving back the ICC Dynamic Data
+fn recover_dynamic_data(icc_key: &IccPublicKey, signed_data: &[u8], terminal_dynamic_data: &[u8]) -> Result<Vec<u8>, OdaError> {
+    let n = icc_key.modulus.len();
+    let x = recover(signed_data, &icc_key.modulus, &icc_key.exponent)?;
+    if x[0] != 0x6a || x[n - 1] != 0xbc || x[1] != 0x05 || 4 + x[3] as usize > n - 21 {
+        return Err(OdaError::Failed("Invalid signed dynamic application data"));
+    }
+    if sha1(&[&x[1..n - 21], terminal_dynamic_data]) != x[n - 21..n - 1] {
+        return Err(OdaError::Failed("Signed dynamic application data hash mismatch"));
+    }
+    Ok(x[4..4 + x[3] as usize].to_vec())
+}
+
+/// Visa fDDA: checks the signature the card made over its dynamic number
+/// and the terminal's dynamic data during GET PROCESSING OPTIONS
+pub fn verify_fdda(icc_key: &IccPublicKey, signed_data: &[u8], terminal_dynamic_data: &[u8]) -> Result<(), OdaError> {
+    recover_dynamic_data(icc_key, signed_data, terminal_dynamic_data).map(|_| ())
+}
+
+/// Book 2 6.6.2: checks the Signed Dynamic Application Data in a format 2
+/// GENERATE AC response. `transaction_data` is the PDOL and CDOL data sent so
+/// far in the transaction, which the card hashes along with its response.
+pub fn verify_cda(icc_key: &IccPublicKey, response: &crate::tlv::TagList, unpredictable_number: &[u8], transaction_data: &[u8]) -> Result<(), OdaError> {
+    let template = response.get_tag(TagID::ResponseMessageTemplateFormat2).ok_or(OdaError::IccDataMissing)?;
+    let objects = match template.contents() {
+        crate::tlv::TagContents::Constructed(tl) => tl.tags(),
+        _ => return Err(OdaError::IccDataMissing)
+    };
+    let signed_data = template.get_tag(TagID::SignedDynamicApplicationData)
+        .map(|t| Vec::<u8>::from(t.contents()))
+        .ok_or(OdaError::IccDataMissing)?;
+    let cid = template.get_tag(TagID::CryptogramInformationData)
+        .map(|t| Vec::<u8>::from(t.contents()))
+        .ok_or(OdaError::IccDataMissing)?;
+
+    // ICC Dynamic Data: ICC Dynamic Number, CID, cryptogram, Transaction Data Hash Code
+    let dynamic_data = recover_dynamic_data(icc_key, &signed_data, unpredictable_number)?;
+    let number_length = dynamic_data[0] as usize;
+    if dynamic_data.len() != number_length + 30 {
+        return Err(OdaError::Failed("Invalid ICC dynamic data"));
+    }
+    if cid.as_slice() != &dynamic_data[number_length + 1..number_length + 2] {
+        return Err(OdaError::Failed("Signed CID doesn't match response"));
+    }
+
+    let mut hashed = transaction_data.to_vec();
+    for object in objects.iter().filter(|o| o.id() != TagID::SignedDynamicApplicationData) {
+        hashed.extend(Vec::<u8>::from(object));
+    }
+    if sha1(&[&hashed]) != dynamic_data[number_length + 10..] {
+        return Err(OdaError::Failed("Transaction data hash mismatch"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+
+/// Where completed transactions are kept until settlement when not told otherwise
+pub const DEFAULT_BATCH_FILE: &str = "emv-term-batch.jsonl";
+
+/// A completed transaction as kept for clearing, one JSON object per line of
+/// the batch file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRecord {
+    pub time: String,
+    /// Only the first six and last four digits
+    pub masked_pan: Option<String>,
+    pub aid: String,
+    pub transaction_type: u8,
+    /// Amount, Authorised in minor units, including any cashback
+    pub amount: u64,
+    pub amount_other: u64,
+    pub currency_code: u16,
+    pub transaction_sequence_counter: u32,
+    pub outcome: crate::transaction::Outcome,
+    /// The cryptogram the transaction finished with
+    pub cryptogram: Option<crate::transaction::Cryptogram>,
+    pub application_cryptogram: Option<String>,
+    pub atc: Option<u16>,
+    pub tvr: String,
+    pub tsi: String,
+    pub authorisation_response_code: Option<String>,
+}
+
+impl BatchRecord {
+    /// The clearing record for a transaction, if it got as far as the card
+    /// giving a cryptogram or track data
+    pub fn new(terminal: &crate::terminal::TerminalData, outcome: &crate::transaction::TransactionOutcome) -> Option<Self> {
+        let aid = outcome.aid.as_ref()?;
+        let last_ac = outcome.second_ac.as_ref().or(outcome.first_ac.as_ref());
+        if last_ac.is_none() && outcome.masked_track2.is_none() {
+            return None;
+        }
+        Some(Self {
+            time: format!("{} {}", terminal.transaction_date(), terminal.transaction_time.time().format("%H:%M:%S")),
+            masked_pan: outcome.masked_pan.clone(),
+            aid: crate::hex::encode(aid),
+            transaction_type: terminal.transaction_type,
+            amount: terminal.amount_authorised,
+            amount_other: terminal.amount_other,
+            currency_code: terminal.currency_code,
+            transaction_sequence_counter: terminal.transaction_sequence_counter,
+            outcome: outcome.outcome,
+            cryptogram: last_ac.map(|ac| ac.cryptogram),
+            application_cryptogram: last_ac.map(|ac| crate::hex::encode(&ac.application_cryptogram)),
+            atc: last_ac.map(|ac| ac.atc),
+            tvr: crate::hex::encode(&outcome.tvr),
+            tsi: crate::hex::encode(&outcome.tsi),
+            authorisation_response_code: outcome.authorisation_response_code.clone(),
+        })
+    }
+}
+
+pub fn append(path: &std::path::Path, record: &BatchRecord) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+/// Reads every record in the batch, an absent batch file being an empty batch
+pub fn load(path: &std::path::Path) -> std::io::Result<Vec<BatchRecord>> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e)
+    };
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).map_err(std::io::Error::from))
+        .collect()
+}
+
+/// Totals for a batch, counting only what's to be cleared
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settlement {
+    pub transactions: usize,
+    pub declined: usize,
+    pub sales: usize,
+    pub sales_total: u64,
+    pub refunds: usize,
+    pub refunds_total: u64,
+    /// Cash given out, whether as a cash advance or cashback
+    pub cash_total: u64,
+}
+
+impl Settlement {
+    pub fn new(records: &[BatchRecord]) -> Self {
+        let mut out = Self {
+            transactions: records.len(),
+            ..Default::default()
+        };
+        for record in records {
+            match record.outcome {
+                crate::transaction::Outcome::Refunded => {
+                    out.refunds += 1;
+                    out.refunds_total += record.amount;
+                }
+                o if o.approved() => {
+                    out.sales += 1;
+                    out.sales_total += record.amount;
+                    out.cash_total += match record.transaction_type {
+                        crate::terminal::TRANSACTION_TYPE_CASH => record.amount,
+                        _ => record.amount_other
+                    };
+                }
+                _ => out.declined += 1
+            }
+        }
+        out
+    }
+
+    /// What the acquirer owes the merchant, sales less refunds
+    pub fn net_total(&self) -> i128 {
+        self.sales_total as i128 - self.refunds_total as i128
+    }
+}
+
+impl std::fmt::Display for Settlement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let amount = |a: u64| crate::util::format_amount(a, 2);
+        writeln!(f, "Transactions: {} ({} declined)", self.transactions, self.declined)?;
+        writeln!(f, "Sales: {} totalling {}", self.sales, amount(self.sales_total))?;
+        writeln!(f, "  of which cash: {}", amount(self.cash_total))?;
+        writeln!(f, "Refunds: {} totalling {}", self.refunds, amount(self.refunds_total))?;
+        let net = self.net_total();
+        write!(f, "Net: {}{}", if net < 0 { "-" } else { "" }, amount(net.unsigned_abs() as u64))
+    }
+}
+
+/// Totals up the batch and empties it, ready for the next
+pub fn settle(path: &std::path::Path) -> std::io::Result<Settlement> {
+    let settlement = Settlement::new(&load(path)?);
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e)
+    }
+    Ok(settlement)
+}
+
+#[cfg(test)]
+mod tests {
+    fn outcome(outcome: crate::transaction::Outcome) -> crate::transaction::TransactionOutcome {
+        let mut out = crate::transaction::TransactionOutcome::without_transaction(outcome, None);
+        out.aid = Some(vec![0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10]);
+        out.masked_pan = Some("476173******0010".to_string());
+        out.masked_track2 = Some("476173******0010D3012201".to_string());
+        out
+    }
+
+    #[test]
+    fn captures_and_settles_batch() {
+        let path = std::env::temp_dir().join(format!("emv-term-batch-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 2500;
+        terminal.amount_other = 500;
+        let record = crate::batch::BatchRecord::new(&terminal, &outcome(crate::transaction::Outcome::ApprovedOffline)).unwrap();
+        assert_eq!(record.masked_pan.as_deref(), Some("476173******0010"));
+        assert_eq!(record.aid, "A0000000031010");
+        crate::batch::append(&path, &record).unwrap();
+
+        terminal.amount_authorised = 4000;
+        terminal.amount_other = 0;
+        crate::batch::append(&path, &crate::batch::BatchRecord::new(&terminal, &outcome(crate::transaction::Outcome::DeclinedOffline)).unwrap()).unwrap();
+
+        terminal.transaction_type = crate::terminal::TRANSACTION_TYPE_REFUND;
+        terminal.amount_authorised = 1000;
+        crate::batch::append(&path, &crate::batch::BatchRecord::new(&terminal, &outcome(crate::transaction::Outcome::Refunded)).unwrap()).unwrap();
+
+        // Nothing to clear when no application was selected
+        let nothing = crate::transaction::TransactionOutcome::without_transaction(crate::transaction::Outcome::Terminated, None);
+        assert!(crate::batch::BatchRecord::new(&terminal, &nothing).is_none());
+
+        assert_eq!(crate::batch::load(&path).unwrap().len(), 3);
+        let settlement = crate::batch::settle(&path).unwrap();
+        assert_eq!(settlement, crate::batch::Settlement {
+            transactions: 3,
+            declined: 1,
+            sales: 1,
+            sales_total: 2500,
+            refunds: 1,
+            refunds_total: 1000,
+            cash_total: 500,
+        });
+        assert_eq!(settlement.net_total(), 1500);
+        assert!(crate::batch::load(&path).unwrap().is_empty());
+        assert_eq!(crate::util::format_amount(1234, 2), "12.34");
+    }
+}
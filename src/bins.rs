@@ -0,0 +1,207 @@
+/// The schemes behind each RID, for applications with no PAN to go on
+const SCHEME_RIDS: [([u8; 5], &str); 12] = [
+    ([0xa0, 0x00, 0x00, 0x00, 0x03], "Visa"),
+    ([0xa0, 0x00, 0x00, 0x00, 0x98], "Visa"),
+    ([0xa0, 0x00, 0x00, 0x00, 0x04], "Mastercard"),
+    ([0xa0, 0x00, 0x00, 0x00, 0x25], "American Express"),
+    ([0xa0, 0x00, 0x00, 0x00, 0x42], "Cartes Bancaires"),
+    ([0xa0, 0x00, 0x00, 0x00, 0x65], "JCB"),
+    ([0xa0, 0x00, 0x00, 0x01, 0x52], "Discover"),
+    ([0xa0, 0x00, 0x00, 0x02, 0x77], "Interac"),
+    ([0xa0, 0x00, 0x00, 0x03, 0x24], "Discover"),
+    ([0xa0, 0x00, 0x00, 0x03, 0x33], "UnionPay"),
+    ([0xa0, 0x00, 0x00, 0x03, 0x59], "girocard"),
+    ([0xa0, 0x00, 0x00, 0x01, 0x41], "Bancomat"),
+];
+
+/// The scheme's own IIN ranges, from the most general to the most specific
+const DEFAULT_RANGES: [(&str, &str, &str); 17] = [
+    ("4", "4", "Visa"),
+    ("51", "55", "Mastercard"),
+    ("2221", "2720", "Mastercard"),
+    ("50", "50", "Maestro"),
+    ("56", "58", "Maestro"),
+    ("6759", "6759", "Maestro"),
+    ("34", "34", "American Express"),
+    ("37", "37", "American Express"),
+    ("6011", "6011", "Discover"),
+    ("644", "649", "Discover"),
+    ("65", "65", "Discover"),
+    ("3528", "3589", "JCB"),
+    ("300", "305", "Diners Club"),
+    ("36", "36", "Diners Club"),
+    ("38", "39", "Diners Club"),
+    ("62", "62", "UnionPay"),
+    ("81", "81", "UnionPay"),
+];
+
+/// The scheme whose RID the AID starts with
+pub fn scheme_for_aid(aid: &[u8]) -> Option<&'static str> {
+    SCHEME_RIDS.iter().find(|(rid, _)| aid.starts_with(rid)).map(|(_, s)| *s)
+}
+
+/// PANs starting with anything from `start` to `end`, both the same number of
+/// digits
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinRange {
+    pub start: String,
+    pub end: String,
+    pub scheme: String,
+    pub issuer: Option<String>,
+}
+
+impl BinRange {
+    pub fn contains(&self, pan: &str) -> bool {
+        match pan.get(..self.start.len()) {
+            Some(prefix) => self.start.as_str() <= prefix && prefix <= self.end.as_str(),
+            None => false
+        }
+    }
+}
+
+fn normalise_field(name: &str) -> String {
+    name.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+fn parse_prefix(name: &str, value: &str) -> Result<String, String> {
+    match !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        true => Ok(value.to_string()),
+        false => Err(format!("Invalid {} in BIN range: {}", name, value))
+    }
+}
+
+/// Parses ranges from CSV, with columns `start`, `end`, `scheme` and `issuer`
+/// unless a header line says otherwise. A range with no end covers just its
+/// start, and `bin` can stand in for `start`.
+pub fn parse_csv(data: &str) -> Result<Vec<BinRange>, String> {
+    let mut columns: Vec<String> = ["start", "end", "scheme", "issuer"]
+        .iter().map(|c| c.to_string()).collect();
+    let mut ranges = vec![];
+
+    let lines = data.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    for (i, line) in lines.enumerate() {
+        let values: Vec<&str> = line.split([',', ';'])
+            .map(|v| v.trim().trim_matches('"'))
+            .collect();
+        if i == 0 && values.iter().any(|v| normalise_field(v) == "scheme") {
+            columns = values.iter().map(|v| match normalise_field(v).as_str() {
+                "bin" | "iin" => "start".to_string(),
+                c => c.to_string()
+            }).collect();
+            continue;
+        }
+        let field = |name: &str| columns.iter().position(|c| c == name)
+            .and_then(|p| values.get(p))
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_string());
+
+        let start = parse_prefix("start", &field("start").unwrap_or_default())?;
+        let end = match field("end") {
+            Some(e) => parse_prefix("end", &e)?,
+            None => start.clone()
+        };
+        if start.len() != end.len() || start > end {
+            return Err(format!("Invalid BIN range: {} to {}", start, end));
+        }
+        ranges.push(BinRange {
+            start,
+            end,
+            scheme: field("scheme").ok_or_else(|| format!("BIN range on line {} has no scheme", i + 1))?,
+            issuer: field("issuer"),
+        });
+    }
+
+    Ok(ranges)
+}
+
+/// Which scheme, and where known which issuer, a PAN belongs to
+#[derive(Debug, Clone, Default)]
+pub struct BinTable {
+    ranges: Vec<BinRange>,
+}
+
+impl BinTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The schemes' own ranges, without any issuers
+    pub fn with_defaults() -> Self {
+        let mut table = Self::new();
+        for (start, end, scheme) in DEFAULT_RANGES.iter() {
+            table.insert(BinRange {
+                start: start.to_string(),
+                end: end.to_string(),
+                scheme: scheme.to_string(),
+                issuer: None,
+            });
+        }
+        table
+    }
+
+    pub fn insert(&mut self, range: BinRange) {
+        self.ranges.push(range);
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The most specific range the PAN falls in, the one added last where
+    /// ranges of the same length overlap
+    pub fn lookup(&self, pan: &str) -> Option<&BinRange> {
+        self.ranges.iter().rev()
+            .filter(|r| r.contains(pan))
+            .fold(None, |best: Option<&BinRange>, r| match best {
+                Some(b) if b.start.len() >= r.start.len() => Some(b),
+                _ => Some(r)
+            })
+    }
+
+    /// Adds every range in a CSV file, giving the number loaded
+    pub fn load_file(&mut self, path: &std::path::Path) -> Result<usize, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let ranges = parse_csv(&data)?;
+        let count = ranges.len();
+        self.ranges.extend(ranges);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn looks_up_bins() {
+        let bins = crate::bins::BinTable::with_defaults();
+        assert_eq!(bins.lookup("4761739001010010").unwrap().scheme, "Visa");
+        assert_eq!(bins.lookup("2223000048400011").unwrap().scheme, "Mastercard");
+        assert_eq!(bins.lookup("6759649826438453").unwrap().scheme, "Maestro");
+        assert_eq!(bins.lookup("374245455400126").unwrap().scheme, "American Express");
+        assert!(bins.lookup("9999999999999999").is_none());
+        assert!(bins.lookup("").is_none());
+
+        // The more specific range wins, wherever it was added
+        let mut bins = crate::bins::BinTable::with_defaults();
+        for range in crate::bins::parse_csv("BIN,Scheme,Issuer\n476173,Visa,Test Bank\n# Comment\n\n").unwrap() {
+            bins.insert(range);
+        }
+        bins.insert(crate::bins::BinRange { start: "47".to_string(), end: "47".to_string(), scheme: "Other".to_string(), issuer: None });
+        assert_eq!(bins.lookup("4761739001010010").unwrap().issuer.as_deref(), Some("Test Bank"));
+        assert_eq!(bins.lookup("4700000000000000").unwrap().scheme, "Other");
+    }
+
+    #[test]
+    fn parses_bin_csv() {
+        let ranges = crate::bins::parse_csv("510000,559999,Mastercard,\n").unwrap();
+        assert!(ranges[0].contains("5412345678901234"));
+        assert_eq!(ranges[0].issuer, None);
+        assert!(crate::bins::parse_csv("55,51,Mastercard").is_err());
+        assert!(crate::bins::parse_csv("5,51,Mastercard").is_err());
+        assert!(crate::bins::parse_csv("4x,,Visa").is_err());
+        assert!(crate::bins::parse_csv("4").is_err());
+    }
+}
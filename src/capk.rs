@@ -0,0 +1,332 @@
+use sha1::{Digest, Sha1};
+
+/// A Certification Authority public key, identified by the RID of the
+/// application it certifies issuers for and the index the card names in 8F
+#[derive(Debug, Clone)]
+pub struct CaPublicKey {
+    pub rid: [u8; 5],
+    pub index: u8,
+    pub modulus: Vec<u8>,
+    pub exponent: Vec<u8>,
+    /// SHA-1 checksum over RID, index, modulus and exponent, as published with the key
+    pub hash: Option<[u8; 20]>,
+    /// Last day the key may be used
+    pub expiry: Option<chrono::NaiveDate>,
+}
+
+impl CaPublicKey {
+    /// The checksum the key's published hash should match
+    pub fn compute_hash(&self) -> [u8; 20] {
+        let mut hasher = Sha1::new();
+        hasher.update(self.rid);
+        hasher.update([self.index]);
+        hasher.update(&self.modulus);
+        hasher.update(&self.exponent);
+        hasher.finalize().into()
+    }
+
+    pub fn expired(&self, today: chrono::NaiveDate) -> bool {
+        matches!(self.expiry, Some(e) if e < today)
+    }
+}
+
+/// The terminal's CA public keys, keyed by RID and index
+#[derive(Debug, Clone, Default)]
+pub struct CapkStore {
+    keys: std::collections::BTreeMap<([u8; 5], u8), CaPublicKey>,
+}
+
+impl CapkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The keys the terminal starts with: the published test keys when built
+    /// with the `test-capks` feature, otherwise none
+    pub fn with_defaults() -> Self {
+        #[allow(unused_mut)]
+        let mut store = Self::new();
+        #[cfg(feature = "test-capks")]
+        for (rid, index, modulus, exponent, hash) in TEST_KEYS.iter() {
+            let key = CaPublicKey {
+                rid: std::convert::TryInto::try_into(crate::hex::decode(rid).expect("Invalid built-in RID").as_slice())
+                    .expect("Invalid built-in RID"),
+                index: *index,
+                modulus: crate::hex::decode(modulus).expect("Invalid built-in modulus"),
+                exponent: crate::hex::decode(exponent).expect("Invalid built-in exponent"),
+                hash: Some(std::convert::TryInto::try_into(crate::hex::decode(hash).expect("Invalid built-in hash").as_slice())
+                    .expect("Invalid built-in hash")),
+                expiry: None,
+            };
+            store.insert(key).expect("Built-in CA public key fails its checksum");
+        }
+        store
+    }
+
+    /// Adds a key, replacing any with the same RID and index. Keys with a hash
+    /// that doesn't match are refused.
+    pub fn insert(&mut self, key: CaPublicKey) -> Result<(), &'static str> {
+        if key.modulus.is_empty() || key.exponent.is_empty() {
+            return Err("CA public key has no modulus or exponent");
+        }
+        if matches!(key.hash, Some(h) if h != key.compute_hash()) {
+            return Err("CA public key checksum mismatch");
+        }
+        self.keys.insert((key.rid, key.index), key);
+        Ok(())
+    }
+
+    pub fn get(&self, rid: &[u8], index: u8) -> Option<&CaPublicKey> {
+        let rid = std::convert::TryInto::try_into(rid).ok()?;
+        self.keys.get(&(rid, index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CaPublicKey> {
+        self.keys.values()
+    }
+}
+
+/// Formats CA public key files come in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImportFormat {
+    Xml,
+    Json,
+    Csv,
+}
+
+impl ImportFormat {
+    /// Guesses the format from a file's extension
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "xml" => Some(ImportFormat::Xml),
+            "json" => Some(ImportFormat::Json),
+            "csv" | "txt" => Some(ImportFormat::Csv),
+            _ => None
+        }
+    }
+}
+
+/// Field names are matched ignoring case and any separators, so `KeyIndex`,
+/// `key_index` and `Key Index` are all the same field
+fn normalise_field(name: &str) -> String {
+    name.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+fn parse_hex_field(name: &str, value: &str) -> Result<Vec<u8>, String> {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    let value = value.trim_start_matches("0x").trim_start_matches("0X");
+    crate::hex::decode(value).ok_or_else(|| format!("Invalid hex in {}: {}", name, value))
+}
+
+/// Accepts YYYY-MM-DD, YYYYMMDD and the DDMMYY used in most terminal configuration files
+fn parse_expiry(value: &str) -> Result<chrono::NaiveDate, String> {
+    let value = value.trim();
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| chrono::NaiveDate::parse_from_str(value, "%Y%m%d"))
+        .or_else(|_| chrono::NaiveDate::parse_from_str(value, "%d%m%y"))
+        .map_err(|_| format!("Invalid expiry date: {}", value))
+}
+
+/// Builds a key from named fields, however the file format supplies them
+fn key_from_fields<F: Fn(&str) -> Option<String>>(field: F) -> Result<CaPublicKey, String> {
+    let any = |names: &[&str]| names.iter().find_map(|n| field(n)).filter(|v| !v.trim().is_empty());
+    let required = |names: &[&str]| any(names).ok_or_else(|| format!("CA public key has no {}", names[0]));
+
+    let rid = parse_hex_field("RID", &required(&["rid"])?)?;
+    let index = parse_hex_field("index", &required(&["index", "keyindex", "capkindex", "pki"])?)?;
+    let key = CaPublicKey {
+        rid: std::convert::TryInto::try_into(rid.as_slice()).map_err(|_| "RID must be 5 bytes".to_string())?,
+        index: match index.as_slice() {
+            [i] => *i,
+            _ => return Err("Key index must be 1 byte".to_string())
+        },
+        modulus: parse_hex_field("modulus", &required(&["modulus", "mod"])?)?,
+        exponent: parse_hex_field("exponent", &required(&["exponent", "exp"])?)?,
+        hash: match any(&["hash", "checksum", "checkvalue", "sha1"]) {
+            Some(h) => Some(std::convert::TryInto::try_into(parse_hex_field("hash", &h)?.as_slice())
+                .map_err(|_| "Hash must be 20 bytes".to_string())?),
+            None => None
+        },
+        expiry: match any(&["expiry", "expirydate", "expirationdate", "expiration"]) {
+            Some(e) => Some(parse_expiry(&e)?),
+            None => None
+        },
+    };
+    if matches!(key.hash, Some(h) if h != key.compute_hash()) {
+        return Err(format!("Checksum mismatch for key {:02X} of RID {}", key.index, crate::hex::Hex(&key.rid)));
+    }
+    Ok(key)
+}
+
+/// Reads keys from XML. Any element with a `RID` child is taken as a key, its
+/// other fields coming from child elements or attributes.
+pub fn parse_xml(data: &str) -> Result<Vec<CaPublicKey>, String> {
+    let doc = roxmltree::Document::parse(data).map_err(|e| format!("Invalid XML: {}", e))?;
+    doc.descendants()
+        .filter(|n| n.children().any(|c| c.is_element() && normalise_field(c.tag_name().name()) == "rid"))
+        .map(|n| key_from_fields(|name| {
+            n.children()
+                .find(|c| c.is_element() && normalise_field(c.tag_name().name()) == name)
+                .map(|c| c.text().unwrap_or_default().to_string())
+                .or_else(|| n.attributes().find(|a| normalise_field(a.name()) == name).map(|a| a.value().to_string()))
+        }))
+        .collect()
+}
+
+/// Reads keys from JSON: an array of key objects, or an object holding one
+pub fn parse_json(data: &str) -> Result<Vec<CaPublicKey>, String> {
+    let value: serde_json::Value = serde_json::from_str(data).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let keys = match &value {
+        serde_json::Value::Array(a) => a,
+        serde_json::Value::Object(o) => match o.values().find_map(|v| v.as_array()) {
+            Some(a) => a,
+            None => return Err("No array of keys in JSON".to_string())
+        },
+        _ => return Err("No array of keys in JSON".to_string())
+    };
+    keys.iter().map(|k| {
+        let k = k.as_object().ok_or_else(|| "CA public key isn't a JSON object".to_string())?;
+        key_from_fields(|name| {
+            k.iter().find(|(f, _)| normalise_field(f) == name).and_then(|(_, v)| match v {
+                serde_json::Value::String(s) => Some(s.to_string()),
+                serde_json::Value::Number(n) => n.as_u64().map(|n| format!("{:02X}", n)),
+                _ => None
+            })
+        })
+    }).collect()
+}
+
+/// Reads keys from CSV, with columns named by a header row or otherwise in the
+/// order RID, index, modulus, exponent, hash, expiry. Blank lines and lines
+/// starting `#` are skipped.
+pub fn parse_csv(data: &str) -> Result<Vec<CaPublicKey>, String> {
+    let mut columns: Vec<String> = ["rid", "index", "modulus", "exponent", "hash", "expiry"]
+        .iter().map(|c| c.to_string()).collect();
+    let mut keys = vec![];
+
+    let lines = data.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    for (i, line) in lines.enumerate() {
+        let values: Vec<&str> = line.split([',', ';'])
+            .map(|v| v.trim().trim_matches('"'))
+            .collect();
+        if i == 0 && values.iter().any(|v| normalise_field(v) == "rid") {
+            columns = values.iter().map(|v| normalise_field(v)).collect();
+            continue;
+        }
+        keys.push(key_from_fields(|name| {
+            columns.iter().position(|c| c == name).and_then(|p| values.get(p)).map(|v| v.to_string())
+        })?);
+    }
+
+    Ok(keys)
+}
+
+impl CapkStore {
+    /// Adds every key in a file, giving the number loaded
+    pub fn load_file(&mut self, path: &std::path::Path) -> Result<usize, String> {
+        let format = ImportFormat::from_path(path)
+            .ok_or_else(|| format!("Unknown CA public key file format: {}", path.display()))?;
+        let data = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let keys = match format {
+            ImportFormat::Xml => parse_xml(&data),
+            ImportFormat::Json => parse_json(&data),
+            ImportFormat::Csv => parse_csv(&data),
+        }?;
+        let count = keys.len();
+        for key in keys {
+            self.insert(key)?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(feature = "test-capks")]
+const TEST_KEYS: [(&str, u8, &str, &str, &str); 10] = [
+    // Visa test key 92
+    ("A000000003", 0x92, "996AF56F569187D09293C14810450ED8EE3357397B18A2458EFAA92DA3B6DF6514EC060195318FD43BE9B8F0CC669E3F844057CBDDF8BDA191BB64473BC8DC9A730DB8F6B4EDE3924186FFD9B8C7735789C23A36BA0B8AF65372EB57EA5D89E7D14E9C7B6B557460F10885DA16AC923F15AF3758F0F03EBD3C5C2C949CBA306DB44E6A2C076C5F67E281D7EF56785DC4D75945E491F01918800A9E2DC66F60080566CE0DAF8D17EAD46AD8E30A247C9F",
+     "03", "429C954A3859CEF91295F663C963E582ED6EB253"),
+    // Visa test key 94
+    ("A000000003", 0x94, "ACD2B12302EE644F3F835ABD1FC7A6F62CCE48FFEC622AA8EF062BEF6FB8BA8BC68BBF6AB5870EED579BC3973E121303D34841A796D6DCBC41DBF9E52C4609795C0CCF7EE86FA1D5CB041071ED2C51D2202F63F1156C58A92D38BC60BDF424E1776E2BC9648078A03B36FB554375FC53D57C73F5160EA59F3AFC5398EC7B67758D65C9BFF7828B6B82D4BE124A416AB7301914311EA462C19F771F31B3B57336000DFF732D3B83DE07052D730354D297BEC72871DCCF0E193F171ABA27EE464C6A97690943D59BDABB2A27EB71CEEBDAFA1176046478FD62FEC452D5CA393296530AA3F41927ADFE434A2DF2AE3054F8840657A26E0FC617",
+     "03", "C4A3C43CCF87327D136B804160E47D43B60E6E0F"),
+    // Visa test key 95
+    ("A000000003", 0x95, "BE9E1FA5E9A803852999C4AB432DB28600DCD9DAB76DFAAA47355A0FE37B1508AC6BF38860D3C6C2E5B12A3CAAF2A7005A7241EBAA7771112C74CF9A0634652FBCA0E5980C54A64761EA101A114E0F0B5572ADD57D010B7C9C887E104CA4EE1272DA66D997B9A90B5A6D624AB6C57E73C8F919000EB5F684898EF8C3DBEFB330C62660BED88EA78E909AFF05F6DA627B",
+     "03", "EE1511CEC71020A9B90443B37B1D5F6E703030F6"),
+    // Visa test key 99
+    ("A000000003", 0x99, "AB79FCC9520896967E776E64444E5DCDD6E13611874F3985722520425295EEA4BD0C2781DE7F31CD3D041F565F747306EED62954B17EDABA3A6C5B85A1DE1BEB9A34141AF38FCF8279C9DEA0D5A6710D08DB4124F041945587E20359BAB47B7575AD94262D4B25F264AF33DEDCF28E09615E937DE32EDC03C54445FE7E382777",
+     "03", "4ABFFD6B1C51212D05552E431C5B17007D2F5E6D"),
+    // Mastercard test key EF
+    ("A000000004", 0xef, "A191CB87473F29349B5D60A88B3EAEE0973AA6F1A082F358D849FDDFF9C091F899EDA9792CAF09EF28F5D22404B88A2293EEBBC1949C43BEA4D60CFD879A1539544E09E0F09F60F065B2BF2A13ECC705F3D468B9D33AE77AD9D3F19CA40F23DCF5EB7C04DC8F69EBA565B1EBCB4686CD274785530FF6F6E9EE43AA43FDB02CE00DAEC15C7B8FD6A9B394BABA419D3F6DC85E16569BE8E76989688EFEA2DF22FF7D35C043338DEAA982A02B866DE5328519EBBCD6F03CDD686673847F84DB651AB86C28CF1462562C577B853564A290C8556D818531268D25CC98A4CC6A0BDFFFDA2DCCA3A94C998559E307FDDF915006D9A987B07DDAEB3B",
+     "03", "21766EBB0EE122AFB65D7845B73DB46BAB65427A"),
+    // Mastercard test key F1
+    ("A000000004", 0xf1, "A0DCF4BDE19C3546B4B6F0414D174DDE294AABBB828C5A834D73AAE27C99B0B053A90278007239B6459FF0BBCD7B4B9C6C50AC02CE91368DA1BD21AAEADBC65347337D89B68F5C99A09D05BE02DD1F8C5BA20E2F13FB2A27C41D3F85CAD5CF6668E75851EC66EDBF98851FD4E42C44C1D59F5984703B27D5B9F21B8FA0D93279FBBF69E090642909C9EA27F898959541AA6757F5F624104F6E1D3A9532F2A6E51515AEAD1B43B3D7835088A2FAFA7BE7",
+     "03", "D8E68DA167AB5A85D8C3D55ECB9B0517A1A5B4BB"),
+    // Mastercard test key F3
+    ("A000000004", 0xf3, "98F0C770F23864C2E766DF02D1E833DFF4FFE92D696E1642F0A88C5694C6479D16DB1537BFE29E4FDC6E6E8AFD1B0EB7EA0124723C333179BF19E93F10658B2F776E829E87DAEDA9C94A8B3382199A350C077977C97AFF08FD11310AC950A72C3CA5002EF513FCCC286E646E3C5387535D509514B3B326E1234F9CB48C36DDD44B416D23654034A66F403BA511C5EFA3",
+     "03", "A69AC7603DAF566E972DEDC2CB433E07E8B01A9A"),
+    // Mastercard test key F8
+    ("A000000004", 0xf8, "A1F5E1C9BD8650BD43AB6EE56B891EF7459C0A24FA84F9127D1A6C79D4930F6DB1852E2510F18B61CD354DB83A356BD190B88AB8DF04284D02A4204A7B6CB7C5551977A9B36379CA3DE1A08E69F301C95CC1C20506959275F41723DD5D2925290579E5A95B0DF6323FC8E9273D6F849198C4996209166D9BFC973C361CC826E1",
+     "03", "F06ECC6D2AAEBF259B7E755A38D9A9B24E2FF3DD"),
+    // Mastercard test key FA
+    ("A000000004", 0xfa, "9C6BE5ADB10B4BE3DCE2099B4B210672B89656EBA091204F613ECC623BEDC9C6D77B660E8BAEEA7F7CE30F1B153879A4E36459343D1FE47ACDBD41FCD710030C2BA1D9461597982C6E1BDD08554B726F5EFF7913CE59E79E357295C321E26D0B8BE270A9442345C753E2AA2ACFC9D30850602FE6CAC00C6DDF6B8D9D9B4879B2826B042A07F0E5AE526A3D3C4D22C72B9EAA52EED8893866F866387AC05A1399",
+     "03", "0ABCADAD2C7558CA9C7081AE55DDDC714F8D45F8"),
+    // Mastercard test key FE
+    ("A000000004", 0xfe, "A653EAC1C0F786C8724F737F172997D63D1C3251C44402049B865BAE877D0F398CBFBE8A6035E24AFA086BEFDE9351E54B95708EE672F0968BCD50DCE40F783322B2ABA04EF137EF18ABF03C7DBC5813AEAEF3AA7797BA15DF7D5BA1CBAF7FD520B5A482D8D3FEE105077871113E23A49AF3926554A70FE10ED728CF793B62A1",
+     "03", "9A295B05FB390EF7923F57618A9FDA2941FC34E0"),
+];
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn ca_key_store_checks_hash() {
+        let mut store = crate::capk::CapkStore::new();
+        let mut key = crate::mock::test_ca_key();
+        key.hash = Some([0; 20]);
+        assert!(store.insert(key.clone()).is_err());
+        key.hash = Some(key.compute_hash());
+        assert!(store.insert(key).is_ok());
+        assert!(store.get(&[0xa0, 0x00, 0x00, 0x00, 0x03], 0x92).is_some());
+        assert!(store.get(&[0xa0, 0x00, 0x00, 0x00, 0x04], 0x92).is_none());
+    }
+
+    #[cfg(feature = "test-capks")]
+    #[test]
+    fn loads_built_in_test_keys() {
+        let store = crate::capk::CapkStore::with_defaults();
+        assert_eq!(store.len(), 10);
+        assert!(store.iter().all(|k| k.hash == Some(k.compute_hash())));
+    }
+
+    #[test]
+    fn imports_ca_keys() {
+        let key = crate::mock::test_ca_key();
+        let modulus = crate::hex::encode(&key.modulus);
+        let hash = crate::hex::encode(&key.compute_hash());
+
+        let xml = format!("<CAPKs><CAPK Index=\"92\"><RID>A000000003</RID><Modulus>{}</Modulus>\
+            <Exponent>03</Exponent><Hash>{}</Hash><ExpiryDate>311230</ExpiryDate></CAPK></CAPKs>", modulus, hash);
+        let keys = crate::capk::parse_xml(&xml).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].modulus, key.modulus);
+        assert_eq!(keys[0].expiry, chrono::NaiveDate::from_ymd_opt(2030, 12, 31));
+
+        let json = format!("{{\"keys\": [{{\"rid\": \"A000000003\", \"index\": \"92\", \"modulus\": \"{}\", \"exponent\": \"03\", \"hash\": \"{}\"}}]}}", modulus, hash);
+        let keys = crate::capk::parse_json(&json).unwrap();
+        assert_eq!((keys[0].rid, keys[0].index), ([0xa0, 0x00, 0x00, 0x00, 0x03], 0x92));
+
+        let csv = format!("# Test keys\nRID,Key Index,Exponent,Modulus,Checksum\nA000000003,92,03,{},{}\n", modulus, hash);
+        let keys = crate::capk::parse_csv(&csv).unwrap();
+        assert_eq!(keys[0].exponent, vec![0x03]);
+
+        // A key that doesn't match its check hash is refused
+        let csv = format!("A000000003,92,{},03,{}", modulus, "00".repeat(20));
+        assert!(crate::capk::parse_csv(&csv).is_err());
+    }
+}
@@ -1,7 +1,13 @@
 use std::convert::TryFrom;
+#[cfg(feature = "pcsc")]
+use std::cell::{Ref, RefCell};
+use crate::transport::{CardTransport, TransportError};
+#[cfg(feature = "pcsc")]
+use std::ffi::{CStr, CString};
 
-pub fn find_reader(ctx: &pcsc::Context) -> Result<std::ffi::CString, pcsc::Error> {
-    println!("Looking for card, insert one now...");
+#[cfg(feature = "pcsc")]
+pub fn find_reader(ctx: &pcsc::Context) -> Result<CString, pcsc::Error> {
+    tracing::info!("Looking for card, insert one now...");
 
     let mut readers_buf = [0; 2048];
     let mut reader_states = vec![
@@ -30,11 +36,9 @@ pub fn find_reader(ctx: &pcsc::Context) -> Result<std::ffi::CString, pcsc::Error
 
         if !first_iter {
             for rs in &reader_states {
-                if rs.name() != pcsc::PNP_NOTIFICATION() {
-                    if rs.event_state().contains(pcsc::State::CHANGED | pcsc::State::PRESENT) {
-                        println!("Found card in reader {:?}", rs.name());
-                        return Ok(rs.name().into());
-                    }
+                if rs.name() != pcsc::PNP_NOTIFICATION() && rs.event_state().contains(pcsc::State::CHANGED | pcsc::State::PRESENT) {
+                    tracing::info!("Found card in reader {:?}", rs.name());
+                    return Ok(rs.name().into());
                 }
             }
         }
@@ -42,15 +46,533 @@ pub fn find_reader(ctx: &pcsc::Context) -> Result<std::ffi::CString, pcsc::Error
     }
 }
 
-pub fn card_read_record(card: &pcsc::Card, short_file_identifier: u8, record_number: u8) -> Result<crate::tlv::TagList, pcsc::Error> {
-    let apdu_cmd = crate::apdu::ApduCommand::new(0x00,0xb2,record_number, (short_file_identifier & 0b00011111) << 3 | 0b00000100, &[], 0);
+#[cfg(feature = "pcsc")]
+pub struct ReaderInfo {
+    name: CString,
+    state: pcsc::State,
+}
+
+#[cfg(feature = "pcsc")]
+impl ReaderInfo {
+    pub fn name(&self) -> &CStr {
+        &self.name
+    }
+
+    pub fn card_present(&self) -> bool {
+        self.state.contains(pcsc::State::PRESENT)
+    }
+
+    pub fn describe_state(&self) -> &'static str {
+        if self.state.contains(pcsc::State::UNAVAILABLE) {
+            "unavailable"
+        } else if self.state.contains(pcsc::State::EXCLUSIVE) || self.state.contains(pcsc::State::INUSE) {
+            "card in use"
+        } else if self.state.contains(pcsc::State::MUTE) {
+            "unresponsive card"
+        } else if self.card_present() {
+            "card present"
+        } else {
+            "empty"
+        }
+    }
+}
+
+#[cfg(feature = "pcsc")]
+pub fn list_readers(ctx: &pcsc::Context) -> Result<Vec<ReaderInfo>, pcsc::Error> {
+    let mut readers_buf = [0; 2048];
+    let names = match ctx.list_readers(&mut readers_buf) {
+        Ok(n) => n,
+        Err(pcsc::Error::NoReadersAvailable) => return Ok(vec![]),
+        Err(e) => return Err(e)
+    };
+    let mut reader_states: Vec<pcsc::ReaderState> = names
+        .map(|n| pcsc::ReaderState::new(n, pcsc::State::UNAWARE))
+        .collect();
+    if reader_states.is_empty() {
+        return Ok(vec![]);
+    }
+
+    ctx.get_status_change(std::time::Duration::from_secs(0), &mut reader_states)?;
+
+    Ok(reader_states.iter().map(|rs| ReaderInfo {
+        name: rs.name().into(),
+        state: rs.event_state(),
+    }).collect())
+}
+
+/// A reader by its name, or by its position in the list of readers
+#[cfg(feature = "pcsc")]
+pub enum ReaderSelector {
+    Name(String),
+    Index(usize),
+}
+
+#[cfg(feature = "pcsc")]
+impl From<&str> for ReaderSelector {
+    fn from(value: &str) -> Self {
+        match value.parse::<usize>() {
+            Ok(i) => ReaderSelector::Index(i),
+            Err(_) => ReaderSelector::Name(value.to_string())
+        }
+    }
+}
+
+#[cfg(feature = "pcsc")]
+pub fn resolve_reader(ctx: &pcsc::Context, selector: &ReaderSelector) -> Result<CString, pcsc::Error> {
+    let readers = list_readers(ctx)?;
+    match selector {
+        ReaderSelector::Index(i) => match readers.get(*i) {
+            Some(r) => Ok(r.name.clone()),
+            None => Err(pcsc::Error::UnknownReader)
+        },
+        ReaderSelector::Name(n) => {
+            if let Some(r) = readers.iter().find(|r| r.name.to_string_lossy() == n.as_str()) {
+                return Ok(r.name.clone());
+            }
+            let n = n.to_lowercase();
+            let matches: Vec<&ReaderInfo> = readers.iter()
+                .filter(|r| r.name.to_string_lossy().to_lowercase().contains(&n))
+                .collect();
+            match matches.len() {
+                1 => Ok(matches[0].name.clone()),
+                _ => Err(pcsc::Error::UnknownReader)
+            }
+        }
+    }
+}
+
+/// Lists every reader with its state and asks which one to use. Returns `None`
+/// when there is no choice to make, i.e. there are fewer than two readers.
+#[cfg(feature = "pcsc")]
+pub fn choose_reader(ctx: &pcsc::Context) -> Result<Option<CString>, pcsc::Error> {
+    let readers = list_readers(ctx)?;
+    if readers.len() < 2 {
+        return Ok(None);
+    }
+
+    println!("Available readers:");
+    for (i, r) in readers.iter().enumerate() {
+        println!("  [{}] {} ({})", i, r.name.to_string_lossy(), r.describe_state());
+    }
+
+    loop {
+        let i = crate::util::get_input::<usize>("Select reader: ");
+        if let Some(r) = readers.get(i) {
+            return Ok(Some(r.name.clone()));
+        }
+    }
+}
+
+#[cfg(feature = "pcsc")]
+pub fn wait_for_card(ctx: &pcsc::Context, reader: &CStr) -> Result<(), pcsc::Error> {
+    let mut reader_states = [pcsc::ReaderState::new(reader.to_owned(), pcsc::State::UNAWARE)];
+    let mut prompted = false;
+
+    loop {
+        ctx.get_status_change(None, &mut reader_states)?;
+        let state = reader_states[0].event_state();
+        if state.intersects(pcsc::State::UNKNOWN | pcsc::State::IGNORE) {
+            return Err(pcsc::Error::UnknownReader);
+        }
+        if state.contains(pcsc::State::PRESENT) && !state.contains(pcsc::State::MUTE) {
+            tracing::info!("Found card in reader {:?}", reader);
+            return Ok(());
+        }
+        if !prompted {
+            tracing::info!("Waiting for card in {:?}, insert one now...", reader);
+            prompted = true;
+        }
+        reader_states[0].sync_current_state();
+    }
+}
+
+/// Watches every reader at once, readers plugged in later included, and
+/// connects to each card presented, or there already, handing the session to
+/// `read` on a thread of its own. Cards in different readers are read side by side, such as the
+/// same card on a contact and a contactless reader; a reader is left alone
+/// until `read` is done with its card. Runs until cancelled, waiting for
+/// every session to finish before returning.
+#[cfg(feature = "pcsc")]
+pub fn monitor_readers<F>(ctx: &pcsc::Context, options: ConnectOptions, canceller: &Canceller, read: F) -> Result<(), pcsc::Error>
+    where F: Fn(Session) + Sync {
+    let mut readers_buf = [0; 2048];
+    let mut reader_states = vec![
+        pcsc::ReaderState::new(pcsc::PNP_NOTIFICATION(), pcsc::State::UNAWARE),
+    ];
+    let read = &read;
+
+    std::thread::scope(|scope| {
+        let mut sessions: Vec<(CString, std::thread::ScopedJoinHandle<()>)> = vec![];
+        loop {
+            reader_states.retain(|rs| !rs.event_state().intersects(pcsc::State::UNKNOWN | pcsc::State::IGNORE));
+            match ctx.list_readers(&mut readers_buf) {
+                Ok(names) => for name in names {
+                    if !reader_states.iter().any(|rs| rs.name() == name) {
+                        reader_states.push(pcsc::ReaderState::new(name, pcsc::State::UNAWARE));
+                    }
+                },
+                Err(pcsc::Error::NoReadersAvailable) => {}
+                Err(e) => return Err(e)
+            }
+            for rs in &mut reader_states {
+                rs.sync_current_state();
+            }
+
+            match ctx.get_status_change(None, &mut reader_states) {
+                Ok(()) => {}
+                Err(pcsc::Error::Cancelled) if canceller.is_cancelled() => return Ok(()),
+                Err(e) => return Err(e)
+            }
+            if canceller.is_cancelled() {
+                return Ok(());
+            }
+
+            sessions.retain(|(_, s)| !s.is_finished());
+            for rs in &reader_states {
+                let state = rs.event_state();
+                if rs.name() == pcsc::PNP_NOTIFICATION() || !state.contains(pcsc::State::CHANGED | pcsc::State::PRESENT)
+                    || state.contains(pcsc::State::MUTE) || sessions.iter().any(|(r, _)| r.as_c_str() == rs.name()) {
+                    continue;
+                }
+                let reader = rs.name().to_owned();
+                tracing::info!("Found card in reader {:?}", reader);
+                let mut session = match Session::connect(ctx, &reader, options) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        tracing::warn!("Unable to connect to the card in {:?}: {}", reader, e);
+                        continue;
+                    }
+                };
+                session.cancel_with(canceller);
+                let handle = scope.spawn(move || {
+                    let _span = tracing::info_span!("reader", name = %session.reader().to_string_lossy()).entered();
+                    read(session)
+                });
+                sessions.push((reader, handle));
+            }
+        }
+    })
+}
+
+/// Stops a wait for a card, or a transaction with one, from elsewhere, such as
+/// on Ctrl-C. Clones all stop the same things.
+#[cfg(feature = "pcsc")]
+#[derive(Clone, Default)]
+pub struct Canceller {
+    cancelled: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    /// Contexts whose waits for a card to break out of
+    contexts: std::sync::Arc<std::sync::Mutex<Vec<pcsc::Context>>>,
+}
+
+#[cfg(feature = "pcsc")]
+impl Canceller {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Has waits on the context give up with `Cancelled` when cancelled
+    pub fn watch(&self, ctx: &pcsc::Context) {
+        self.contexts.lock().unwrap().push(ctx.clone());
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, std::sync::atomic::Ordering::SeqCst);
+        for ctx in self.contexts.lock().unwrap().iter() {
+            if let Err(e) = ctx.cancel() {
+                tracing::warn!("Unable to cancel waiting for a card: {}", e);
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Lets the next wait or transaction go ahead
+    pub fn reset(&self) {
+        self.cancelled.store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "pcsc")]
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectOptions {
+    pub share_mode: pcsc::ShareMode,
+    pub protocols: pcsc::Protocols,
+    pub disposition: pcsc::Disposition,
+}
+
+#[cfg(feature = "pcsc")]
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            share_mode: pcsc::ShareMode::Exclusive,
+            protocols: pcsc::Protocols::ANY,
+            disposition: pcsc::Disposition::ResetCard,
+        }
+    }
+}
+
+/// A connection to the card in a reader. What the card answers to SELECT and
+/// READ RECORD is kept in a [`CardCache`](crate::transport::CardCache) for as
+/// long as the session lasts.
+#[cfg(feature = "pcsc")]
+pub struct Session {
+    ctx: pcsc::Context,
+    reader: CString,
+    card: RefCell<pcsc::Card>,
+    options: ConnectOptions,
+    canceller: Option<Canceller>,
+    buffers: RefCell<crate::apdu::ApduBuffers>,
+    cache: RefCell<crate::transport::CardCache>,
+}
+
+#[cfg(feature = "pcsc")]
+impl Session {
+    pub fn connect(ctx: &pcsc::Context, reader: &CStr, options: ConnectOptions) -> Result<Self, pcsc::Error> {
+        let card = ctx.connect(reader, options.share_mode, options.protocols)?;
+        Ok(Self {
+            ctx: ctx.clone(),
+            reader: reader.to_owned(),
+            card: RefCell::new(card),
+            options,
+            canceller: None,
+            buffers: RefCell::default(),
+            cache: RefCell::default(),
+        })
+    }
+
+    /// Has every exchange with the card fail with `Cancelled` once cancelled
+    pub fn cancel_with(&mut self, canceller: &Canceller) {
+        self.canceller = Some(canceller.clone());
+    }
+
+    fn cancelled(&self) -> bool {
+        self.canceller.as_ref().is_some_and(Canceller::is_cancelled)
+    }
+
+    /// Ends the session, leaving the card in the state chosen when connecting
+    pub fn disconnect(self) -> Result<(), pcsc::Error> {
+        let disposition = self.options.disposition;
+        self.disconnect_with(disposition)
+    }
+
+    pub fn disconnect_with(self, disposition: pcsc::Disposition) -> Result<(), pcsc::Error> {
+        self.card.into_inner().disconnect(disposition).map_err(|(_, e)| e)
+    }
+
+    pub fn reader(&self) -> &CStr {
+        &self.reader
+    }
+
+    pub fn card(&self) -> Ref<'_, pcsc::Card> {
+        self.card.borrow()
+    }
+
+    /// Polls the reader without blocking to see if the card is still there
+    pub fn card_present(&self) -> bool {
+        let mut reader_states = [pcsc::ReaderState::new(self.reader.clone(), pcsc::State::UNAWARE)];
+        match self.ctx.get_status_change(std::time::Duration::from_secs(0), &mut reader_states) {
+            Ok(()) => {
+                let state = reader_states[0].event_state();
+                state.contains(pcsc::State::PRESENT) && !state.contains(pcsc::State::MUTE)
+            }
+            Err(pcsc::Error::Timeout) => true,
+            Err(_) => false
+        }
+    }
+}
+
+#[cfg(feature = "pcsc")]
+impl CardTransport for Session {
+    /// Sends raw bytes to the card. Only once an exchange fails is the reader asked
+    /// whether the card is still there, reporting `RemovedCard` if it has left
+    /// rather than whatever error the exchange happened to fail with
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        if self.cancelled() {
+            return Err(TransportError::Cancelled);
+        }
+
+        // Anything already read from this card in this session comes from the
+        // cache, even across a transaction after reading it
+        self.cache.borrow_mut().exchange(send_buffer, receive_buffer, |send_buffer, receive_buffer| {
+            match self.card.borrow().transmit(send_buffer, receive_buffer) {
+                Ok(r) => Ok(r),
+                Err(pcsc::Error::RemovedCard) | Err(pcsc::Error::NoSmartcard) => Err(TransportError::RemovedCard),
+                Err(e) => {
+                    if self.card_present() {
+                        Err(e.into())
+                    } else {
+                        Err(TransportError::RemovedCard)
+                    }
+                }
+            }
+        })
+    }
+
+    /// Re-establishes the connection after the card has been reset underneath us.
+    /// Any selected application is lost, so callers need to select it again.
+    fn reconnect(&mut self) -> Result<(), TransportError> {
+        if self.cancelled() {
+            return Err(TransportError::Cancelled);
+        }
+        if !self.card_present() {
+            return Err(TransportError::RemovedCard);
+        }
+        self.cache.get_mut().deselect();
+        Ok(self.card.get_mut().reconnect(self.options.share_mode, self.options.protocols, pcsc::Disposition::LeaveCard)?)
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, TransportError> {
+        let mut atr_buf = [0; pcsc::MAX_ATR_SIZE];
+        let card = self.card.borrow();
+        let atr = card.get_attribute(pcsc::Attribute::AtrString, &mut atr_buf)?;
+        crate::atr::Atr::try_from(atr).map_err(|_| TransportError::InvalidAtr)
+    }
+
+    fn reader_name(&self) -> Option<String> {
+        Some(self.reader.to_string_lossy().into_owned())
+    }
+
+    /// Sends a reader control command, e.g. for CCID escape or pinpad features
+    fn control<'buf>(&self, control_code: u32, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        Ok(self.card.borrow().control(control_code as _, send_buffer, receive_buffer)?)
+    }
+
+    fn buffers(&self) -> Option<std::cell::RefMut<'_, crate::apdu::ApduBuffers>> {
+        self.buffers.try_borrow_mut().ok()
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Interface {
+    Contact,
+    Contactless,
+}
+
+impl Interface {
+    /// The directory listing applications over this interface, PSE or PPSE
+    pub fn directory_name(&self) -> &'static [u8] {
+        match self {
+            Interface::Contact => b"1PAY.SYS.DDF01",
+            Interface::Contactless => b"2PAY.SYS.DDF01",
+        }
+    }
+
+    /// Kernel Identifiers the terminal will activate over this interface
+    pub fn allowed_kernels(&self) -> &'static [u8] {
+        match self {
+            Interface::Contact => &[],
+            Interface::Contactless => &[2, 3],
+        }
+    }
+}
+
+impl std::fmt::Display for Interface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interface::Contact => write!(f, "contact"),
+            Interface::Contactless => write!(f, "contactless"),
+        }
+    }
+}
+
+fn is_contactless_reader_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("contactless") || name.contains("picc") || name.contains(" cl ")
+        || name.contains("-cl ") || name.ends_with(" cl")
+}
+
+/// Works out which interface the card is being talked to over, trusting the
+/// ATR over the reader name as dual-interface readers name both slots alike
+pub fn detect_interface<T: CardTransport + ?Sized>(card: &T) -> Interface {
+    if let Ok(atr) = card.atr() {
+        if atr.is_contactless() {
+            return Interface::Contactless;
+        }
+    }
+    match card.reader_name() {
+        Some(n) if is_contactless_reader_name(&n) => Interface::Contactless,
+        _ => Interface::Contact
+    }
+}
+
+/// Why a command to the card failed or its response couldn't be used
+#[derive(Debug, thiserror::Error)]
+pub enum CardError {
+    #[error(transparent)]
+    Apdu(#[from] crate::apdu::ApduError),
+    #[error("Invalid response from the card: {0}")]
+    Tlv(#[from] crate::tlv::TlvError),
+    #[error("Invalid response from the reader's pinpad")]
+    InvalidPinpadResponse,
+}
+
+impl From<TransportError> for CardError {
+    fn from(value: TransportError) -> Self {
+        CardError::Apdu(crate::apdu::ApduError::Transport(value))
+    }
+}
+
+#[cfg(feature = "pcsc")]
+impl From<pcsc::Error> for CardError {
+    fn from(value: pcsc::Error) -> Self {
+        TransportError::from(value).into()
+    }
+}
+
+impl CardError {
+    /// The transport error underneath, if it was the reader that failed
+    pub fn transport(&self) -> Option<&TransportError> {
+        match self {
+            CardError::Apdu(crate::apdu::ApduError::Transport(e)) => Some(e),
+            _ => None
+        }
+    }
+}
+
+pub fn is_card_removed(err: &CardError) -> bool {
+    matches!(err.transport(), Some(TransportError::RemovedCard))
+}
+
+pub fn is_card_reset(err: &CardError) -> bool {
+    matches!(err.transport(), Some(TransportError::ResetCard))
+}
+
+/// Whether the exchange was stopped by a [`Canceller`]
+pub fn is_cancelled(err: &CardError) -> bool {
+    matches!(err.transport(), Some(TransportError::Cancelled))
+}
+
+/// Turns a failed command into `None` unless the failure means the session
+/// itself is gone, in which case it is passed on
+pub fn optional<T>(result: Result<T, CardError>) -> Result<Option<T>, CardError> {
+    match result {
+        Ok(r) => Ok(Some(r)),
+        Err(e) if is_card_removed(&e) || is_card_reset(&e) || is_cancelled(&e) => Err(e),
+        Err(_) => Ok(None)
+    }
+}
+
+/// P2 of READ RECORD for a record in the file with the SFI
+fn record_reference(short_file_identifier: u8) -> u8 {
+    (short_file_identifier & 0b00011111) << 3 | 0b00000100
+}
+
+pub fn card_read_record_raw<T: CardTransport + ?Sized>(card: &T, short_file_identifier: u8, record_number: u8) -> Result<Vec<u8>, CardError> {
+    let apdu_cmd = crate::apdu::ApduCommand::new(0x00,0xb2,record_number, record_reference(short_file_identifier), &[], 0);
 
     let data = crate::apdu::send_apdu(card, &apdu_cmd)?;
-    let tag_list = crate::tlv::TagList::try_from(data.data())?;
+    Ok(data.into_data())
+}
+
+pub fn card_read_record<T: CardTransport + ?Sized>(card: &T, short_file_identifier: u8, record_number: u8) -> Result<crate::tlv::TagList, CardError> {
+    let data = card_read_record_raw(card, short_file_identifier, record_number)?;
+    let tag_list = crate::tlv::TagList::try_from(data.as_slice())?;
     Ok(tag_list)
 }
 
-pub fn card_select(card: &pcsc::Card, file_name: &[u8], next: bool) -> Result<crate::tlv::TagList, pcsc::Error> {
+pub fn card_select_raw<T: CardTransport + ?Sized>(card: &T, file_name: &[u8], next: bool) -> Result<Vec<u8>, CardError> {
     let mut param2 = 0;
     if next {
         param2 |= 0b10;
@@ -59,16 +581,195 @@ pub fn card_select(card: &pcsc::Card, file_name: &[u8], next: bool) -> Result<cr
     let apdu_cmd = crate::apdu::ApduCommand::new( 0x00, 0xa4,0b00000100, param2,file_name, 0);
 
     let data = crate::apdu::send_apdu(card, &apdu_cmd)?;
+    Ok(data.data().to_vec())
+}
 
-    let tag_list = crate::tlv::TagList::try_from(data.data())?;
+/// SELECT that hands back the status as well, as a blocked application still
+/// answers with its FCI but with a 6283 warning
+pub fn card_select_response<T: CardTransport + ?Sized>(card: &T, file_name: &[u8], next: bool) -> Result<crate::apdu::ApduResponse, CardError> {
+    let param2 = if next { 0b10 } else { 0 };
+    let apdu_cmd = crate::apdu::ApduCommand::new( 0x00, 0xa4,0b00000100, param2,file_name, 0);
+
+    Ok(crate::apdu::exchange_apdu(card, &apdu_cmd)?)
+}
+
+pub fn card_select<T: CardTransport + ?Sized>(card: &T, file_name: &[u8], next: bool) -> Result<crate::tlv::TagList, CardError> {
+    let data = card_select_raw(card, file_name, next)?;
+
+    let tag_list = crate::tlv::TagList::try_from(data.as_slice())?;
     Ok(tag_list)
 }
 
-pub fn card_get_processing_options(card: &pcsc::Card, pdol: &[u8]) -> Result<crate::tlv::TagList, pcsc::Error> {
+/// Wraps filled in PDOL data in the command template expected by GET PROCESSING OPTIONS
+pub fn gpo_command_data(pdol: crate::tlv::DOL) -> Vec<u8> {
+    let pdol_bytes: Vec<u8> = pdol.into();
+    let mut pdol_tlv = crate::tlv::TagList::new();
+    pdol_tlv.add_tag(crate::tlv::Tag::new(crate::tlv::TagID::CommandTemplate, crate::tlv::TagContents::Bytes(pdol_bytes.into())));
+    Vec::<u8>::from(&pdol_tlv)
+}
+
+pub fn card_get_processing_options_raw<T: CardTransport + ?Sized>(card: &T, pdol: &[u8]) -> Result<Vec<u8>, CardError> {
     let apdu_cmd = crate::apdu::ApduCommand::new( 0x80, 0xa8,0x00, 0x00,pdol, 0);
 
     let data = crate::apdu::send_apdu(card, &apdu_cmd)?;
+    Ok(data.data().to_vec())
+}
+
+pub fn card_get_processing_options<T: CardTransport + ?Sized>(card: &T, pdol: &[u8]) -> Result<crate::tlv::TagList, CardError> {
+    let data = card_get_processing_options_raw(card, pdol)?;
+
+    let tag_list = crate::tlv::TagList::try_from(data.as_slice())?;
+    Ok(tag_list)
+}
+
+/// GENERATE AC, `reference_control` being P1 with the requested cryptogram type
+pub fn card_generate_ac_raw<T: CardTransport + ?Sized>(card: &T, reference_control: u8, cdol: &[u8]) -> Result<Vec<u8>, CardError> {
+    let apdu_cmd = crate::apdu::ApduCommand::new( 0x80, 0xae,reference_control, 0x00,cdol, 0);
+
+    let data = crate::apdu::send_apdu(card, &apdu_cmd)?;
+    Ok(data.data().to_vec())
+}
+
+pub fn card_generate_ac<T: CardTransport + ?Sized>(card: &T, reference_control: u8, cdol: &[u8]) -> Result<crate::tlv::TagList, CardError> {
+    let data = card_generate_ac_raw(card, reference_control, cdol)?;
+
+    let tag_list = crate::tlv::TagList::try_from(data.as_slice())?;
+    Ok(tag_list)
+}
+
+pub fn card_get_data_raw<T: CardTransport + ?Sized>(card: &T, tag: u16) -> Result<Vec<u8>, CardError> {
+    let [param1, param2] = tag.to_be_bytes();
+    let apdu_cmd = crate::apdu::ApduCommand::new( 0x80, 0xca,param1, param2,&[], 0);
+
+    let data = crate::apdu::send_apdu(card, &apdu_cmd)?;
+    Ok(data.data().to_vec())
+}
+
+pub fn card_get_data<T: CardTransport + ?Sized>(card: &T, tag: u16) -> Result<crate::tlv::TagList, CardError> {
+    let data = card_get_data_raw(card, tag)?;
+
+    let tag_list = crate::tlv::TagList::try_from(data.as_slice())?;
+    Ok(tag_list)
+}
+
+/// VERIFY qualifiers for the two ways of sending an offline PIN
+pub const VERIFY_PLAINTEXT_PIN: u8 = 0x80;
+pub const VERIFY_ENCIPHERED_PIN: u8 = 0x88;
+
+pub fn card_verify<T: CardTransport + ?Sized>(card: &T, qualifier: u8, pin_block: &[u8]) -> Result<crate::apdu::ApduResponse, CardError> {
+    let apdu_cmd = crate::apdu::ApduCommand::new( 0x00, 0x20,0x00, qualifier,pin_block, 0);
+
+    Ok(crate::apdu::exchange_apdu(card, &apdu_cmd)?)
+}
+
+pub fn card_external_authenticate<T: CardTransport + ?Sized>(card: &T, issuer_authentication_data: &[u8]) -> Result<crate::apdu::ApduResponse, CardError> {
+    let apdu_cmd = crate::apdu::ApduCommand::new( 0x00, 0x82,0x00, 0x00,issuer_authentication_data, 0);
+
+    Ok(crate::apdu::exchange_apdu(card, &apdu_cmd)?)
+}
 
+/// COMPUTE CRYPTOGRAPHIC CHECKSUM, generating the CVC3 for Mastercard's
+/// mag-stripe mode over the UDOL data
+pub fn card_compute_cryptographic_checksum<T: CardTransport + ?Sized>(card: &T, udol: &[u8]) -> Result<crate::tlv::TagList, CardError> {
+    let apdu_cmd = crate::apdu::ApduCommand::new( 0x80, 0x2a,0x8e, 0x80,udol, 0);
+
+    let data = crate::apdu::send_apdu(card, &apdu_cmd)?;
     let tag_list = crate::tlv::TagList::try_from(data.data())?;
     Ok(tag_list)
-}
\ No newline at end of file
+}
+
+/// Sends a command from an issuer script, already checked to be a well formed C-APDU
+pub fn card_issuer_script_command<T: CardTransport + ?Sized>(card: &T, command: &[u8]) -> Result<crate::apdu::ApduResponse, CardError> {
+    let (data, length_expected) = match command.len() {
+        4 => (&[][..], 0),
+        5 => (&[][..], command[4]),
+        _ => {
+            let data = &command[5..5 + command[4] as usize];
+            (data, command.get(5 + data.len()).cloned().unwrap_or(0))
+        }
+    };
+    let apdu_cmd = crate::apdu::ApduCommand::new( command[0], command[1],command[2], command[3],data, length_expected);
+
+    Ok(crate::apdu::exchange_apdu(card, &apdu_cmd)?)
+}
+
+pub fn card_get_challenge<T: CardTransport + ?Sized>(card: &T) -> Result<Vec<u8>, CardError> {
+    let apdu_cmd = crate::apdu::ApduCommand::new( 0x00, 0x84,0x00, 0x00,&[], 0);
+
+    let data = crate::apdu::send_apdu(card, &apdu_cmd)?;
+    Ok(data.data().to_vec())
+}
+
+/// A record from [`read_records_bulk`] with the status the card answered
+/// READ RECORD with, its data only there if that was 9000
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordResponse {
+    pub sfi: u8,
+    pub record: u8,
+    /// Whether the AFL has the record included in offline data authentication
+    pub for_oda: bool,
+    pub sw1: u8,
+    pub sw2: u8,
+    pub data: Vec<u8>,
+}
+
+impl RecordResponse {
+    pub fn is_ok(&self) -> bool {
+        (self.sw1, self.sw2) == (0x90, 0x00)
+    }
+}
+
+/// Sends READ RECORD for every record the AFL lists in turn, the command only
+/// built once and its P1 and P2 changed from one record to the next, handing
+/// each response to `each`
+fn read_afl<T, F>(card: &T, afl: &[crate::data::AflEntry], mut each: F) -> Result<(), CardError>
+    where T: CardTransport + ?Sized, F: FnMut(&crate::data::AflEntry, u8, crate::apdu::ApduResponse) -> Result<(), CardError> {
+    let mut apdu_cmd = crate::apdu::ApduCommand::new(0x00, 0xb2, 0, 0, &[], 0);
+
+    for entry in afl {
+        for record in entry.records() {
+            apdu_cmd.set_params(record, record_reference(entry.short_file_identifier));
+            each(entry, record, crate::apdu::exchange_apdu(card, &apdu_cmd)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads every record the AFL lists in one go, giving each with the status
+/// the card answered. Only the card going away, being reset or the read
+/// being cancelled stop it early; records the card won't give are there
+/// with the status it gave instead.
+pub fn read_records_bulk<T: CardTransport + ?Sized>(card: &T, afl: &[crate::data::AflEntry]) -> Result<Vec<RecordResponse>, CardError> {
+    let mut out = Vec::with_capacity(afl.iter().map(|e| e.records().len()).sum());
+
+    read_afl(card, afl, |entry, record, response| {
+        let (sw1, sw2) = (*response.status().0, *response.status().1);
+        out.push(RecordResponse {
+            sfi: entry.short_file_identifier,
+            record,
+            for_oda: record - entry.first_record < entry.oda_records,
+            sw1,
+            sw2,
+            data: response.into_data(),
+        });
+        Ok(())
+    })?;
+
+    Ok(out)
+}
+
+/// Reads every record the AFL lists, keeping track of the data covered by
+/// offline data authentication along the way
+pub fn card_read_application_records<T: CardTransport + ?Sized>(card: &T, afl: &[crate::data::AflEntry]) -> Result<crate::data::ApplicationRecords, CardError> {
+    let mut out = crate::data::ApplicationRecords::default();
+
+    read_afl(card, afl, |entry, record, response| {
+        let data = response.check()?.into_data();
+        let for_oda = record - entry.first_record < entry.oda_records;
+        out.add_record(entry.short_file_identifier, &data, for_oda)?;
+        Ok(())
+    })?;
+
+    Ok(out)
+}
@@ -0,0 +1,397 @@
+use std::convert::TryFrom;
+use crate::terminal;
+use crate::transport::CardTransport;
+
+pub const METHOD_FAIL_CVM: u8 = 0x00;
+pub const METHOD_PLAINTEXT_PIN: u8 = 0x01;
+pub const METHOD_ONLINE_PIN: u8 = 0x02;
+pub const METHOD_PLAINTEXT_PIN_AND_SIGNATURE: u8 = 0x03;
+pub const METHOD_ENCIPHERED_PIN: u8 = 0x04;
+pub const METHOD_ENCIPHERED_PIN_AND_SIGNATURE: u8 = 0x05;
+pub const METHOD_SIGNATURE: u8 = 0x1e;
+pub const METHOD_NO_CVM_REQUIRED: u8 = 0x1f;
+
+const CONDITION_ALWAYS: u8 = 0x00;
+const CONDITION_UNATTENDED_CASH: u8 = 0x01;
+const CONDITION_NOT_CASH: u8 = 0x02;
+const CONDITION_TERMINAL_SUPPORTS: u8 = 0x03;
+const CONDITION_MANUAL_CASH: u8 = 0x04;
+const CONDITION_CASHBACK: u8 = 0x05;
+const CONDITION_UNDER_X: u8 = 0x06;
+const CONDITION_OVER_X: u8 = 0x07;
+const CONDITION_UNDER_Y: u8 = 0x08;
+const CONDITION_OVER_Y: u8 = 0x09;
+
+const FAIL_CASCADE: u8 = 0x40;
+
+pub const RESULT_UNKNOWN: u8 = 0x00;
+pub const RESULT_FAILED: u8 = 0x01;
+pub const RESULT_SUCCESSFUL: u8 = 0x02;
+
+fn method_name(method: u8) -> &'static str {
+    match method {
+        METHOD_FAIL_CVM => "Fail CVM processing",
+        METHOD_PLAINTEXT_PIN => "Plaintext PIN verified by the card",
+        METHOD_ONLINE_PIN => "Enciphered PIN verified online",
+        METHOD_PLAINTEXT_PIN_AND_SIGNATURE => "Plaintext PIN verified by the card and signature",
+        METHOD_ENCIPHERED_PIN => "Enciphered PIN verified by the card",
+        METHOD_ENCIPHERED_PIN_AND_SIGNATURE => "Enciphered PIN verified by the card and signature",
+        METHOD_SIGNATURE => "Signature",
+        METHOD_NO_CVM_REQUIRED => "No CVM required",
+        0x3f => "No CVM performed",
+        _ => "Unknown method"
+    }
+}
+
+fn condition_name(condition: u8) -> &'static str {
+    match condition {
+        CONDITION_ALWAYS => "always",
+        CONDITION_UNATTENDED_CASH => "if unattended cash",
+        CONDITION_NOT_CASH => "if not cash or cashback",
+        CONDITION_TERMINAL_SUPPORTS => "if the terminal supports the CVM",
+        CONDITION_MANUAL_CASH => "if manual cash",
+        CONDITION_CASHBACK => "if cashback",
+        CONDITION_UNDER_X => "if under X",
+        CONDITION_OVER_X => "if over X",
+        CONDITION_UNDER_Y => "if under Y",
+        CONDITION_OVER_Y => "if over Y",
+        _ => "on an unknown condition"
+    }
+}
+
+/// Spells out the CVM Results: the method performed, the condition of the
+/// rule it came from, and how it went
+pub fn describe_cvm_results(results: &[u8]) -> String {
+    let (code, condition, result) = match results {
+        [c, d, r] => (*c, *d, *r),
+        _ => return "Invalid CVM results".to_string()
+    };
+    let result = match result {
+        RESULT_UNKNOWN => "result unknown",
+        RESULT_FAILED => "failed",
+        RESULT_SUCCESSFUL => "successful",
+        _ => "unknown result"
+    };
+    format!("{} ({}), {}", method_name(code & 0x3f), condition_name(condition), result)
+}
+
+/// One Cardholder Verification Rule: a method and when to use it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CvmRule {
+    pub code: u8,
+    pub condition: u8,
+}
+
+impl CvmRule {
+    pub fn method(&self) -> u8 {
+        self.code & 0x3f
+    }
+
+    /// Whether the next rule should be tried if this one fails
+    pub fn fail_cascade(&self) -> bool {
+        self.code & FAIL_CASCADE != 0
+    }
+}
+
+/// The CVM List (8E): the amounts conditions can refer to, then the rules in
+/// the order the issuer wants them tried
+#[derive(Debug, Clone)]
+pub struct CvmList {
+    pub amount_x: u32,
+    pub amount_y: u32,
+    pub rules: Vec<CvmRule>,
+}
+
+impl TryFrom<&[u8]> for CvmList {
+    type Error = &'static str;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() < 8 || !value.len().is_multiple_of(2) {
+            return Err("Invalid CVM list length");
+        }
+        Ok(CvmList {
+            amount_x: u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+            amount_y: u32::from_be_bytes([value[4], value[5], value[6], value[7]]),
+            rules: value[8..].chunks_exact(2).map(|r| CvmRule {
+                code: r[0],
+                condition: r[1],
+            }).collect(),
+        })
+    }
+}
+
+/// Where the cardholder's PIN comes from
+#[derive(Debug, Clone)]
+pub enum PinEntry {
+    /// Ask for it, on the reader's pinpad if it has one
+    Prompt,
+    /// Use this PIN without asking, for scripted runs
+    Fixed(String),
+    /// Nobody is there to enter a PIN, so any PIN CVM is bypassed
+    Bypass,
+}
+
+/// What cardholder verification left for the rest of the transaction to do
+#[derive(Debug, Default, Clone)]
+pub struct CvmOutcome {
+    /// The cardholder has to sign the receipt
+    pub signature_required: bool,
+    /// Encrypted PIN block to send online in the authorisation request
+    pub online_pin_block: Option<Vec<u8>>,
+}
+
+/// Everything CVM processing needs from the card besides the card itself
+pub struct CvmContext<'a> {
+    pub cvm_list: Option<&'a [u8]>,
+    /// Application Currency Code (9F42), which amounts X and Y are in
+    pub application_currency: Option<&'a [u8]>,
+    pub pin_entry: &'a PinEntry,
+    /// Key to encipher offline PINs under, if the ICC public key could be recovered
+    pub pin_key: Option<&'a crate::auth::IccPublicKey>,
+    /// Key to encrypt online PIN blocks under, without which online PIN can't be done
+    pub online_pin_key: Option<&'a crate::pinblock::PinKey>,
+    pub pan: Option<crate::data::Pan>,
+}
+
+/// Whether the terminal can perform a method at all. Signatures need someone
+/// there to check them, so are never supported unattended.
+pub fn terminal_supports(terminal: &terminal::TerminalData, method: u8) -> bool {
+    let capabilities = terminal.terminal_capabilities[1];
+    let attended = !terminal.is_unattended();
+    match method {
+        METHOD_FAIL_CVM => true,
+        METHOD_PLAINTEXT_PIN => capabilities & 0x80 != 0,
+        METHOD_ONLINE_PIN => capabilities & 0x40 != 0,
+        METHOD_PLAINTEXT_PIN_AND_SIGNATURE => capabilities & 0xa0 == 0xa0 && attended,
+        METHOD_ENCIPHERED_PIN => capabilities & 0x10 != 0,
+        METHOD_ENCIPHERED_PIN_AND_SIGNATURE => capabilities & 0x30 == 0x30 && attended,
+        METHOD_SIGNATURE => capabilities & 0x20 != 0 && attended,
+        METHOD_NO_CVM_REQUIRED => capabilities & 0x08 != 0,
+        _ => false
+    }
+}
+
+fn is_pin_method(method: u8) -> bool {
+    matches!(method, METHOD_PLAINTEXT_PIN | METHOD_ONLINE_PIN | METHOD_PLAINTEXT_PIN_AND_SIGNATURE |
+        METHOD_ENCIPHERED_PIN | METHOD_ENCIPHERED_PIN_AND_SIGNATURE)
+}
+
+fn is_known_method(method: u8) -> bool {
+    is_pin_method(method) || matches!(method, METHOD_FAIL_CVM | METHOD_SIGNATURE | METHOD_NO_CVM_REQUIRED)
+}
+
+/// Book 3 10.5.1: whether a rule's condition is met. Conditions this terminal
+/// doesn't understand, and amount conditions in another currency, never are.
+fn condition_met(rule: &CvmRule, list: &CvmList, terminal: &terminal::TerminalData, application_currency: Option<&[u8]>) -> bool {
+    let unattended = terminal.is_unattended();
+    let same_currency = application_currency == Some(&crate::util::to_bcd(terminal.currency_code as u64, 2)[..]);
+    let amount = terminal.amount_authorised;
+    match rule.condition {
+        CONDITION_ALWAYS => true,
+        CONDITION_UNATTENDED_CASH => unattended && terminal.is_cash(),
+        CONDITION_NOT_CASH => !terminal.is_cash() && !terminal.is_cashback(),
+        CONDITION_TERMINAL_SUPPORTS => terminal_supports(terminal, rule.method()),
+        CONDITION_MANUAL_CASH => !unattended && terminal.is_cash(),
+        CONDITION_CASHBACK => terminal.is_cashback(),
+        CONDITION_UNDER_X => same_currency && amount < list.amount_x as u64,
+        CONDITION_OVER_X => same_currency && amount > list.amount_x as u64,
+        CONDITION_UNDER_Y => same_currency && amount < list.amount_y as u64,
+        CONDITION_OVER_Y => same_currency && amount > list.amount_y as u64,
+        _ => false
+    }
+}
+
+fn pin_try_counter<T: CardTransport + ?Sized>(card: &T) -> Result<Option<u8>, crate::transaction::TransactionError> {
+    let data = match crate::card::optional(crate::card::card_get_data_raw(card, 0x9f17))? {
+        Some(d) => d,
+        None => return Ok(None)
+    };
+    Ok(crate::tlv::template_value(&data, crate::tlv::TagID::PINTryCounter).and_then(|v| v.first().copied()))
+}
+
+fn read_pin(pin_entry: &PinEntry, language: crate::language::Language) -> Option<String> {
+    match pin_entry {
+        PinEntry::Prompt => Some(crate::util::get_pin(&format!("{}: ", language.message(crate::language::Message::EnterPin)))),
+        PinEntry::Fixed(p) => Some(p.clone()),
+        PinEntry::Bypass => None
+    }
+}
+
+/// Book 3 10.5.1: offline PIN verification, sending the PIN to the card in the
+/// clear or enciphered under the ICC's key
+fn offline_pin<T: CardTransport + ?Sized>(card: &T, terminal: &mut terminal::TerminalData, context: &CvmContext, enciphered: bool) -> Result<u8, crate::transaction::TransactionError> {
+    if let PinEntry::Bypass = context.pin_entry {
+        terminal.set_tvr(terminal::TVR_PIN_NOT_ENTERED);
+        return Ok(RESULT_FAILED);
+    }
+    if pin_try_counter(card)? == Some(0) {
+        terminal.set_tvr(terminal::TVR_PIN_TRY_LIMIT_EXCEEDED);
+        return Ok(RESULT_FAILED);
+    }
+    // The ICC key may be there from CDA even for a plaintext PIN rule
+    let pin_key = match (enciphered, context.pin_key) {
+        (true, None) => return Ok(RESULT_FAILED),
+        (true, k) => k,
+        (false, _) => None
+    };
+
+    loop {
+        let result = match (context.pin_entry, pin_key) {
+            (PinEntry::Prompt, None) => crate::pinpad::verify_offline_pin(card, terminal)?,
+            (pin_entry, _) => {
+                let pin_block = match read_pin(pin_entry, terminal.language).and_then(|p| crate::pinpad::make_plaintext_pin_block(&p)) {
+                    Some(b) => b,
+                    None if matches!(pin_entry, PinEntry::Prompt) => continue,
+                    None => return Ok(RESULT_FAILED)
+                };
+                match pin_key {
+                    Some(key) => {
+                        let challenge = crate::card::card_get_challenge(card)?;
+                        let enciphered_block = crate::auth::encipher_pin(key, &pin_block, &challenge)
+                            .map_err(crate::transaction::TransactionError::PinEncipherment)?;
+                        crate::pinpad::verify_pin_block(card, crate::card::VERIFY_ENCIPHERED_PIN, &enciphered_block)?
+                    }
+                    None => crate::pinpad::verify_pin_block(card, crate::card::VERIFY_PLAINTEXT_PIN, &pin_block)?
+                }
+            }
+        };
+        tracing::info!("{}", result);
+
+        match result {
+            crate::pinpad::PinVerifyResult::Verified => return Ok(RESULT_SUCCESSFUL),
+            crate::pinpad::PinVerifyResult::Blocked | crate::pinpad::PinVerifyResult::Incorrect(Some(0)) => {
+                terminal.set_tvr(terminal::TVR_PIN_TRY_LIMIT_EXCEEDED);
+                return Ok(RESULT_FAILED);
+            }
+            // Only someone at the terminal gets another go
+            crate::pinpad::PinVerifyResult::Incorrect(_) if matches!(context.pin_entry, PinEntry::Prompt) => continue,
+            crate::pinpad::PinVerifyResult::Incorrect(_) => return Ok(RESULT_FAILED),
+            crate::pinpad::PinVerifyResult::Cancelled | crate::pinpad::PinVerifyResult::Timeout => {
+                terminal.set_tvr(terminal::TVR_PIN_NOT_ENTERED);
+                return Ok(RESULT_FAILED);
+            }
+            crate::pinpad::PinVerifyResult::Failed(_, _) => return Ok(RESULT_FAILED),
+        }
+    }
+}
+
+/// Captures a PIN for online verification and encrypts it, giving None if the
+/// cardholder doesn't enter one. The PIN is always typed on the host, as a
+/// reader's pinpad can only pass a PIN straight to the card.
+pub fn online_pin(pin_entry: &PinEntry, pan: &str, key: &crate::pinblock::PinKey, terminal: &terminal::TerminalData) -> Option<Vec<u8>> {
+    loop {
+        let pin = read_pin(pin_entry, terminal.language)?;
+        if pin.is_empty() {
+            return None;
+        }
+        match crate::pinblock::encrypt(&pin, pan, key) {
+            Some(b) => return Some(b),
+            None if matches!(pin_entry, PinEntry::Prompt) => crate::events::emit(&terminal.events, crate::events::Event::CardholderMessage {
+                message: terminal.language.message(crate::language::Message::IncorrectPin).to_string(),
+            }),
+            None => return None
+        }
+    }
+}
+
+/// Performs one method, giving the result byte for the CVM Results
+fn perform_method<T: CardTransport + ?Sized>(card: &T, terminal: &mut terminal::TerminalData, context: &CvmContext, method: u8, outcome: &mut CvmOutcome) -> Result<u8, crate::transaction::TransactionError> {
+    let required = match method {
+        METHOD_PLAINTEXT_PIN | METHOD_ENCIPHERED_PIN | METHOD_PLAINTEXT_PIN_AND_SIGNATURE | METHOD_ENCIPHERED_PIN_AND_SIGNATURE => Some(crate::events::Cvm::OfflinePin),
+        METHOD_ONLINE_PIN => Some(crate::events::Cvm::OnlinePin),
+        METHOD_SIGNATURE => Some(crate::events::Cvm::Signature),
+        _ => None
+    };
+    if let Some(cvm) = required {
+        crate::events::emit(&terminal.events, crate::events::Event::CvmRequired { cvm });
+    }
+    Ok(match method {
+        METHOD_FAIL_CVM => RESULT_FAILED,
+        METHOD_PLAINTEXT_PIN | METHOD_ENCIPHERED_PIN => offline_pin(card, terminal, context, method == METHOD_ENCIPHERED_PIN)?,
+        METHOD_PLAINTEXT_PIN_AND_SIGNATURE | METHOD_ENCIPHERED_PIN_AND_SIGNATURE => {
+            match offline_pin(card, terminal, context, method == METHOD_ENCIPHERED_PIN_AND_SIGNATURE)? {
+                RESULT_SUCCESSFUL => {
+                    crate::events::emit(&terminal.events, crate::events::Event::CvmRequired { cvm: crate::events::Cvm::Signature });
+                    outcome.signature_required = true;
+                    RESULT_UNKNOWN
+                }
+                r => r
+            }
+        }
+        METHOD_ONLINE_PIN => match (context.online_pin_key, &context.pan) {
+            (Some(key), Some(pan)) => match online_pin(context.pin_entry, pan.reveal(), key, terminal) {
+                Some(block) => {
+                    outcome.online_pin_block = Some(block);
+                    terminal.set_tvr(terminal::TVR_ONLINE_PIN_ENTERED);
+                    RESULT_UNKNOWN
+                }
+                None => {
+                    terminal.set_tvr(terminal::TVR_PIN_NOT_ENTERED);
+                    RESULT_FAILED
+                }
+            },
+            _ => {
+                terminal.set_tvr(terminal::TVR_PIN_PAD_NOT_PRESENT);
+                RESULT_FAILED
+            }
+        },
+        METHOD_SIGNATURE => {
+            outcome.signature_required = true;
+            RESULT_UNKNOWN
+        }
+        METHOD_NO_CVM_REQUIRED => RESULT_SUCCESSFUL,
+        _ => RESULT_FAILED
+    })
+}
+
+/// Book 3 10.5: works through the CVM List until a rule whose condition is met
+/// succeeds, or fails without allowing the next to be tried, recording the
+/// outcome in the CVM Results and TVR
+pub fn perform<T: CardTransport + ?Sized>(card: &T, terminal: &mut terminal::TerminalData, context: &CvmContext) -> Result<CvmOutcome, crate::transaction::TransactionError> {
+    let mut outcome = CvmOutcome::default();
+    let list = match context.cvm_list.map(CvmList::try_from) {
+        Some(Ok(l)) if !l.rules.is_empty() => l,
+        _ => {
+            terminal.set_tvr(terminal::TVR_ICC_DATA_MISSING);
+            terminal.cvm_results = [0x3f, 0x00, RESULT_UNKNOWN];
+            return Ok(outcome);
+        }
+    };
+    terminal.set_tsi(terminal::TSI_CARDHOLDER_VERIFICATION_PERFORMED);
+
+    let mut last_failed = None;
+    for rule in &list.rules {
+        if !condition_met(rule, &list, terminal, context.application_currency) {
+            continue;
+        }
+
+        let method = rule.method();
+        let result = if !is_known_method(method) {
+            terminal.set_tvr(terminal::TVR_UNRECOGNISED_CVM);
+            RESULT_FAILED
+        } else if !terminal_supports(terminal, method) {
+            if is_pin_method(method) {
+                terminal.set_tvr(terminal::TVR_PIN_PAD_NOT_PRESENT);
+            }
+            RESULT_FAILED
+        } else {
+            perform_method(card, terminal, context, method, &mut outcome)?
+        };
+
+        if result != RESULT_FAILED {
+            terminal.cvm_results = [rule.code, rule.condition, result];
+            return Ok(outcome);
+        }
+        last_failed = Some(rule);
+        if !rule.fail_cascade() {
+            break;
+        }
+    }
+
+    terminal.cvm_results = match last_failed {
+        Some(rule) => [rule.code, rule.condition, RESULT_FAILED],
+        None => [0x3f, 0x00, RESULT_FAILED]
+    };
+    terminal.set_tvr(terminal::TVR_CARDHOLDER_VERIFICATION_FAILED);
+    Ok(outcome)
+}
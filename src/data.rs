@@ -1,6 +1,64 @@
 use std::convert::TryFrom;
 
-#[derive(Debug)]
+/// Why data from the card, or an issuer's response, doesn't make sense
+#[derive(Debug, PartialEq, Copy, Clone, thiserror::Error)]
+pub enum DataError {
+    #[error("Invalid Application Priority Indicator")]
+    InvalidPriorityIndicator,
+    #[error("No application name")]
+    NoApplicationName,
+    #[error("Invalid ADF name")]
+    InvalidAdfName,
+    #[error("No ADF name")]
+    NoAdfName,
+    #[error("AFL not a multiple of 4 bytes")]
+    AflLength,
+    #[error("Invalid AFL entry")]
+    InvalidAflEntry,
+    #[error("Invalid format 1 response")]
+    InvalidFormat1Response,
+    #[error("Format 1 response too short")]
+    Format1TooShort,
+    #[error("No response message template")]
+    NoResponseMessageTemplate,
+    #[error("No AIP")]
+    NoAip,
+    #[error("Invalid AFL")]
+    InvalidAfl,
+    #[error("No CID")]
+    NoCid,
+    #[error("No ATC")]
+    NoAtc,
+    #[error("No application cryptogram")]
+    NoApplicationCryptogram,
+    #[error("Not an issuer script template")]
+    NotIssuerScriptTemplate,
+    #[error("Issuer script template not constructed")]
+    IssuerScriptNotConstructed,
+    #[error("Invalid issuer script identifier")]
+    InvalidIssuerScriptIdentifier,
+    #[error("Invalid issuer script command")]
+    InvalidIssuerScriptCommand,
+    #[error("No authorisation response code")]
+    NoAuthorisationResponseCode,
+    #[error("Invalid PAN")]
+    InvalidPan,
+    #[error("PAN check digit wrong")]
+    PanCheckDigit,
+    #[error("Invalid service code")]
+    InvalidServiceCode,
+    #[error("Invalid language preference")]
+    InvalidLanguagePreference,
+    #[error("Invalid chip data: {0}")]
+    ChipData(#[source] crate::tlv::TlvError),
+}
+
+/// The Application Priority Indicator (87). Indicators order by priority,
+/// the greater being the one to pick: 1 is the highest priority and 15 the
+/// lowest, with no priority at all below them. Between two of the same
+/// priority, the one that can be selected without the cardholder confirming
+/// it comes first.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ApplicationPriorityIndicator {
     auto_selection_allowed: bool,
     priority: u8,
@@ -11,18 +69,51 @@ impl ApplicationPriorityIndicator {
         self.auto_selection_allowed
     }
 
-    pub fn prority(&self) -> u8 {
-        self.priority
+    /// From 1, the highest, to 15, or `None` if the application has no
+    /// priority assigned
+    pub fn priority(&self) -> Option<u8> {
+        match self.priority {
+            0 => None,
+            p => Some(p)
+        }
+    }
+
+    /// Lower for higher priority, with no priority after all the rest
+    fn rank(&self) -> u8 {
+        self.priority().unwrap_or(16)
+    }
+}
+
+impl Ord for ApplicationPriorityIndicator {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.rank().cmp(&self.rank())
+            .then(self.auto_selection_allowed.cmp(&other.auto_selection_allowed))
+    }
+}
+
+impl PartialOrd for ApplicationPriorityIndicator {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Default for ApplicationPriorityIndicator {
+    /// No priority assigned and no confirmation needed, as when the API is absent
+    fn default() -> Self {
+        Self {
+            auto_selection_allowed: true,
+            priority: 0,
+        }
     }
 }
 
 impl TryFrom<&crate::tlv::Tag> for ApplicationPriorityIndicator {
-    type Error = &'static str;
+    type Error = DataError;
 
     fn try_from(value: &crate::tlv::Tag) -> Result<Self, Self::Error> {
         let contents = match value.contents() {
             crate::tlv::TagContents::Byte(b) => b,
-            _ => return Err("Not a byte value")
+            _ => return Err(DataError::InvalidPriorityIndicator)
         };
         let auto_selection_allowed = contents & 0b10000000 == 0;
         let priority = contents & 0b1111;
@@ -33,38 +124,59 @@ impl TryFrom<&crate::tlv::Tag> for ApplicationPriorityIndicator {
     }
 }
 
+#[derive(Clone)]
 pub struct Application {
     name: String,
     adf_name: Vec<u8>,
     priority: ApplicationPriorityIndicator,
+    kernel_identifier: Option<Vec<u8>>,
 }
 
 impl Application {
+    /// The Application Preferred Name where the terminal can show it, else the
+    /// Application Label. Either being malformed counts as it not being there.
     fn get_application_name(tag: &crate::tlv::Tag) -> Option<String> {
-        match match (tag.get_tag(crate::tlv::TagID::ApplicationPreferredName), tag.get_tag(crate::tlv::TagID::IssuerCodeTableIndex)) {
-            (Some(n), Some(i)) => match &n.contents() {
-                crate::tlv::TagContents::Bytes(b) => match &i.contents() {
-                    crate::tlv::TagContents::Byte(i) => crate::util::code_table_index_decode(b, *i),
-                    _ => unreachable!()
-                },
-                _ => unreachable!()
+        let preferred_name = match (tag.get_tag(crate::tlv::TagID::ApplicationPreferredName), tag.get_tag(crate::tlv::TagID::IssuerCodeTableIndex)) {
+            (Some(n), Some(i)) => match (n.contents(), i.contents()) {
+                (crate::tlv::TagContents::Bytes(b), crate::tlv::TagContents::Byte(i)) => crate::util::code_table_index_decode(b, *i),
+                _ => None
             },
-            (_, _) => None
-        } {
+            _ => None
+        };
+        match preferred_name {
             Some(s) => Some(s.to_string()),
-            None => {
-                match tag.get_tag(crate::tlv::TagID::ApplicationLabel) {
-                    Some(n) => match &n.contents() {
-                        crate::tlv::TagContents::String(s) => Some(s.to_string()),
-                        crate::tlv::TagContents::Invalid => None,
-                        _ => unreachable!()
-                    },
-                    None => None
-                }
+            None => match tag.get_tag(crate::tlv::TagID::ApplicationLabel).map(|n| n.contents()) {
+                Some(crate::tlv::TagContents::String(s)) => Some(s.to_string()),
+                _ => None
             }
         }
     }
 
+    fn new(tag: &crate::tlv::Tag, adf_name: &[u8]) -> Result<Self, DataError> {
+        // Without a label of its own, a built-in application's name will do
+        let name = match Application::get_application_name(tag).or_else(|| crate::selection::application_name(adf_name).map(str::to_string)) {
+            Some(s) => s,
+            None => return Err(DataError::NoApplicationName)
+        };
+        let api = match tag.get_tag(crate::tlv::TagID::ApplicationPriorityIndicator) {
+            Some(p) => ApplicationPriorityIndicator::try_from(p)?,
+            None => ApplicationPriorityIndicator::default()
+        };
+
+        Ok(Self {
+            name,
+            adf_name: adf_name.to_owned(),
+            priority: api,
+            kernel_identifier: tag.get_tag(crate::tlv::TagID::KernelIdentifier).map(|k| Vec::<u8>::from(k.contents())),
+        })
+    }
+
+    /// Builds an application from the FCI proprietary template returned when
+    /// selecting it, rather than from a directory entry
+    pub fn from_fci(df_name: &[u8], fcipt: &crate::tlv::Tag) -> Result<Self, DataError> {
+        Application::new(fcipt, df_name)
+    }
+
     pub fn priority(&self) -> &ApplicationPriorityIndicator {
         &self.priority
     }
@@ -76,32 +188,714 @@ impl Application {
     pub fn aid(&self) -> &[u8] {
         &self.adf_name
     }
+
+    /// Kernel Identifier from the PPSE directory entry, if it gave one
+    pub fn kernel_identifier(&self) -> Option<&[u8]> {
+        self.kernel_identifier.as_deref()
+    }
 }
 
 impl TryFrom<&crate::tlv::Tag> for Application {
-    type Error = &'static str;
+    type Error = DataError;
 
     fn try_from(value: &crate::tlv::Tag) -> Result<Self, Self::Error> {
-        let name = match Application::get_application_name(value) {
-            Some(s) => s,
-            None => return Err("No application name")
+        let adf = match value.get_tag(crate::tlv::TagID::ApplicationDedicatedFileName).map(|a| a.contents()) {
+            Some(crate::tlv::TagContents::Bytes(b)) => b,
+            Some(_) => return Err(DataError::InvalidAdfName),
+            None => return Err(DataError::NoAdfName)
+        };
+
+        Application::new(value, adf)
+    }
+}
+#[derive(Debug, Copy, Clone)]
+pub struct AflEntry {
+    pub short_file_identifier: u8,
+    pub first_record: u8,
+    pub last_record: u8,
+    pub oda_records: u8,
+}
+
+impl AflEntry {
+    pub fn records(&self) -> std::ops::RangeInclusive<u8> {
+        self.first_record..=self.last_record
+    }
+}
+
+pub fn parse_afl(afl: &[u8]) -> Result<Vec<AflEntry>, DataError> {
+    if !afl.len().is_multiple_of(4) {
+        return Err(DataError::AflLength);
+    }
+    afl.chunks(4).map(|entry| {
+        let out = AflEntry {
+            short_file_identifier: entry[0] >> 3,
+            first_record: entry[1],
+            last_record: entry[2],
+            oda_records: entry[3],
+        };
+        if out.short_file_identifier == 0 || out.short_file_identifier == 31 || out.first_record == 0
+            || out.last_record < out.first_record || out.oda_records > out.last_record - out.first_record + 1 {
+            return Err(DataError::InvalidAflEntry);
+        }
+        Ok(out)
+    }).collect()
+}
+
+#[derive(Debug)]
+pub struct ProcessingOptions {
+    pub aip: [u8; 2],
+    pub afl: Vec<AflEntry>,
+}
+
+impl TryFrom<&crate::tlv::TagList> for ProcessingOptions {
+    type Error = DataError;
+
+    fn try_from(value: &crate::tlv::TagList) -> Result<Self, Self::Error> {
+        if let Some(f1) = value.get_tag(crate::tlv::TagID::ResponseMessageTemplateFormat1) {
+            let data = match f1.contents() {
+                crate::tlv::TagContents::Bytes(b) => b,
+                _ => return Err(DataError::InvalidFormat1Response)
+            };
+            if data.len() < 2 {
+                return Err(DataError::Format1TooShort);
+            }
+            return Ok(Self {
+                aip: [data[0], data[1]],
+                afl: parse_afl(&data[2..])?,
+            });
+        }
+
+        let f2 = match value.get_tag(crate::tlv::TagID::ResponseMessageTemplateFormat2) {
+            Some(t) => t,
+            None => return Err(DataError::NoResponseMessageTemplate)
+        };
+        let aip = match f2.get_tag(crate::tlv::TagID::ApplicationInterchangeProfile).map(|t| t.contents()) {
+            Some(crate::tlv::TagContents::Bytes(b)) if b.len() == 2 => [b[0], b[1]],
+            _ => return Err(DataError::NoAip)
         };
-        let api = ApplicationPriorityIndicator::try_from(match value.get_tag(crate::tlv::TagID::ApplicationPriorityIndicator) {
-            Some(p) => p,
-            None => return Err("No API")
-        })?;
-        let adf = match match value.get_tag(crate::tlv::TagID::ApplicationDedicatedFileName) {
-            Some(a) => a,
-            None => return Err("No ADF name")
-        }.contents() {
-            crate:: tlv::TagContents::Bytes(b) => b,
-            _ => unreachable!()
+        let afl = match f2.get_tag(crate::tlv::TagID::ApplicationFileLocator).map(|t| t.contents()) {
+            Some(crate::tlv::TagContents::Bytes(b)) => parse_afl(b)?,
+            None => vec![],
+            _ => return Err(DataError::InvalidAfl)
         };
 
         Ok(Self {
-            name,
-            adf_name: adf.to_owned(),
-            priority: api,
+            aip,
+            afl,
+        })
+    }
+}
+
+/// The card's response to GENERATE AC, in either format
+#[derive(Debug, Clone)]
+pub struct GenerateAcResponse {
+    /// Cryptogram Information Data
+    pub cid: u8,
+    /// Application Transaction Counter
+    pub atc: u16,
+    pub application_cryptogram: [u8; 8],
+    pub issuer_application_data: Option<Vec<u8>>,
+    /// Signed Dynamic Application Data, present when CDA was asked for
+    pub signed_dynamic_application_data: Option<Vec<u8>>,
+    /// POS Cardholder Interaction Information, from mobile wallets
+    pub pos_cardholder_interaction_information: Option<Vec<u8>>,
+}
+
+impl TryFrom<&crate::tlv::TagList> for GenerateAcResponse {
+    type Error = DataError;
+
+    fn try_from(value: &crate::tlv::TagList) -> Result<Self, Self::Error> {
+        if let Some(f1) = value.get_tag(crate::tlv::TagID::ResponseMessageTemplateFormat1) {
+            // CID, ATC, cryptogram, then optionally the IAD
+            let data = Vec::<u8>::from(f1.contents());
+            if data.len() < 11 {
+                return Err(DataError::Format1TooShort);
+            }
+            return Ok(Self {
+                cid: data[0],
+                atc: u16::from_be_bytes([data[1], data[2]]),
+                application_cryptogram: [data[3], data[4], data[5], data[6], data[7], data[8], data[9], data[10]],
+                issuer_application_data: if data.len() > 11 { Some(data[11..].to_vec()) } else { None },
+                signed_dynamic_application_data: None,
+                pos_cardholder_interaction_information: None,
+            });
+        }
+
+        let f2 = match value.get_tag(crate::tlv::TagID::ResponseMessageTemplateFormat2) {
+            Some(t) => t,
+            None => return Err(DataError::NoResponseMessageTemplate)
+        };
+        let bytes = |tag| f2.get_tag(tag).map(|t| Vec::<u8>::from(t.contents()));
+        let cid = match bytes(crate::tlv::TagID::CryptogramInformationData) {
+            Some(b) if b.len() == 1 => b[0],
+            _ => return Err(DataError::NoCid)
+        };
+        let atc = match bytes(crate::tlv::TagID::ApplicationTransactionCounter) {
+            Some(b) if b.len() == 2 => u16::from_be_bytes([b[0], b[1]]),
+            _ => return Err(DataError::NoAtc)
+        };
+        // With CDA the cryptogram is inside the signature rather than in the clear
+        let signed_dynamic_application_data = bytes(crate::tlv::TagID::SignedDynamicApplicationData);
+        let application_cryptogram = match bytes(crate::tlv::TagID::ApplicationCryptogram) {
+            Some(b) if b.len() == 8 => [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]],
+            None if signed_dynamic_application_data.is_some() => [0; 8],
+            _ => return Err(DataError::NoApplicationCryptogram)
+        };
+        Ok(Self {
+            cid,
+            atc,
+            application_cryptogram,
+            issuer_application_data: bytes(crate::tlv::TagID::IssuerApplicationData),
+            signed_dynamic_application_data,
+            pos_cardholder_interaction_information: bytes(crate::tlv::TagID::PosCardholderInteractionInformation),
         })
     }
-}
\ No newline at end of file
+}
+
+/// An Issuer Script Template from the online response
+#[derive(Debug, Clone)]
+pub struct IssuerScript {
+    /// Set for template 1 (71), run before the final GENERATE AC rather than after it
+    pub before_final_ac: bool,
+    pub identifier: Option<[u8; 4]>,
+    /// Each command as a complete C-APDU
+    pub commands: Vec<Vec<u8>>,
+}
+
+impl TryFrom<&crate::tlv::Tag> for IssuerScript {
+    type Error = DataError;
+
+    fn try_from(value: &crate::tlv::Tag) -> Result<Self, Self::Error> {
+        let before_final_ac = match value.id() {
+            crate::tlv::TagID::IssuerScriptTemplate1 => true,
+            crate::tlv::TagID::IssuerScriptTemplate2 => false,
+            _ => return Err(DataError::NotIssuerScriptTemplate)
+        };
+        let contents = match value.contents() {
+            crate::tlv::TagContents::Constructed(c) => c,
+            _ => return Err(DataError::IssuerScriptNotConstructed)
+        };
+
+        let identifier = match contents.get_tag(crate::tlv::TagID::IssuerScriptIdentifier) {
+            Some(i) => match Vec::<u8>::from(i.contents()).as_slice() {
+                [a, b, c, d] => Some([*a, *b, *c, *d]),
+                _ => return Err(DataError::InvalidIssuerScriptIdentifier)
+            },
+            None => None
+        };
+
+        let mut commands = vec![];
+        for command in contents.get_tags(crate::tlv::TagID::IssuerScriptCommand) {
+            let command = Vec::<u8>::from(command.contents());
+            // Header, then optionally Lc and data, then optionally Le
+            let well_formed = match command.len() {
+                4 | 5 => true,
+                l => l >= 6 && matches!((l - 5).checked_sub(command[4] as usize), Some(0 | 1)),
+            };
+            if !well_formed {
+                return Err(DataError::InvalidIssuerScriptCommand);
+            }
+            commands.push(command);
+        }
+
+        Ok(Self {
+            before_final_ac,
+            identifier,
+            commands,
+        })
+    }
+}
+
+/// A Primary Account Number with a correct Luhn check digit. It's masked
+/// wherever it's printed, `Debug` included, as `521234******1234`; the full
+/// number only comes out of [`reveal`](Pan::reveal), for the few places that
+/// really need it such as PIN blocks and the authorisation request.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pan(String);
+
+impl Pan {
+    /// From the Application PAN (5A), packed BCD padded with F
+    pub fn from_bcd(data: &[u8]) -> Result<Self, DataError> {
+        Self::from_digits(crate::hex::encode(data).trim_end_matches('F'))
+    }
+
+    /// From the Track 2 Equivalent Data (57), where the PAN comes before the
+    /// D separator
+    pub fn from_track2(data: &[u8]) -> Result<Self, DataError> {
+        match crate::hex::encode(data).split_once('D') {
+            Some((pan, _)) => Self::from_digits(pan),
+            None => Err(DataError::InvalidPan)
+        }
+    }
+
+    /// From the PAN written out in digits
+    pub fn from_digits(digits: &str) -> Result<Self, DataError> {
+        if !(12..=19).contains(&digits.len()) || !digits.bytes().all(|d| d.is_ascii_digit()) {
+            return Err(DataError::InvalidPan);
+        }
+        if !luhn_valid(digits) {
+            return Err(DataError::PanCheckDigit);
+        }
+        Ok(Self(digits.to_string()))
+    }
+
+    /// The full PAN. Anything it's given to should be kept out of logs and
+    /// outcomes; printing the `Pan` itself gives the masked form.
+    pub fn reveal(&self) -> &str {
+        &self.0
+    }
+
+    /// All but the first six and last four digits hidden
+    pub fn masked(&self) -> String {
+        crate::util::mask_pan(&self.0)
+    }
+}
+
+/// Whether the last digit is the Luhn check digit for the rest
+fn luhn_valid(digits: &str) -> bool {
+    let sum: u32 = digits.bytes().rev().enumerate().map(|(i, d)| {
+        let d = (d - b'0') as u32;
+        match i % 2 {
+            0 => d,
+            _ if d > 4 => d * 2 - 9,
+            _ => d * 2
+        }
+    }).sum();
+    sum.is_multiple_of(10)
+}
+
+impl std::fmt::Display for Pan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.masked())
+    }
+}
+
+impl std::fmt::Debug for Pan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pan({})", self.masked())
+    }
+}
+
+impl std::str::FromStr for Pan {
+    type Err = DataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_digits(s)
+    }
+}
+
+/// Track 2 Equivalent Data (57) as it can be shown, with the PAN masked as
+/// [`Pan`] masks it and the discretionary data, where the dCVV or CVC3 goes,
+/// dropped: `476173******0010D3012201`
+pub fn mask_track2(data: &[u8]) -> Option<String> {
+    let hex = crate::hex::encode(data);
+    let (pan, rest) = hex.split_once('D')?;
+    // The expiry date and service code
+    let rest = rest.get(..7).unwrap_or_else(|| rest.trim_end_matches('F'));
+    Some(format!("{}D{}", crate::util::mask_pan(pan), rest))
+}
+
+/// The Cardholder Name (5F20) or Cardholder Name Extended (9F0B) as it can be
+/// shown. They're in the common character set, which is a part of ISO 8859,
+/// padded out with trailing spaces; a name that's nothing but padding is no
+/// name at all.
+pub fn decode_cardholder_name(data: &[u8]) -> Option<String> {
+    let name = crate::util::code_table_index_decode(data, 1)?;
+    let name = name.trim_end_matches(' ');
+    match name.is_empty() {
+        true => None,
+        false => Some(name.to_string())
+    }
+}
+
+/// Who has to approve a transaction, from the second digit of a service code
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Authorisation {
+    Normal,
+    /// Always online to the issuer
+    ByIssuer,
+    /// Online to the issuer unless there's a bilateral agreement otherwise
+    ByIssuerUnlessAgreed,
+}
+
+/// What the card can be used for, from the third digit of a service code
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllowedServices {
+    Any,
+    GoodsAndServices,
+    AtmOnly,
+    CashOnly,
+}
+
+/// When a PIN is needed, from the third digit of a service code
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PinRequirement {
+    NotRequired,
+    Required,
+    /// Only where there's a PIN pad to enter it on
+    IfPinPadPresent,
+}
+
+/// The three digit service code from the magnetic stripe, as the card gives it
+/// in the Track 2 Equivalent Data after the expiry date
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ServiceCode([u8; 3]);
+
+impl ServiceCode {
+    pub fn from_track2(data: &[u8]) -> Result<Self, DataError> {
+        let hex = crate::hex::encode(data);
+        match hex.split_once('D') {
+            Some((_, rest)) if rest.len() >= 7 => rest[4..7].parse(),
+            _ => Err(DataError::InvalidServiceCode)
+        }
+    }
+
+    pub fn digits(&self) -> [u8; 3] {
+        self.0
+    }
+
+    /// Whether the card can be used abroad rather than only in its own country
+    pub fn international(&self) -> bool {
+        matches!(self.0[0], 1 | 2)
+    }
+
+    /// Whether the card has a chip, which has to be used wherever the
+    /// terminal can
+    pub fn chip(&self) -> bool {
+        matches!(self.0[0], 2 | 6)
+    }
+
+    /// Whether the card's a test card
+    pub fn test(&self) -> bool {
+        self.0[0] == 9
+    }
+
+    /// `None` for digits ISO/IEC 7813 leaves for future use
+    pub fn authorisation(&self) -> Option<Authorisation> {
+        match self.0[1] {
+            0 => Some(Authorisation::Normal),
+            2 => Some(Authorisation::ByIssuer),
+            4 => Some(Authorisation::ByIssuerUnlessAgreed),
+            _ => None
+        }
+    }
+
+    pub fn allowed_services(&self) -> Option<AllowedServices> {
+        match self.0[2] {
+            0 | 1 | 6 => Some(AllowedServices::Any),
+            2 | 5 | 7 => Some(AllowedServices::GoodsAndServices),
+            3 => Some(AllowedServices::AtmOnly),
+            4 => Some(AllowedServices::CashOnly),
+            _ => None
+        }
+    }
+
+    pub fn pin_requirement(&self) -> Option<PinRequirement> {
+        match self.0[2] {
+            1 | 2 | 4 => Some(PinRequirement::NotRequired),
+            0 | 3 | 5 => Some(PinRequirement::Required),
+            6 | 7 => Some(PinRequirement::IfPinPadPresent),
+            _ => None
+        }
+    }
+
+    /// Whether a terminal that can read chips should take the magnetic stripe
+    /// of a card with this service code. A chip card's stripe is only for
+    /// when its chip has failed and the transaction falls back.
+    pub fn swipe_allowed(&self, chip_failed: bool) -> bool {
+        !self.chip() || chip_failed
+    }
+}
+
+impl std::str::FromStr for ServiceCode {
+    type Err = DataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [a, b, c] if s.bytes().all(|d| d.is_ascii_digit()) => Ok(Self([a - b'0', b - b'0', c - b'0'])),
+            _ => Err(DataError::InvalidServiceCode)
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceCode {
+    /// The digits, then what they mean
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{} (", self.0[0], self.0[1], self.0[2])?;
+        match self.0[0] {
+            1 | 2 => f.write_str("international")?,
+            5 | 6 => f.write_str("national")?,
+            7 => f.write_str("private")?,
+            9 => f.write_str("test")?,
+            _ => f.write_str("reserved interchange")?
+        }
+        if self.chip() {
+            f.write_str(" chip card")?;
+        }
+        match self.authorisation() {
+            Some(Authorisation::Normal) => f.write_str(", normal authorisation")?,
+            Some(Authorisation::ByIssuer) => f.write_str(", online authorisation")?,
+            Some(Authorisation::ByIssuerUnlessAgreed) => f.write_str(", online authorisation unless agreed otherwise")?,
+            None => f.write_str(", reserved authorisation")?
+        }
+        match self.allowed_services() {
+            Some(AllowedServices::Any) => f.write_str(", no restrictions")?,
+            Some(AllowedServices::GoodsAndServices) => f.write_str(", goods and services only")?,
+            Some(AllowedServices::AtmOnly) => f.write_str(", ATM only")?,
+            Some(AllowedServices::CashOnly) => f.write_str(", cash only")?,
+            None => f.write_str(", reserved services")?
+        }
+        match self.pin_requirement() {
+            Some(PinRequirement::NotRequired) | None => {}
+            Some(PinRequirement::Required) => f.write_str(", PIN required")?,
+            Some(PinRequirement::IfPinPadPresent) => f.write_str(", PIN if there's a PIN pad")?
+        }
+        f.write_str(")")
+    }
+}
+
+/// What the card's records say about the application and whose it is
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApplicationData {
+    pub pan: Option<Pan>,
+    pub cardholder_name: Option<String>,
+    /// Application Effective Date (5F25), the first day it can be used
+    pub effective_date: Option<chrono::NaiveDate>,
+    /// Application Expiration Date (5F24), the last day it can be used
+    pub expiry_date: Option<chrono::NaiveDate>,
+    /// From the Track 2 Equivalent Data
+    pub service_code: Option<ServiceCode>,
+}
+
+impl ApplicationData {
+    /// Whether the application's expiry date has gone by on `at`. Without an
+    /// expiry date it never expires.
+    pub fn is_expired(&self, at: chrono::NaiveDate) -> bool {
+        matches!(self.expiry_date, Some(d) if at > d)
+    }
+
+    /// Whether `at` is before the application's effective date
+    pub fn is_not_yet_effective(&self, at: chrono::NaiveDate) -> bool {
+        matches!(self.effective_date, Some(d) if at < d)
+    }
+}
+
+impl From<&ApplicationRecords> for ApplicationData {
+    fn from(records: &ApplicationRecords) -> Self {
+        let date = |tag| records.get_bytes(tag).and_then(|d| crate::util::from_bcd_date(&d));
+        Self {
+            pan: records.pan(),
+            cardholder_name: records.cardholder_name(),
+            effective_date: date(crate::tlv::TagID::ApplicationEffectiveDate),
+            expiry_date: date(crate::tlv::TagID::ApplicationExpirationDate),
+            service_code: records.get_bytes(crate::tlv::TagID::Track2EquivalentData).and_then(|t| ServiceCode::from_track2(&t).ok()),
+        }
+    }
+}
+
+/// What the card says about its issuer and the account behind it, from the
+/// FCI Issuer Discretionary Data (BF0C) or, failing that, the records
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CardInfo {
+    /// Issuer Identification Number (42), the six digits the issuer's PANs
+    /// start with
+    pub iin: Option<String>,
+    pub issuer_url: Option<String>,
+    pub iban: Option<String>,
+    pub bic: Option<String>,
+    /// From the Issuer Country Code (5F28), or either of its alphabetic forms
+    /// (5F55 and 5F56)
+    pub issuer_country: Option<&'static crate::iso::Country>,
+}
+
+impl CardInfo {
+    /// From the application's SELECT response and its records
+    pub fn new(fci: &crate::tlv::TagList, records: &ApplicationRecords) -> Self {
+        use crate::tlv::TagID;
+        let discretionary = fci.get_tag(TagID::FileControlInformationTemplate)
+            .and_then(|t| t.get_tag(TagID::FileControlInformationProprietaryTemplate))
+            .and_then(|t| t.get_tag(TagID::FileControlInformationIssuerDiscretionaryData));
+        let bytes = |tag| discretionary.and_then(|d| d.get_tag(tag))
+            .map(|t| Vec::<u8>::from(t.contents()))
+            .or_else(|| records.get_bytes(tag));
+        let text = |tag| bytes(tag)
+            .and_then(|b| String::from_utf8(b).ok())
+            .map(|s| s.trim_end().to_string())
+            .filter(|s| !s.is_empty());
+        let country = bytes(TagID::IssuerCountryCode)
+            .and_then(|c| crate::util::from_bcd(&c))
+            .and_then(|n| crate::iso::country(n as u16))
+            .or_else(|| text(TagID::IssuerCountryCodeAlpha2).and_then(|c| crate::iso::parse_country(&c)))
+            .or_else(|| text(TagID::IssuerCountryCodeAlpha6).and_then(|c| crate::iso::parse_country(&c)));
+        Self {
+            iin: bytes(TagID::IssuerIdentificationNumber)
+                .map(|i| crate::hex::encode(&i).trim_end_matches('F').to_string())
+                .filter(|i| !i.is_empty() && i.bytes().all(|d| d.is_ascii_digit())),
+            issuer_url: text(TagID::IssuerURL),
+            iban: text(TagID::InternationalBankAccountNumber),
+            bic: text(TagID::BankIdentifierCode),
+            issuer_country: country,
+        }
+    }
+
+    /// Whether the card said nothing about its issuer at all
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// What the card did say, each with a name to print it under
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("IIN", self.iin.as_deref()),
+            ("Issuer URL", self.issuer_url.as_deref()),
+            ("IBAN", self.iban.as_deref()),
+            ("BIC", self.bic.as_deref()),
+            ("Issuer country", self.issuer_country.map(|c| c.name)),
+        ].iter().filter_map(|(n, v)| v.map(|v| (*n, v))).collect()
+    }
+}
+
+/// Everything read from the records listed in the AFL
+#[derive(Debug, Default)]
+pub struct ApplicationRecords {
+    pub records: Vec<crate::tlv::TagList>,
+    /// Concatenated record data covered by offline data authentication
+    pub oda_data: Vec<u8>,
+    /// Set when a record marked for ODA in SFIs 1-10 wasn't a 70 template,
+    /// which means ODA has to be treated as failed
+    pub oda_record_invalid: bool,
+}
+
+impl ApplicationRecords {
+    pub fn get_tag(&self, tag_id: crate::tlv::TagID) -> Option<&crate::tlv::Tag> {
+        self.records.iter()
+            .filter_map(|r| r.get_tag(crate::tlv::TagID::ReadRecordResponseMessageTemplate))
+            .find_map(|t| t.get_tag(tag_id))
+    }
+
+    pub fn get_bytes(&self, tag_id: crate::tlv::TagID) -> Option<Vec<u8>> {
+        self.get_tag(tag_id).map(|t| Vec::<u8>::from(t.contents()))
+    }
+
+    /// The Cardholder Name, the extended one being used where the card has it
+    /// for names too long for the other
+    pub fn cardholder_name(&self) -> Option<String> {
+        self.get_bytes(crate::tlv::TagID::CardholderNameExtended).and_then(|n| decode_cardholder_name(&n))
+            .or_else(|| self.get_bytes(crate::tlv::TagID::CardholderName).and_then(|n| decode_cardholder_name(&n)))
+    }
+
+    /// The PAN from the Application PAN, or failing that the Track 2
+    /// Equivalent Data, if it has a correct check digit
+    pub fn pan(&self) -> Option<Pan> {
+        self.get_bytes(crate::tlv::TagID::ApplicationPrimaryAccountNumber)
+            .and_then(|p| Pan::from_bcd(&p).ok())
+            .or_else(|| self.get_bytes(crate::tlv::TagID::Track2EquivalentData).and_then(|t| Pan::from_track2(&t).ok()))
+    }
+
+    /// Adds a record as read from the card, including it in the ODA input if
+    /// the AFL says it should be
+    pub fn add_record(&mut self, sfi: u8, data: &[u8], for_oda: bool) -> Result<(), crate::tlv::TlvError> {
+        let template = crate::tlv::template_value(data, crate::tlv::TagID::ReadRecordResponseMessageTemplate);
+        if for_oda {
+            if sfi <= 10 {
+                match template {
+                    Some(t) => self.oda_data.extend(t),
+                    None => self.oda_record_invalid = true
+                }
+            } else {
+                self.oda_data.extend(data);
+            }
+        }
+
+        self.records.push(crate::tlv::TagList::try_from(data)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parses_pans() {
+        use crate::data::{DataError, Pan};
+        let pan = Pan::from_bcd(&[0x52, 0x12, 0x34, 0x56, 0x78, 0x90, 0x12, 0x9f]).unwrap();
+        assert_eq!(pan.reveal(), "521234567890129");
+        assert_eq!(pan.to_string(), "521234*****0129");
+        assert_eq!(format!("{:?}", pan), "Pan(521234*****0129)");
+        let pan = Pan::from_track2(&[0x47, 0x61, 0x73, 0x90, 0x01, 0x01, 0x00, 0x10, 0xd2, 0x01, 0x22, 0x01]).unwrap();
+        assert_eq!(pan.to_string(), "476173******0010");
+        assert_eq!(pan, "4761739001010010".parse().unwrap());
+
+        assert_eq!(Pan::from_digits("4761739001010011"), Err(DataError::PanCheckDigit));
+        assert_eq!(Pan::from_digits("47617390010"), Err(DataError::InvalidPan));
+        assert_eq!(Pan::from_bcd(&[0x47, 0x61, 0x73, 0x90, 0x01, 0x01, 0x00, 0x1a]), Err(DataError::InvalidPan));
+        assert_eq!(Pan::from_track2(&[0x47, 0x61, 0x73, 0x90, 0x01, 0x01, 0x00, 0x10]), Err(DataError::InvalidPan));
+    }
+
+    #[test]
+    fn masks_track_2() {
+        assert_eq!(crate::data::mask_track2(&crate::hex::decode("4761739001010010D3012201000012345678").unwrap()).as_deref(),
+                   Some("476173******0010D3012201"));
+        assert_eq!(crate::data::mask_track2(&crate::hex::decode("5413330089600010D3012F").unwrap()).as_deref(),
+                   Some("541333******0010D3012"));
+        assert_eq!(crate::data::mask_track2(&[0x47, 0x61, 0x73, 0x90]), None);
+    }
+
+    #[test]
+    fn parses_service_codes() {
+        use crate::data::{AllowedServices, Authorisation, DataError, PinRequirement, ServiceCode};
+        let service_code = ServiceCode::from_track2(&[0x47, 0x61, 0x73, 0x90, 0x01, 0x01, 0x00, 0x10, 0xd3, 0x01, 0x22, 0x01, 0x0f]).unwrap();
+        assert_eq!(service_code.digits(), [2, 0, 1]);
+        assert!(service_code.international() && service_code.chip());
+        assert_eq!(service_code.pin_requirement(), Some(PinRequirement::NotRequired));
+        assert!(!service_code.swipe_allowed(false));
+        assert!(service_code.swipe_allowed(true));
+        assert_eq!(service_code.to_string(), "201 (international chip card, normal authorisation, no restrictions)");
+        let service_code: ServiceCode = "625".parse().unwrap();
+        assert!(!service_code.international());
+        assert_eq!(service_code.authorisation(), Some(Authorisation::ByIssuer));
+        assert_eq!(service_code.allowed_services(), Some(AllowedServices::GoodsAndServices));
+        assert_eq!(service_code.to_string(), "625 (national chip card, online authorisation, goods and services only, PIN required)");
+        assert_eq!(ServiceCode::from_track2(&[0x47, 0x61, 0xd3, 0x01, 0x2f]), Err(DataError::InvalidServiceCode));
+        assert!("20".parse::<ServiceCode>().is_err());
+    }
+
+    #[test]
+    fn reads_card_info() {
+        use crate::data::{ApplicationRecords, CardInfo};
+        use std::convert::TryFrom;
+
+        let fci = crate::tlv::TagList::try_from(crate::hex::decode(
+            "6F428407A0000000031010A537BF0C3442034761735F500B6578616D706C652E636F6D5F530E47423239544553543030303030305F5408544553544742324C5F55024445"
+        ).unwrap().as_slice()).unwrap();
+        let info = CardInfo::new(&fci, &ApplicationRecords::default());
+        assert_eq!(info.iin.as_deref(), Some("476173"));
+        assert_eq!(info.issuer_url.as_deref(), Some("example.com"));
+        assert_eq!(info.iban.as_deref(), Some("GB29TEST000000"));
+        assert_eq!(info.bic.as_deref(), Some("TESTGB2L"));
+        assert_eq!(info.issuer_country.map(|c| c.alpha2), Some("DE"));
+        assert_eq!(info.fields()[0], ("IIN", "476173"));
+        assert!(CardInfo::default().is_empty());
+
+        // The numeric country code in the records wins over the alphabetic one
+        let mut records = ApplicationRecords::default();
+        records.add_record(1, &[0x70, 0x05, 0x5f, 0x28, 0x02, 0x08, 0x26], false).unwrap();
+        let info = CardInfo::new(&fci, &records);
+        assert_eq!(info.issuer_country.map(|c| c.alpha2), Some("GB"));
+        assert_eq!(info.iin.as_deref(), Some("476173"));
+    }
+
+    #[test]
+    fn rejects_malformed_issuer_scripts() {
+        use crate::data::{DataError, IssuerScript};
+        use std::convert::TryFrom;
+
+        let script = |hex: &str| {
+            let tags = crate::tlv::TagList::try_from(crate::hex::decode(hex).unwrap().as_slice()).unwrap();
+            IssuerScript::try_from(tags.get_tag(crate::tlv::TagID::IssuerScriptTemplate2).unwrap())
+        };
+        assert_eq!(script("7208860600A4040001AA").unwrap().commands.len(), 1);
+        // Lc says there's more data than the command has
+        assert_eq!(script("7208860600A40400FF00").unwrap_err(), DataError::InvalidIssuerScriptCommand);
+        assert_eq!(script("72058603842400").unwrap_err(), DataError::InvalidIssuerScriptCommand);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::transport::CardTransport;
+
+/// Everything read from a card, with the scheme and issuer of each application
+/// where the BIN table knows them
+pub type CardData = crate::dump::CardDump;
+
+/// A terminal working through PC/SC, finding cards in whichever reader they're
+/// put in and running transactions with them without anyone to ask.
+///
+/// The PC/SC context is only set up once a card is first needed, and a card is
+/// held from [`wait_for_card`](Terminal::wait_for_card) until a transaction
+/// with it is over or it's [released](Terminal::release_card). Without the
+/// `pcsc` feature there are only cards reached some other way, through
+/// [`read_card_from`](Terminal::read_card_from) and
+/// [`run_transaction_on`](Terminal::run_transaction_on).
+pub struct Terminal {
+    /// What every transaction starts from, apart from the interface which
+    /// comes from the card
+    pub data: crate::terminal::TerminalData,
+    /// The interface to use in place of working it out from the card's ATR
+    pub interface: Option<crate::card::Interface>,
+    /// The reader to wait for a card in, rather than any of them
+    #[cfg(feature = "pcsc")]
+    pub reader: Option<crate::card::ReaderSelector>,
+    #[cfg(feature = "pcsc")]
+    pub connect: crate::card::ConnectOptions,
+    pub ca_keys: crate::capk::CapkStore,
+    pub bins: crate::bins::BinTable,
+    pub online_pin_key: Option<crate::pinblock::PinKey>,
+    /// Where to send transactions needing to go online, if anywhere
+    pub acquirer: Option<Box<dyn crate::acquirer::Acquirer>>,
+    #[cfg(feature = "pcsc")]
+    canceller: crate::card::Canceller,
+    #[cfg(feature = "pcsc")]
+    ctx: Option<pcsc::Context>,
+    #[cfg(feature = "pcsc")]
+    card: Option<crate::card::Session>,
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Terminal {
+    /// A terminal with the default terminal data, CA public keys and BIN table
+    pub fn new() -> Self {
+        Self {
+            data: crate::terminal::TerminalData::new(crate::card::Interface::Contact),
+            interface: None,
+            #[cfg(feature = "pcsc")]
+            reader: None,
+            #[cfg(feature = "pcsc")]
+            connect: Default::default(),
+            ca_keys: crate::capk::CapkStore::with_defaults(),
+            bins: crate::bins::BinTable::with_defaults(),
+            online_pin_key: None,
+            acquirer: None,
+            #[cfg(feature = "pcsc")]
+            canceller: Default::default(),
+            #[cfg(feature = "pcsc")]
+            ctx: None,
+            #[cfg(feature = "pcsc")]
+            card: None,
+        }
+    }
+
+    /// Stops waiting for a card, or the transaction with it, from another
+    /// thread. The card is reset and the transaction gives
+    /// [`Aborted`](crate::transaction::Outcome::Aborted).
+    #[cfg(feature = "pcsc")]
+    pub fn canceller(&self) -> crate::card::Canceller {
+        self.canceller.clone()
+    }
+
+    #[cfg(feature = "pcsc")]
+    fn context(&mut self) -> Result<&pcsc::Context, pcsc::Error> {
+        let ctx = match self.ctx.take() {
+            Some(c) => c,
+            None => {
+                let ctx = pcsc::Context::establish(pcsc::Scope::User)?;
+                self.canceller.watch(&ctx);
+                ctx
+            }
+        };
+        Ok(self.ctx.insert(ctx))
+    }
+
+    #[cfg(feature = "pcsc")]
+    fn take_card(&mut self) -> Result<crate::card::Session, pcsc::Error> {
+        if let Some(card) = self.card.take() {
+            return Ok(card);
+        }
+        self.canceller.reset();
+        let reader = self.reader.take();
+        let options = self.connect;
+        let canceller = self.canceller.clone();
+        let ctx = self.context();
+        let connected = ctx.and_then(|ctx| {
+            let reader = match &reader {
+                Some(s) => {
+                    let r = crate::card::resolve_reader(ctx, s)?;
+                    crate::card::wait_for_card(ctx, &r)?;
+                    r
+                }
+                None => crate::card::find_reader(ctx)?
+            };
+            let mut card = crate::card::Session::connect(ctx, &reader, options)?;
+            card.cancel_with(&canceller);
+            Ok(card)
+        });
+        self.reader = reader;
+        connected
+    }
+
+    /// Waits for a card to be presented and connects to it, if there isn't
+    /// one connected already
+    #[cfg(feature = "pcsc")]
+    pub fn wait_for_card(&mut self) -> Result<&crate::card::Session, pcsc::Error> {
+        let card = self.take_card()?;
+        Ok(self.card.insert(card))
+    }
+
+    /// Disconnects from the card, leaving it for the next one to be waited for
+    #[cfg(feature = "pcsc")]
+    pub fn release_card(&mut self) -> Result<(), pcsc::Error> {
+        match self.card.take() {
+            Some(card) => card.disconnect(),
+            None => Ok(())
+        }
+    }
+
+    /// Events from each transaction from now on, as it goes along, in place of
+    /// wherever they went before
+    pub fn events(&mut self) -> std::sync::mpsc::Receiver<crate::events::Event> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.data.events = Some(sender);
+        receiver
+    }
+
+    /// The terminal data to start a transaction with the card from
+    pub fn terminal_data<T: CardTransport + ?Sized>(&self, card: &T) -> crate::terminal::TerminalData {
+        let mut terminal = self.data.clone();
+        terminal.interface = self.interface.unwrap_or_else(|| crate::card::detect_interface(card));
+        terminal
+    }
+
+    /// Reads everything from the card, waiting for one first if need be. The
+    /// card stays connected for a transaction to follow.
+    #[cfg(feature = "pcsc")]
+    pub fn read_card(&mut self) -> Result<CardData, crate::card::CardError> {
+        let card = self.take_card()?;
+        let card_data = self.read_card_from(&card);
+        self.card = Some(card);
+        card_data
+    }
+
+    /// Reads everything from a card reached some other way than PC/SC
+    pub fn read_card_from<T: CardTransport + ?Sized>(&self, card: &T) -> Result<CardData, crate::card::CardError> {
+        let mut card_data = crate::dump::read_card(card, &self.terminal_data(card), &self.ca_keys)?;
+        card_data.annotate(&self.bins);
+        Ok(card_data)
+    }
+
+    /// Runs a transaction with the card, waiting for one first if need be,
+    /// then lets it go. Errors are for not getting as far as a card at all.
+    #[cfg(feature = "pcsc")]
+    pub fn run_transaction(&mut self, request: &crate::terminal::TransactionRequest) -> Result<crate::transaction::TransactionOutcome, crate::card::CardError> {
+        let mut card = self.take_card()?;
+        let outcome = self.run_transaction_on(&mut card, request);
+        match outcome.outcome {
+            o if o.card_removed() => {}
+            // Left as it was, the card would still be part way through
+            crate::transaction::Outcome::Aborted => card.disconnect_with(pcsc::Disposition::ResetCard)?,
+            _ => card.disconnect()?
+        }
+        Ok(outcome)
+    }
+
+    /// Runs a transaction with a card reached some other way than PC/SC
+    pub fn run_transaction_on<T: CardTransport + ?Sized>(&mut self, card: &mut T, request: &crate::terminal::TransactionRequest) -> crate::transaction::TransactionOutcome {
+        self.data.transaction_sequence_counter += 1;
+        let mut terminal = self.terminal_data(card);
+        request.apply(&mut terminal);
+        let acquirer = self.acquirer.as_mut().map(|a| &mut **a as &mut dyn crate::acquirer::Acquirer);
+        crate::run(card, &terminal, &self.ca_keys, self.online_pin_key.as_ref(), acquirer, false)
+            .unwrap_or_else(|e| crate::transaction::TransactionOutcome::card_error(&e, terminal.interface))
+    }
+}
@@ -0,0 +1,180 @@
+use aes::cipher::{BlockEncrypt, KeyInit};
+use std::convert::TryInto;
+
+/// The transaction counter is the rightmost 21 bits of the Key Serial Number
+const COUNTER_MASK: u32 = 0x1f_ffff;
+/// A counter with more one bits than this would need too many future keys
+const MAX_COUNTER_BITS: u32 = 10;
+
+const KEY_MASK: [u8; 16] = [0xc0, 0xc0, 0xc0, 0xc0, 0, 0, 0, 0, 0xc0, 0xc0, 0xc0, 0xc0, 0, 0, 0, 0];
+const PIN_VARIANT: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0xff];
+const DATA_VARIANT: [u8; 16] = [0, 0, 0, 0, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0xff, 0, 0];
+
+fn xor<const N: usize>(a: &[u8], b: &[u8]) -> [u8; N] {
+    let mut out = [0; N];
+    for (o, (a, b)) in out.iter_mut().zip(a.iter().zip(b)) {
+        *o = a ^ b;
+    }
+    out
+}
+
+fn des_encrypt(key: &[u8], block: [u8; 8]) -> [u8; 8] {
+    let mut block = aes::cipher::generic_array::GenericArray::from(block);
+    des::Des::new_from_slice(key).expect("Invalid DES key").encrypt_block(&mut block);
+    block.into()
+}
+
+fn tdes_encrypt(key: &[u8], block: &[u8]) -> Vec<u8> {
+    crate::pinblock::tdes_encrypt(key, block.try_into().expect("Not a DES block"))
+}
+
+fn counter(ksn: &[u8; 10]) -> u32 {
+    u32::from_be_bytes([0, ksn[7], ksn[8], ksn[9]]) & COUNTER_MASK
+}
+
+fn with_counter(ksn: &[u8; 10], counter: u32) -> [u8; 10] {
+    let mut out = *ksn;
+    let bytes = (u32::from_be_bytes([0, ksn[7], ksn[8], ksn[9]]) & !COUNTER_MASK | counter).to_be_bytes();
+    out[7..].copy_from_slice(&bytes[1..]);
+    out
+}
+
+/// The Key Serial Number with its transaction counter cleared, as the key was
+/// first injected
+pub fn initial_ksn(ksn: &[u8; 10]) -> [u8; 10] {
+    with_counter(ksn, 0)
+}
+
+/// The Key Serial Number of the transaction after `ksn`, skipping counters
+/// with more than ten one bits, or None once the key is used up
+pub fn next_ksn(ksn: &[u8; 10]) -> Option<[u8; 10]> {
+    let next = (counter(ksn) + 1..=COUNTER_MASK).find(|c| c.count_ones() <= MAX_COUNTER_BITS)?;
+    Some(with_counter(ksn, next))
+}
+
+/// ANSI X9.24-1 Initial PIN Encryption Key, from the Base Derivation Key and
+/// the Key Serial Number
+pub fn ipek(bdk: &[u8], ksn: &[u8; 10]) -> [u8; 16] {
+    let initial = initial_ksn(ksn);
+    let mut out = [0; 16];
+    out[..8].copy_from_slice(&tdes_encrypt(bdk, &initial[..8]));
+    out[8..].copy_from_slice(&tdes_encrypt(&xor::<16>(bdk, &KEY_MASK), &initial[..8]));
+    out
+}
+
+/// The non-reversible key generation process, giving the future key for the
+/// rightmost eight bytes of the KSN
+fn non_reversible_key(key: &[u8; 16], ksn_register: [u8; 8]) -> [u8; 16] {
+    let half = |key: &[u8; 16]| xor::<8>(&des_encrypt(&key[..8], xor(&ksn_register, &key[8..])), &key[8..]);
+    let mut out = [0; 16];
+    out[..8].copy_from_slice(&half(&xor(key, &KEY_MASK)));
+    out[8..].copy_from_slice(&half(key));
+    out
+}
+
+/// A DUKPT key as injected into the terminal, at the Key Serial Number of the
+/// transaction it's to be used for
+#[derive(Debug, Clone, PartialEq)]
+pub struct DukptKey {
+    pub ipek: [u8; 16],
+    pub ksn: [u8; 10],
+}
+
+impl std::str::FromStr for DukptKey {
+    type Err = String;
+
+    /// Parses `<ipek hex>:<ksn hex>`, or `bdk:<bdk hex>:<ksn hex>` for test
+    /// keys where the IPEK is derived here
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bdk, s) = match s.strip_prefix("bdk:") {
+            Some(s) => (true, s),
+            None => (false, s)
+        };
+        let (key, ksn) = s.split_once(':').ok_or_else(|| "DUKPT key must be <ipek>:<ksn> or bdk:<bdk>:<ksn>".to_string())?;
+        let key = crate::hex::decode(key).filter(|k| k.len() == 16).ok_or_else(|| "DUKPT key must be 16 bytes of hex".to_string())?;
+        let ksn: [u8; 10] = crate::hex::decode(ksn).and_then(|k| k.try_into().ok()).ok_or_else(|| "KSN must be 10 bytes of hex".to_string())?;
+        Ok(Self {
+            ipek: match bdk {
+                true => ipek(&key, &ksn),
+                false => key.try_into().unwrap()
+            },
+            ksn,
+        })
+    }
+}
+
+impl DukptKey {
+    /// The future key for this KSN, found by running the non-reversible key
+    /// generation process for each one bit of the counter in turn
+    pub fn transaction_key(&self) -> [u8; 16] {
+        let counter = counter(&self.ksn);
+        let mut register: [u8; 8] = initial_ksn(&self.ksn)[2..].try_into().unwrap();
+        let mut key = self.ipek;
+        for bit in (0..21).rev().map(|b| 1u32 << b).filter(|b| counter & b != 0) {
+            let bytes = bit.to_be_bytes();
+            for (r, b) in register[5..].iter_mut().zip(&bytes[1..]) {
+                *r |= b;
+            }
+            key = non_reversible_key(&key, register);
+        }
+        key
+    }
+
+    /// The key PIN blocks are encrypted under for this transaction
+    pub fn pin_key(&self) -> [u8; 16] {
+        xor(&self.transaction_key(), &PIN_VARIANT)
+    }
+
+    /// The request data encryption key, the data variant enciphered under
+    /// itself
+    pub fn data_key(&self) -> [u8; 16] {
+        let variant = xor::<16>(&self.transaction_key(), &DATA_VARIANT);
+        let mut out = [0; 16];
+        out[..8].copy_from_slice(&tdes_encrypt(&variant, &variant[..8]));
+        out[8..].copy_from_slice(&tdes_encrypt(&variant, &variant[8..]));
+        out
+    }
+
+    /// Enciphers data under the data key in CBC mode with a zero IV, padding
+    /// it with zeros to a whole number of blocks
+    pub fn encrypt_data(&self, data: &[u8]) -> Vec<u8> {
+        let key = self.data_key();
+        let mut out = vec![];
+        let mut chain = [0; 8];
+        for chunk in data.chunks(8) {
+            let mut block = [0; 8];
+            block[..chunk.len()].copy_from_slice(chunk);
+            let enciphered = tdes_encrypt(&key, &xor::<8>(&block, &chain));
+            chain.copy_from_slice(&enciphered);
+            out.extend(chain);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn derives_dukpt_keys() {
+        use std::convert::TryInto;
+
+        // ANSI X9.24-1 test key
+        let key: crate::dukpt::DukptKey = "bdk:0123456789ABCDEFFEDCBA9876543210:FFFF9876543210E00000".parse().unwrap();
+        assert_eq!(crate::hex::encode(&key.ipek), "6AC292FAA1315B4D858AB3A3D7D5933A");
+
+        let mut state = crate::state::TerminalState::default();
+        let ksn = state.next_ksn(&key.ksn).unwrap();
+        assert_eq!(crate::hex::encode(&ksn), "FFFF9876543210E00001");
+        assert_eq!(crate::hex::encode(&state.next_ksn(&key.ksn).unwrap()), "FFFF9876543210E00002");
+        // Counters with more than ten one bits are skipped
+        assert_eq!(crate::dukpt::next_ksn(&crate::hex::decode("FFFF9876543210EFFC00").unwrap().try_into().unwrap()).map(|k| crate::hex::encode(&k)),
+                   Some("FFFF9876543210F00000".to_string()));
+        assert_eq!(crate::dukpt::next_ksn(&crate::hex::decode("FFFF9876543210FFF800").unwrap().try_into().unwrap()), None);
+
+        let key = crate::dukpt::DukptKey { ksn, ..key };
+        assert_eq!(crate::hex::encode(&key.pin_key()), "042666B49184CF5C68DE9628D0397B36");
+        assert_eq!(crate::hex::encode(&key.data_key()), "448D3F076D8304036A55A3D7E0055A78");
+        let pin_key = crate::pinblock::PinKey::Dukpt(key);
+        assert_eq!(crate::pinblock::encrypt("1234", "4012345678909", &pin_key).map(|b| crate::hex::encode(&b)), Some("1B9C1845EB993A7A".to_string()));
+    }
+}
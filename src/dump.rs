@@ -0,0 +1,678 @@
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::transport::CardTransport;
+
+const GET_DATA_TAGS: [u16; 4] = [0x9f36, 0x9f13, 0x9f17, 0x9f4f];
+
+/// Everything else commonly held for GET DATA, for exploring a card: offline
+/// limits and counters, and scheme specific data
+const EXPLORE_GET_DATA_TAGS: [u16; 14] = [
+    0x9f14, 0x9f23, 0x9f51, 0x9f52, 0x9f53, 0x9f54, 0x9f56, 0x9f58,
+    0x9f59, 0x9f5c, 0x9f68, 0x9f6d, 0x9f78, 0x9f79,
+];
+
+/// SFIs 1 to 10 hold the application's records and 11 to 20 the issuer's own
+/// files, with 21 to 30 left to the payment system
+const SCAN_SFIS: std::ops::RangeInclusive<u8> = 1..=30;
+
+// Private class tags used to lay out the TLV blob format. Every dump is an E0
+// template holding the ATR, an E1 template for the PSE and an E2 template per
+// application; records, log records and GET DATA responses are E3, E4 and E5
+// templates nested inside those, and any ICC public key recovered is an E6
+// template.
+const TAG_DUMP: u32 = 0xe0;
+const TAG_DIRECTORY: u32 = 0xe1;
+const TAG_APPLICATION: u32 = 0xe2;
+const TAG_RECORD: u32 = 0xe3;
+const TAG_LOG_RECORD: u32 = 0xe4;
+const TAG_DATA_OBJECT: u32 = 0xe5;
+const TAG_PUBLIC_KEY: u32 = 0xe6;
+const TAG_ATR: u32 = 0xdf01;
+const TAG_NAME: u32 = 0xdf02;
+const TAG_FCI: u32 = 0xdf03;
+const TAG_GPO: u32 = 0xdf04;
+const TAG_RECORD_LOCATION: u32 = 0xdf05;
+const TAG_RECORD_DATA: u32 = 0xdf06;
+const TAG_DATA_OBJECT_TAG: u32 = 0xdf07;
+const TAG_DATA_OBJECT_VALUE: u32 = 0xdf08;
+const TAG_PUBLIC_KEY_MODULUS: u32 = 0xdf09;
+const TAG_PUBLIC_KEY_EXPONENT: u32 = 0xdf0a;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DumpFormat {
+    Json,
+    /// The TLV blob described above, keeping where everything was read from
+    Tlv,
+    /// The card's BER-TLV responses back to back
+    Ber,
+    /// The card's BER-TLV responses in hex, one per line
+    Hex,
+    /// A `tag=value` line in hex for every primitive data object the card
+    /// sent
+    TagValue,
+}
+
+pub fn as_hex<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&crate::hex::encode(data))
+}
+
+pub fn as_optional_hex<S: Serializer>(data: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match data {
+        Some(d) => serializer.serialize_some(&crate::hex::encode(d)),
+        None => serializer.serialize_none()
+    }
+}
+
+pub fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    crate::hex::decode(&s).ok_or_else(|| serde::de::Error::custom(format!("Invalid hex: {}", s)))
+}
+
+pub fn from_optional_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => crate::hex::decode(&s).map(Some).ok_or_else(|| serde::de::Error::custom(format!("Invalid hex: {}", s))),
+        None => Ok(None)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordDump {
+    pub sfi: u8,
+    pub record: u8,
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataObjectDump {
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub tag: Vec<u8>,
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublicKeyDump {
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub modulus: Vec<u8>,
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub exponent: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectoryDump {
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub name: Vec<u8>,
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub fci: Vec<u8>,
+    pub records: Vec<RecordDump>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApplicationDump {
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub aid: Vec<u8>,
+    #[serde(serialize_with = "as_hex", deserialize_with = "from_hex")]
+    pub fci: Vec<u8>,
+    #[serde(serialize_with = "as_optional_hex", deserialize_with = "from_optional_hex")]
+    pub processing_options: Option<Vec<u8>>,
+    pub records: Vec<RecordDump>,
+    pub log: Vec<RecordDump>,
+    pub data_objects: Vec<DataObjectDump>,
+    /// The ICC public key, if the card has one and it could be recovered with
+    /// the terminal's CA keys
+    pub icc_public_key: Option<PublicKeyDump>,
+    /// The payment scheme, from the PAN or failing that the AID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+}
+
+impl ApplicationDump {
+    /// The BER-TLV the card sent for the application: its FCI, GPO response,
+    /// records and GET DATA responses
+    pub fn card_objects(&self) -> Vec<&[u8]> {
+        let mut out = vec![self.fci.as_slice()];
+        out.extend(self.processing_options.as_deref());
+        out.extend(self.records.iter().map(|r| r.data.as_slice()));
+        out.extend(self.data_objects.iter().map(|d| d.data.as_slice()));
+        out
+    }
+
+    /// The currencies and countries the card gave, by name
+    pub fn codes(&self) -> Vec<(crate::tlv::TagID, String)> {
+        self.card_objects().into_iter()
+            .filter_map(|o| crate::tlv::TagList::try_from(o).ok())
+            .flat_map(|t| crate::iso::describe_tags(&t))
+            .collect()
+    }
+
+    /// The transaction log read with the application, decoded by the Log
+    /// Format if that was read too
+    pub fn transaction_log(&self) -> Option<crate::history::TransactionLog> {
+        let format = self.data_objects.iter()
+            .find(|d| d.tag == [0x9f, 0x4f])
+            .and_then(|d| crate::history::parse_log_format(&d.data))?;
+        Some(crate::history::TransactionLog {
+            aid: self.aid.clone(),
+            entries: crate::history::decode_log(&format, &self.log),
+        })
+    }
+
+    /// The PAN, cardholder name, dates and service code from the
+    /// application's records
+    pub fn application_data(&self) -> crate::data::ApplicationData {
+        let mut records = crate::data::ApplicationRecords::default();
+        for r in &self.records {
+            let _ = records.add_record(r.sfi, &r.data, false);
+        }
+        crate::data::ApplicationData::from(&records)
+    }
+
+    /// The issuer's details from the application's FCI and records
+    pub fn card_info(&self) -> crate::data::CardInfo {
+        let mut records = crate::data::ApplicationRecords::default();
+        for r in &self.records {
+            let _ = records.add_record(r.sfi, &r.data, false);
+        }
+        let fci = crate::tlv::TagList::try_from(self.fci.as_slice()).unwrap_or_default();
+        crate::data::CardInfo::new(&fci, &records)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CardDump {
+    #[serde(serialize_with = "as_optional_hex", deserialize_with = "from_optional_hex")]
+    pub atr: Option<Vec<u8>>,
+    pub pse: Option<DirectoryDump>,
+    pub applications: Vec<ApplicationDump>,
+}
+
+pub(crate) fn read_records<T: CardTransport + ?Sized>(card: &T, sfi: u8, records: impl Iterator<Item=u8>) -> Result<Vec<RecordDump>, crate::card::CardError> {
+    let mut out = vec![];
+    for record in records {
+        match crate::card::optional(crate::card::card_read_record_raw(card, sfi, record))? {
+            Some(data) => out.push(RecordDump {
+                sfi,
+                record,
+                data,
+            }),
+            None => break
+        }
+    }
+    Ok(out)
+}
+
+pub(crate) fn read_directory<T: CardTransport + ?Sized>(card: &T, name: &[u8]) -> Result<Option<DirectoryDump>, crate::card::CardError> {
+    let fci = match crate::card::optional(crate::card::card_select_raw(card, name, false))? {
+        Some(f) => f,
+        None => return Ok(None)
+    };
+    let sfi = crate::tlv::TagList::try_from(fci.as_slice()).ok().and_then(|tl| {
+        match tl.get_tag(crate::tlv::TagID::FileControlInformationTemplate)?
+            .get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate)?
+            .get_tag(crate::tlv::TagID::ShortFileIdentifier)?
+            .contents() {
+            crate::tlv::TagContents::Byte(b) => Some(*b),
+            _ => None
+        }
+    });
+    let records = match sfi {
+        Some(sfi) => read_records(card, sfi, 1..=u8::MAX)?,
+        None => vec![]
+    };
+
+    Ok(Some(DirectoryDump {
+        name: name.to_vec(),
+        fci,
+        records,
+    }))
+}
+
+fn push_aids(out: &mut Vec<Vec<u8>>, applications: Vec<&crate::tlv::Tag>) {
+    for application in applications {
+        if let Some(crate::tlv::TagContents::Bytes(aid)) = application.get_tag(crate::tlv::TagID::ApplicationDedicatedFileName).map(|t| t.contents()) {
+            if !out.iter().any(|a| a[..] == aid[..]) {
+                out.push(aid.to_vec());
+            }
+        }
+    }
+}
+
+pub(crate) fn directory_aids(directory: &DirectoryDump) -> Vec<Vec<u8>> {
+    let mut out = vec![];
+    // A PPSE lists its entries straight in the FCI
+    if let Ok(fci) = crate::tlv::TagList::try_from(directory.fci.as_slice()) {
+        let entries = fci.get_tag(crate::tlv::TagID::FileControlInformationTemplate)
+            .and_then(|t| t.get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate))
+            .and_then(|t| t.get_tag(crate::tlv::TagID::FileControlInformationIssuerDiscretionaryData));
+        if let Some(entries) = entries {
+            push_aids(&mut out, entries.get_tags(crate::tlv::TagID::ApplicationTemplate));
+        }
+    }
+    for record in &directory.records {
+        let record = match crate::tlv::TagList::try_from(record.data.as_slice()) {
+            Ok(r) => r,
+            Err(_) => continue
+        };
+        let template = match record.get_tag(crate::tlv::TagID::ReadRecordResponseMessageTemplate) {
+            Some(t) => t,
+            None => continue
+        };
+        push_aids(&mut out, template.get_tags(crate::tlv::TagID::ApplicationTemplate));
+    }
+    out
+}
+
+fn read_application<T: CardTransport + ?Sized>(card: &T, aid: &[u8], terminal: &crate::terminal::TerminalData, ca_keys: &crate::capk::CapkStore, explore: bool) -> Result<Option<ApplicationDump>, crate::card::CardError> {
+    let fci = match crate::card::optional(crate::card::card_select_raw(card, aid, false))? {
+        Some(f) => f,
+        None => return Ok(None)
+    };
+    let fci_tags = crate::tlv::TagList::try_from(fci.as_slice()).ok();
+    let fcipt = fci_tags.as_ref()
+        .and_then(|tl| tl.get_tag(crate::tlv::TagID::FileControlInformationTemplate))
+        .and_then(|fci| fci.get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate));
+
+    let mut log = vec![];
+    let log_entry = fcipt
+        .and_then(|t| t.get_tag(crate::tlv::TagID::FileControlInformationIssuerDiscretionaryData))
+        .and_then(|t| t.get_tag(crate::tlv::TagID::LogEntry));
+    if let Some(crate::tlv::TagContents::Bytes(b)) = log_entry.map(|t| t.contents()) {
+        if b.len() == 2 {
+            log = read_records(card, b[0], 1..=b[1])?;
+        }
+    }
+
+    let mut pdol = match fcipt.and_then(|t| t.get_tag(crate::tlv::TagID::ProcessingOptionsDataObjectList)).map(|t| t.contents()) {
+        Some(crate::tlv::TagContents::Bytes(b)) => crate::tlv::DOL::try_from(&b[..]).unwrap_or_else(|_| crate::tlv::DOL::new()),
+        _ => crate::tlv::DOL::new()
+    };
+    pdol.fill(terminal);
+    let processing_options = crate::card::optional(crate::card::card_get_processing_options_raw(card, &crate::card::gpo_command_data(pdol)))?;
+
+    let mut records = vec![];
+    let mut application_records = crate::data::ApplicationRecords::default();
+    let parsed_options = processing_options.as_ref()
+        .and_then(|po| crate::tlv::TagList::try_from(po.as_slice()).ok())
+        .and_then(|tl| crate::data::ProcessingOptions::try_from(&tl).ok());
+    if let Some(po) = &parsed_options {
+        for entry in &po.afl {
+            let entry_records = read_records(card, entry.short_file_identifier, entry.records())?;
+            for r in &entry_records {
+                let for_oda = r.record - entry.first_record < entry.oda_records;
+                let _ = application_records.add_record(r.sfi, &r.data, for_oda);
+            }
+            records.extend(entry_records);
+        }
+    }
+    // Without an AFL to go on, every record in every SFI is worth a try
+    if explore && records.is_empty() {
+        for sfi in SCAN_SFIS {
+            records.extend(read_records(card, sfi, 1..=u8::MAX)?);
+        }
+    }
+
+    let icc_public_key = parsed_options.as_ref()
+        .and_then(|po| crate::auth::recover_icc_key(ca_keys, aid, &application_records, po.aip, terminal.transaction_date()).ok())
+        .map(|k| PublicKeyDump {
+            modulus: k.modulus,
+            exponent: k.exponent,
+        });
+
+    let mut data_objects = vec![];
+    let get_data_tags = match explore {
+        true => [&GET_DATA_TAGS[..], &EXPLORE_GET_DATA_TAGS[..]].concat(),
+        false => GET_DATA_TAGS.to_vec()
+    };
+    for tag in &get_data_tags {
+        if let Some(data) = crate::card::optional(crate::card::card_get_data_raw(card, *tag))? {
+            data_objects.push(DataObjectDump {
+                tag: tag.to_be_bytes().to_vec(),
+                data,
+            });
+        }
+    }
+
+    Ok(Some(ApplicationDump {
+        aid: aid.to_vec(),
+        fci,
+        processing_options,
+        records,
+        log,
+        data_objects,
+        icc_public_key,
+        scheme: None,
+        issuer: None,
+    }))
+}
+
+/// Reads everything of interest off the card: the PSE (or PPSE when contactless),
+/// and for every application listed in it (or every known application if there
+/// is no directory) the FCI, the GPO response, all AFL records, the transaction
+/// log and common GET DATA objects. ICC public keys are recovered where
+/// `ca_keys` has the key their issuer is certified under.
+pub fn read_card<T: CardTransport + ?Sized>(card: &T, terminal: &crate::terminal::TerminalData, ca_keys: &crate::capk::CapkStore) -> Result<CardDump, crate::card::CardError> {
+    read(card, terminal, ca_keys, false)
+}
+
+/// Reads even more than [`read_card`], for exploring a card: every known
+/// application is tried whether the directory lists it or not, applications
+/// without an AFL have their SFIs scanned for records, and many more GET DATA
+/// objects are asked for.
+pub fn explore_card<T: CardTransport + ?Sized>(card: &T, terminal: &crate::terminal::TerminalData, ca_keys: &crate::capk::CapkStore) -> Result<CardDump, crate::card::CardError> {
+    read(card, terminal, ca_keys, true)
+}
+
+fn read<T: CardTransport + ?Sized>(card: &T, terminal: &crate::terminal::TerminalData, ca_keys: &crate::capk::CapkStore, explore: bool) -> Result<CardDump, crate::card::CardError> {
+    let atr = card.atr().ok().map(|a| a.raw().to_vec());
+    let pse = read_directory(card, terminal.interface.directory_name())?;
+
+    let known_aids = || terminal.applications.iter().map(|a| a.aid.clone());
+    let mut aids = pse.as_ref().map(directory_aids).unwrap_or_default();
+    if aids.is_empty() || explore {
+        for aid in known_aids() {
+            if !aids.iter().any(|a| a.starts_with(&aid)) {
+                aids.push(aid);
+            }
+        }
+    }
+
+    let mut applications = vec![];
+    for aid in aids {
+        if let Some(a) = read_application(card, &aid, terminal, ca_keys, explore)? {
+            applications.push(a);
+        }
+    }
+
+    Ok(CardDump {
+        atr,
+        pse,
+        applications,
+    })
+}
+
+fn bytes_tag(id: u32, data: &[u8]) -> crate::tlv::Tag {
+    crate::tlv::Tag::new(crate::tlv::TagID::from(id), crate::tlv::TagContents::Bytes(data.into()))
+}
+
+fn constructed_tag(id: u32, tags: Vec<crate::tlv::Tag>) -> crate::tlv::Tag {
+    let mut tl = crate::tlv::TagList::new();
+    for t in tags {
+        tl.add_tag(t);
+    }
+    crate::tlv::Tag::new(crate::tlv::TagID::from(id), crate::tlv::TagContents::Constructed(tl))
+}
+
+fn records_tags(template: u32, records: &[RecordDump]) -> Vec<crate::tlv::Tag> {
+    records.iter().map(|r| constructed_tag(template, vec![
+        bytes_tag(TAG_RECORD_LOCATION, &[r.sfi, r.record]),
+        bytes_tag(TAG_RECORD_DATA, &r.data),
+    ])).collect()
+}
+
+impl From<&CardDump> for Vec<u8> {
+    fn from(value: &CardDump) -> Self {
+        let mut tags = vec![];
+        if let Some(atr) = &value.atr {
+            tags.push(bytes_tag(TAG_ATR, atr));
+        }
+        if let Some(pse) = &value.pse {
+            let mut pse_tags = vec![bytes_tag(TAG_NAME, &pse.name), bytes_tag(TAG_FCI, &pse.fci)];
+            pse_tags.extend(records_tags(TAG_RECORD, &pse.records));
+            tags.push(constructed_tag(TAG_DIRECTORY, pse_tags));
+        }
+        for application in &value.applications {
+            let mut app_tags = vec![bytes_tag(TAG_NAME, &application.aid), bytes_tag(TAG_FCI, &application.fci)];
+            if let Some(po) = &application.processing_options {
+                app_tags.push(bytes_tag(TAG_GPO, po));
+            }
+            app_tags.extend(records_tags(TAG_RECORD, &application.records));
+            app_tags.extend(records_tags(TAG_LOG_RECORD, &application.log));
+            for data_object in &application.data_objects {
+                app_tags.push(constructed_tag(TAG_DATA_OBJECT, vec![
+                    bytes_tag(TAG_DATA_OBJECT_TAG, &data_object.tag),
+                    bytes_tag(TAG_DATA_OBJECT_VALUE, &data_object.data),
+                ]));
+            }
+            if let Some(key) = &application.icc_public_key {
+                app_tags.push(constructed_tag(TAG_PUBLIC_KEY, vec![
+                    bytes_tag(TAG_PUBLIC_KEY_MODULUS, &key.modulus),
+                    bytes_tag(TAG_PUBLIC_KEY_EXPONENT, &key.exponent),
+                ]));
+            }
+            tags.push(constructed_tag(TAG_APPLICATION, app_tags));
+        }
+
+        let mut tl = crate::tlv::TagList::new();
+        tl.add_tag(constructed_tag(TAG_DUMP, tags));
+        Vec::<u8>::from(&tl)
+    }
+}
+
+impl CardDump {
+    /// Notes the scheme and issuer of each application from the BIN table
+    pub fn annotate(&mut self, bins: &crate::bins::BinTable) {
+        for application in &mut self.applications {
+            let range = application.application_data().pan.and_then(|p| bins.lookup(p.reveal()).cloned());
+            application.scheme = range.as_ref().map(|r| r.scheme.clone())
+                .or_else(|| crate::bins::scheme_for_aid(&application.aid).map(str::to_string));
+            application.issuer = range.and_then(|r| r.issuer);
+        }
+    }
+
+    /// The BER-TLV the card sent, in the order it was read: the PSE's FCI and
+    /// records, then each application's FCI, GPO response, records and GET DATA
+    /// responses. Log records have no tags of their own so are left out.
+    pub fn card_objects(&self) -> Vec<&[u8]> {
+        let mut out = vec![];
+        if let Some(pse) = &self.pse {
+            out.push(pse.fci.as_slice());
+            out.extend(pse.records.iter().map(|r| r.data.as_slice()));
+        }
+        for application in &self.applications {
+            out.extend(application.card_objects());
+        }
+        out
+    }
+}
+
+/// A response from the card as a tree of tags, or in hex where it isn't
+/// BER-TLV
+fn pretty_object(f: &mut std::fmt::Formatter<'_>, title: &str, data: &[u8]) -> std::fmt::Result {
+    writeln!(f, "  {}:", title)?;
+    match crate::tlv::TagList::try_from(data) {
+        Ok(tags) if !tags.tags().is_empty() => write!(f, "{}", tags.pretty(4)),
+        _ => write!(f, "{}", crate::hex::Dump::new(data).indent(4))
+    }
+}
+
+impl std::fmt::Display for CardDump {
+    /// Everything read, with the card's responses pretty printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(atr) = &self.atr {
+            writeln!(f, "ATR: {}", crate::hex::Hex(atr))?;
+        }
+        match &self.pse {
+            Some(pse) => {
+                writeln!(f, "Directory {}", String::from_utf8_lossy(&pse.name))?;
+                pretty_object(f, "FCI", &pse.fci)?;
+                for r in &pse.records {
+                    pretty_object(f, &format!("Record {} in SFI {}", r.record, r.sfi), &r.data)?;
+                }
+            }
+            None => writeln!(f, "No directory")?
+        }
+        for application in &self.applications {
+            write!(f, "Application {}", crate::hex::Hex(&application.aid))?;
+            match (&application.scheme, &application.issuer) {
+                (Some(scheme), Some(issuer)) => writeln!(f, " ({} card issued by {})", scheme, issuer)?,
+                (Some(scheme), None) => writeln!(f, " ({} card)", scheme)?,
+                _ => writeln!(f)?
+            }
+            let data = application.application_data();
+            if let Some(name) = &data.cardholder_name {
+                writeln!(f, "  Cardholder: {}", name)?;
+            }
+            match (data.effective_date, data.expiry_date) {
+                (Some(effective), Some(expiry)) => writeln!(f, "  Valid from {} until {}", effective, expiry)?,
+                (None, Some(expiry)) => writeln!(f, "  Valid until {}", expiry)?,
+                (Some(effective), None) => writeln!(f, "  Valid from {}", effective)?,
+                (None, None) => {}
+            }
+            if let Some(service_code) = data.service_code {
+                writeln!(f, "  Service code {}", service_code)?;
+            }
+            for (name, value) in application.card_info().fields() {
+                writeln!(f, "  {}: {}", name, value)?;
+            }
+            pretty_object(f, "FCI", &application.fci)?;
+            match &application.processing_options {
+                Some(po) => pretty_object(f, "GET PROCESSING OPTIONS", po)?,
+                None => writeln!(f, "  GET PROCESSING OPTIONS refused")?
+            }
+            for r in &application.records {
+                pretty_object(f, &format!("Record {} in SFI {}", r.record, r.sfi), &r.data)?;
+            }
+            for r in &application.log {
+                writeln!(f, "  Log record {}: {}", r.record, crate::hex::Hex(&r.data))?;
+            }
+            for d in &application.data_objects {
+                pretty_object(f, &format!("GET DATA {}", crate::hex::Hex(&d.tag)), &d.data)?;
+            }
+            for (tag, description) in application.codes() {
+                writeln!(f, "  {:?}: {}", tag, description)?;
+            }
+            if let Some(key) = &application.icc_public_key {
+                writeln!(f, "  ICC public key: {}, exponent {}", crate::hex::Hex(&key.modulus), crate::hex::Hex(&key.exponent))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tag_value_lines(tags: &crate::tlv::TagList, out: &mut String) {
+    for tag in tags.tags() {
+        match tag.contents() {
+            crate::tlv::TagContents::Constructed(tl) => tag_value_lines(tl, out),
+            contents => {
+                let _ = writeln!(out, "{}={}", crate::hex::Hex(&Vec::<u8>::from(tag.id())), crate::hex::Hex(&Vec::<u8>::from(contents)));
+            }
+        }
+    }
+}
+
+/// The dump in the given format. Objects that aren't valid BER-TLV are left
+/// out of the `tag=value` format.
+pub fn encode_dump(card_dump: &CardDump, format: DumpFormat) -> std::io::Result<Vec<u8>> {
+    Ok(match format {
+        DumpFormat::Json => serde_json::to_vec_pretty(card_dump)?,
+        DumpFormat::Tlv => Vec::<u8>::from(card_dump),
+        DumpFormat::Ber => card_dump.card_objects().concat(),
+        DumpFormat::Hex => {
+            let mut out = String::new();
+            for object in card_dump.card_objects() {
+                let _ = writeln!(out, "{}", crate::hex::Hex(object));
+            }
+            out.into_bytes()
+        }
+        DumpFormat::TagValue => {
+            let mut out = String::new();
+            for object in card_dump.card_objects() {
+                if let Ok(tags) = crate::tlv::TagList::try_from(object) {
+                    tag_value_lines(&tags, &mut out);
+                }
+            }
+            out.into_bytes()
+        }
+    })
+}
+
+pub fn write_dump(card_dump: &CardDump, path: &str, format: DumpFormat) -> std::io::Result<()> {
+    std::fs::write(path, encode_dump(card_dump, format)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    /// What reading a Visa Debit card gives: the PSE's FCI and record, then the
+    /// application's FCI, GPO response and a record with the PAN
+    fn visa_dump() -> crate::dump::CardDump {
+        let hex = |h: &str| crate::hex::decode(h).unwrap();
+        crate::dump::CardDump {
+            atr: None,
+            pse: Some(crate::dump::DirectoryDump {
+                name: b"1PAY.SYS.DDF01".to_vec(),
+                fci: hex("6F1A840E315041592E5359532E4444463031A5088801015F2D02656E"),
+                records: vec![crate::dump::RecordDump {
+                    sfi: 1,
+                    record: 1,
+                    data: hex("701A61184F07A0000000031010500A56495341204445424954870101"),
+                }],
+            }),
+            applications: vec![crate::dump::ApplicationDump {
+                aid: hex("A0000000031010"),
+                fci: hex("6F1A8407A0000000031010A50F500A56495341204445424954870101"),
+                processing_options: Some(hex("80061C0008010100")),
+                records: vec![crate::dump::RecordDump {
+                    sfi: 1,
+                    record: 1,
+                    data: hex("70105A0847617390010100105F2403301231"),
+                }],
+                log: vec![],
+                data_objects: vec![],
+                icc_public_key: None,
+                scheme: None,
+                issuer: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn exports_tlv_formats() {
+        let card_dump = visa_dump();
+        let objects = card_dump.card_objects();
+        assert_eq!(objects.len(), 5);
+
+        let ber = crate::dump::encode_dump(&card_dump, crate::dump::DumpFormat::Ber).unwrap();
+        assert_eq!(ber, objects.concat());
+        assert_eq!(ber[0], 0x6f);
+        assert!(crate::tlv::TagList::try_from(ber.as_slice()).is_ok());
+
+        let hex = String::from_utf8(crate::dump::encode_dump(&card_dump, crate::dump::DumpFormat::Hex).unwrap()).unwrap();
+        assert_eq!(hex.lines().count(), objects.len());
+        assert_eq!(crate::hex::decode(hex.lines().nth(2).unwrap()).as_deref(), Some(objects[2]));
+
+        let tags = String::from_utf8(crate::dump::encode_dump(&card_dump, crate::dump::DumpFormat::TagValue).unwrap()).unwrap();
+        assert!(tags.lines().any(|l| l == "5A=4761739001010010"));
+        assert!(tags.lines().any(|l| l == "4F=A0000000031010"));
+        assert!(tags.lines().all(|l| l.split_once('=').is_some()));
+    }
+
+    #[test]
+    fn annotates_dumps_with_bins() {
+        let mut card_dump = visa_dump();
+        let mut bins = crate::bins::BinTable::with_defaults();
+        card_dump.annotate(&bins);
+        assert_eq!(card_dump.applications[0].scheme.as_deref(), Some("Visa"));
+        assert_eq!(card_dump.applications[0].issuer, None);
+
+        for range in crate::bins::parse_csv("476173,476173,Visa,Test Bank").unwrap() {
+            bins.insert(range);
+        }
+        card_dump.annotate(&bins);
+        assert_eq!(card_dump.applications[0].issuer.as_deref(), Some("Test Bank"));
+        let json = serde_json::to_value(&card_dump).unwrap();
+        assert_eq!(json["applications"][0]["issuer"], "Test Bank");
+
+        // Without a PAN the AID says which scheme it is
+        card_dump.applications[0].records.clear();
+        card_dump.annotate(&bins);
+        assert_eq!(card_dump.applications[0].scheme.as_deref(), Some("Visa"));
+        assert_eq!(card_dump.applications[0].issuer, None);
+        assert!(serde_json::to_value(&card_dump).unwrap()["applications"][0].get("issuer").is_none());
+    }
+}
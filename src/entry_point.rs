@@ -0,0 +1,190 @@
+use std::convert::TryFrom;
+use crate::terminal::TerminalData;
+use crate::transport::CardTransport;
+
+pub const KERNEL_MASTERCARD: u8 = 2;
+pub const KERNEL_VISA: u8 = 3;
+pub const KERNEL_AMEX: u8 = 4;
+pub const KERNEL_JCB: u8 = 5;
+pub const KERNEL_DISCOVER: u8 = 6;
+pub const KERNEL_UNIONPAY: u8 = 7;
+
+const DEFAULT_KERNELS: [([u8; 5], u8); 8] = [
+    ([0xa0, 0x00, 0x00, 0x00, 0x04], KERNEL_MASTERCARD),
+    ([0xa0, 0x00, 0x00, 0x00, 0x03], KERNEL_VISA),
+    ([0xa0, 0x00, 0x00, 0x00, 0x98], KERNEL_VISA),
+    ([0xa0, 0x00, 0x00, 0x03, 0x24], KERNEL_DISCOVER),
+    ([0xa0, 0x00, 0x00, 0x00, 0x25], KERNEL_AMEX),
+    ([0xa0, 0x00, 0x00, 0x00, 0x65], KERNEL_JCB),
+    ([0xa0, 0x00, 0x00, 0x01, 0x52], KERNEL_DISCOVER),
+    ([0xa0, 0x00, 0x00, 0x03, 0x33], KERNEL_UNIONPAY),
+];
+
+// Terminal Transaction Qualifiers
+const TTQ_OFFLINE_ONLY: (usize, u8) = (0, 0x08);
+const TTQ_SIGNATURE_SUPPORTED: (usize, u8) = (0, 0x02);
+const TTQ_ONLINE_CRYPTOGRAM_REQUIRED: (usize, u8) = (1, 0x80);
+const TTQ_CVM_REQUIRED: (usize, u8) = (1, 0x40);
+
+/// Contactless limits for one application, each falling back to the
+/// terminal-wide setting when not given
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ReaderLimits {
+    /// At or above this the application can't be used contactless at all
+    pub transaction_limit: Option<u64>,
+    pub floor_limit: Option<u64>,
+    /// At or above this the cardholder has to be verified
+    pub cvm_required_limit: Option<u64>,
+    /// Whether a transaction for a single unit of currency is taken as a
+    /// request to check the card's status online
+    pub status_check_supported: bool,
+    pub zero_amount_allowed: bool,
+}
+
+/// Book B 3.1.1: what pre-processing found for one application
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PreProcessingIndicators {
+    pub status_check_requested: bool,
+    pub contactless_application_not_allowed: bool,
+    pub zero_amount: bool,
+    pub cvm_required_limit_exceeded: bool,
+    pub floor_limit_exceeded: bool,
+    /// The TTQ to send the card, with the online cryptogram and CVM required
+    /// bits set to suit the amount
+    pub ttq: [u8; 4],
+}
+
+/// Book B 3.1.1: checks the amount against the application's reader limits
+pub fn pre_process(terminal: &TerminalData, application: &crate::selection::TerminalApplication) -> PreProcessingIndicators {
+    let limits = &application.reader_limits;
+    let amount = terminal.amount_authorised;
+    let mut out = PreProcessingIndicators {
+        ttq: terminal.terminal_transaction_qualifiers,
+        ..Default::default()
+    };
+    let set_ttq = |ttq: &mut [u8; 4], (byte, mask): (usize, u8)| ttq[byte] |= mask;
+    out.ttq[1] &= !(TTQ_ONLINE_CRYPTOGRAM_REQUIRED.1 | TTQ_CVM_REQUIRED.1);
+    if terminal.is_unattended() {
+        out.ttq[TTQ_SIGNATURE_SUPPORTED.0] &= !TTQ_SIGNATURE_SUPPORTED.1;
+    }
+
+    if limits.status_check_supported && amount == 10u64.pow(terminal.currency_exponent as u32) {
+        out.status_check_requested = true;
+    }
+    if amount == 0 {
+        if limits.zero_amount_allowed {
+            out.zero_amount = true;
+        } else {
+            out.contactless_application_not_allowed = true;
+        }
+    }
+    if amount >= limits.transaction_limit.unwrap_or(terminal.contactless_transaction_limit) {
+        out.contactless_application_not_allowed = true;
+    }
+    if amount > limits.floor_limit.unwrap_or(application.floor_limit) {
+        out.floor_limit_exceeded = true;
+    }
+    if amount >= limits.cvm_required_limit.unwrap_or(terminal.contactless_cvm_limit) {
+        out.cvm_required_limit_exceeded = true;
+    }
+
+    if out.floor_limit_exceeded || out.status_check_requested {
+        set_ttq(&mut out.ttq, TTQ_ONLINE_CRYPTOGRAM_REQUIRED);
+    }
+    if out.zero_amount {
+        // An offline only reader has no way to take a zero amount online
+        if out.ttq[TTQ_OFFLINE_ONLY.0] & TTQ_OFFLINE_ONLY.1 != 0 {
+            out.contactless_application_not_allowed = true;
+        } else {
+            set_ttq(&mut out.ttq, TTQ_ONLINE_CRYPTOGRAM_REQUIRED);
+        }
+    }
+    if out.cvm_required_limit_exceeded {
+        set_ttq(&mut out.ttq, TTQ_CVM_REQUIRED);
+    }
+    out
+}
+
+/// The kernel an AID uses when its directory entry doesn't name one
+pub fn default_kernel(aid: &[u8]) -> Option<u8> {
+    DEFAULT_KERNELS.iter().find(|(rid, _)| aid.starts_with(rid)).map(|(_, k)| *k)
+}
+
+/// Book B 3.3.2.5: the kernel a directory entry asks for, falling back to the
+/// scheme's own kernel when it doesn't say. Only the international kernels
+/// are known, so domestic and reserved kernel types give `None`.
+pub fn requested_kernel(application: &crate::data::Application) -> Option<u8> {
+    match application.kernel_identifier() {
+        None | Some([]) | Some([0, ..]) => default_kernel(application.aid()),
+        Some([k, ..]) if k & 0xc0 == 0x00 => Some(k & 0x3f),
+        Some(_) => None
+    }
+}
+
+/// An application on the card paired with the kernel to process it, that the
+/// terminal supports and pre-processing allows
+#[derive(Clone)]
+pub struct Combination {
+    pub application: crate::data::Application,
+    pub kernel_id: u8,
+    pub indicators: PreProcessingIndicators,
+}
+
+/// Book B 3.3: builds the candidate combinations from the PPSE, highest
+/// priority first
+pub fn build_combinations<T: CardTransport + ?Sized>(card: &T, terminal: &TerminalData, terminal_applications: &[crate::selection::TerminalApplication]) -> Result<Vec<Combination>, crate::card::CardError> {
+    let entries = match crate::selection::read_ppse_entries(card)? {
+        Some(e) => e,
+        None => return Ok(vec![])
+    };
+
+    let mut combinations = vec![];
+    for entry in &entries {
+        let application = match crate::data::Application::try_from(entry) {
+            Ok(a) => a,
+            Err(_) => continue
+        };
+        let kernel_id = match requested_kernel(&application) {
+            Some(k) => k,
+            None => continue
+        };
+        let config = terminal_applications.iter().find(|t| {
+            let supported_kernel = match t.kernel_id {
+                0 => default_kernel(&t.aid),
+                k => Some(k)
+            };
+            t.matches(application.aid()) && supported_kernel == Some(kernel_id)
+        });
+        if let Some(config) = config {
+            let indicators = pre_process(terminal, config);
+            if !indicators.contactless_application_not_allowed {
+                combinations.push(Combination {
+                    application,
+                    kernel_id,
+                    indicators,
+                });
+            }
+        }
+    }
+
+    crate::selection::sort_by_priority(&mut combinations, |c| c.application.priority());
+    Ok(combinations)
+}
+
+/// Picks the highest priority combination. There's no cardholder choice over
+/// contactless, and with nothing usable the card has to be tried another way.
+pub fn select<T: CardTransport + ?Sized>(card: &T, terminal: &TerminalData, terminal_applications: &[crate::selection::TerminalApplication]) -> Result<Option<Combination>, crate::card::CardError> {
+    let combination = build_combinations(card, terminal, terminal_applications)?.into_iter().next();
+    match &combination {
+        Some(c) => tracing::info!("Selected {} for kernel {}", c.application.name(), c.kernel_id),
+        None => tracing::info!("No usable contactless application, try another interface")
+    }
+    Ok(combination)
+}
+
+/// Book B 3.4: hands the transaction over to the kernel with the TTQ worked
+/// out in pre-processing
+pub fn activate(terminal: &mut TerminalData, combination: &Combination) {
+    terminal.terminal_transaction_qualifiers = combination.indicators.ttq;
+    tracing::info!("Activating kernel {}", combination.kernel_id);
+}
@@ -0,0 +1,80 @@
+//! How far a transaction has got, as it goes, for embedders drawing their own
+//! interface. Events are sent down the channel in
+//! [`TerminalData::events`](crate::terminal::TerminalData::events), nothing
+//! being sent without one.
+
+use serde::Serialize;
+
+/// Something that's happened in the transaction, tagged by `event` as JSON
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    CardDetected {
+        reader: Option<String>,
+        #[serde(serialize_with = "crate::dump::as_optional_hex")]
+        atr: Option<Vec<u8>>,
+        interface: String,
+    },
+    ApplicationSelected {
+        #[serde(serialize_with = "crate::dump::as_hex")]
+        aid: Vec<u8>,
+        name: String,
+    },
+    /// The card has answered GET PROCESSING OPTIONS
+    GpoDone {
+        #[serde(serialize_with = "crate::dump::as_hex")]
+        aip: Vec<u8>,
+        /// How many records the AFL has the terminal read
+        records: usize,
+    },
+    RecordsRead {
+        records: usize,
+    },
+    OdaResult {
+        method: OdaMethod,
+        success: bool,
+    },
+    /// The cardholder has something to do to be verified
+    CvmRequired {
+        cvm: Cvm,
+    },
+    /// Something to show the cardholder, already in their language
+    CardholderMessage {
+        message: String,
+    },
+    /// The card wants the issuer called before it'll approve
+    ReferralRequired {
+        masked_pan: Option<String>,
+    },
+    OutcomeReady(Box<crate::transaction::TransactionOutcome>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OdaMethod {
+    Sda,
+    /// For CDA the ICC key being recovered, the signature itself coming with
+    /// GENERATE AC
+    Cda,
+    Fdda,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cvm {
+    OfflinePin,
+    OnlinePin,
+    Signature,
+    /// Verified on the cardholder's own device, such as their phone
+    ConsumerDevice,
+}
+
+pub type EventSender = std::sync::mpsc::Sender<Event>;
+
+/// Sends the event if there's anywhere to send it, going on regardless if
+/// whoever was listening has gone
+pub(crate) fn emit(events: &Option<EventSender>, event: Event) {
+    if let Some(sender) = events {
+        let _ = sender.send(event);
+    }
+}
@@ -0,0 +1,142 @@
+//! Hex as the terminal writes it, in upper case without separators, and hex
+//! dumps for data that isn't anything more structured. [`Hex`] and [`Dump`]
+//! write straight to whatever they're formatted into, so logging and dumping
+//! don't build a string for every byte on the way. Like the TLV parser, this
+//! needs nothing more than `core` and `alloc`.
+
+use core::fmt::Write;
+use alloc::{string::String, vec::Vec};
+
+const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Both hex digits of a byte
+fn digits(byte: u8) -> [u8; 2] {
+    [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]]
+}
+
+fn write_byte<W: core::fmt::Write + ?Sized>(w: &mut W, byte: u8) -> core::fmt::Result {
+    let digits = digits(byte);
+    w.write_char(digits[0] as char)?;
+    w.write_char(digits[1] as char)
+}
+
+/// Bytes formatted as hex, such as in a log field, without any allocation
+#[derive(Copy, Clone)]
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl core::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for b in self.0 {
+            write_byte(f, *b)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::fmt::Debug for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
+
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 2);
+    for b in data {
+        let digits = digits(*b);
+        out.push(digits[0] as char);
+        out.push(digits[1] as char);
+    }
+    out
+}
+
+fn value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None
+    }
+}
+
+/// Decodes a string of hex digits in either case, giving None if it has an
+/// odd length or a character that isn't a hex digit
+pub fn decode(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.as_bytes();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks_exact(2).map(|pair| Some(value(pair[0])? << 4 | value(pair[1])?)).collect()
+}
+
+/// A hex dump, sixteen bytes to a line in two groups of eight, each line
+/// starting with the offset of its first byte and ending with those bytes
+/// that are printable ASCII
+#[derive(Copy, Clone)]
+pub struct Dump<'a> {
+    data: &'a [u8],
+    indent: usize,
+}
+
+impl<'a> Dump<'a> {
+    pub const WIDTH: usize = 16;
+
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            indent: 0,
+        }
+    }
+
+    /// Starts each line with `indent` spaces
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+}
+
+impl core::fmt::Display for Dump<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, line) in self.data.chunks(Self::WIDTH).enumerate() {
+            write!(f, "{:indent$}{:04X} ", "", i * Self::WIDTH, indent = self.indent)?;
+            for column in 0..Self::WIDTH {
+                if column % 8 == 0 {
+                    f.write_char(' ')?;
+                }
+                match line.get(column) {
+                    Some(b) => write_byte(f, *b)?,
+                    None => f.write_str("  ")?
+                }
+                f.write_char(' ')?;
+            }
+            f.write_char(' ')?;
+            for b in line {
+                f.write_char(if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })?;
+            }
+            f.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+impl core::fmt::Debug for Dump<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn formats_hex() {
+        assert_eq!(crate::hex::encode(&[0x00, 0x9f, 0x4a]), "009F4A");
+        assert_eq!(crate::hex::decode("009f4A"), Some(vec![0x00, 0x9f, 0x4a]));
+        assert_eq!(crate::hex::decode("009"), None);
+        assert_eq!(crate::hex::decode("0G"), None);
+        assert_eq!(format!("{}", crate::hex::Hex(&[0xa0, 0x00, 0x00, 0x00, 0x03])), "A000000003");
+
+        let data = b"\x70\x12Hello, card\x00\xff world";
+        assert_eq!(crate::hex::Dump::new(data).indent(2).to_string(),
+                   "  0000  70 12 48 65 6C 6C 6F 2C  20 63 61 72 64 00 FF 20  p.Hello, card.. \n  \
+                      0010  77 6F 72 6C 64                                    world\n");
+    }
+}
@@ -0,0 +1,194 @@
+//! The transaction log some cards keep of their last few transactions. Where
+//! it is and how many records it has are in the Log Entry, and what's in each
+//! record is laid out by the Log Format, a DOL without any data of its own.
+
+use std::convert::TryFrom;
+use crate::transport::CardTransport;
+
+/// One transaction from the log, with each field the Log Format lists
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub record: u8,
+    pub fields: Vec<(crate::tlv::TagID, Vec<u8>)>,
+}
+
+impl LogEntry {
+    pub fn get(&self, tag: crate::tlv::TagID) -> Option<&[u8]> {
+        self.fields.iter().find(|(id, _)| *id == tag).map(|(_, v)| v.as_slice())
+    }
+
+    pub fn date(&self) -> Option<chrono::NaiveDate> {
+        crate::util::from_bcd_date(self.get(crate::tlv::TagID::TransactionDate)?)
+    }
+
+    /// The time of the transaction, from HHMMSS in BCD
+    pub fn time(&self) -> Option<chrono::NaiveTime> {
+        let time = self.get(crate::tlv::TagID::TransactionTime)?;
+        if time.len() != 3 {
+            return None;
+        }
+        chrono::NaiveTime::from_hms_opt(
+            crate::util::from_bcd(&time[..1])? as u32,
+            crate::util::from_bcd(&time[1..2])? as u32,
+            crate::util::from_bcd(&time[2..])? as u32,
+        )
+    }
+
+    /// Amount, Authorised in minor units of the transaction's currency
+    pub fn amount(&self) -> Option<u64> {
+        crate::util::from_bcd(self.get(crate::tlv::TagID::AmountAuthorised)?)
+    }
+
+    pub fn amount_other(&self) -> Option<u64> {
+        crate::util::from_bcd(self.get(crate::tlv::TagID::AmountOther)?)
+    }
+
+    /// The numeric code of the transaction's currency
+    pub fn currency_code(&self) -> Option<u16> {
+        crate::util::from_bcd(self.get(crate::tlv::TagID::TransactionCurrencyCode)?).map(|c| c as u16)
+    }
+
+    pub fn currency(&self) -> Option<&'static crate::iso::Currency> {
+        crate::iso::currency(self.currency_code()?)
+    }
+
+    /// The numeric code of the country the terminal was in
+    pub fn country_code(&self) -> Option<u16> {
+        crate::util::from_bcd(self.get(crate::tlv::TagID::TerminalCountryCode)?).map(|c| c as u16)
+    }
+
+    pub fn country(&self) -> Option<&'static crate::iso::Country> {
+        crate::iso::country(self.country_code()?)
+    }
+
+    pub fn transaction_type(&self) -> Option<u8> {
+        self.get(crate::tlv::TagID::TransactionType)?.first().copied()
+    }
+
+    pub fn application_transaction_counter(&self) -> Option<u16> {
+        match self.get(crate::tlv::TagID::ApplicationTransactionCounter)? {
+            [high, low] => Some(u16::from_be_bytes([*high, *low])),
+            _ => None
+        }
+    }
+
+    pub fn merchant_name(&self) -> Option<String> {
+        let name = self.get(crate::tlv::TagID::MerchantNameAndLocation)?;
+        let name = String::from_utf8_lossy(name).trim_end_matches(['\0', ' ']).to_string();
+        match name.is_empty() {
+            true => None,
+            false => Some(name)
+        }
+    }
+}
+
+/// An application's transaction log, most recent transaction first
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionLog {
+    pub aid: Vec<u8>,
+    pub entries: Vec<LogEntry>,
+}
+
+/// The SFI and number of records from a Log Entry
+pub fn parse_log_entry(data: &[u8]) -> Option<(u8, u8)> {
+    match data {
+        [sfi, records] if (1..=30).contains(sfi) => Some((*sfi, *records)),
+        _ => None
+    }
+}
+
+/// Finds the Log Entry in an application's FCI, where it is in the FCI Issuer
+/// Discretionary Data
+pub fn fci_log_entry(fci: &[u8]) -> Option<(u8, u8)> {
+    let tags = crate::tlv::TagList::try_from(fci).ok()?;
+    let log_entry = tags.get_tag(crate::tlv::TagID::FileControlInformationTemplate)?
+        .get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate)?
+        .get_tag(crate::tlv::TagID::FileControlInformationIssuerDiscretionaryData)?
+        .get_tag(crate::tlv::TagID::LogEntry)?;
+    parse_log_entry(&Vec::<u8>::from(log_entry.contents()))
+}
+
+/// Finds a data object in a GET DATA response, giving its value
+fn data_object(response: &[u8], tag: crate::tlv::TagID) -> Option<Vec<u8>> {
+    let tags = crate::tlv::TagList::try_from(response).ok()?;
+    tags.get_tag(tag).map(|t| Vec::<u8>::from(t.contents()))
+}
+
+/// The Log Format from a GET DATA response
+pub fn parse_log_format(response: &[u8]) -> Option<crate::tlv::DOL> {
+    crate::tlv::DOL::try_from(data_object(response, crate::tlv::TagID::LogFormat)?.as_slice()).ok()
+}
+
+/// Decodes log records by the Log Format, leaving out any too short for it
+pub fn decode_log(format: &crate::tlv::DOL, records: &[crate::dump::RecordDump]) -> Vec<LogEntry> {
+    records.iter().filter_map(|r| {
+        let fields = format.split(&r.data);
+        if fields.is_none() {
+            tracing::warn!(record = r.record, "Log record too short for the Log Format");
+        }
+        Some(LogEntry {
+            record: r.record,
+            fields: fields?,
+        })
+    }).collect()
+}
+
+/// Reads the transaction log of an application, without running a
+/// transaction. Gives None if the card doesn't have the application or it
+/// doesn't keep a log.
+pub fn read_log<T: CardTransport + ?Sized>(card: &T, aid: &[u8]) -> Result<Option<TransactionLog>, crate::card::CardError> {
+    let fci = match crate::card::optional(crate::card::card_select_raw(card, aid, false))? {
+        Some(f) => f,
+        None => return Ok(None)
+    };
+
+    // Some cards only give the Log Entry over GET DATA
+    let log_entry = match fci_log_entry(&fci) {
+        Some(l) => Some(l),
+        None => crate::card::optional(crate::card::card_get_data_raw(card, 0x9f4d))?
+            .and_then(|r| data_object(&r, crate::tlv::TagID::LogEntry))
+            .and_then(|l| parse_log_entry(&l))
+    };
+    let (sfi, count) = match log_entry {
+        Some(l) => l,
+        None => {
+            tracing::info!(aid = %crate::hex::Hex(aid), "Application has no transaction log");
+            return Ok(None);
+        }
+    };
+    let format = match crate::card::optional(crate::card::card_get_data_raw(card, 0x9f4f))?.and_then(|r| parse_log_format(&r)) {
+        Some(f) => f,
+        None => {
+            tracing::warn!(aid = %crate::hex::Hex(aid), "Unable to get the Log Format");
+            return Ok(None);
+        }
+    };
+
+    let records = crate::dump::read_records(card, sfi, 1..=count)?;
+    Ok(Some(TransactionLog {
+        aid: aid.to_vec(),
+        entries: decode_log(&format, &records),
+    }))
+}
+
+/// Reads the transaction log of every application on the card that keeps
+/// one and the terminal supports: those in the PSE (or PPSE when
+/// contactless), or failing that each of the terminal's AIDs
+pub fn read_logs<T: CardTransport + ?Sized>(card: &T, terminal: &crate::terminal::TerminalData) -> Result<Vec<TransactionLog>, crate::card::CardError> {
+    let pse = crate::dump::read_directory(card, terminal.interface.directory_name())?;
+    let aids = match pse.as_ref().map(crate::dump::directory_aids).unwrap_or_default()
+        .into_iter()
+        .filter(|aid| terminal.applications.iter().any(|a| a.matches(aid)))
+        .collect::<Vec<_>>() {
+        a if a.is_empty() => terminal.applications.iter().map(|a| a.aid.clone()).collect(),
+        a => a
+    };
+
+    let mut logs = vec![];
+    for aid in aids {
+        if let Some(log) = read_log(card, &aid)? {
+            logs.push(log);
+        }
+    }
+    Ok(logs)
+}
@@ -0,0 +1,145 @@
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+/// How long to wait on the host before giving up, when not told otherwise
+pub const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Where the acquirer's host is and how to reach it
+#[derive(Debug, Clone)]
+pub struct HostConfig {
+    /// `host:port`
+    pub address: String,
+    pub tls: bool,
+    /// PEM file of CA certificates to trust instead of the usual web roots,
+    /// for hosts with a private CA
+    pub ca_file: Option<String>,
+    /// Applies to connecting and to each read and write
+    pub timeout: std::time::Duration,
+}
+
+impl HostConfig {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            tls: false,
+            ca_file: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    fn tls_config(&self) -> std::io::Result<rustls::ClientConfig> {
+        use rustls::pki_types::pem::PemObject;
+
+        let mut roots = rustls::RootCertStore::empty();
+        match &self.ca_file {
+            Some(path) => {
+                let certs = rustls::pki_types::CertificateDer::pem_file_iter(path)
+                    .and_then(|c| c.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                roots.add_parsable_certificates(certs);
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+        }
+        Ok(rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(std::io::Error::other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth())
+    }
+}
+
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// A connection to the acquirer's host, carrying ISO 8583 messages each
+/// preceded by their length as two bytes big endian
+pub struct HostConnection {
+    stream: Box<dyn Stream>,
+}
+
+impl HostConnection {
+    pub fn connect(config: &HostConfig) -> std::io::Result<Self> {
+        let address = std::net::ToSocketAddrs::to_socket_addrs(config.address.as_str())?
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Host address doesn't resolve"))?;
+        let tcp = std::net::TcpStream::connect_timeout(&address, config.timeout)?;
+        tcp.set_read_timeout(Some(config.timeout))?;
+        tcp.set_write_timeout(Some(config.timeout))?;
+        tcp.set_nodelay(true)?;
+
+        let stream: Box<dyn Stream> = if config.tls {
+            let host_name = config.address.rsplit_once(':').map(|(h, _)| h).unwrap_or(&config.address);
+            let server_name = rustls::pki_types::ServerName::try_from(host_name.to_string())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            let connection = rustls::ClientConnection::new(std::sync::Arc::new(config.tls_config()?), server_name)
+                .map_err(std::io::Error::other)?;
+            Box::new(rustls::StreamOwned::new(connection, tcp))
+        } else {
+            Box::new(tcp)
+        };
+        Ok(Self {
+            stream,
+        })
+    }
+
+    pub fn send(&mut self, message: &crate::online::IsoMessage) -> std::io::Result<()> {
+        let data = message.pack().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let len = u16::try_from(data.len()).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Message too long"))?;
+        self.stream.write_all(&len.to_be_bytes())?;
+        self.stream.write_all(&data)?;
+        self.stream.flush()
+    }
+
+    pub fn receive(&mut self) -> std::io::Result<crate::online::IsoMessage> {
+        let mut len = [0; 2];
+        self.stream.read_exact(&mut len)?;
+        let mut data = vec![0; u16::from_be_bytes(len) as usize];
+        self.stream.read_exact(&mut data)?;
+        crate::online::IsoMessage::unpack(&data).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Sends a request and waits for its response, which has the same MTI
+    /// with the function digit moved on by one
+    pub fn exchange(&mut self, request: &crate::online::IsoMessage) -> std::io::Result<crate::online::IsoMessage> {
+        self.send(request)?;
+        let response = self.receive()?;
+        let expected = request.mti.parse::<u16>().map(|m| format!("{:04}", m + 10)).ok();
+        if expected.as_deref() != Some(response.mti.as_str()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unexpected response MTI {}", response.mti)));
+        }
+        if request.get(11).is_some() && response.get(11) != request.get(11) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Response is for another request"));
+        }
+        Ok(response)
+    }
+
+    /// Signs the terminal on with the host, which some hosts need before
+    /// they'll take any authorisations
+    pub fn sign_on(&mut self, stan: u32) -> std::io::Result<()> {
+        let mut request = crate::online::IsoMessage::new(crate::online::MTI_NETWORK_MANAGEMENT_REQUEST);
+        request.set(7, chrono::Utc::now().format("%m%d%H%M%S").to_string());
+        request.set(11, format!("{:06}", stan % 1_000_000));
+        request.set(70, crate::online::NETWORK_SIGN_ON);
+        let response = self.exchange(&request)?;
+        match response.get_str(39) {
+            Some("00") => Ok(()),
+            code => Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("Sign on refused with response code {}", code.unwrap_or("missing"))))
+        }
+    }
+}
+
+impl crate::acquirer::Acquirer for HostConnection {
+    fn authorise(&mut self, request: &crate::acquirer::AuthorisationRequest) -> std::io::Result<crate::transaction::OnlineResponse> {
+        let response = self.exchange(&crate::online::authorisation_message(request, crate::online::MTI_AUTHORISATION_REQUEST))?;
+        crate::online::online_response(&response).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    fn reverse(&mut self, request: &crate::acquirer::AuthorisationRequest) -> std::io::Result<()> {
+        self.exchange(&crate::online::authorisation_message(request, crate::online::MTI_REVERSAL_REQUEST)).map(|_| ())
+    }
+
+    fn advice(&mut self, request: &crate::acquirer::AuthorisationRequest) -> std::io::Result<()> {
+        self.exchange(&crate::online::authorisation_message(request, crate::online::MTI_AUTHORISATION_ADVICE)).map(|_| ())
+    }
+}
@@ -0,0 +1,210 @@
+#[cfg(feature = "pkcs11")]
+use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle};
+
+/// Where the user PIN for a PKCS#11 token is read from, so it stays out of the
+/// command line
+pub const PIN_VARIABLE: &str = "EMV_TERM_PKCS11_PIN";
+
+/// Why a `pkcs11:` key can't be used when built without the `pkcs11` feature
+#[cfg(not(feature = "pkcs11"))]
+pub(crate) const NO_PKCS11: &str = "Built without PKCS#11 support";
+
+/// A secret key kept in a PKCS#11 token, found by its label
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pkcs11Key {
+    /// Path to the token's PKCS#11 module
+    pub module: String,
+    /// Index among the slots with a token present
+    pub slot: usize,
+    pub label: String,
+}
+
+impl std::str::FromStr for Pkcs11Key {
+    type Err = String;
+
+    /// Parses `<module>:<slot>:<label>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(3, ':');
+        let label = parts.next().filter(|l| !l.is_empty());
+        let slot = parts.next();
+        let module = parts.next().filter(|m| !m.is_empty());
+        match (module, slot, label) {
+            (Some(module), Some(slot), Some(label)) => Ok(Self {
+                module: module.to_string(),
+                slot: slot.parse().map_err(|_| format!("Invalid PKCS#11 slot: {}", slot))?,
+                label: label.to_string(),
+            }),
+            _ => Err("PKCS#11 key must be pkcs11:<module>:<slot>:<label>".to_string())
+        }
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+fn error(e: cryptoki::error::Error) -> String {
+    format!("PKCS#11 error: {}", e)
+}
+
+/// A logged in session on the token holding a key. Keys derived from it are
+/// kept on the same session, and go when it closes.
+#[cfg(feature = "pkcs11")]
+pub struct TokenKey {
+    session: std::rc::Rc<cryptoki::session::Session>,
+    handle: ObjectHandle,
+    key_type: KeyType,
+}
+
+#[cfg(feature = "pkcs11")]
+impl Pkcs11Key {
+    /// Loads the module, logs in with the PIN from the environment if there is
+    /// one, and finds the key
+    pub fn open(&self) -> Result<TokenKey, String> {
+        let pkcs11 = cryptoki::context::Pkcs11::new(&self.module).map_err(error)?;
+        pkcs11.initialize(cryptoki::context::CInitializeArgs::OsThreads).map_err(error)?;
+        let slot = *pkcs11.get_slots_with_token().map_err(error)?
+            .get(self.slot)
+            .ok_or_else(|| format!("No token in PKCS#11 slot {}", self.slot))?;
+        let session = pkcs11.open_ro_session(slot).map_err(error)?;
+        if let Ok(pin) = std::env::var(PIN_VARIABLE) {
+            session.login(cryptoki::session::UserType::User, Some(&cryptoki::types::AuthPin::new(pin))).map_err(error)?;
+        }
+
+        let handle = *session.find_objects(&[
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::Label(self.label.as_bytes().to_vec()),
+        ]).map_err(error)?
+            .first()
+            .ok_or_else(|| format!("No key labelled {} on the token", self.label))?;
+        let key_type = match session.get_attributes(handle, &[AttributeType::KeyType]).map_err(error)?.first() {
+            Some(Attribute::KeyType(t)) => *t,
+            _ => return Err(format!("Key {} has no type", self.label))
+        };
+        Ok(TokenKey {
+            session: std::rc::Rc::new(session),
+            handle,
+            key_type,
+        })
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+impl TokenKey {
+    pub fn is_aes(&self) -> bool {
+        self.key_type == KeyType::AES
+    }
+
+    /// Encrypts whole blocks in ECB mode, the key never leaving the token
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mechanism = match self.key_type {
+            KeyType::AES => cryptoki::mechanism::Mechanism::AesEcb,
+            KeyType::DES2 | KeyType::DES3 => cryptoki::mechanism::Mechanism::Des3Ecb,
+            t => return Err(format!("Unsupported key type: {}", t))
+        };
+        self.session.encrypt(&mechanism, self.handle, data).map_err(error)
+    }
+
+    /// Derives a double length TDES key on the token by encrypting the data
+    /// under this key in ECB mode. The new key can't be taken off the token.
+    pub fn derive_tdes(&self, data: &[u8]) -> Result<TokenKey, String> {
+        use cryptoki::mechanism::{Mechanism, MechanismType, vendor_defined::VendorDefinedMechanism};
+
+        if self.is_aes() {
+            return Err("Can't derive a TDES key from an AES key".to_string());
+        }
+        let params = cryptoki_sys::CK_KEY_DERIVATION_STRING_DATA {
+            pData: data.as_ptr() as *mut _,
+            ulLen: data.len() as cryptoki_sys::CK_ULONG,
+        };
+        // cryptoki has no Mechanism for CKM_DES3_ECB_ENCRYPT_DATA.
+        // SAFETY: MechanismType is a repr(transparent) CK_MECHANISM_TYPE.
+        let mechanism_type: MechanismType = unsafe { std::mem::transmute(cryptoki_sys::CKM_DES3_ECB_ENCRYPT_DATA) };
+        let mechanism = Mechanism::VendorDefined(VendorDefinedMechanism::new(mechanism_type, Some(&params)));
+        let handle = self.session.derive_key(&mechanism, self.handle, &[
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::KeyType(KeyType::DES2),
+            Attribute::Token(false),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Encrypt(true),
+            Attribute::Derive(true),
+        ]).map_err(error)?;
+        Ok(TokenKey {
+            session: self.session.clone(),
+            handle,
+            key_type: KeyType::DES2,
+        })
+    }
+}
+
+/// A double or triple length TDES key, either given in hex or kept in a PKCS#11
+/// token as `pkcs11:<module>:<slot>:<label>`
+#[derive(Debug, Clone)]
+pub enum TdesKey {
+    Software(Vec<u8>),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(Pkcs11Key),
+}
+
+impl std::str::FromStr for TdesKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(feature = "pkcs11")]
+        if let Some(key) = s.strip_prefix("pkcs11:") {
+            return Ok(TdesKey::Pkcs11(key.parse()?));
+        }
+        #[cfg(not(feature = "pkcs11"))]
+        if s.starts_with("pkcs11:") {
+            return Err(NO_PKCS11.to_string());
+        }
+        match crate::hex::decode(s) {
+            Some(k) if k.len() == 16 || k.len() == 24 => Ok(TdesKey::Software(k)),
+            Some(_) => Err("TDES key must be 16 or 24 bytes".to_string()),
+            None => Err("Invalid hex in TDES key".to_string())
+        }
+    }
+}
+
+impl TdesKey {
+    /// Gets the key ready to use, logging in to its token if it's on one
+    pub fn open(&self) -> Result<OpenTdesKey, String> {
+        match self {
+            TdesKey::Software(k) => Ok(OpenTdesKey::Software(k.clone())),
+            #[cfg(feature = "pkcs11")]
+            TdesKey::Pkcs11(k) => {
+                let key = k.open()?;
+                if key.is_aes() {
+                    return Err(format!("Key {} isn't a TDES key", k.label));
+                }
+                Ok(OpenTdesKey::Pkcs11(key))
+            }
+        }
+    }
+}
+
+/// A TDES key ready to use, either in memory or as a handle on a token
+pub enum OpenTdesKey {
+    Software(Vec<u8>),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(TokenKey),
+}
+
+impl OpenTdesKey {
+    /// Encrypts each block in turn
+    pub fn encrypt_blocks(&self, blocks: &[[u8; 8]]) -> Result<Vec<u8>, String> {
+        match self {
+            OpenTdesKey::Software(k) => Ok(blocks.iter().flat_map(|b| crate::pinblock::tdes_encrypt(k, *b)).collect()),
+            #[cfg(feature = "pkcs11")]
+            OpenTdesKey::Pkcs11(k) => k.encrypt(&blocks.concat()),
+        }
+    }
+
+    /// The double length key that's the two blocks encrypted under this one,
+    /// derived on the token for a key kept on one
+    pub fn derive(&self, blocks: &[[u8; 8]; 2]) -> Result<OpenTdesKey, String> {
+        match self {
+            OpenTdesKey::Software(_) => self.encrypt_blocks(blocks).map(OpenTdesKey::Software),
+            #[cfg(feature = "pkcs11")]
+            OpenTdesKey::Pkcs11(k) => k.derive_tdes(&blocks.concat()).map(OpenTdesKey::Pkcs11),
+        }
+    }
+}
@@ -0,0 +1,530 @@
+/// An ISO 4217 currency
+#[derive(Debug, PartialEq)]
+pub struct Currency {
+    pub numeric: u16,
+    pub code: &'static str,
+    /// Digits after the decimal point in amounts
+    pub exponent: u8,
+    pub name: &'static str,
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({:03})", self.code, self.numeric)
+    }
+}
+
+/// An ISO 3166-1 country
+#[derive(Debug, PartialEq)]
+pub struct Country {
+    pub numeric: u16,
+    pub alpha2: &'static str,
+    pub alpha3: &'static str,
+    pub name: &'static str,
+}
+
+impl std::fmt::Display for Country {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+pub fn currency(numeric: u16) -> Option<&'static Currency> {
+    CURRENCIES.iter().find(|c| c.numeric == numeric)
+}
+
+pub fn country(numeric: u16) -> Option<&'static Country> {
+    COUNTRIES.iter().find(|c| c.numeric == numeric)
+}
+
+/// Finds a currency by its alphabetic or numeric code
+pub fn parse_currency(value: &str) -> Option<&'static Currency> {
+    let value = value.trim();
+    match value.parse::<u16>() {
+        Ok(n) => currency(n),
+        Err(_) => CURRENCIES.iter().find(|c| c.code.eq_ignore_ascii_case(value))
+    }
+}
+
+/// Finds a country by its two or three letter or numeric code
+pub fn parse_country(value: &str) -> Option<&'static Country> {
+    let value = value.trim();
+    match value.parse::<u16>() {
+        Ok(n) => country(n),
+        Err(_) => COUNTRIES.iter().find(|c| c.alpha2.eq_ignore_ascii_case(value) || c.alpha3.eq_ignore_ascii_case(value))
+    }
+}
+
+/// A three digit code as the card gives it, in BCD with a leading zero
+fn numeric_code(data: &[u8]) -> Option<u16> {
+    let digits = crate::hex::encode(data);
+    match data.len() == 2 && digits.chars().all(|c| c.is_ascii_digit()) {
+        true => digits.parse().ok(),
+        false => None
+    }
+}
+
+/// What a currency or country data element stands for, where it's one of the
+/// codes in these tables
+pub fn describe(tag: crate::tlv::TagID, data: &[u8]) -> Option<String> {
+    use crate::tlv::TagID;
+    match tag {
+        TagID::TransactionCurrencyCode | TagID::ApplicationCurrencyCode => numeric_code(data).and_then(currency).map(|c| c.to_string()),
+        TagID::TerminalCountryCode | TagID::IssuerCountryCode => numeric_code(data).and_then(country).map(|c| c.to_string()),
+        TagID::IssuerCountryCodeAlpha2 | TagID::IssuerCountryCodeAlpha6 => std::str::from_utf8(data).ok()
+            .and_then(parse_country).map(|c| c.to_string()),
+        _ => None
+    }
+}
+
+/// Describes every currency and country in the tags, constructed or not
+pub fn describe_tags(tags: &crate::tlv::TagList) -> Vec<(crate::tlv::TagID, String)> {
+    let mut out = vec![];
+    for tag in tags.tags() {
+        match tag.contents() {
+            crate::tlv::TagContents::Constructed(tl) => out.extend(describe_tags(tl)),
+            contents => out.extend(describe(tag.id(), &Vec::<u8>::from(contents)).map(|d| (tag.id(), d)))
+        }
+    }
+    out
+}
+
+const CURRENCIES: [Currency; 156] = [
+    Currency { numeric: 8, code: "ALL", exponent: 2, name: "Albanian lek" },
+    Currency { numeric: 12, code: "DZD", exponent: 2, name: "Algerian dinar" },
+    Currency { numeric: 32, code: "ARS", exponent: 2, name: "Argentine peso" },
+    Currency { numeric: 36, code: "AUD", exponent: 2, name: "Australian dollar" },
+    Currency { numeric: 44, code: "BSD", exponent: 2, name: "Bahamian dollar" },
+    Currency { numeric: 48, code: "BHD", exponent: 3, name: "Bahraini dinar" },
+    Currency { numeric: 50, code: "BDT", exponent: 2, name: "Bangladeshi taka" },
+    Currency { numeric: 51, code: "AMD", exponent: 2, name: "Armenian dram" },
+    Currency { numeric: 52, code: "BBD", exponent: 2, name: "Barbados dollar" },
+    Currency { numeric: 60, code: "BMD", exponent: 2, name: "Bermudian dollar" },
+    Currency { numeric: 64, code: "BTN", exponent: 2, name: "Bhutanese ngultrum" },
+    Currency { numeric: 68, code: "BOB", exponent: 2, name: "Boliviano" },
+    Currency { numeric: 72, code: "BWP", exponent: 2, name: "Botswana pula" },
+    Currency { numeric: 84, code: "BZD", exponent: 2, name: "Belize dollar" },
+    Currency { numeric: 90, code: "SBD", exponent: 2, name: "Solomon Islands dollar" },
+    Currency { numeric: 96, code: "BND", exponent: 2, name: "Brunei dollar" },
+    Currency { numeric: 104, code: "MMK", exponent: 2, name: "Myanmar kyat" },
+    Currency { numeric: 108, code: "BIF", exponent: 0, name: "Burundian franc" },
+    Currency { numeric: 116, code: "KHR", exponent: 2, name: "Cambodian riel" },
+    Currency { numeric: 124, code: "CAD", exponent: 2, name: "Canadian dollar" },
+    Currency { numeric: 132, code: "CVE", exponent: 2, name: "Cape Verdean escudo" },
+    Currency { numeric: 136, code: "KYD", exponent: 2, name: "Cayman Islands dollar" },
+    Currency { numeric: 144, code: "LKR", exponent: 2, name: "Sri Lankan rupee" },
+    Currency { numeric: 152, code: "CLP", exponent: 0, name: "Chilean peso" },
+    Currency { numeric: 156, code: "CNY", exponent: 2, name: "Renminbi (Chinese) yuan" },
+    Currency { numeric: 170, code: "COP", exponent: 2, name: "Colombian peso" },
+    Currency { numeric: 174, code: "KMF", exponent: 0, name: "Comoro franc" },
+    Currency { numeric: 188, code: "CRC", exponent: 2, name: "Costa Rican colon" },
+    Currency { numeric: 191, code: "HRK", exponent: 2, name: "Croatian kuna" },
+    Currency { numeric: 192, code: "CUP", exponent: 2, name: "Cuban peso" },
+    Currency { numeric: 203, code: "CZK", exponent: 2, name: "Czech koruna" },
+    Currency { numeric: 208, code: "DKK", exponent: 2, name: "Danish krone" },
+    Currency { numeric: 214, code: "DOP", exponent: 2, name: "Dominican peso" },
+    Currency { numeric: 222, code: "SVC", exponent: 2, name: "Salvadoran colón" },
+    Currency { numeric: 230, code: "ETB", exponent: 2, name: "Ethiopian birr" },
+    Currency { numeric: 232, code: "ERN", exponent: 2, name: "Eritrean nakfa" },
+    Currency { numeric: 238, code: "FKP", exponent: 2, name: "Falkland Islands pound" },
+    Currency { numeric: 242, code: "FJD", exponent: 2, name: "Fiji dollar" },
+    Currency { numeric: 262, code: "DJF", exponent: 0, name: "Djiboutian franc" },
+    Currency { numeric: 270, code: "GMD", exponent: 2, name: "Gambian dalasi" },
+    Currency { numeric: 292, code: "GIP", exponent: 2, name: "Gibraltar pound" },
+    Currency { numeric: 320, code: "GTQ", exponent: 2, name: "Guatemalan quetzal" },
+    Currency { numeric: 324, code: "GNF", exponent: 0, name: "Guinean franc" },
+    Currency { numeric: 328, code: "GYD", exponent: 2, name: "Guyanese dollar" },
+    Currency { numeric: 332, code: "HTG", exponent: 2, name: "Haitian gourde" },
+    Currency { numeric: 340, code: "HNL", exponent: 2, name: "Honduran lempira" },
+    Currency { numeric: 344, code: "HKD", exponent: 2, name: "Hong Kong dollar" },
+    Currency { numeric: 348, code: "HUF", exponent: 2, name: "Hungarian forint" },
+    Currency { numeric: 352, code: "ISK", exponent: 0, name: "Icelandic króna" },
+    Currency { numeric: 356, code: "INR", exponent: 2, name: "Indian rupee" },
+    Currency { numeric: 360, code: "IDR", exponent: 2, name: "Indonesian rupiah" },
+    Currency { numeric: 364, code: "IRR", exponent: 2, name: "Iranian rial" },
+    Currency { numeric: 368, code: "IQD", exponent: 3, name: "Iraqi dinar" },
+    Currency { numeric: 376, code: "ILS", exponent: 2, name: "Israeli new shekel" },
+    Currency { numeric: 388, code: "JMD", exponent: 2, name: "Jamaican dollar" },
+    Currency { numeric: 392, code: "JPY", exponent: 0, name: "Japanese yen" },
+    Currency { numeric: 398, code: "KZT", exponent: 2, name: "Kazakhstani tenge" },
+    Currency { numeric: 400, code: "JOD", exponent: 3, name: "Jordanian dinar" },
+    Currency { numeric: 404, code: "KES", exponent: 2, name: "Kenyan shilling" },
+    Currency { numeric: 408, code: "KPW", exponent: 2, name: "North Korean won" },
+    Currency { numeric: 410, code: "KRW", exponent: 0, name: "South Korean won" },
+    Currency { numeric: 414, code: "KWD", exponent: 3, name: "Kuwaiti dinar" },
+    Currency { numeric: 417, code: "KGS", exponent: 2, name: "Kyrgyzstani som" },
+    Currency { numeric: 418, code: "LAK", exponent: 2, name: "Lao kip" },
+    Currency { numeric: 422, code: "LBP", exponent: 2, name: "Lebanese pound" },
+    Currency { numeric: 426, code: "LSL", exponent: 2, name: "Lesotho loti" },
+    Currency { numeric: 430, code: "LRD", exponent: 2, name: "Liberian dollar" },
+    Currency { numeric: 434, code: "LYD", exponent: 3, name: "Libyan dinar" },
+    Currency { numeric: 446, code: "MOP", exponent: 2, name: "Macanese pataca" },
+    Currency { numeric: 454, code: "MWK", exponent: 2, name: "Malawian kwacha" },
+    Currency { numeric: 458, code: "MYR", exponent: 2, name: "Malaysian ringgit" },
+    Currency { numeric: 462, code: "MVR", exponent: 2, name: "Maldivian rufiyaa" },
+    Currency { numeric: 480, code: "MUR", exponent: 2, name: "Mauritian rupee" },
+    Currency { numeric: 484, code: "MXN", exponent: 2, name: "Mexican peso" },
+    Currency { numeric: 496, code: "MNT", exponent: 2, name: "Mongolian tögrög" },
+    Currency { numeric: 498, code: "MDL", exponent: 2, name: "Moldovan leu" },
+    Currency { numeric: 504, code: "MAD", exponent: 2, name: "Moroccan dirham" },
+    Currency { numeric: 512, code: "OMR", exponent: 3, name: "Omani rial" },
+    Currency { numeric: 516, code: "NAD", exponent: 2, name: "Namibian dollar" },
+    Currency { numeric: 524, code: "NPR", exponent: 2, name: "Nepalese rupee" },
+    Currency { numeric: 532, code: "ANG", exponent: 2, name: "Netherlands Antillean guilder" },
+    Currency { numeric: 533, code: "AWG", exponent: 2, name: "Aruban florin" },
+    Currency { numeric: 548, code: "VUV", exponent: 0, name: "Vanuatu vatu" },
+    Currency { numeric: 554, code: "NZD", exponent: 2, name: "New Zealand dollar" },
+    Currency { numeric: 558, code: "NIO", exponent: 2, name: "Nicaraguan córdoba" },
+    Currency { numeric: 566, code: "NGN", exponent: 2, name: "Nigerian naira" },
+    Currency { numeric: 578, code: "NOK", exponent: 2, name: "Norwegian krone" },
+    Currency { numeric: 586, code: "PKR", exponent: 2, name: "Pakistani rupee" },
+    Currency { numeric: 590, code: "PAB", exponent: 2, name: "Panamanian balboa" },
+    Currency { numeric: 598, code: "PGK", exponent: 2, name: "Papua New Guinean kina" },
+    Currency { numeric: 600, code: "PYG", exponent: 0, name: "Paraguayan guaraní" },
+    Currency { numeric: 604, code: "PEN", exponent: 2, name: "Peruvian sol" },
+    Currency { numeric: 608, code: "PHP", exponent: 2, name: "Philippine peso" },
+    Currency { numeric: 634, code: "QAR", exponent: 2, name: "Qatari riyal" },
+    Currency { numeric: 643, code: "RUB", exponent: 2, name: "Russian ruble" },
+    Currency { numeric: 646, code: "RWF", exponent: 0, name: "Rwandan franc" },
+    Currency { numeric: 654, code: "SHP", exponent: 2, name: "Saint Helena pound" },
+    Currency { numeric: 682, code: "SAR", exponent: 2, name: "Saudi riyal" },
+    Currency { numeric: 690, code: "SCR", exponent: 2, name: "Seychelles rupee" },
+    Currency { numeric: 702, code: "SGD", exponent: 2, name: "Singapore dollar" },
+    Currency { numeric: 704, code: "VND", exponent: 0, name: "Vietnamese đồng" },
+    Currency { numeric: 706, code: "SOS", exponent: 2, name: "Somali shilling" },
+    Currency { numeric: 710, code: "ZAR", exponent: 2, name: "South African rand" },
+    Currency { numeric: 728, code: "SSP", exponent: 2, name: "South Sudanese pound" },
+    Currency { numeric: 748, code: "SZL", exponent: 2, name: "Swazi lilangeni" },
+    Currency { numeric: 752, code: "SEK", exponent: 2, name: "Swedish krona/kronor" },
+    Currency { numeric: 756, code: "CHF", exponent: 2, name: "Swiss franc" },
+    Currency { numeric: 760, code: "SYP", exponent: 2, name: "Syrian pound" },
+    Currency { numeric: 764, code: "THB", exponent: 2, name: "Thai baht" },
+    Currency { numeric: 776, code: "TOP", exponent: 2, name: "Tongan paʻanga" },
+    Currency { numeric: 780, code: "TTD", exponent: 2, name: "Trinidad and Tobago dollar" },
+    Currency { numeric: 784, code: "AED", exponent: 2, name: "United Arab Emirates dirham" },
+    Currency { numeric: 788, code: "TND", exponent: 3, name: "Tunisian dinar" },
+    Currency { numeric: 800, code: "UGX", exponent: 0, name: "Ugandan shilling" },
+    Currency { numeric: 807, code: "MKD", exponent: 2, name: "Macedonian denar" },
+    Currency { numeric: 818, code: "EGP", exponent: 2, name: "Egyptian pound" },
+    Currency { numeric: 826, code: "GBP", exponent: 2, name: "Pound sterling" },
+    Currency { numeric: 834, code: "TZS", exponent: 2, name: "Tanzanian shilling" },
+    Currency { numeric: 840, code: "USD", exponent: 2, name: "United States dollar" },
+    Currency { numeric: 858, code: "UYU", exponent: 2, name: "Uruguayan peso" },
+    Currency { numeric: 860, code: "UZS", exponent: 2, name: "Uzbekistan som" },
+    Currency { numeric: 882, code: "WST", exponent: 2, name: "Samoan tala" },
+    Currency { numeric: 886, code: "YER", exponent: 2, name: "Yemeni rial" },
+    Currency { numeric: 901, code: "TWD", exponent: 2, name: "New Taiwan dollar" },
+    Currency { numeric: 925, code: "SLE", exponent: 2, name: "Sierra Leonean leone" },
+    Currency { numeric: 928, code: "VES", exponent: 2, name: "Venezuelan bolívar soberano" },
+    Currency { numeric: 929, code: "MRU", exponent: 2, name: "Mauritanian ouguiya" },
+    Currency { numeric: 930, code: "STN", exponent: 2, name: "São Tomé and Príncipe dobra" },
+    Currency { numeric: 932, code: "ZWL", exponent: 2, name: "Zimbabwean dollar" },
+    Currency { numeric: 933, code: "BYN", exponent: 2, name: "Belarusian ruble" },
+    Currency { numeric: 934, code: "TMT", exponent: 2, name: "Turkmenistan manat" },
+    Currency { numeric: 936, code: "GHS", exponent: 2, name: "Ghanaian cedi" },
+    Currency { numeric: 938, code: "SDG", exponent: 2, name: "Sudanese pound" },
+    Currency { numeric: 941, code: "RSD", exponent: 2, name: "Serbian dinar" },
+    Currency { numeric: 943, code: "MZN", exponent: 2, name: "Mozambican metical" },
+    Currency { numeric: 944, code: "AZN", exponent: 2, name: "Azerbaijani manat" },
+    Currency { numeric: 946, code: "RON", exponent: 2, name: "Romanian leu" },
+    Currency { numeric: 949, code: "TRY", exponent: 2, name: "Turkish lira" },
+    Currency { numeric: 950, code: "XAF", exponent: 0, name: "CFA franc BEAC" },
+    Currency { numeric: 951, code: "XCD", exponent: 2, name: "East Caribbean dollar" },
+    Currency { numeric: 952, code: "XOF", exponent: 0, name: "CFA franc BCEAO" },
+    Currency { numeric: 953, code: "XPF", exponent: 0, name: "CFP franc (franc Pacifique)" },
+    Currency { numeric: 967, code: "ZMW", exponent: 2, name: "Zambian kwacha" },
+    Currency { numeric: 968, code: "SRD", exponent: 2, name: "Surinamese dollar" },
+    Currency { numeric: 969, code: "MGA", exponent: 2, name: "Malagasy ariary" },
+    Currency { numeric: 971, code: "AFN", exponent: 2, name: "Afghan afghani" },
+    Currency { numeric: 972, code: "TJS", exponent: 2, name: "Tajikistani somoni" },
+    Currency { numeric: 973, code: "AOA", exponent: 2, name: "Angolan kwanza" },
+    Currency { numeric: 975, code: "BGN", exponent: 2, name: "Bulgarian lev" },
+    Currency { numeric: 976, code: "CDF", exponent: 2, name: "Congolese franc" },
+    Currency { numeric: 977, code: "BAM", exponent: 2, name: "Bosnia and Herzegovina convertible mark" },
+    Currency { numeric: 978, code: "EUR", exponent: 2, name: "Euro" },
+    Currency { numeric: 980, code: "UAH", exponent: 2, name: "Ukrainian hryvnia" },
+    Currency { numeric: 981, code: "GEL", exponent: 2, name: "Georgian lari" },
+    Currency { numeric: 985, code: "PLN", exponent: 2, name: "Polish złoty" },
+    Currency { numeric: 986, code: "BRL", exponent: 2, name: "Brazilian real" },
+];
+
+const COUNTRIES: [Country; 249] = [
+    Country { numeric: 4, alpha2: "AF", alpha3: "AFG", name: "Afghanistan" },
+    Country { numeric: 8, alpha2: "AL", alpha3: "ALB", name: "Albania" },
+    Country { numeric: 10, alpha2: "AQ", alpha3: "ATA", name: "Antarctica" },
+    Country { numeric: 12, alpha2: "DZ", alpha3: "DZA", name: "Algeria" },
+    Country { numeric: 16, alpha2: "AS", alpha3: "ASM", name: "American Samoa" },
+    Country { numeric: 20, alpha2: "AD", alpha3: "AND", name: "Andorra" },
+    Country { numeric: 24, alpha2: "AO", alpha3: "AGO", name: "Angola" },
+    Country { numeric: 28, alpha2: "AG", alpha3: "ATG", name: "Antigua and Barbuda" },
+    Country { numeric: 31, alpha2: "AZ", alpha3: "AZE", name: "Azerbaijan" },
+    Country { numeric: 32, alpha2: "AR", alpha3: "ARG", name: "Argentina" },
+    Country { numeric: 36, alpha2: "AU", alpha3: "AUS", name: "Australia" },
+    Country { numeric: 40, alpha2: "AT", alpha3: "AUT", name: "Austria" },
+    Country { numeric: 44, alpha2: "BS", alpha3: "BHS", name: "Bahamas" },
+    Country { numeric: 48, alpha2: "BH", alpha3: "BHR", name: "Bahrain" },
+    Country { numeric: 50, alpha2: "BD", alpha3: "BGD", name: "Bangladesh" },
+    Country { numeric: 51, alpha2: "AM", alpha3: "ARM", name: "Armenia" },
+    Country { numeric: 52, alpha2: "BB", alpha3: "BRB", name: "Barbados" },
+    Country { numeric: 56, alpha2: "BE", alpha3: "BEL", name: "Belgium" },
+    Country { numeric: 60, alpha2: "BM", alpha3: "BMU", name: "Bermuda" },
+    Country { numeric: 64, alpha2: "BT", alpha3: "BTN", name: "Bhutan" },
+    Country { numeric: 68, alpha2: "BO", alpha3: "BOL", name: "Bolivia" },
+    Country { numeric: 70, alpha2: "BA", alpha3: "BIH", name: "Bosnia and Herzegovina" },
+    Country { numeric: 72, alpha2: "BW", alpha3: "BWA", name: "Botswana" },
+    Country { numeric: 74, alpha2: "BV", alpha3: "BVT", name: "Bouvet Island" },
+    Country { numeric: 76, alpha2: "BR", alpha3: "BRA", name: "Brazil" },
+    Country { numeric: 84, alpha2: "BZ", alpha3: "BLZ", name: "Belize" },
+    Country { numeric: 86, alpha2: "IO", alpha3: "IOT", name: "British Indian Ocean Territory" },
+    Country { numeric: 90, alpha2: "SB", alpha3: "SLB", name: "Solomon Islands" },
+    Country { numeric: 92, alpha2: "VG", alpha3: "VGB", name: "British Virgin Islands" },
+    Country { numeric: 96, alpha2: "BN", alpha3: "BRN", name: "Brunei" },
+    Country { numeric: 100, alpha2: "BG", alpha3: "BGR", name: "Bulgaria" },
+    Country { numeric: 104, alpha2: "MM", alpha3: "MMR", name: "Myanmar" },
+    Country { numeric: 108, alpha2: "BI", alpha3: "BDI", name: "Burundi" },
+    Country { numeric: 112, alpha2: "BY", alpha3: "BLR", name: "Belarus" },
+    Country { numeric: 116, alpha2: "KH", alpha3: "KHM", name: "Cambodia" },
+    Country { numeric: 120, alpha2: "CM", alpha3: "CMR", name: "Cameroon" },
+    Country { numeric: 124, alpha2: "CA", alpha3: "CAN", name: "Canada" },
+    Country { numeric: 132, alpha2: "CV", alpha3: "CPV", name: "Cabo Verde" },
+    Country { numeric: 136, alpha2: "KY", alpha3: "CYM", name: "Cayman Islands" },
+    Country { numeric: 140, alpha2: "CF", alpha3: "CAF", name: "Central African Republic" },
+    Country { numeric: 144, alpha2: "LK", alpha3: "LKA", name: "Sri Lanka" },
+    Country { numeric: 148, alpha2: "TD", alpha3: "TCD", name: "Chad" },
+    Country { numeric: 152, alpha2: "CL", alpha3: "CHL", name: "Chile" },
+    Country { numeric: 156, alpha2: "CN", alpha3: "CHN", name: "China" },
+    Country { numeric: 158, alpha2: "TW", alpha3: "TWN", name: "Taiwan" },
+    Country { numeric: 162, alpha2: "CX", alpha3: "CXR", name: "Christmas Island" },
+    Country { numeric: 166, alpha2: "CC", alpha3: "CCK", name: "Cocos (Keeling) Islands" },
+    Country { numeric: 170, alpha2: "CO", alpha3: "COL", name: "Colombia" },
+    Country { numeric: 174, alpha2: "KM", alpha3: "COM", name: "Comoros" },
+    Country { numeric: 175, alpha2: "YT", alpha3: "MYT", name: "Mayotte" },
+    Country { numeric: 178, alpha2: "CG", alpha3: "COG", name: "Congo" },
+    Country { numeric: 180, alpha2: "CD", alpha3: "COD", name: "Democratic Republic of the Congo" },
+    Country { numeric: 184, alpha2: "CK", alpha3: "COK", name: "Cook Islands" },
+    Country { numeric: 188, alpha2: "CR", alpha3: "CRI", name: "Costa Rica" },
+    Country { numeric: 191, alpha2: "HR", alpha3: "HRV", name: "Croatia" },
+    Country { numeric: 192, alpha2: "CU", alpha3: "CUB", name: "Cuba" },
+    Country { numeric: 196, alpha2: "CY", alpha3: "CYP", name: "Cyprus" },
+    Country { numeric: 203, alpha2: "CZ", alpha3: "CZE", name: "Czechia" },
+    Country { numeric: 204, alpha2: "BJ", alpha3: "BEN", name: "Benin" },
+    Country { numeric: 208, alpha2: "DK", alpha3: "DNK", name: "Denmark" },
+    Country { numeric: 212, alpha2: "DM", alpha3: "DMA", name: "Dominica" },
+    Country { numeric: 214, alpha2: "DO", alpha3: "DOM", name: "Dominican Republic" },
+    Country { numeric: 218, alpha2: "EC", alpha3: "ECU", name: "Ecuador" },
+    Country { numeric: 222, alpha2: "SV", alpha3: "SLV", name: "El Salvador" },
+    Country { numeric: 226, alpha2: "GQ", alpha3: "GNQ", name: "Equatorial Guinea" },
+    Country { numeric: 231, alpha2: "ET", alpha3: "ETH", name: "Ethiopia" },
+    Country { numeric: 232, alpha2: "ER", alpha3: "ERI", name: "Eritrea" },
+    Country { numeric: 233, alpha2: "EE", alpha3: "EST", name: "Estonia" },
+    Country { numeric: 234, alpha2: "FO", alpha3: "FRO", name: "Faroe Islands" },
+    Country { numeric: 238, alpha2: "FK", alpha3: "FLK", name: "Falkland Islands" },
+    Country { numeric: 239, alpha2: "GS", alpha3: "SGS", name: "South Georgia and The South Sandwich Islands" },
+    Country { numeric: 242, alpha2: "FJ", alpha3: "FJI", name: "Fiji" },
+    Country { numeric: 246, alpha2: "FI", alpha3: "FIN", name: "Finland" },
+    Country { numeric: 248, alpha2: "AX", alpha3: "ALA", name: "Åland Islands" },
+    Country { numeric: 250, alpha2: "FR", alpha3: "FRA", name: "France" },
+    Country { numeric: 254, alpha2: "GF", alpha3: "GUF", name: "French Guiana" },
+    Country { numeric: 258, alpha2: "PF", alpha3: "PYF", name: "French Polynesia" },
+    Country { numeric: 260, alpha2: "TF", alpha3: "ATF", name: "French Southern Territories" },
+    Country { numeric: 262, alpha2: "DJ", alpha3: "DJI", name: "Djibouti" },
+    Country { numeric: 266, alpha2: "GA", alpha3: "GAB", name: "Gabon" },
+    Country { numeric: 268, alpha2: "GE", alpha3: "GEO", name: "Georgia" },
+    Country { numeric: 270, alpha2: "GM", alpha3: "GMB", name: "Gambia" },
+    Country { numeric: 275, alpha2: "PS", alpha3: "PSE", name: "Palestine" },
+    Country { numeric: 276, alpha2: "DE", alpha3: "DEU", name: "Germany" },
+    Country { numeric: 288, alpha2: "GH", alpha3: "GHA", name: "Ghana" },
+    Country { numeric: 292, alpha2: "GI", alpha3: "GIB", name: "Gibraltar" },
+    Country { numeric: 296, alpha2: "KI", alpha3: "KIR", name: "Kiribati" },
+    Country { numeric: 300, alpha2: "GR", alpha3: "GRC", name: "Greece" },
+    Country { numeric: 304, alpha2: "GL", alpha3: "GRL", name: "Greenland" },
+    Country { numeric: 308, alpha2: "GD", alpha3: "GRD", name: "Grenada" },
+    Country { numeric: 312, alpha2: "GP", alpha3: "GLP", name: "Guadeloupe" },
+    Country { numeric: 316, alpha2: "GU", alpha3: "GUM", name: "Guam" },
+    Country { numeric: 320, alpha2: "GT", alpha3: "GTM", name: "Guatemala" },
+    Country { numeric: 324, alpha2: "GN", alpha3: "GIN", name: "Guinea" },
+    Country { numeric: 328, alpha2: "GY", alpha3: "GUY", name: "Guyana" },
+    Country { numeric: 332, alpha2: "HT", alpha3: "HTI", name: "Haiti" },
+    Country { numeric: 334, alpha2: "HM", alpha3: "HMD", name: "Heard Island and McDonald Islands" },
+    Country { numeric: 336, alpha2: "VA", alpha3: "VAT", name: "Holy See" },
+    Country { numeric: 340, alpha2: "HN", alpha3: "HND", name: "Honduras" },
+    Country { numeric: 344, alpha2: "HK", alpha3: "HKG", name: "Hong Kong" },
+    Country { numeric: 348, alpha2: "HU", alpha3: "HUN", name: "Hungary" },
+    Country { numeric: 352, alpha2: "IS", alpha3: "ISL", name: "Iceland" },
+    Country { numeric: 356, alpha2: "IN", alpha3: "IND", name: "India" },
+    Country { numeric: 360, alpha2: "ID", alpha3: "IDN", name: "Indonesia" },
+    Country { numeric: 364, alpha2: "IR", alpha3: "IRN", name: "Iran" },
+    Country { numeric: 368, alpha2: "IQ", alpha3: "IRQ", name: "Iraq" },
+    Country { numeric: 372, alpha2: "IE", alpha3: "IRL", name: "Ireland" },
+    Country { numeric: 376, alpha2: "IL", alpha3: "ISR", name: "Israel" },
+    Country { numeric: 380, alpha2: "IT", alpha3: "ITA", name: "Italy" },
+    Country { numeric: 384, alpha2: "CI", alpha3: "CIV", name: "Côte d'Ivoire" },
+    Country { numeric: 388, alpha2: "JM", alpha3: "JAM", name: "Jamaica" },
+    Country { numeric: 392, alpha2: "JP", alpha3: "JPN", name: "Japan" },
+    Country { numeric: 398, alpha2: "KZ", alpha3: "KAZ", name: "Kazakhstan" },
+    Country { numeric: 400, alpha2: "JO", alpha3: "JOR", name: "Jordan" },
+    Country { numeric: 404, alpha2: "KE", alpha3: "KEN", name: "Kenya" },
+    Country { numeric: 408, alpha2: "KP", alpha3: "PRK", name: "North Korea" },
+    Country { numeric: 410, alpha2: "KR", alpha3: "KOR", name: "South Korea" },
+    Country { numeric: 414, alpha2: "KW", alpha3: "KWT", name: "Kuwait" },
+    Country { numeric: 417, alpha2: "KG", alpha3: "KGZ", name: "Kyrgyzstan" },
+    Country { numeric: 418, alpha2: "LA", alpha3: "LAO", name: "Laos" },
+    Country { numeric: 422, alpha2: "LB", alpha3: "LBN", name: "Lebanon" },
+    Country { numeric: 426, alpha2: "LS", alpha3: "LSO", name: "Lesotho" },
+    Country { numeric: 428, alpha2: "LV", alpha3: "LVA", name: "Latvia" },
+    Country { numeric: 430, alpha2: "LR", alpha3: "LBR", name: "Liberia" },
+    Country { numeric: 434, alpha2: "LY", alpha3: "LBY", name: "Libya" },
+    Country { numeric: 438, alpha2: "LI", alpha3: "LIE", name: "Liechtenstein" },
+    Country { numeric: 440, alpha2: "LT", alpha3: "LTU", name: "Lithuania" },
+    Country { numeric: 442, alpha2: "LU", alpha3: "LUX", name: "Luxembourg" },
+    Country { numeric: 446, alpha2: "MO", alpha3: "MAC", name: "Macao" },
+    Country { numeric: 450, alpha2: "MG", alpha3: "MDG", name: "Madagascar" },
+    Country { numeric: 454, alpha2: "MW", alpha3: "MWI", name: "Malawi" },
+    Country { numeric: 458, alpha2: "MY", alpha3: "MYS", name: "Malaysia" },
+    Country { numeric: 462, alpha2: "MV", alpha3: "MDV", name: "Maldives" },
+    Country { numeric: 466, alpha2: "ML", alpha3: "MLI", name: "Mali" },
+    Country { numeric: 470, alpha2: "MT", alpha3: "MLT", name: "Malta" },
+    Country { numeric: 474, alpha2: "MQ", alpha3: "MTQ", name: "Martinique" },
+    Country { numeric: 478, alpha2: "MR", alpha3: "MRT", name: "Mauritania" },
+    Country { numeric: 480, alpha2: "MU", alpha3: "MUS", name: "Mauritius" },
+    Country { numeric: 484, alpha2: "MX", alpha3: "MEX", name: "Mexico" },
+    Country { numeric: 492, alpha2: "MC", alpha3: "MCO", name: "Monaco" },
+    Country { numeric: 496, alpha2: "MN", alpha3: "MNG", name: "Mongolia" },
+    Country { numeric: 498, alpha2: "MD", alpha3: "MDA", name: "Moldova" },
+    Country { numeric: 499, alpha2: "ME", alpha3: "MNE", name: "Montenegro" },
+    Country { numeric: 500, alpha2: "MS", alpha3: "MSR", name: "Montserrat" },
+    Country { numeric: 504, alpha2: "MA", alpha3: "MAR", name: "Morocco" },
+    Country { numeric: 508, alpha2: "MZ", alpha3: "MOZ", name: "Mozambique" },
+    Country { numeric: 512, alpha2: "OM", alpha3: "OMN", name: "Oman" },
+    Country { numeric: 516, alpha2: "NA", alpha3: "NAM", name: "Namibia" },
+    Country { numeric: 520, alpha2: "NR", alpha3: "NRU", name: "Nauru" },
+    Country { numeric: 524, alpha2: "NP", alpha3: "NPL", name: "Nepal" },
+    Country { numeric: 528, alpha2: "NL", alpha3: "NLD", name: "Netherlands" },
+    Country { numeric: 531, alpha2: "CW", alpha3: "CUW", name: "Curaçao" },
+    Country { numeric: 533, alpha2: "AW", alpha3: "ABW", name: "Aruba" },
+    Country { numeric: 534, alpha2: "SX", alpha3: "SXM", name: "Sint Maarten" },
+    Country { numeric: 535, alpha2: "BQ", alpha3: "BES", name: "Bonaire, Sint Eustatius and Saba" },
+    Country { numeric: 540, alpha2: "NC", alpha3: "NCL", name: "New Caledonia" },
+    Country { numeric: 548, alpha2: "VU", alpha3: "VUT", name: "Vanuatu" },
+    Country { numeric: 554, alpha2: "NZ", alpha3: "NZL", name: "New Zealand" },
+    Country { numeric: 558, alpha2: "NI", alpha3: "NIC", name: "Nicaragua" },
+    Country { numeric: 562, alpha2: "NE", alpha3: "NER", name: "Niger" },
+    Country { numeric: 566, alpha2: "NG", alpha3: "NGA", name: "Nigeria" },
+    Country { numeric: 570, alpha2: "NU", alpha3: "NIU", name: "Niue" },
+    Country { numeric: 574, alpha2: "NF", alpha3: "NFK", name: "Norfolk Island" },
+    Country { numeric: 578, alpha2: "NO", alpha3: "NOR", name: "Norway" },
+    Country { numeric: 580, alpha2: "MP", alpha3: "MNP", name: "Northern Mariana Islands" },
+    Country { numeric: 581, alpha2: "UM", alpha3: "UMI", name: "United States Minor Outlying Islands" },
+    Country { numeric: 583, alpha2: "FM", alpha3: "FSM", name: "Micronesia" },
+    Country { numeric: 584, alpha2: "MH", alpha3: "MHL", name: "Marshall Islands" },
+    Country { numeric: 585, alpha2: "PW", alpha3: "PLW", name: "Palau" },
+    Country { numeric: 586, alpha2: "PK", alpha3: "PAK", name: "Pakistan" },
+    Country { numeric: 591, alpha2: "PA", alpha3: "PAN", name: "Panama" },
+    Country { numeric: 598, alpha2: "PG", alpha3: "PNG", name: "Papua New Guinea" },
+    Country { numeric: 600, alpha2: "PY", alpha3: "PRY", name: "Paraguay" },
+    Country { numeric: 604, alpha2: "PE", alpha3: "PER", name: "Peru" },
+    Country { numeric: 608, alpha2: "PH", alpha3: "PHL", name: "Philippines" },
+    Country { numeric: 612, alpha2: "PN", alpha3: "PCN", name: "Pitcairn" },
+    Country { numeric: 616, alpha2: "PL", alpha3: "POL", name: "Poland" },
+    Country { numeric: 620, alpha2: "PT", alpha3: "PRT", name: "Portugal" },
+    Country { numeric: 624, alpha2: "GW", alpha3: "GNB", name: "Guinea-Bissau" },
+    Country { numeric: 626, alpha2: "TL", alpha3: "TLS", name: "Timor-Leste" },
+    Country { numeric: 630, alpha2: "PR", alpha3: "PRI", name: "Puerto Rico" },
+    Country { numeric: 634, alpha2: "QA", alpha3: "QAT", name: "Qatar" },
+    Country { numeric: 638, alpha2: "RE", alpha3: "REU", name: "Réunion" },
+    Country { numeric: 642, alpha2: "RO", alpha3: "ROU", name: "Romania" },
+    Country { numeric: 643, alpha2: "RU", alpha3: "RUS", name: "Russia" },
+    Country { numeric: 646, alpha2: "RW", alpha3: "RWA", name: "Rwanda" },
+    Country { numeric: 652, alpha2: "BL", alpha3: "BLM", name: "Saint Barthélemy" },
+    Country { numeric: 654, alpha2: "SH", alpha3: "SHN", name: "Saint Helena, Ascension and Tristan da Cunha" },
+    Country { numeric: 659, alpha2: "KN", alpha3: "KNA", name: "Saint Kitts and Nevis" },
+    Country { numeric: 660, alpha2: "AI", alpha3: "AIA", name: "Anguilla" },
+    Country { numeric: 662, alpha2: "LC", alpha3: "LCA", name: "Saint Lucia" },
+    Country { numeric: 663, alpha2: "MF", alpha3: "MAF", name: "Saint Martin" },
+    Country { numeric: 666, alpha2: "PM", alpha3: "SPM", name: "Saint Pierre and Miquelon" },
+    Country { numeric: 670, alpha2: "VC", alpha3: "VCT", name: "Saint Vincent and The Grenadines" },
+    Country { numeric: 674, alpha2: "SM", alpha3: "SMR", name: "San Marino" },
+    Country { numeric: 678, alpha2: "ST", alpha3: "STP", name: "São Tomé and Príncipe" },
+    Country { numeric: 682, alpha2: "SA", alpha3: "SAU", name: "Saudi Arabia" },
+    Country { numeric: 686, alpha2: "SN", alpha3: "SEN", name: "Senegal" },
+    Country { numeric: 688, alpha2: "RS", alpha3: "SRB", name: "Serbia" },
+    Country { numeric: 690, alpha2: "SC", alpha3: "SYC", name: "Seychelles" },
+    Country { numeric: 694, alpha2: "SL", alpha3: "SLE", name: "Sierra Leone" },
+    Country { numeric: 702, alpha2: "SG", alpha3: "SGP", name: "Singapore" },
+    Country { numeric: 703, alpha2: "SK", alpha3: "SVK", name: "Slovakia" },
+    Country { numeric: 704, alpha2: "VN", alpha3: "VNM", name: "Vietnam" },
+    Country { numeric: 705, alpha2: "SI", alpha3: "SVN", name: "Slovenia" },
+    Country { numeric: 706, alpha2: "SO", alpha3: "SOM", name: "Somalia" },
+    Country { numeric: 710, alpha2: "ZA", alpha3: "ZAF", name: "South Africa" },
+    Country { numeric: 716, alpha2: "ZW", alpha3: "ZWE", name: "Zimbabwe" },
+    Country { numeric: 724, alpha2: "ES", alpha3: "ESP", name: "Spain" },
+    Country { numeric: 728, alpha2: "SS", alpha3: "SSD", name: "South Sudan" },
+    Country { numeric: 729, alpha2: "SD", alpha3: "SDN", name: "Sudan" },
+    Country { numeric: 732, alpha2: "EH", alpha3: "ESH", name: "Western Sahara" },
+    Country { numeric: 740, alpha2: "SR", alpha3: "SUR", name: "Suriname" },
+    Country { numeric: 744, alpha2: "SJ", alpha3: "SJM", name: "Svalbard and Jan Mayen" },
+    Country { numeric: 748, alpha2: "SZ", alpha3: "SWZ", name: "Eswatini" },
+    Country { numeric: 752, alpha2: "SE", alpha3: "SWE", name: "Sweden" },
+    Country { numeric: 756, alpha2: "CH", alpha3: "CHE", name: "Switzerland" },
+    Country { numeric: 760, alpha2: "SY", alpha3: "SYR", name: "Syria" },
+    Country { numeric: 762, alpha2: "TJ", alpha3: "TJK", name: "Tajikistan" },
+    Country { numeric: 764, alpha2: "TH", alpha3: "THA", name: "Thailand" },
+    Country { numeric: 768, alpha2: "TG", alpha3: "TGO", name: "Togo" },
+    Country { numeric: 772, alpha2: "TK", alpha3: "TKL", name: "Tokelau" },
+    Country { numeric: 776, alpha2: "TO", alpha3: "TON", name: "Tonga" },
+    Country { numeric: 780, alpha2: "TT", alpha3: "TTO", name: "Trinidad and Tobago" },
+    Country { numeric: 784, alpha2: "AE", alpha3: "ARE", name: "United Arab Emirates" },
+    Country { numeric: 788, alpha2: "TN", alpha3: "TUN", name: "Tunisia" },
+    Country { numeric: 792, alpha2: "TR", alpha3: "TUR", name: "Turkey" },
+    Country { numeric: 795, alpha2: "TM", alpha3: "TKM", name: "Turkmenistan" },
+    Country { numeric: 796, alpha2: "TC", alpha3: "TCA", name: "Turks and Caicos Islands" },
+    Country { numeric: 798, alpha2: "TV", alpha3: "TUV", name: "Tuvalu" },
+    Country { numeric: 800, alpha2: "UG", alpha3: "UGA", name: "Uganda" },
+    Country { numeric: 804, alpha2: "UA", alpha3: "UKR", name: "Ukraine" },
+    Country { numeric: 807, alpha2: "MK", alpha3: "MKD", name: "North Macedonia" },
+    Country { numeric: 818, alpha2: "EG", alpha3: "EGY", name: "Egypt" },
+    Country { numeric: 826, alpha2: "GB", alpha3: "GBR", name: "United Kingdom" },
+    Country { numeric: 831, alpha2: "GG", alpha3: "GGY", name: "Guernsey" },
+    Country { numeric: 832, alpha2: "JE", alpha3: "JEY", name: "Jersey" },
+    Country { numeric: 833, alpha2: "IM", alpha3: "IMN", name: "Isle of Man" },
+    Country { numeric: 834, alpha2: "TZ", alpha3: "TZA", name: "Tanzania" },
+    Country { numeric: 840, alpha2: "US", alpha3: "USA", name: "United States" },
+    Country { numeric: 850, alpha2: "VI", alpha3: "VIR", name: "US Virgin Islands" },
+    Country { numeric: 854, alpha2: "BF", alpha3: "BFA", name: "Burkina Faso" },
+    Country { numeric: 858, alpha2: "UY", alpha3: "URY", name: "Uruguay" },
+    Country { numeric: 860, alpha2: "UZ", alpha3: "UZB", name: "Uzbekistan" },
+    Country { numeric: 862, alpha2: "VE", alpha3: "VEN", name: "Venezuela" },
+    Country { numeric: 876, alpha2: "WF", alpha3: "WLF", name: "Wallis and Futuna" },
+    Country { numeric: 882, alpha2: "WS", alpha3: "WSM", name: "Samoa" },
+    Country { numeric: 887, alpha2: "YE", alpha3: "YEM", name: "Yemen" },
+    Country { numeric: 894, alpha2: "ZM", alpha3: "ZMB", name: "Zambia" },
+];
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn names_currencies_and_countries() {
+        use crate::tlv::TagID;
+        use std::convert::TryFrom;
+
+        assert_eq!(crate::iso::currency(826).unwrap().to_string(), "GBP (826)");
+        assert_eq!(crate::iso::parse_currency("jpy").unwrap().exponent, 0);
+        assert_eq!(crate::iso::parse_currency("036").unwrap().code, "AUD");
+        assert!(crate::iso::parse_currency("XXY").is_none());
+        assert_eq!(crate::iso::parse_country("GB").unwrap().numeric, 826);
+        assert_eq!(crate::iso::parse_country("deu").unwrap().name, "Germany");
+        assert_eq!(crate::iso::country(840).unwrap().to_string(), "United States");
+        assert_eq!(crate::iso::describe(TagID::TerminalCountryCode, &[0x08, 0x26]).as_deref(), Some("United Kingdom"));
+        assert_eq!(crate::iso::describe(TagID::TransactionCurrencyCode, &[0x09, 0x78]).as_deref(), Some("EUR (978)"));
+        assert_eq!(crate::iso::describe(TagID::TransactionCurrencyCode, &[0x09, 0x7a]), None);
+        assert_eq!(crate::iso::describe(TagID::AmountAuthorised, &[0x08, 0x26]), None);
+
+        let record = crate::hex::decode("700F5F280208269F420209785F55024E4C").unwrap();
+        let codes = crate::iso::describe_tags(&crate::tlv::TagList::try_from(record.as_slice()).unwrap());
+        assert_eq!(codes, vec![
+            (TagID::IssuerCountryCode, "United Kingdom".to_string()),
+            (TagID::ApplicationCurrencyCode, "EUR (978)".to_string()),
+            (TagID::IssuerCountryCodeAlpha2, "Netherlands".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,169 @@
+use std::convert::TryFrom;
+use crate::terminal::TerminalData;
+
+// Application Interchange Profile
+const AIP_ON_DEVICE_CVM_SUPPORTED: (usize, u8) = (0, 0x02);
+const AIP_EMV_MODE_SUPPORTED: (usize, u8) = (1, 0x80);
+
+fn is_set(data: &[u8], (byte, mask): (usize, u8)) -> bool {
+    data.get(byte).map(|b| b & mask != 0).unwrap_or(false)
+}
+
+/// Whether the card can take the EMV mode path rather than mag-stripe mode
+pub fn emv_mode_supported(aip: [u8; 2]) -> bool {
+    is_set(&aip, AIP_EMV_MODE_SUPPORTED)
+}
+
+/// Book C-2: the CVM Results when there's no need to go through the CVM
+/// List, either because the amount is under the reader's CVM limit or the
+/// cardholder verifies on their own device
+pub fn cvm_results_without_list(terminal: &TerminalData, aip: [u8; 2]) -> Option<[u8; 3]> {
+    if !terminal.contactless_cvm_required() {
+        Some([0x1f, 0x00, 0x02])
+    } else if is_set(&aip, AIP_ON_DEVICE_CVM_SUPPORTED) {
+        Some([0x01, 0x00, 0x02])
+    } else {
+        None
+    }
+}
+
+/// Whether the card declined pending something the cardholder has to do on
+/// their phone, going by the POS Cardholder Interaction Information
+pub fn see_phone(response: &crate::data::GenerateAcResponse) -> bool {
+    match response.pos_cardholder_interaction_information.as_deref() {
+        Some([_, b2, b3, ..]) => b2 & 0x03 != 0 || b3 & 0x0f != 0,
+        _ => false
+    }
+}
+
+/// Book C-2: the CVM for mag-stripe mode, where there's no CVM List to
+/// fall back on
+pub fn mag_stripe_cvm(terminal: &TerminalData, aip: [u8; 2]) -> crate::kernel3::Cvm {
+    if !terminal.contactless_cvm_required() {
+        crate::kernel3::Cvm::NotRequired
+    } else if is_set(&aip, AIP_ON_DEVICE_CVM_SUPPORTED) {
+        crate::kernel3::Cvm::ConsumerDevice
+    } else {
+        crate::kernel3::Cvm::Signature
+    }
+}
+
+/// The track data a card gives for mag-stripe mode, and where in its
+/// discretionary data the terminal puts the dynamic parts
+#[derive(Debug, Clone)]
+pub struct MagStripeData {
+    pub track2: Vec<u8>,
+    /// Digits of the discretionary data taken by the CVC3, the lowest bit
+    /// being the rightmost digit
+    pub pcvc3: u16,
+    /// Digits of the discretionary data taken by the unpredictable number and
+    /// ATC
+    pub punatc: u16,
+    /// How many digits of the ATC go in
+    pub natc: u8,
+    pub udol: Option<Vec<u8>>,
+}
+
+impl TryFrom<&crate::data::ApplicationRecords> for MagStripeData {
+    type Error = &'static str;
+
+    fn try_from(value: &crate::data::ApplicationRecords) -> Result<Self, Self::Error> {
+        let track2 = match value.get_bytes(crate::tlv::TagID::Track2Data) {
+            Some(t) => t,
+            None => return Err("No Track 2 Data")
+        };
+        let bitmap = |tag| match value.get_bytes(tag) {
+            Some(b) if b.len() == 2 => Ok(u16::from_be_bytes([b[0], b[1]])),
+            _ => Err("Missing track 2 bitmap")
+        };
+        // Mastercard reuses 9F66, the TTQ elsewhere, for PUNATC(Track2)
+        let punatc = bitmap(crate::tlv::TagID::TerminalTransactionQualifiers)?;
+        let pcvc3 = bitmap(crate::tlv::TagID::Pcvc3Track2)?;
+        let natc = match value.get_bytes(crate::tlv::TagID::NatcTrack2) {
+            Some(b) if b.len() == 1 => b[0],
+            _ => return Err("No NATC(Track2)")
+        };
+        if natc as u32 > punatc.count_ones() {
+            return Err("More ATC digits than room for them");
+        }
+        if punatc.count_ones() - natc as u32 > 8 {
+            return Err("More unpredictable number digits than the terminal can send");
+        }
+
+        Ok(Self {
+            track2,
+            pcvc3,
+            punatc,
+            natc,
+            // ...and 9F69, Card Authentication Related Data elsewhere, for the UDOL
+            udol: value.get_bytes(crate::tlv::TagID::CardAuthenticationRelatedData),
+        })
+    }
+}
+
+/// Writes the last digits of `value` into the places `bitmap` picks out,
+/// counting from the right of `digits`
+fn place_digits(digits: &mut [char], bitmap: u16, value: u64) -> Result<(), &'static str> {
+    let mut value = value;
+    for bit in 0..16 {
+        if bitmap & (1 << bit) == 0 {
+            continue;
+        }
+        if bit >= digits.len() {
+            return Err("Bitmap runs past the discretionary data");
+        }
+        digits[digits.len() - 1 - bit] = std::char::from_digit((value % 10) as u32, 10).unwrap();
+        value /= 10;
+    }
+    Ok(())
+}
+
+impl MagStripeData {
+    /// How many digits of unpredictable number the track has room for
+    pub fn un_digits(&self) -> u32 {
+        self.punatc.count_ones() - self.natc as u32
+    }
+
+    /// Book C-2 S13: the Track 2 Data with the CVC3, unpredictable number and
+    /// ATC put into its discretionary data, as sent online
+    pub fn dynamic_track2(&self, un: u32, atc: u16, cvc3: u16) -> Result<Vec<u8>, &'static str> {
+        let hex = crate::hex::encode(&self.track2);
+        let mut digits: Vec<char> = hex.trim_end_matches('F').chars().collect();
+        let separator = match digits.iter().position(|c| *c == 'D') {
+            Some(s) => s,
+            None => return Err("No field separator in Track 2 Data")
+        };
+        // The expiry date and service code come before the discretionary data
+        let start = separator + 8;
+        if digits.len() <= start {
+            return Err("No discretionary data in Track 2 Data");
+        }
+        let discretionary = &mut digits[start..];
+
+        let un_digits = self.un_digits();
+        place_digits(discretionary, 0x0001, un_digits as u64)?;
+        place_digits(discretionary, self.pcvc3, cvc3 as u64)?;
+        let atc = atc as u64 % 10u64.pow(self.natc as u32);
+        let un = un as u64 % 10u64.pow(un_digits);
+        place_digits(discretionary, self.punatc, un * 10u64.pow(self.natc as u32) + atc)?;
+
+        let mut out: String = digits.into_iter().collect();
+        if !out.len().is_multiple_of(2) {
+            out.push('F');
+        }
+        crate::hex::decode(&out).ok_or("Invalid Track 2 Data")
+    }
+}
+
+/// The CVC3 for track 2 and the ATC from COMPUTE CRYPTOGRAPHIC CHECKSUM
+pub fn cryptographic_checksum(response: &crate::tlv::TagList) -> Result<(u16, u16), &'static str> {
+    let template = match response.get_tag(crate::tlv::TagID::ResponseMessageTemplateFormat2) {
+        Some(t) => t,
+        None => return Err("Not a COMPUTE CRYPTOGRAPHIC CHECKSUM response")
+    };
+    let value = |tag, name| match template.get_tag(tag).map(|t| Vec::<u8>::from(t.contents())) {
+        Some(b) if b.len() == 2 => Ok(u16::from_be_bytes([b[0], b[1]])),
+        _ => Err(name)
+    };
+    Ok((value(crate::tlv::TagID::Cvc3Track2, "No CVC3(Track2)")?, value(crate::tlv::TagID::ApplicationTransactionCounter, "No ATC")?))
+}
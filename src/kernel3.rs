@@ -0,0 +1,194 @@
+use std::convert::TryFrom;
+use crate::terminal::TerminalData;
+
+// Card Transaction Qualifiers
+const CTQ_ONLINE_PIN_REQUIRED: (usize, u8) = (0, 0x80);
+const CTQ_SIGNATURE_REQUIRED: (usize, u8) = (0, 0x40);
+const CTQ_ONLINE_IF_ODA_FAILS: (usize, u8) = (0, 0x20);
+const CTQ_SWITCH_INTERFACE_IF_ODA_FAILS: (usize, u8) = (0, 0x10);
+const CTQ_CDCVM_PERFORMED: (usize, u8) = (1, 0x80);
+
+// Terminal Transaction Qualifiers
+const TTQ_MSD_SUPPORTED: (usize, u8) = (0, 0x80);
+const TTQ_OFFLINE_ONLY: (usize, u8) = (0, 0x08);
+const TTQ_ONLINE_PIN_SUPPORTED: (usize, u8) = (0, 0x04);
+const TTQ_SIGNATURE_SUPPORTED: (usize, u8) = (0, 0x02);
+const TTQ_ONLINE_CRYPTOGRAM_REQUIRED: (usize, u8) = (1, 0x80);
+const TTQ_CVM_REQUIRED: (usize, u8) = (1, 0x40);
+
+fn is_set(data: &[u8], (byte, mask): (usize, u8)) -> bool {
+    data.get(byte).map(|b| b & mask != 0).unwrap_or(false)
+}
+
+/// What the card hands back from GET PROCESSING OPTIONS on the qVSDC path,
+/// which takes the place of GENERATE AC
+#[derive(Debug, Clone)]
+pub struct QvsdcResponse {
+    pub cryptogram: crate::data::GenerateAcResponse,
+    pub ctq: Option<[u8; 2]>,
+    /// Card Authentication Related Data, covered by the fDDA signature
+    pub card_authentication_related_data: Option<Vec<u8>>,
+}
+
+impl TryFrom<&crate::tlv::TagList> for QvsdcResponse {
+    type Error = &'static str;
+
+    fn try_from(value: &crate::tlv::TagList) -> Result<Self, Self::Error> {
+        let f2 = match value.get_tag(crate::tlv::TagID::ResponseMessageTemplateFormat2) {
+            Some(t) => t,
+            None => return Err("Not a qVSDC response")
+        };
+        let bytes = |tag| f2.get_tag(tag).map(|t| Vec::<u8>::from(t.contents()));
+        // Without a CID the cryptogram can only be taken as an ARQC
+        let cid = match bytes(crate::tlv::TagID::CryptogramInformationData) {
+            Some(b) if b.len() == 1 => b[0],
+            Some(_) => return Err("Invalid CID"),
+            None => 0x80
+        };
+        let atc = match bytes(crate::tlv::TagID::ApplicationTransactionCounter) {
+            Some(b) if b.len() == 2 => u16::from_be_bytes([b[0], b[1]]),
+            _ => return Err("No ATC")
+        };
+        let application_cryptogram = match bytes(crate::tlv::TagID::ApplicationCryptogram) {
+            Some(b) if b.len() == 8 => [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]],
+            _ => return Err("No application cryptogram")
+        };
+        let ctq = match bytes(crate::tlv::TagID::CardTransactionQualifiers) {
+            Some(b) if b.len() == 2 => Some([b[0], b[1]]),
+            Some(_) => return Err("Invalid CTQ"),
+            None => None
+        };
+
+        Ok(Self {
+            cryptogram: crate::data::GenerateAcResponse {
+                cid,
+                atc,
+                application_cryptogram,
+                issuer_application_data: bytes(crate::tlv::TagID::IssuerApplicationData),
+                signed_dynamic_application_data: bytes(crate::tlv::TagID::SignedDynamicApplicationData),
+                pos_cardholder_interaction_information: bytes(crate::tlv::TagID::PosCardholderInteractionInformation),
+            },
+            ctq,
+            card_authentication_related_data: bytes(crate::tlv::TagID::CardAuthenticationRelatedData),
+        })
+    }
+}
+
+impl QvsdcResponse {
+    /// Whether the copy of the CTQ in the Card Authentication Related Data,
+    /// which unlike the CTQ itself is covered by fDDA, agrees that the
+    /// cardholder was verified on their device
+    pub fn cdcvm_confirmed(&self) -> bool {
+        match (&self.card_authentication_related_data, self.ctq) {
+            (Some(data), Some(ctq)) if data.len() >= 7 => data[5..7] == ctq && is_set(&ctq, CTQ_CDCVM_PERFORMED),
+            _ => false
+        }
+    }
+
+    /// The terminal data fDDA signs: just the unpredictable number for
+    /// version 00, or with the amount, currency and Card Authentication
+    /// Related Data for version 01
+    pub fn terminal_dynamic_data(&self, terminal: &TerminalData) -> Vec<u8> {
+        let mut out = terminal.unpredictable_number.to_vec();
+        if let Some(data) = &self.card_authentication_related_data {
+            if data.first() == Some(&0x01) {
+                out.extend(crate::util::to_bcd(terminal.amount_authorised, 6));
+                out.extend(crate::util::to_bcd(terminal.currency_code as u64, 2));
+                out.extend(data);
+            }
+        }
+        out
+    }
+}
+
+/// Whether the reader takes the legacy MSD path from cards without qVSDC
+pub fn msd_supported(ttq: &[u8; 4]) -> bool {
+    is_set(ttq, TTQ_MSD_SUPPORTED)
+}
+
+/// Whether the reader can send transactions online at all
+pub fn online_capable(ttq: &[u8; 4]) -> bool {
+    !is_set(ttq, TTQ_OFFLINE_ONLY)
+}
+
+/// The cryptogram the TTQ asked the card for
+pub fn requested_cryptogram(ttq: &[u8; 4]) -> crate::transaction::Cryptogram {
+    if is_set(ttq, TTQ_ONLINE_CRYPTOGRAM_REQUIRED) {
+        crate::transaction::Cryptogram::ARQC
+    } else {
+        crate::transaction::Cryptogram::TC
+    }
+}
+
+/// What to do with an offline approval when fDDA fails
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OdaFailure {
+    GoOnline,
+    SwitchInterface,
+    Decline,
+}
+
+/// Book C-3: the card's say in the CTQ on what happens after fDDA fails
+pub fn oda_failure(ttq: &[u8; 4], ctq: Option<[u8; 2]>) -> OdaFailure {
+    let ctq = match ctq {
+        Some(c) => c,
+        None => return OdaFailure::Decline
+    };
+    if is_set(&ctq, CTQ_ONLINE_IF_ODA_FAILS) && online_capable(ttq) {
+        OdaFailure::GoOnline
+    } else if is_set(&ctq, CTQ_SWITCH_INTERFACE_IF_ODA_FAILS) {
+        OdaFailure::SwitchInterface
+    } else {
+        OdaFailure::Decline
+    }
+}
+
+/// A CVM for a contactless transaction
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize)]
+pub enum Cvm {
+    OnlinePin,
+    Signature,
+    /// Verified on the cardholder's own device, such as a phone
+    ConsumerDevice,
+    NotRequired,
+    /// A CVM is required but the card and reader have none in common
+    Failed,
+}
+
+impl Cvm {
+    /// CVM Results to record for the method
+    pub fn results(&self) -> [u8; 3] {
+        match self {
+            Cvm::OnlinePin => [0x02, 0x00, 0x00],
+            Cvm::Signature => [0x1e, 0x00, 0x00],
+            Cvm::ConsumerDevice => [0x01, 0x00, 0x02],
+            Cvm::NotRequired => [0x1f, 0x00, 0x02],
+            Cvm::Failed => [0x3f, 0x00, 0x01],
+        }
+    }
+}
+
+/// Book C-3: picks the CVM from what the card asks for in the CTQ and what
+/// the reader supports in the TTQ, online PIN only being possible online.
+/// Without a CTQ the reader falls back on what it supports itself.
+pub fn cvm(ttq: &[u8; 4], ctq: Option<[u8; 2]>, online: bool) -> Cvm {
+    if !is_set(ttq, TTQ_CVM_REQUIRED) {
+        return Cvm::NotRequired;
+    }
+    let ctq = match ctq {
+        Some(c) => c,
+        None if is_set(ttq, TTQ_SIGNATURE_SUPPORTED) => return Cvm::Signature,
+        None if is_set(ttq, TTQ_ONLINE_PIN_SUPPORTED) && online => return Cvm::OnlinePin,
+        None => return Cvm::Failed
+    };
+
+    if is_set(&ctq, CTQ_ONLINE_PIN_REQUIRED) && is_set(ttq, TTQ_ONLINE_PIN_SUPPORTED) && online {
+        Cvm::OnlinePin
+    } else if is_set(&ctq, CTQ_CDCVM_PERFORMED) {
+        Cvm::ConsumerDevice
+    } else if is_set(&ctq, CTQ_SIGNATURE_REQUIRED) && is_set(ttq, TTQ_SIGNATURE_SUPPORTED) {
+        Cvm::Signature
+    } else {
+        Cvm::Failed
+    }
+}
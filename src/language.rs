@@ -0,0 +1,306 @@
+//! What the terminal says to the cardholder, in the languages it knows. Book 1
+//! 12.4 has the terminal use the first language in the card's Language
+//! Preference that it supports, or its own language if there isn't one.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "cy")]
+    Welsh,
+    #[serde(rename = "fr")]
+    French,
+    #[serde(rename = "de")]
+    German,
+    #[serde(rename = "es")]
+    Spanish,
+    #[serde(rename = "it")]
+    Italian,
+}
+
+/// A message for the cardholder. Those from Book 4 11.2's standard messages
+/// are worded as they are there.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Message {
+    Approved,
+    Declined,
+    EnterPin,
+    EnterPinOnPinpad,
+    IncorrectPin,
+    NotAccepted,
+    CardError,
+    ProcessingError,
+    TryAgain,
+    InsertCard,
+    UseMagStripe,
+    SeePhone,
+    SignatureRequired,
+    /// The transaction was stopped before it finished
+    Cancelled,
+    AvailableApplications,
+    SelectApplication,
+    /// Asks whether to go ahead with an application needing confirmation,
+    /// followed by its name
+    UseApplication,
+    Cancel,
+}
+
+impl Language {
+    pub const ALL: [Language; 6] = [
+        Language::English,
+        Language::Welsh,
+        Language::French,
+        Language::German,
+        Language::Spanish,
+        Language::Italian,
+    ];
+
+    /// The ISO 639-1 code, as the card gives it in its Language Preference
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Welsh => "cy",
+            Language::French => "fr",
+            Language::German => "de",
+            Language::Spanish => "es",
+            Language::Italian => "it",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.iter().find(|l| l.code().eq_ignore_ascii_case(code)).copied()
+    }
+
+    /// The language of a POSIX locale, such as `cy_GB.UTF-8`
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        Self::from_code(locale.get(..2)?)
+    }
+
+    /// The language of the locale the terminal is running in, going by the
+    /// environment as `setlocale` would
+    pub fn from_environment() -> Option<Self> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|v| std::env::var(v).ok())
+            .find(|v| !v.is_empty())
+            .and_then(|v| Self::from_locale(&v))
+    }
+
+    pub fn message(&self, message: Message) -> &'static str {
+        use Message::*;
+        match self {
+            Language::English => match message {
+                Approved => "Approved",
+                Declined => "Declined",
+                EnterPin => "Enter PIN",
+                EnterPinOnPinpad => "Enter PIN on the reader's pinpad",
+                IncorrectPin => "Incorrect PIN",
+                NotAccepted => "Not accepted",
+                CardError => "Card error",
+                ProcessingError => "Processing error",
+                TryAgain => "Try again",
+                InsertCard => "Insert card",
+                UseMagStripe => "Use mag stripe",
+                SeePhone => "See phone",
+                SignatureRequired => "Signature required",
+                Cancelled => "Cancelled",
+                AvailableApplications => "Available applications",
+                SelectApplication => "Select application",
+                UseApplication => "Use",
+                Cancel => "Cancel",
+            },
+            Language::Welsh => match message {
+                Approved => "Cymeradwywyd",
+                Declined => "Gwrthodwyd",
+                EnterPin => "Rhowch eich PIN",
+                EnterPinOnPinpad => "Rhowch eich PIN ar fysellbad y darllenydd",
+                IncorrectPin => "PIN anghywir",
+                NotAccepted => "Heb ei dderbyn",
+                CardError => "Gwall cerdyn",
+                ProcessingError => "Gwall prosesu",
+                TryAgain => "Rhowch gynnig arall",
+                InsertCard => "Rhowch y cerdyn i mewn",
+                UseMagStripe => "Defnyddiwch y stribed magnetig",
+                SeePhone => "Edrychwch ar eich ffôn",
+                SignatureRequired => "Angen llofnod",
+                Cancelled => "Canslwyd",
+                AvailableApplications => "Rhaglenni sydd ar gael",
+                SelectApplication => "Dewiswch raglen",
+                UseApplication => "Defnyddio",
+                Cancel => "Canslo",
+            },
+            Language::French => match message {
+                Approved => "Accepté",
+                Declined => "Refusé",
+                EnterPin => "Saisissez votre code",
+                EnterPinOnPinpad => "Saisissez votre code sur le clavier du lecteur",
+                IncorrectPin => "Code faux",
+                NotAccepted => "Non accepté",
+                CardError => "Erreur carte",
+                ProcessingError => "Erreur de traitement",
+                TryAgain => "Réessayez",
+                InsertCard => "Insérez la carte",
+                UseMagStripe => "Utilisez la piste",
+                SeePhone => "Voir téléphone",
+                SignatureRequired => "Signature requise",
+                Cancelled => "Annulé",
+                AvailableApplications => "Applications disponibles",
+                SelectApplication => "Choisissez l'application",
+                UseApplication => "Utiliser",
+                Cancel => "Annuler",
+            },
+            Language::German => match message {
+                Approved => "Genehmigt",
+                Declined => "Abgelehnt",
+                EnterPin => "PIN eingeben",
+                EnterPinOnPinpad => "PIN am Kartenleser eingeben",
+                IncorrectPin => "Falsche PIN",
+                NotAccepted => "Nicht akzeptiert",
+                CardError => "Kartenfehler",
+                ProcessingError => "Verarbeitungsfehler",
+                TryAgain => "Erneut versuchen",
+                InsertCard => "Karte einstecken",
+                UseMagStripe => "Magnetstreifen verwenden",
+                SeePhone => "Siehe Telefon",
+                SignatureRequired => "Unterschrift erforderlich",
+                Cancelled => "Abgebrochen",
+                AvailableApplications => "Verfügbare Anwendungen",
+                SelectApplication => "Anwendung wählen",
+                UseApplication => "Verwenden",
+                Cancel => "Abbrechen",
+            },
+            Language::Spanish => match message {
+                Approved => "Aprobada",
+                Declined => "Denegada",
+                EnterPin => "Introduzca su PIN",
+                EnterPinOnPinpad => "Introduzca su PIN en el teclado del lector",
+                IncorrectPin => "PIN incorrecto",
+                NotAccepted => "No aceptada",
+                CardError => "Error de tarjeta",
+                ProcessingError => "Error de proceso",
+                TryAgain => "Inténtelo de nuevo",
+                InsertCard => "Inserte la tarjeta",
+                UseMagStripe => "Use la banda magnética",
+                SeePhone => "Vea su teléfono",
+                SignatureRequired => "Firma requerida",
+                Cancelled => "Cancelada",
+                AvailableApplications => "Aplicaciones disponibles",
+                SelectApplication => "Seleccione la aplicación",
+                UseApplication => "Usar",
+                Cancel => "Cancelar",
+            },
+            Language::Italian => match message {
+                Approved => "Approvata",
+                Declined => "Rifiutata",
+                EnterPin => "Digitare il PIN",
+                EnterPinOnPinpad => "Digitare il PIN sulla tastiera del lettore",
+                IncorrectPin => "PIN errato",
+                NotAccepted => "Non accettata",
+                CardError => "Errore carta",
+                ProcessingError => "Errore di elaborazione",
+                TryAgain => "Riprovare",
+                InsertCard => "Inserire la carta",
+                UseMagStripe => "Usare la banda magnetica",
+                SeePhone => "Vedere il telefono",
+                SignatureRequired => "Firma richiesta",
+                Cancelled => "Annullata",
+                AvailableApplications => "Applicazioni disponibili",
+                SelectApplication => "Selezionare l'applicazione",
+                UseApplication => "Usare",
+                Cancel => "Annulla",
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    /// The language's name in itself
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Language::English => "English",
+            Language::Welsh => "Cymraeg",
+            Language::French => "Français",
+            Language::German => "Deutsch",
+            Language::Spanish => "Español",
+            Language::Italian => "Italiano",
+        })
+    }
+}
+
+/// A Language Preference (5F2D): up to four ISO 639-1 codes, most preferred
+/// first, which the card gives run together as in `frde`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguagePreference(Vec<String>);
+
+impl LanguagePreference {
+    pub const MAX_LANGUAGES: usize = 4;
+
+    /// None unless it's one to four two letter codes
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.is_empty() || !data.len().is_multiple_of(2) || data.len() > Self::MAX_LANGUAGES * 2 || !data.iter().all(u8::is_ascii_alphabetic) {
+            return None;
+        }
+        Some(Self(data.chunks(2).map(|c| String::from_utf8_lossy(c).to_ascii_lowercase()).collect()))
+    }
+
+    /// The codes in lower case, in the card's order
+    pub fn codes(&self) -> impl Iterator<Item=&str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    /// The first of the card's languages that's also one of `supported`,
+    /// whatever order they're in there
+    pub fn best_match(&self, supported: &[Language]) -> Option<Language> {
+        self.codes().filter_map(Language::from_code).find(|l| supported.contains(l))
+    }
+}
+
+impl std::str::FromStr for LanguagePreference {
+    type Err = crate::data::DataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s.as_bytes()).ok_or(crate::data::DataError::InvalidLanguagePreference)
+    }
+}
+
+impl std::fmt::Display for LanguagePreference {
+    /// The codes separated by commas
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join(", "))
+    }
+}
+
+/// The first language in a Language Preference that the terminal supports, or
+/// the terminal's own language if none are
+pub fn select(preference: &LanguagePreference, terminal: Language) -> Language {
+    preference.best_match(&Language::ALL).unwrap_or(terminal)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parses_language_preferences() {
+        use crate::language::{Language, LanguagePreference};
+        let preference: LanguagePreference = "nlfrdeen".parse().unwrap();
+        assert_eq!(preference.codes().collect::<Vec<_>>(), vec!["nl", "fr", "de", "en"]);
+        assert_eq!(preference.to_string(), "nl, fr, de, en");
+        assert_eq!(preference.best_match(&[Language::English, Language::German]), Some(Language::German));
+        assert_eq!(preference.best_match(&[Language::Welsh]), None);
+        assert_eq!("nlfrdeenit".parse::<LanguagePreference>(), Err(crate::data::DataError::InvalidLanguagePreference));
+        assert!("fre".parse::<LanguagePreference>().is_err());
+        assert!("".parse::<LanguagePreference>().is_err());
+    }
+
+    #[test]
+    fn picks_language() {
+        use crate::language::Language;
+        let preference = |p: &str| p.parse::<crate::language::LanguagePreference>().unwrap();
+        assert_eq!(crate::language::select(&preference("frde"), Language::English), Language::French);
+        assert_eq!(crate::language::select(&preference("nlDE"), Language::English), Language::German);
+        assert_eq!(crate::language::select(&preference("nlsv"), Language::Welsh), Language::Welsh);
+        assert_eq!(Language::from_locale("cy_GB.UTF-8"), Some(Language::Welsh));
+        assert_eq!(Language::from_locale("C"), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! EMV terminal processing over any [`CardTransport`],
+//! from application selection through to the transaction outcome.
+//!
+//! [`Terminal`] waits for cards over PC/SC, reads them and runs transactions
+//! with them given a [`TransactionRequest`], looking after the rest itself.
+//! Underneath it, [`run`] takes a card and the terminal's data and goes
+//! through the whole transaction, giving a
+//! [`TransactionOutcome`](transaction::TransactionOutcome). For finer control,
+//! [`choose_application`] and [`Transaction`](transaction::Transaction) take it
+//! a step at a time. Cards are reached over PC/SC with [`card::Session`], or
+//! scripted with `mock::MockCard` for testing. How far a transaction has got
+//! comes as [`events::Event`]s, for drawing an interface around it.
+//!
+//! Everything to do with readers is behind the default `pcsc` feature. Without
+//! it the crate builds without the smart card libraries, for parsing dumps and
+//! running transactions over transports of its own. Reaching acquirer hosts
+//! and webhooks is likewise behind `online`, and keys in PKCS#11 tokens behind
+//! `pkcs11`. With none of the three the crate builds for
+//! `wasm32-unknown-unknown`, so a dump viewer in the browser can decode cards
+//! just as the terminal does. The scripted cards in `mock` are behind the
+//! `mock` feature, for testing against in other crates.
+
+#![allow(clippy::upper_case_acronyms)]
+
+#[cfg(feature = "pcsc")]
+extern crate pcsc;
+extern crate encoding;
+extern crate alloc;
+
+pub mod tlv;
+pub mod hex;
+pub mod apdu;
+pub mod atr;
+pub mod util;
+pub mod card;
+pub mod data;
+pub mod transaction;
+pub mod transport;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod dump;
+pub mod history;
+pub mod terminal;
+pub mod pinpad;
+pub mod selection;
+pub mod auth;
+pub mod capk;
+pub mod cvm;
+pub mod pinblock;
+pub mod entry_point;
+pub mod kernel2;
+pub mod kernel3;
+pub mod state;
+pub mod batch;
+pub mod online;
+#[cfg(feature = "online")]
+pub mod host;
+pub mod acquirer;
+#[cfg(feature = "online")]
+pub mod webhook;
+pub mod hsm;
+pub mod dukpt;
+pub mod serve;
+pub mod replay;
+pub mod bins;
+pub mod iso;
+pub mod language;
+pub mod events;
+pub mod stdio;
+pub mod device;
+
+pub use device::{CardData, Terminal};
+pub use terminal::TransactionRequest;
+
+use transport::CardTransport;
+
+
+/// Selects an application by AID, giving its DF Name and FCI Proprietary
+/// Template if the card has it
+pub fn select_aid<T: CardTransport + ?Sized>(card: &T, aid: &[u8]) -> Result<Option<(Vec<u8>, tlv::Tag)>, card::CardError> {
+    let select_resp = match card::card_select(card, aid, false) {
+        Ok(r) => r,
+        Err(e) if card::is_card_removed(&e) || card::is_cancelled(&e) => return Err(e),
+        Err(_) => return Ok(None)
+    };
+    let fci = match select_resp.get_tag(tlv::TagID::FileControlInformationTemplate) {
+        Some(f) => f,
+        None => return Ok(None)
+    };
+    let (df_name, fcipt) = match (fci.get_tag(tlv::TagID::DedicatedFileName), fci.get_tag(tlv::TagID::FileControlInformationProprietaryTemplate)) {
+        (Some(d), Some(f)) => (d, f),
+        _ => return Ok(None)
+    };
+    let df_name = match df_name.contents() {
+        tlv::TagContents::Bytes(b) => b,
+        _ => return Ok(None)
+    };
+    Ok(Some((df_name.to_vec(), fcipt.to_owned())))
+}
+
+/// Picks the application to use from the candidate list. Without a cardholder
+/// to ask, applications needing confirmation are left out and the highest
+/// priority one left is used.
+pub fn choose_application<T: CardTransport + ?Sized>(card: &T, interface: card::Interface, terminal_applications: &[selection::TerminalApplication], interactive: bool, language: language::Language) -> Result<Option<data::Application>, card::CardError> {
+    let mut possible_applications = selection::build_candidate_list(card, interface, terminal_applications)?;
+    if !interactive {
+        possible_applications.retain(|a| a.priority().auto_selection_allowed());
+    }
+    selection::sort_candidates(&mut possible_applications);
+
+    if possible_applications.is_empty() {
+        tracing::info!("No possible applications found");
+        Ok(None)
+    } else if !interactive || possible_applications.len() == 1 {
+        let application = possible_applications.into_iter().next().unwrap();
+        if !application.priority().auto_selection_allowed() {
+            let selected = util::get_input_bool(&format!("{} {}?", language.message(language::Message::UseApplication), application.name()));
+            if !selected {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(application))
+    } else {
+        Ok(selection::choose_from_menu(&possible_applications, language).cloned())
+    }
+}
+
+const MAX_CARD_RESETS: u8 = 3;
+/// How many times selection is tried over contact, resetting the card in
+/// between, before giving up on the chip
+const MAX_SELECTION_ATTEMPTS: u8 = 3;
+
+/// Runs a transaction with `terminal` as the starting terminal data, restarting
+/// it from scratch if the card is reset along the way
+pub fn run<T: CardTransport + ?Sized>(card: &mut T, terminal: &terminal::TerminalData, ca_keys: &capk::CapkStore, online_pin_key: Option<&pinblock::PinKey>, acquirer: Option<&mut dyn acquirer::Acquirer>, interactive: bool) -> Result<transaction::TransactionOutcome, transaction::TransactionError> {
+    let _span = tracing::info_span!("transaction", interface = %terminal.interface, amount = terminal.amount_authorised).entered();
+    events::emit(&terminal.events, events::Event::CardDetected {
+        reader: card.reader_name(),
+        atr: card.atr().ok().map(|a| a.raw().to_vec()),
+        interface: terminal.interface.to_string(),
+    });
+    let mut card = transport::Counting::new(card);
+    let result = run_transaction(&mut card, terminal, ca_keys, online_pin_key, acquirer, interactive).map(|mut outcome| {
+        outcome.apdu_count = card.count();
+        outcome
+    });
+    // Those listening hear how it ended even when the card couldn't be talked to
+    events::emit(&terminal.events, events::Event::OutcomeReady(Box::new(match &result {
+        Ok(outcome) => outcome.clone(),
+        Err(e) => transaction::TransactionOutcome::card_error(e, terminal.interface)
+    })));
+    result
+}
+
+/// Like [`run`], without counting the C-APDUs exchanged
+pub fn run_transaction<T: CardTransport + ?Sized>(card: &mut T, terminal: &terminal::TerminalData, ca_keys: &capk::CapkStore, online_pin_key: Option<&pinblock::PinKey>, mut acquirer: Option<&mut dyn acquirer::Acquirer>, interactive: bool) -> Result<transaction::TransactionOutcome, transaction::TransactionError> {
+    let interface = terminal.interface;
+    let mut application: Option<data::Application> = None;
+    let mut combination: Option<entry_point::Combination> = None;
+    let mut resets = 0;
+    let mut selection_attempts = 0;
+
+    let pin_entry = if interactive { cvm::PinEntry::Prompt } else { cvm::PinEntry::Bypass };
+    let terminal_applications = &terminal.applications;
+
+    loop {
+        let selection = tracing::info_span!("selection").entered();
+        let chosen = match &application {
+            Some(a) => Ok(Some(a.clone())),
+            None if interface == card::Interface::Contactless => entry_point::select(card, terminal, terminal_applications).map(|c| {
+                combination = c;
+                combination.as_ref().map(|c| c.application.clone())
+            }),
+            None => choose_application(card, interface, terminal_applications, interactive, terminal.language)
+        };
+        // Selection failing over contact is only the chip's fault if it doesn't
+        // answer a plain SELECT either
+        let chip_failed = application.is_none() && interface == card::Interface::Contact && match &chosen {
+            Ok(None) => !selection::chip_responds(card)?,
+            Err(e) => !card::is_card_removed(e) && !card::is_card_reset(e) && !card::is_cancelled(e),
+            Ok(Some(_)) => false
+        };
+        selection.exit();
+        if chip_failed {
+            selection_attempts += 1;
+            if selection_attempts >= MAX_SELECTION_ATTEMPTS {
+                return Ok(transaction::TransactionOutcome::without_transaction(transaction::Outcome::Fallback, Some(format!("Chip failed selection {} times", selection_attempts))));
+            }
+            tracing::warn!("Chip not responding, resetting it");
+            card.reconnect()?;
+            continue;
+        }
+
+        let result = match chosen {
+            Ok(Some(a)) => {
+                events::emit(&terminal.events, events::Event::ApplicationSelected {
+                    aid: a.aid().to_vec(),
+                    name: a.name().to_string(),
+                });
+                application = Some(a.clone());
+                let mut terminal = terminal.next_transaction();
+                if let Some(config) = selection::find_terminal_application(terminal_applications, a.aid()) {
+                    terminal.configure_for(config);
+                }
+                if let Some(c) = &combination {
+                    entry_point::activate(&mut terminal, c);
+                }
+                let mut transaction = transaction::Transaction::new(card, terminal, ca_keys, a);
+                transaction.pin_entry = pin_entry.clone();
+                transaction.online_pin_key = online_pin_key.cloned();
+                transaction.acquirer = acquirer.as_mut().map(|a| &mut **a as &mut dyn acquirer::Acquirer);
+                transaction.kernel_id = combination.as_ref().map(|c| c.kernel_id);
+                transaction.process().map(|o| transaction.report(o))
+            }
+            Ok(None) => return Ok(transaction::TransactionOutcome::without_transaction(transaction::Outcome::Terminated, None)),
+            Err(e) => Err(e.into())
+        };
+
+        match result {
+            Err(e) if e.is_card_reset() && resets < MAX_CARD_RESETS => {
+                resets += 1;
+                match &application {
+                    Some(a) => tracing::warn!("Card was reset, reconnecting and re-selecting {}", a.name()),
+                    None => tracing::warn!("Card was reset, reconnecting and restarting application selection"),
+                }
+                card.reconnect()?;
+            }
+            r => return r
+        }
+    }
+}
@@ -0,0 +1,2239 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+
+fn parse_hex(hex: &str) -> Vec<Option<u8>> {
+    let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    digits.chunks(2).map(|pair| {
+        let pair: String = pair.iter().collect();
+        if pair == "??" {
+            None
+        } else {
+            Some(u8::from_str_radix(&pair, 16).expect("Invalid hex in mock script"))
+        }
+    }).collect()
+}
+
+/// A C-APDU to match against, written as hex where `??` matches any single
+/// byte and a trailing `*` matches anything (including nothing) after it
+struct ApduPattern {
+    bytes: Vec<Option<u8>>,
+    any_suffix: bool,
+}
+
+impl ApduPattern {
+    fn new(pattern: &str) -> Self {
+        let pattern = pattern.trim();
+        let (pattern, any_suffix) = match pattern.strip_suffix('*') {
+            Some(p) => (p, true),
+            None => (pattern, false)
+        };
+        Self {
+            bytes: parse_hex(pattern),
+            any_suffix,
+        }
+    }
+
+    fn matches(&self, command: &[u8]) -> bool {
+        if command.len() < self.bytes.len() || (!self.any_suffix && command.len() != self.bytes.len()) {
+            return false;
+        }
+        self.bytes.iter().zip(command).all(|(p, c)| match p {
+            Some(p) => p == c,
+            None => true
+        })
+    }
+}
+
+struct Exchange {
+    command: ApduPattern,
+    response: Vec<u8>,
+}
+
+/// A scripted stand-in for a real card. Each incoming C-APDU is answered by the
+/// first scripted exchange whose pattern matches it, or `6D00` if none do.
+pub struct MockCard {
+    atr: Vec<u8>,
+    exchanges: Vec<Exchange>,
+    transcript: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+    resets: u32,
+    buffers: RefCell<crate::apdu::ApduBuffers>,
+}
+
+impl MockCard {
+    pub fn new(atr: &str) -> Self {
+        Self {
+            atr: parse_hex(atr).into_iter().map(|b| b.expect("Wildcard in ATR")).collect(),
+            exchanges: vec![],
+            transcript: RefCell::new(vec![]),
+            resets: 0,
+            buffers: RefCell::default(),
+        }
+    }
+
+    /// Adds a response to send back when a command matching `command` is received.
+    /// The response includes the trailing status word.
+    pub fn expect(mut self, command: &str, response: &str) -> Self {
+        self.exchanges.push(Exchange {
+            command: ApduPattern::new(command),
+            response: parse_hex(response).into_iter().map(|b| b.expect("Wildcard in response")).collect(),
+        });
+        self
+    }
+
+    /// Adds a response that takes precedence over any already scripted, for
+    /// varying a fixture
+    pub fn expect_first(mut self, command: &str, response: &str) -> Self {
+        self.exchanges.insert(0, Exchange {
+            command: ApduPattern::new(command),
+            response: parse_hex(response).into_iter().map(|b| b.expect("Wildcard in response")).collect(),
+        });
+        self
+    }
+
+    /// Every command received so far along with the response that was given
+    pub fn transcript(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.transcript.borrow().clone()
+    }
+
+    pub fn resets(&self) -> u32 {
+        self.resets
+    }
+}
+
+impl crate::transport::CardTransport for MockCard {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], crate::transport::TransportError> {
+        let response = match self.exchanges.iter().find(|e| e.command.matches(send_buffer)) {
+            Some(e) => e.response.clone(),
+            None => vec![0x6d, 0x00]
+        };
+        if response.len() > receive_buffer.len() {
+            return Err(crate::transport::TransportError::InsufficientBuffer);
+        }
+
+        self.transcript.borrow_mut().push((send_buffer.to_vec(), response.clone()));
+        receive_buffer[..response.len()].copy_from_slice(&response);
+        Ok(&receive_buffer[..response.len()])
+    }
+
+    fn reconnect(&mut self) -> Result<(), crate::transport::TransportError> {
+        self.resets += 1;
+        Ok(())
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, crate::transport::TransportError> {
+        crate::atr::Atr::try_from(self.atr.as_slice()).map_err(|_| crate::transport::TransportError::InvalidAtr)
+    }
+
+    fn buffers(&self) -> Option<std::cell::RefMut<'_, crate::apdu::ApduBuffers>> {
+        self.buffers.try_borrow_mut().ok()
+    }
+}
+
+/// A contact Visa debit card with a PSE, a single application and a typical
+/// set of AFL records
+pub fn visa_card() -> MockCard {
+    visa_card_with_cvm_rule("4203")
+}
+
+/// `visa_card` with the first rule of its CVM List, normally online PIN if the
+/// terminal supports it, swapped for another
+fn visa_card_with_cvm_rule(rule: &str) -> MockCard {
+    MockCard::new("3B 68 00 00 80 66 B0 07 01 01 77 07")
+        // SELECT 1PAY.SYS.DDF01
+        .expect("00A404000E315041592E5359532E4444463031 00",
+                "6F1A840E315041592E5359532E4444463031A5088801015F2D02656E 9000")
+        // PSE directory, SFI 1
+        .expect("00B2010C00", "701A61184F07A0000000031010500A56495341204445424954870101 9000")
+        .expect("00B2??0C00", "6A83")
+        // SELECT Visa debit
+        .expect("00A4040007A0000000031010 00",
+                "6F368407A0000000031010A52B500A564953412044454249548701015F2D02656E9F380C9F66049F02069F37045F2A02BF0C059F4D020B0A 9000")
+        // GET PROCESSING OPTIONS, AFL of SFI 2 record 1 and SFI 3 records 1-2
+        .expect("80A80000*", "770E82021C0094081001010018010201 9000")
+        .expect("00B2011400",
+                "704557134761739001010010D30122011143804400000F5F201A43415244484F4C4445522F5649534120202020202020202020209F1F1031313433383030343430303030303030 9000")
+        .expect("00B2011C00",
+                &format!("704C5A0847617390010100105F24033012315F25032001015F280208265F3401019F0702FF008E0E0000000000000000{}1E031F009F0D05F0400088009F0E0500100000009F0F05F040009800 9000", rule))
+        .expect("00B2021C00",
+                "703B8C219F02069F03069F1A0295055F2A029A039C019F37049F35019F45029F4C089F34038D0C910A8A0295059F37049F4C089F0802008C9F42020826 9000")
+        .expect("00B2????00", "6A83")
+        // EXTERNAL AUTHENTICATE, only accepting an ARPC of 1122334455667788
+        .expect("008200000A11223344556677883030 00", "9000")
+        .expect("00820000*", "6300")
+        // GENERATE AC, answering with whatever was asked for
+        .expect("80AE80*", "8012800001112233445566778806010A03A00000 9000")
+        .expect("80AE40*", "8012400002112233445566778806010A03600000 9000")
+        .expect("80AE00*", "8012000002112233445566778806010A03200000 9000")
+}
+
+/// `visa_card` asking for plaintext offline PIN first, which is 1234
+pub fn pin_card() -> MockCard {
+    visa_card_with_cvm_rule("4103")
+        .expect("80CA9F1700", "9F170103 9000")
+        .expect("0020008008241234FFFFFFFFFF00", "9000")
+        .expect("00200080*", "63C2")
+}
+
+/// `visa_card` with consecutive offline limits of 3 and 5, having made 10
+/// transactions and last gone online on the 5th
+pub fn velocity_card() -> MockCard {
+    visa_card()
+        .expect_first("00B2021C00",
+                      "70438C219F02069F03069F1A0295055F2A029A039C019F37049F35019F45029F4C089F34038D0C910A8A0295059F37049F4C089F0802008C9F420208269F1401039F230105 9000")
+        .expect("80CA9F3600", "9F3602000A 9000")
+        .expect("80CA9F1300", "9F13020005 9000")
+}
+
+/// Test CA key, index 92 under the Visa RID, that certifies the issuer key of `sda_card`
+pub fn test_ca_key() -> crate::capk::CaPublicKey {
+    crate::capk::CaPublicKey {
+        rid: [0xa0, 0x00, 0x00, 0x00, 0x03],
+        index: 0x92,
+        modulus: parse_hex("E64A0B3D068BAF7ABB4923F3F8529098D299919108D1D60DA606E8360627ACF13131CC60F8B96B185CDA001B0788FAAB2BDB59CE10DA591B203F83F915C9FD13CC5D0F33EC73A1B4255609A0DD73F9631011B955F35A0881DFB3481B51D18D4286E37B08296BC28372002C5601588913936035512E311974870BC5A2BCAF5D4D")
+            .into_iter().map(|b| b.unwrap()).collect(),
+        exponent: vec![0x03],
+        hash: None,
+        expiry: None,
+    }
+}
+
+/// A CA key store holding just `test_ca_key`
+pub fn test_ca_keys() -> crate::capk::CapkStore {
+    let mut store = crate::capk::CapkStore::new();
+    store.insert(test_ca_key()).unwrap();
+    store
+}
+
+/// A card supporting only SDA, its static data signed under `test_ca_key`.
+/// Its IACs take an ODA failure online, declining it when that isn't possible.
+pub fn sda_card() -> MockCard {
+    MockCard::new("3B 68 00 00 80 66 B0 07 01 01 77 07")
+        .expect("00A4040007A0000000031010 00", "6F148407A0000000031010A509500756495341205344 9000")
+        .expect("80A8000002830000", "8006480008010201 9000")
+        .expect("00B2010C00",
+                "704D5A0847617390010100105F24033012318F01929F32010392146434BD2B07C72EB6231D5F660896DFB976EDC2879F4A01828C0295059F0D05F8400080009F0E0500000000009F0F05F840008000 9000")
+        .expect("00B2020C00",
+                "7081F5908180E1A8B1220B1D604019A28E34FFEBB2319694D0996781E7AB4721D6C20B283766799B024A22D8A04AC609398EC7251CD3B1FB3BA314812392F572B8631DDA57C219365859C02C619CE94096BE51B4DDBC90A399E0C481BD019683B416714B348DD4D29460CB23058EA0F08C7DA6FA7C34BF2D5C60F5537A4D20AAA43161F23CD39370AF3159A7D351FFA76EBF0A22B8014C020E2EBE0EFC169E16B167457C2D00979219978A0A404C832A8CCF780BA3B8289EF3F9662F9BF72AB282469FD080E4846BA142EB356E7E578890CBB835761183519D24ACDB81ECCB7F1196FE599A677B010ABB6161F5200C63B829F53860DBCD02 9000")
+        .expect("80AE80*", "8012800001112233445566778806010000000000 9000")
+        .expect("80AE40*", "8012400001112233445566778806010000000000 9000")
+        .expect("80AE00*", "8012000001112233445566778806010000000000 9000")
+}
+
+/// A card supporting CDA, its ICC key certified under `test_ca_key`. The
+/// signature on its TC only checks out for an unpredictable number of 01020304,
+/// its CDOL1 asking for nothing else.
+pub fn cda_card() -> MockCard {
+    MockCard::new("3B 68 00 00 80 66 B0 07 01 01 77 07")
+        .expect("00A4040007A0000000031010 00", "6F148407A0000000031010A509500756495341205344 9000")
+        .expect("80A8000002830000", "8006090008010201 9000")
+        .expect("00B2010C00",
+                "706F5A0847617390010100105F24033012318F01929F32010392146434BD2B07C72EB6231D5F660896DFB976EDC2879F4701039F481ACF5382028977CF915BA463033909B3E6EA17A6462E130A498ECB9F4A01828C039F37049F0D05FC400080009F0E0500000000009F0F05FC40008000 9000")
+        .expect("00B2020C00",
+                "7081F6908180E1A8B1220B1D604019A28E34FFEBB2319694D0996781E7AB4721D6C20B283766799B024A22D8A04AC609398EC7251CD3B1FB3BA314812392F572B8631DDA57C219365859C02C619CE94096BE51B4DDBC90A399E0C481BD019683B416714B348DD4D29460CB23058EA0F08C7DA6FA7C34BF2D5C60F5537A4D20AAA43161F23CD39F467093420278CE2B583649941BD35F6BC4CA5CF7B8D11F62F6CD26BD05AD7D4C537A5FA194316C591AE6C28151DCAFDF0B7E052285A69D3F49CC528AAC4D3462ABF3DC9FE5FA98D8B0B52EE2466D554A24074858CDBC07351A608363D2F9FC856A17D6B6FE0516D7EBFD06CD8ECFA010866A 9000")
+        .expect("80AE50*",
+                "77769F2701409F360200019F4B608AD9B3DE55DBCEEDADFDE873D84341C13576EADCDC799B7784EFCD23D9FD4A5950B32D83F328F58916214E7D5600414CAE321A0EEA8F2B4181085694C9C6C5BF70F68F52CE70811D0CA618C8008AB2560A1E3FD1C62594F45D6553A99DA50AFE9F100706010A03600000 9000")
+        .expect("80AE80*", "8012800001112233445566778806010000000000 9000")
+        .expect("80AE00*", "8012000001112233445566778806010000000000 9000")
+}
+
+/// A contactless Visa card taking the qVSDC path, approving offline from GET
+/// PROCESSING OPTIONS. Its fDDA signature, under an ICC key certified by
+/// `test_ca_key`, only checks out for an unpredictable number of 01020304 and
+/// 20.00 in pounds sterling.
+pub fn qvsdc_card() -> MockCard {
+    MockCard::new("3B 88 80 01 00 00 00 00 00 00 00 00 09")
+        .expect("00A404000E325041592E5359532E4444463031 00",
+                "6F2F840E325041592E5359532E4444463031A51DBF0C1A61184F07A0000000031010500A56495341204445424954870101 9000")
+        // PDOL of TTQ, amount, unpredictable number and currency
+        .expect("00A4040007A0000000031010 00",
+                "6F268407A0000000031010A51B500A564953412044454249549F380C9F66049F02069F37045F2A02 9000")
+        .expect("80A80000*",
+                "77819A820220009404080102019F360200019F260811223344556677889F1007060112030000009F6C0240009F2701409F690701AABBCCDD40009F4B609F62910C3F6569EAC7D61DCB2A2A34A7E04AF73DD3DAF2E6703CDC0552FE7710EF6DB5BCD76B088F35404A9607FFA96F2BF767D270F0BAE5E3614C0443BB5C304D28D4896BF49A5F397D529FF2C33697568F3C6C96677F49B4C620B867458E75 9000")
+        .expect("00B2010C00",
+                "70525A0847617390010100105F24033012318F01929F32010392146434BD2B07C72EB6231D5F660896DFB976EDC2879F4701039F481ACF5382028977CF915BA463033909B3E6EA17A6462E130A498ECB9F4A0182 9000")
+        .expect("00B2020C00",
+                "7081F6908180E1A8B1220B1D604019A28E34FFEBB2319694D0996781E7AB4721D6C20B283766799B024A22D8A04AC609398EC7251CD3B1FB3BA314812392F572B8631DDA57C219365859C02C619CE94096BE51B4DDBC90A399E0C481BD019683B416714B348DD4D29460CB23058EA0F08C7DA6FA7C34BF2D5C60F5537A4D20AAA43161F23CD39F4670516D440CF70807DDF9763A8729F15B644EBC15A8ECFE8A63A4546D55B721E61967D8B736D78132A22C4C5DAB3F37F7AC51C64C49912B3295C83754F68C09CCB8648F16F05058B98E4AF4B9715617329D19BDACF76268163400288BC55FCE9409E80AE7CB189804964C15D4E170869408 9000")
+}
+
+/// `qvsdc_card` asking to go online instead, with a CTQ asking for a signature
+/// if the reader needs a CVM
+pub fn qvsdc_online_card() -> MockCard {
+    qvsdc_card()
+        .expect_first("80A80000*", "772D820220009404080102019F360200019F260811223344556677889F1007060112030000009F6C0240009F270180 9000")
+}
+
+/// A Mastercard on the contactless interface, in EMV mode with on-device CVM
+/// and an issuer action code sending everything online
+pub fn mastercard_contactless_card() -> MockCard {
+    MockCard::new("3B 88 80 01 00 00 00 00 00 00 00 00 09")
+        .expect("00A404000E325041592E5359532E4444463031 00",
+                "6F33840E325041592E5359532E4444463031A521BF0C1E611C4F07A0000000041010500A4D4153544552434152448701019F2A0102 9000")
+        .expect("00A4040007A0000000041010 00",
+                "6F178407A0000000041010A50C500A4D415354455243415244 9000")
+        .expect("80A8000002830000", "80061A8008010100 9000")
+        .expect("00B2010C00",
+                "70545A0854133300896000105F24033012318C159F02069F03069F1A0295055F2A029A039C019F37048D058A029F37048E0C000000000000000042031F039F0D0500000000009F0E0500000000009F0F058000000000 9000")
+        .expect("80AE*", "8012800001112233445566778806010A03A00000 9000")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::CardTransport;
+
+    #[test]
+    fn wildcards_match() {
+        let pattern = ApduPattern::new("00 B2 ?? 0C 00");
+        assert!(pattern.matches(&[0x00, 0xb2, 0x05, 0x0c, 0x00]));
+        assert!(!pattern.matches(&[0x00, 0xb2, 0x05, 0x14, 0x00]));
+        assert!(!pattern.matches(&[0x00, 0xb2, 0x05, 0x0c]));
+
+        let pattern = ApduPattern::new("80A80000*");
+        assert!(pattern.matches(&[0x80, 0xa8, 0x00, 0x00, 0x02, 0x83, 0x00, 0x00]));
+        assert!(!pattern.matches(&[0x80, 0xa8, 0x00]));
+    }
+
+    #[test]
+    fn unscripted_command_is_rejected() {
+        let card = visa_card();
+        let mut buf = [0; 258];
+        assert_eq!(card.transmit(&[0x80, 0xca, 0x9f, 0x17, 0x00], &mut buf).unwrap(), &[0x6d, 0x00]);
+    }
+
+    #[test]
+    fn atr_parses() {
+        let atr = visa_card().atr().unwrap();
+        assert_eq!(atr.protocols(), vec![0]);
+        assert_eq!(atr.historical_bytes().len(), 8);
+    }
+
+    #[test]
+    fn reads_pse() {
+        let card = visa_card();
+        assert_eq!(crate::selection::get_pse_sfi(&card).unwrap(), Some(1));
+        let entries = crate::selection::read_pse_entries(&card, 1).unwrap();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn selects_application() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        assert_eq!(application.name(), "VISA DEBIT");
+        assert_eq!(application.aid(), &[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10]);
+        assert!(application.priority().auto_selection_allowed());
+
+        let (df_name, fcipt) = crate::select_aid(&card, application.aid()).unwrap().unwrap();
+        assert_eq!(df_name, application.aid());
+        assert!(fcipt.get_tag(crate::tlv::TagID::ProcessingOptionsDataObjectList).is_some());
+    }
+
+    #[test]
+    fn contactless_uses_ppse() {
+        let card = MockCard::new("3B 88 80 01 00 00 00 00 00 00 00 00 09")
+            .expect("00A404000E325041592E5359532E4444463031 00",
+                    "6F2F840E325041592E5359532E4444463031A51DBF0C1A61184F07A0000000031010500A56495341204445424954870101 9000");
+        let interface = crate::card::detect_interface(&card);
+        assert_eq!(interface, crate::card::Interface::Contactless);
+
+        let application = crate::choose_application(&card, interface, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        assert_eq!(application.name(), "VISA DEBIT");
+        assert_eq!(crate::selection::get_pse_sfi(&card).unwrap(), None);
+
+        let mut terminal = crate::terminal::TerminalData::new(interface);
+        terminal.amount_authorised = 15000;
+        assert!(terminal.contactless_cvm_required());
+        assert_eq!(terminal.value(crate::tlv::TagID::TerminalTransactionQualifiers).unwrap(), vec![0x36, 0x40, 0x40, 0x00]);
+        terminal.amount_authorised = 150000;
+        assert!(!terminal.within_interface_limits());
+    }
+
+    #[test]
+    fn entry_point_selects_combination() {
+        // Visa with no Kernel Identifier, Mastercard naming kernel 2 at a higher priority
+        let card = MockCard::new("3B 88 80 01 00 00 00 00 00 00 00 00 09")
+            .expect("00A404000E325041592E5359532E4444463031 00",
+                    "6F4D840E325041592E5359532E4444463031A53BBF0C3861184F07A0000000031010500A564953412044454249548701 02\
+                     611C4F07A0000000041010500A4D4153544552434152448701019F2A0102 9000");
+        let mut terminal_applications = crate::selection::default_terminal_applications();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contactless);
+        terminal.amount_authorised = 2000;
+
+        let combinations = crate::entry_point::build_combinations(&card, &terminal, &terminal_applications).unwrap();
+        let chosen: Vec<_> = combinations.iter().map(|c| (c.application.name(), c.kernel_id)).collect();
+        assert_eq!(chosen, vec![("MASTERCARD", crate::entry_point::KERNEL_MASTERCARD), ("VISA DEBIT", crate::entry_point::KERNEL_VISA)]);
+        // Over the floor limit of 0 but under the CVM limit
+        assert!(combinations[0].indicators.floor_limit_exceeded);
+        assert!(!combinations[0].indicators.cvm_required_limit_exceeded);
+        assert_eq!(combinations[0].indicators.ttq, [0x36, 0x80, 0x40, 0x00]);
+
+        // Mastercard's limit rules it out, and Visa's CVM limit is lower
+        let visa = terminal_applications.iter().position(|a| a.matches(&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10])).unwrap();
+        terminal_applications[0].reader_limits.transaction_limit = Some(2000);
+        terminal_applications[visa].reader_limits.cvm_required_limit = Some(1000);
+        terminal_applications[visa].reader_limits.floor_limit = Some(5000);
+        let combination = crate::entry_point::select(&card, &terminal, &terminal_applications).unwrap().unwrap();
+        assert_eq!(combination.application.name(), "VISA DEBIT");
+        assert_eq!(combination.indicators.ttq, [0x36, 0x40, 0x40, 0x00]);
+        let mut activated = terminal.next_transaction();
+        crate::entry_point::activate(&mut activated, &combination);
+        assert_eq!(activated.value(crate::tlv::TagID::TerminalTransactionQualifiers).unwrap(), vec![0x36, 0x40, 0x40, 0x00]);
+
+        // Nothing is allowed for a zero amount without saying so
+        terminal.amount_authorised = 0;
+        assert!(crate::entry_point::select(&card, &terminal, &terminal_applications).unwrap().is_none());
+        terminal_applications[visa].reader_limits.zero_amount_allowed = true;
+        let combination = crate::entry_point::select(&card, &terminal, &terminal_applications).unwrap().unwrap();
+        assert!(combination.indicators.zero_amount);
+        assert_eq!(combination.indicators.ttq[1] & 0x80, 0x80);
+    }
+
+    #[test]
+    fn routes_to_requested_kernel() {
+        // Visa names a domestic kernel this reader doesn't have, so it's passed
+        // over for Mastercard's kernel 2
+        let mut card = mastercard_contactless_card()
+            .expect_first("00A404000E325041592E5359532E4444463031 00",
+                          "6F53840E325041592E5359532E4444463031A541BF0C3E611E4F07A0000000031010500A5649534120444542495487010\
+                           19F2A03810001611C4F07A0000000041010500A4D4153544552434152448701029F2A0102 9000");
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contactless);
+        terminal.amount_authorised = 2000;
+        let outcome = crate::run(&mut card, &terminal, &test_ca_keys(), None, None, false).unwrap();
+        // No host to send the ARQC to
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::DeclinedOffline);
+        assert_eq!(outcome.aid, Some(vec![0xa0, 0x00, 0x00, 0x00, 0x04, 0x10, 0x10]));
+        let visa_select = crate::hex::decode("00A4040007A000000003101000").unwrap();
+        assert!(card.transcript().iter().all(|(c, _)| *c != visa_select));
+        assert_eq!(card.transcript().iter().filter(|(c, _)| c[1] == 0xae).count(), 1);
+    }
+
+    fn qvsdc_transaction<'a>(card: &'a MockCard, ca_keys: &'a crate::capk::CapkStore, amount: u64) -> crate::transaction::Transaction<'a, MockCard> {
+        let terminal_applications = crate::selection::default_terminal_applications();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contactless);
+        terminal.amount_authorised = amount;
+        terminal.unpredictable_number = [0x01, 0x02, 0x03, 0x04];
+        let combination = crate::entry_point::select(card, &terminal, &terminal_applications).unwrap().unwrap();
+        crate::entry_point::activate(&mut terminal, &combination);
+
+        let mut transaction = crate::transaction::Transaction::new(card, terminal, ca_keys, combination.application);
+        transaction.kernel_id = Some(combination.kernel_id);
+        transaction
+    }
+
+    #[test]
+    fn approves_qvsdc_offline() {
+        let card = qvsdc_card();
+        let ca_keys = test_ca_keys();
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 2000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOffline);
+        assert_eq!(transaction.terminal.tvr, [0, 0, 0, 0, 0]);
+        assert_eq!(transaction.terminal.tsi[0] & 0x80, 0x80);
+        assert_eq!(transaction.terminal.cvm_results, [0x1f, 0x00, 0x02]);
+        assert_eq!(transaction.first_ac().unwrap().response.atc, 1);
+        // No GENERATE AC, everything comes back from GET PROCESSING OPTIONS
+        assert!(card.transcript().iter().all(|(c, _)| c[1] != 0xae));
+
+        // A different unpredictable number breaks the fDDA signature, so with
+        // no host to go online to it's declined
+        let mut card = qvsdc_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contactless);
+        terminal.amount_authorised = 2000;
+        let outcome = crate::run(&mut card, &terminal, &test_ca_keys(), None, None, false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::DeclinedOffline);
+        assert_eq!(outcome.tvr[0] & 0x08, 0x08);
+    }
+
+    #[test]
+    fn replays_transcripts() {
+        use crate::transport::CardTransport;
+
+        // The fDDA signature is over this unpredictable number
+        let mut card = qvsdc_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contactless);
+        terminal.amount_authorised = 2000;
+        terminal.unpredictable_number_override = Some([0x01, 0x02, 0x03, 0x04]);
+        let mut recording = crate::transport::Recording::new(&mut card);
+        let outcome = crate::run(&mut recording, &terminal, &test_ca_keys(), None, None, false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::ApprovedOffline);
+        let atr = recording.atr().ok().map(|a| a.raw().to_vec());
+        let transcript = crate::replay::Transcript::new(&terminal, &outcome, atr, recording.into_exchanges());
+        assert_eq!(transcript.exchanges.len(), outcome.apdu_count);
+
+        let transcript: crate::replay::Transcript = match serde_json::from_slice(&serde_json::to_vec(&transcript).unwrap()).unwrap() {
+            crate::replay::Recording::Transcript(t) => t,
+            r => panic!("Not a transcript: {:?}", r)
+        };
+        let mut replay = crate::replay::Replay::new(&transcript);
+        let replayed = crate::run(&mut replay, &transcript.terminal(), &test_ca_keys(), None, None, false).unwrap();
+        assert_eq!(replayed.outcome, crate::transaction::Outcome::ApprovedOffline);
+        assert_eq!(replayed.tvr, outcome.tvr);
+        assert_eq!(replayed.transaction_time, outcome.transaction_time);
+        assert_eq!(replay.divergence(), None);
+        assert_eq!(replay.remaining(), 0);
+
+        // Without the CA key the card's signature can't be checked
+        let mut replay = crate::replay::Replay::new(&transcript);
+        let replayed = crate::run(&mut replay, &transcript.terminal(), &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(replayed.outcome, crate::transaction::Outcome::DeclinedOffline);
+
+        // A different amount goes into the PDOL, so the card wasn't asked that
+        let mut transcript = transcript;
+        transcript.amount_authorised = 3000;
+        let mut replay = crate::replay::Replay::new(&transcript);
+        crate::run(&mut replay, &transcript.terminal(), &test_ca_keys(), None, None, false).unwrap();
+        assert!(replay.divergence().unwrap().starts_with("Command 3 was 80A80000"), "{:?}", replay.divergence());
+    }
+
+    #[test]
+    fn analyses_card_dumps() {
+        let card = visa_card();
+        let card_dump = crate::dump::read_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        let card_dump: crate::dump::CardDump = match serde_json::from_slice(&serde_json::to_vec(&card_dump).unwrap()).unwrap() {
+            crate::replay::Recording::Dump(d) => d,
+            r => panic!("Not a card dump: {:?}", r)
+        };
+
+        let mut card = crate::replay::DumpCard::new(&card_dump);
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 1000;
+        // Processing gets as far as asking for a cryptogram
+        assert!(crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, None, false).is_err());
+        assert_eq!(card.unanswered().unwrap()[..2], [0x80, 0xae]);
+    }
+
+    #[test]
+    fn approves_qvsdc_online() {
+        let card = qvsdc_online_card();
+        let ca_keys = test_ca_keys();
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 15000);
+        transaction.online_response = Some(crate::transaction::OnlineResponse {
+            authorisation_response_code: *b"00",
+            issuer_authentication_data: None,
+            issuer_scripts: vec![],
+        });
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOnline);
+        // Over the CVM limit, and the CTQ asks for a signature
+        assert_eq!(transaction.terminal.cvm_results, [0x1e, 0x00, 0x00]);
+        assert!(transaction.report(crate::transaction::Outcome::ApprovedOnline).signature_required);
+        assert_eq!(transaction.first_ac().unwrap().cryptogram, crate::transaction::Cryptogram::ARQC);
+    }
+
+    #[test]
+    fn approves_mastercard_contactless_online() {
+        let card = mastercard_contactless_card();
+        let ca_keys = test_ca_keys();
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 2000);
+        assert_eq!(transaction.kernel_id, Some(crate::entry_point::KERNEL_MASTERCARD));
+        transaction.online_response = Some(crate::transaction::OnlineResponse {
+            authorisation_response_code: *b"00",
+            issuer_authentication_data: None,
+            issuer_scripts: vec![],
+        });
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(transaction.terminal.cvm_results, [0x1f, 0x00, 0x02]);
+        assert_eq!(transaction.first_ac().unwrap().requested, crate::transaction::Cryptogram::ARQC);
+        // Only the one GENERATE AC over contactless
+        assert_eq!(card.transcript().iter().filter(|(c, _)| c[1] == 0xae).count(), 1);
+
+        // Over the CVM limit the cardholder verifies on their phone
+        let card = mastercard_contactless_card();
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 15000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::DeclinedOffline);
+        assert_eq!(transaction.terminal.cvm_results, [0x01, 0x00, 0x02]);
+    }
+
+    #[test]
+    fn mastercard_contactless_see_phone() {
+        let card = mastercard_contactless_card()
+            .expect_first("80AE*", "77249F2701009F360200019F260811223344556677889F100706010A03200000DF4B03000100 9000");
+        let ca_keys = test_ca_keys();
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 15000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::SeePhone);
+        let response = &transaction.first_ac().unwrap().response;
+        assert_eq!(response.pos_cardholder_interaction_information, Some(vec![0x00, 0x01, 0x00]));
+    }
+
+    #[test]
+    fn builds_mag_stripe_mode_track() {
+        // No EMV mode in the AIP; the CVC3 goes in three digits, and the
+        // unpredictable number and three digits of ATC in eight more
+        let card = mastercard_contactless_card()
+            .expect_first("80A8000002830000", "80061A0008010100 9000")
+            .expect_first("00B2010C00", "70249F6B135413330089600010D30122010000000000000F9F6502000E9F66020FF09F670103 9000")
+            .expect("802A8E800400009060 00", "770A9F610201239F36020007 9000");
+        let ca_keys = test_ca_keys();
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 2000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::DeclinedOffline);
+        let outcome = transaction.report(crate::transaction::Outcome::DeclinedOffline);
+        assert_eq!(transaction.tag_value(crate::tlv::TagID::Track2EquivalentData).map(|t| crate::hex::encode(&t)).as_deref(),
+                   Some("5413330089600010D30122010090600072915F"));
+        // The CVC3 is left out of the outcome
+        assert_eq!(outcome.masked_track2.as_deref(), Some("541333******0010D3012201"));
+        assert!(outcome.first_ac.is_none());
+
+        // Without the bitmaps there's nowhere to put the CVC3
+        let card = mastercard_contactless_card()
+            .expect_first("80A8000002830000", "80061A0008010100 9000");
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 2000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::Terminated);
+    }
+
+    #[test]
+    fn reads_msd_track() {
+        let msd_card = || qvsdc_card()
+            .expect_first("80A80000*", "8006008008010100 9000")
+            .expect_first("00B2010C00", "701457124761739001010010D3012201000012345678 9000");
+        let ca_keys = test_ca_keys();
+
+        // A reader that doesn't do MSD has nothing to fall back on
+        let card = msd_card();
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 15000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::Terminated);
+
+        let card = msd_card();
+        let terminal_applications = crate::selection::default_terminal_applications();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contactless);
+        terminal.amount_authorised = 15000;
+        terminal.terminal_transaction_qualifiers[0] |= 0x80;
+        let combination = crate::entry_point::select(&card, &terminal, &terminal_applications).unwrap().unwrap();
+        crate::entry_point::activate(&mut terminal, &combination);
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, combination.application);
+        transaction.kernel_id = Some(combination.kernel_id);
+        transaction.online_response = Some(crate::transaction::OnlineResponse {
+            authorisation_response_code: *b"00",
+            issuer_authentication_data: None,
+            issuer_scripts: vec![],
+        });
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOnline);
+        let outcome = transaction.report(crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(outcome.masked_track2.as_deref(), Some("476173******0010D3012201"));
+        // No CTQ, so a signature over the CVM limit
+        assert!(outcome.signature_required);
+    }
+
+    #[test]
+    fn processes_ctq() {
+        let ca_keys = test_ca_keys();
+        // Verified on the phone, with the signed copy of the CTQ agreeing
+        let card = qvsdc_card()
+            .expect_first("80A80000*", "77819A820220009404080102019F360200019F260811223344556677889F1007060112030000009F6C0200809F2701409F690701AABBCCDD00809F4B6010180CAF7277E37C1D141C3103D7EED66D1AF70FE754F305138E8FB35969FFFF0034887A1F49B06AB938FD6594268C9B1CAF72158F4B3456758A4483EAE9C93239E4C5A5A902FBE09D1189046B27608A25D34BC85E38FEC9134FE3B9800D4739 9000");
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 15000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOffline);
+        assert_eq!(transaction.terminal.cvm_results, [0x01, 0x00, 0x02]);
+        let outcome = transaction.report(crate::transaction::Outcome::ApprovedOffline);
+        assert_eq!(outcome.contactless_cvm, Some(crate::kernel3::Cvm::ConsumerDevice));
+        assert!(!outcome.signature_required);
+
+        // The CTQ claims CDCVM but the signed copy doesn't
+        let card = qvsdc_card()
+            .expect_first("80A80000*", "77819A820220009404080102019F360200019F260811223344556677889F1007060112030000009F6C0200809F2701409F690701AABBCCDD00009F4B600A3924B4CBC6ABDDA7F9FB6AFD263FD685CCCFFB4912DE69888E7D5A8E2E1D325E3DEB0BE84C09383ED9F31F595C9698559F275D328B5649BB9A43D2A4A40B7D99A69468CA96C7F3FAB425C37DD5745FC87E47917E7071A0389E30A106CB556F 9000");
+        let mut transaction = qvsdc_transaction(&card, &ca_keys, 15000);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::DeclinedOffline);
+
+        // fDDA fails against a fresh unpredictable number, and the card would
+        // rather be tried over contact
+        let mut card = qvsdc_card()
+            .expect_first("80A80000*", "77819A820220009404080102019F360200019F260811223344556677889F1007060112030000009F6C0210009F2701409F690701AABBCCDD40009F4B609F62910C3F6569EAC7D61DCB2A2A34A7E04AF73DD3DAF2E6703CDC0552FE7710EF6DB5BCD76B088F35404A9607FFA96F2BF767D270F0BAE5E3614C0443BB5C304D28D4896BF49A5F397D529FF2C33697568F3C6C96677F49B4C620B867458E75 9000");
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contactless);
+        terminal.amount_authorised = 2000;
+        let outcome = crate::run(&mut card, &terminal, &ca_keys, None, None, false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::TryAnotherInterface);
+    }
+
+    #[test]
+    fn builds_candidates_from_list_of_aids() {
+        // No PSE; a blocked Mastercard and two Visa applications found by partial selection
+        let card = MockCard::new("3B 68 00 00 80 66 B0 07 01 01 77 07")
+            .expect("00A4040007A0000000041010 00",
+                    "6F178407A0000000041010A50C500A4D415354455243415244 6283")
+            .expect("00A4040207A0000000041010 00", "6A82")
+            .expect("00A4040007A0000000031010 00",
+                    "6F1A8407A0000000031010A50F500A564953412044454249548701 02 9000")
+            .expect("00A4040207A0000000031010 00",
+                    "6F1C8408A000000003101001A510500B56495341204352454449548701 01 9000")
+            .expect("00A4040207A0000000031010 00", "6A82");
+        let terminal_applications = crate::selection::default_terminal_applications();
+        let candidates = crate::selection::build_candidate_list(&card, crate::card::Interface::Contact, &terminal_applications).unwrap();
+        let names: Vec<_> = candidates.iter().map(|c| c.name()).collect();
+        assert_eq!(names, vec!["VISA DEBIT", "VISA CREDIT"]);
+        assert_eq!(candidates[1].aid().len(), 8);
+
+        let mut sorted = candidates.clone();
+        crate::selection::sort_candidates(&mut sorted);
+        assert_eq!(sorted[0].name(), "VISA CREDIT");
+
+        let indicator = |b| crate::data::ApplicationPriorityIndicator::try_from(&crate::tlv::Tag::new(crate::tlv::TagID::ApplicationPriorityIndicator, crate::tlv::TagContents::Byte(b))).unwrap();
+        assert_eq!(indicator(0x81).priority(), Some(1));
+        assert_eq!(indicator(0x00).priority(), None);
+        assert!(indicator(0x01) > indicator(0x02));
+        assert!(indicator(0x0f) > indicator(0x00));
+        assert!(indicator(0x02) > indicator(0x82));
+        assert_eq!(indicator(0x03).max(indicator(0x81)), indicator(0x81));
+
+        let mut exact = vec![crate::selection::TerminalApplication::new(&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10])];
+        exact[0].partial_selection = false;
+        let candidates = crate::selection::build_candidate_list(&card, crate::card::Interface::Contact, &exact).unwrap();
+        assert_eq!(candidates.len(), 1);
+    }
+
+    #[test]
+    fn parses_transaction_details() {
+        assert_eq!(crate::util::parse_amount("12.34", 2), Some(1234));
+        assert_eq!(crate::util::parse_amount("12.3", 2), Some(1230));
+        assert_eq!(crate::util::parse_amount("12", 2), Some(1200));
+        assert_eq!(crate::util::parse_amount(".5", 2), Some(50));
+        assert_eq!(crate::util::parse_amount("12.345", 2), None);
+        assert_eq!(crate::util::parse_amount("-1", 2), None);
+        assert_eq!(crate::util::parse_amount("10000000000.00", 2), None);
+        assert_eq!(crate::terminal::transaction_type_from_name("refund"), Some(crate::terminal::TRANSACTION_TYPE_REFUND));
+        assert_eq!(crate::terminal::transaction_type_from_name("transfer"), None);
+    }
+
+    #[test]
+    fn batches_card_outcomes() {
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 2500;
+        let mut card = visa_card();
+        let outcome = crate::run(&mut card, &terminal, &ca_keys, None, None, false).unwrap();
+        let record = crate::batch::BatchRecord::new(&terminal, &outcome).unwrap();
+        assert_eq!(record.masked_pan.as_deref(), Some("476173******0010"));
+        assert_eq!(record.outcome, outcome.outcome);
+        assert_eq!(record.application_cryptogram.is_some(), outcome.first_ac.is_some());
+
+        terminal.transaction_type = crate::terminal::TRANSACTION_TYPE_REFUND;
+        terminal.amount_authorised = 1000;
+        let mut card = visa_card();
+        let outcome = crate::run(&mut card, &terminal, &ca_keys, None, None, false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Refunded);
+        assert_eq!(crate::batch::BatchRecord::new(&terminal, &outcome).unwrap().outcome, crate::transaction::Outcome::Refunded);
+    }
+
+    #[test]
+    fn non_interactive_skips_confirmation() {
+        // VISA CREDIT has the higher priority but asks for confirmation
+        let card = MockCard::new("3B 68 00 00 80 66 B0 07 01 01 77 07")
+            .expect("00A404000E315041592E5359532E4444463031 00",
+                    "6F1A840E315041592E5359532E4444463031A5088801015F2D02656E 9000")
+            .expect("00B2010C00",
+                    "703661184F07A0000000031010500A56495341204445424954870102611A4F08A000000003101001500B5649534120435245444954870181 9000")
+            .expect("00B2??0C00", "6A83");
+        let candidates = crate::selection::build_candidate_list(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications()).unwrap();
+        assert_eq!(candidates.len(), 2);
+
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        assert_eq!(application.name(), "VISA DEBIT");
+    }
+
+    fn sda_application() -> crate::data::Application {
+        let entry = crate::tlv::TagList::try_from(&[0x61, 0x15, 0x4f, 0x07, 0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10,
+            0x50, 0x07, 0x56, 0x49, 0x53, 0x41, 0x20, 0x53, 0x44, 0x87, 0x01, 0x01][..]).unwrap();
+        crate::data::Application::try_from(entry.get_tag(crate::tlv::TagID::ApplicationTemplate).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn performs_sda() {
+        let card = sda_card();
+        let terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        let ca_keys = test_ca_keys();
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, sda_application());
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOffline);
+        assert_eq!(transaction.terminal.tvr, [0x02, 0, 0, 0, 0]);
+        assert_eq!(transaction.terminal.tsi[0] & 0x80, 0x80);
+    }
+
+    #[test]
+    fn sda_fails_without_ca_key() {
+        let card = sda_card();
+        let terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, sda_application());
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::DeclinedOffline);
+        assert_eq!(transaction.terminal.tvr[0], 0x42);
+
+        // A corrupted signature fails the hash check rather than counting as missing
+        let card = sda_card();
+        let mut key = test_ca_key();
+        key.modulus[10] ^= 0x01;
+        let mut ca_keys = crate::capk::CapkStore::new();
+        ca_keys.insert(key).unwrap();
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, sda_application());
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[0], 0x42);
+
+        // As does a key past its expiry date
+        let card = sda_card();
+        let mut key = test_ca_key();
+        key.expiry = chrono::NaiveDate::from_ymd_opt(2020, 1, 1);
+        let mut ca_keys = crate::capk::CapkStore::new();
+        ca_keys.insert(key).unwrap();
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, sda_application());
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[0], 0x42);
+    }
+
+    #[test]
+    fn recovers_issuer_public_key() {
+        let card = sda_card();
+        let afl = crate::data::parse_afl(&[0x08, 0x01, 0x02, 0x01]).unwrap();
+        let records = crate::card::card_read_application_records(&card, &afl).unwrap();
+        let certificate = records.get_bytes(crate::tlv::TagID::IssuerPublicKeyCertificate).unwrap();
+        let remainder = records.get_bytes(crate::tlv::TagID::IssuerPublicKeyRemainder).unwrap_or_default();
+        let exponent = records.get_bytes(crate::tlv::TagID::IssuerPublicKeyExponent).unwrap();
+        let pan = records.get_bytes(crate::tlv::TagID::ApplicationPrimaryAccountNumber).unwrap();
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+
+        let key = crate::auth::recover_issuer_public_key(&test_ca_key(), &certificate, &remainder, &exponent, &pan, today).unwrap();
+        assert_eq!(key.modulus.len(), 112);
+        assert_eq!(key.exponent, vec![0x03]);
+        assert!(crate::hex::encode(&pan).starts_with(crate::hex::encode(&key.issuer_identifier).trim_end_matches('F')));
+
+        let other_pan = [0x51, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert!(crate::auth::recover_issuer_public_key(&test_ca_key(), &certificate, &remainder, &exponent, &other_pan, today).is_err());
+    }
+
+    #[test]
+    fn performs_cda() {
+        let card = cda_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.unpredictable_number = [0x01, 0x02, 0x03, 0x04];
+        let ca_keys = test_ca_keys();
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, sda_application());
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOffline);
+        assert_eq!(transaction.terminal.tvr, [0, 0, 0, 0, 0]);
+        let generate_ac = card.transcript().into_iter().find(|(c, _)| c[1] == 0xae).unwrap().0;
+        assert_eq!(generate_ac[2], 0x50);
+    }
+
+    #[test]
+    fn cda_failure_declines_tc() {
+        // A different unpredictable number to the one the card signed
+        let card = cda_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.unpredictable_number = [0x04, 0x03, 0x02, 0x01];
+        let ca_keys = test_ca_keys();
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, sda_application());
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::DeclinedOffline);
+        assert_eq!(transaction.terminal.tvr[0], 0x04);
+    }
+
+    #[test]
+    fn gets_processing_options() {
+        let mut card = visa_card();
+        let outcome = crate::run(&mut card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        // The IACs ask for ARQC when ODA isn't performed, and with no host to
+        // go online to the IAC - Default then declines it
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::DeclinedOffline);
+
+        let transcript = card.transcript();
+        let (gpo, response) = transcript.iter().find(|(c, _)| c[..2] == [0x80, 0xa8]).unwrap();
+        assert_eq!(&gpo[..4], &[0x80, 0xa8, 0x00, 0x00]);
+        // 83 template holding TTQ, amount, UN and currency from the PDOL
+        assert_eq!(&gpo[5..7], &[0x83, 16]);
+        assert_eq!(&gpo[7..11], &[0x36, 0x00, 0x40, 0x00]);
+        assert_eq!(&gpo[21..23], &[0x08, 0x26]);
+        assert_eq!(&response[response.len() - 2..], &[0x90, 0x00]);
+        let generate_ac: Vec<_> = transcript.iter().filter(|(c, _)| c[..2] == [0x80, 0xae]).map(|(c, _)| c.clone()).collect();
+        assert_eq!(generate_ac.len(), 2);
+        assert_eq!(generate_ac[0][2], 0x80);
+        // CDOL1 is 43 bytes, with the TVR after amounts and country code
+        assert_eq!(generate_ac[0][4], 43);
+        assert_eq!(generate_ac[0][5 + 14], 0x80);
+        // CVM Results from the signature rule
+        assert_eq!(&generate_ac[0][5 + 40..5 + 43], &[0x1e, 0x03, 0x00]);
+        assert_eq!(generate_ac[1][2], 0x00);
+        assert_eq!(card.resets(), 0);
+    }
+
+    #[test]
+    fn falls_back_when_chip_unreadable() {
+        let terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        let ca_keys = crate::capk::CapkStore::new();
+
+        // A chip that answers nothing it's sent
+        let mut card = MockCard::new("3B 02 14 50");
+        let outcome = crate::run(&mut card, &terminal, &ca_keys, None, None, false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Fallback);
+        assert_eq!(card.resets(), 2);
+
+        // A working chip with nothing the terminal accepts is no reason to fall back
+        let mut card = MockCard::new("3B 02 14 50")
+            .expect("00A40400??315041592E5359532E4444463031 00", "6A82");
+        let outcome = crate::run(&mut card, &terminal, &ca_keys, None, None, false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Terminated);
+        assert_eq!(card.resets(), 0);
+    }
+
+    #[test]
+    fn checks_application_usage_control() {
+        // The card's AUC allows everything but cashback
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, application.clone());
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[1] & 0x10, 0);
+
+        let card = visa_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.transaction_type = 0x09;
+        terminal.amount_other = 1000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[1] & 0x10, 0x10);
+    }
+
+    #[test]
+    fn checks_application_dates() {
+        // The card is effective from 2020-01-01 and expires 2030-12-31
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.date_override = chrono::NaiveDate::from_ymd_opt(2031, 1, 1);
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application.clone());
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[1] & 0x60, 0x40);
+
+        let card = visa_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.date_override = chrono::NaiveDate::from_ymd_opt(2019, 6, 1);
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[1] & 0x60, 0x20);
+    }
+
+    #[test]
+    fn verifies_offline_pin() {
+        let ca_keys = crate::capk::CapkStore::new();
+        let run = |pin_entry| {
+            let card = pin_card();
+            let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+            let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, application);
+            transaction.pin_entry = pin_entry;
+            transaction.process().unwrap();
+            (transaction.terminal.cvm_results, transaction.terminal.tvr[2], transaction.terminal.tsi[0] & 0x40)
+        };
+
+        assert_eq!(run(crate::cvm::PinEntry::Fixed("1234".to_string())), ([0x41, 0x03, 0x02], 0x00, 0x40));
+        // A wrong PIN fails the rule, cascading on to signature
+        assert_eq!(run(crate::cvm::PinEntry::Fixed("4321".to_string())), ([0x1e, 0x03, 0x00], 0x00, 0x40));
+        assert_eq!(run(crate::cvm::PinEntry::Bypass), ([0x1e, 0x03, 0x00], 0x08, 0x40));
+    }
+
+    #[test]
+    fn sends_plaintext_pin_with_icc_key() {
+        // The ICC key of a CDA card, as recovered before cardholder verification
+        let card = cda_card();
+        let mut records = crate::data::ApplicationRecords::default();
+        for record in 1..=2 {
+            let data = crate::card::card_read_record_raw(&card, 1, record).unwrap();
+            records.add_record(1, &data, record == 1).unwrap();
+        }
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let icc_key = crate::auth::recover_icc_key(&test_ca_keys(), &[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10], &records, [0x09, 0x00], today).unwrap();
+
+        // A plaintext PIN rule still sends the PIN in the clear, without a GET CHALLENGE
+        let card = MockCard::new("3B 68 00 00 80 66 B0 07 01 01 77 07")
+            .expect("80CA9F1700", "9F170103 9000")
+            .expect("0020008008241234FFFFFFFFFF00", "9000");
+        let cvm_list = parse_hex("000000000000000041031E03").into_iter().flatten().collect::<Vec<_>>();
+        let pin_entry = crate::cvm::PinEntry::Fixed("1234".to_string());
+        let context = crate::cvm::CvmContext {
+            cvm_list: Some(&cvm_list),
+            application_currency: None,
+            pin_entry: &pin_entry,
+            pin_key: Some(&icc_key),
+            online_pin_key: None,
+            pan: None,
+        };
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        crate::cvm::perform(&card, &mut terminal, &context).unwrap();
+        assert_eq!(terminal.cvm_results, [0x41, 0x03, 0x02]);
+        assert!(card.transcript().iter().all(|(c, _)| c[..2] != [0x00, 0x84]));
+    }
+
+    #[test]
+    #[cfg(feature = "pkcs11")]
+    fn parses_pkcs11_keys() {
+        let expected = crate::hsm::Pkcs11Key {
+            module: "/usr/lib/softhsm/libsofthsm2.so".to_string(),
+            slot: 1,
+            label: "pin-key".to_string(),
+        };
+        match "pkcs11:/usr/lib/softhsm/libsofthsm2.so:1:pin-key".parse().unwrap() {
+            crate::pinblock::PinKey::Pkcs11(k) => assert_eq!(k, expected),
+            k => panic!("Wrong PIN key: {:?}", k)
+        }
+        assert!("pkcs11:/usr/lib/softhsm/libsofthsm2.so:first:pin-key".parse::<crate::pinblock::PinKey>().is_err());
+        assert!("pkcs11:pin-key".parse::<crate::pinblock::PinKey>().is_err());
+    }
+
+    #[test]
+    fn captures_online_pin() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, application);
+        transaction.pin_entry = crate::cvm::PinEntry::Fixed("1234".to_string());
+        transaction.online_pin_key = Some("tdes:0123456789ABCDEFFEDCBA9876543210".parse().unwrap());
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.cvm_results, [0x42, 0x03, 0x00]);
+        assert_eq!(transaction.terminal.tvr[2], 0x04);
+        assert_eq!(transaction.online_pin_block().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn unattended_terminal_skips_signature() {
+        let card = visa_card_with_cvm_rule("1E03");
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.terminal_type = 0x25;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application.clone());
+        transaction.process().unwrap();
+        // Falls through both signature rules to No CVM Required
+        assert_eq!(transaction.terminal.cvm_results, [0x1f, 0x00, 0x02]);
+
+        let card = visa_card_with_cvm_rule("1E03");
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, application);
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.cvm_results, [0x1e, 0x03, 0x00]);
+        assert!(!crate::terminal::valid_terminal_type(0x27));
+    }
+
+    #[test]
+    fn processes_refunds_and_cash() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.transaction_type = crate::terminal::TRANSACTION_TYPE_REFUND;
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application.clone());
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::Refunded);
+        assert_eq!(transaction.terminal.tsi[0] & 0x08, 0);
+        let generate_ac = card.transcript().into_iter().find(|(c, _)| c[1] == 0xae).unwrap().0;
+        assert_eq!(generate_ac[2], 0x00);
+        // Transaction Type in the CDOL1 data, after the amounts, country, TVR, currency and date
+        assert_eq!(generate_ac[5 + 6 + 6 + 2 + 5 + 2 + 3], 0x20);
+
+        // Cash goes online whatever the floor limit
+        let card = visa_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        let mut config = crate::selection::TerminalApplication::new(&[0xa0, 0x00, 0x00, 0x00, 0x03]);
+        config.floor_limit = 10000;
+        terminal.configure_for(&config);
+        terminal.transaction_type = crate::terminal::TRANSACTION_TYPE_CASH;
+        terminal.amount_authorised = 1000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[3] & 0x80, 0x80);
+    }
+
+    #[test]
+    fn checks_floor_limit() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        let mut config = crate::selection::TerminalApplication::new(&[0xa0, 0x00, 0x00, 0x00, 0x03]);
+        config.floor_limit = 1000;
+        terminal.configure_for(&config);
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[3] & 0x80, 0x80);
+        // The card's IAC - Online takes a transaction over the floor limit online
+        let generate_ac = card.transcript().into_iter().find(|(c, _)| c[1] == 0xae).unwrap().0;
+        assert_eq!(generate_ac[2], 0x80);
+    }
+
+    #[test]
+    fn selects_randomly_for_online() {
+        let always = crate::terminal::RandomSelection {
+            target_percentage: 99,
+            max_target_percentage: 99,
+            threshold: 1000,
+        };
+        assert!(always.select(500, 5000));
+        assert!(always.select(4000, 5000));
+        // Nothing at or above the floor limit is picked, as it goes online anyway
+        assert!(!always.select(5000, 5000));
+        assert!(!crate::terminal::RandomSelection::default().select(500, 5000));
+
+        // Below the threshold only the target percentage applies
+        let biased = crate::terminal::RandomSelection {
+            target_percentage: 0,
+            max_target_percentage: 99,
+            threshold: 1000,
+        };
+        assert!(!biased.select(500, 5000));
+
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.floor_limit = 5000;
+        terminal.random_selection = always;
+        terminal.amount_authorised = 100;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[3] & 0x90, 0x10);
+    }
+
+    #[test]
+    fn checks_velocity() {
+        let card = velocity_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, application.clone());
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[3] & 0x60, 0x40);
+        assert_eq!(transaction.terminal.tvr[1] & 0x08, 0x00);
+
+        // Without counters both limits count as exceeded
+        let card = velocity_card().expect_first("80CA*", "6A88");
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, application);
+        transaction.process().unwrap();
+        assert_eq!(transaction.terminal.tvr[3] & 0x60, 0x60);
+    }
+
+    #[test]
+    fn analyses_action_codes() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        terminal.action_codes.denial = [0x00, 0x00, 0x00, 0x80, 0x00];
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::DeclinedOffline);
+        assert_eq!(transaction.decision_trace(), &[
+            "Transaction exceeds floor limit matches TAC-Denial".to_string(),
+            "Requesting AAC".to_string(),
+        ]);
+        let generate_ac = card.transcript().into_iter().find(|(c, _)| c[1] == 0xae).unwrap().0;
+        assert_eq!(generate_ac[2], 0x00);
+    }
+
+    #[test]
+    fn parses_first_generate_ac() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.process().unwrap();
+
+        let first = transaction.first_ac().unwrap();
+        assert_eq!(first.requested, crate::transaction::Cryptogram::ARQC);
+        assert_eq!(first.cryptogram, crate::transaction::Cryptogram::ARQC);
+        assert_eq!(first.response.atc, 1);
+        assert_eq!(first.response.application_cryptogram, [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]);
+        assert_eq!(first.response.issuer_application_data, Some(vec![0x06, 0x01, 0x0a, 0x03, 0xa0, 0x00, 0x00]));
+
+        // Amount, TVR and unpredictable number all go in from the terminal's data
+        let generate_ac = card.transcript().into_iter().find(|(c, _)| c[1] == 0xae).unwrap().0;
+        assert_eq!(&generate_ac[5..11], &[0x00, 0x00, 0x00, 0x00, 0x50, 0x00]);
+        assert_eq!(&generate_ac[19..24], &transaction.terminal.tvr);
+    }
+
+    #[test]
+    fn completes_with_online_response() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application.clone());
+        transaction.online_response = Some(crate::transaction::OnlineResponse {
+            authorisation_response_code: *b"00",
+            issuer_authentication_data: Some(vec![0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x30, 0x30]),
+            issuer_scripts: vec![],
+        });
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOnline);
+
+        let completion = transaction.completion().unwrap();
+        assert!(completion.online);
+        assert_eq!(&completion.authorisation_response_code, b"00");
+        assert_eq!(completion.cryptogram, crate::transaction::Cryptogram::TC);
+        assert_eq!(completion.response.atc, 2);
+
+        // CDOL2 starts with the Issuer Authentication Data and response code
+        let second = card.transcript().into_iter().filter(|(c, _)| c[1] == 0xae).nth(1).unwrap().0;
+        assert_eq!(second[2], 0x40);
+        assert_eq!(&second[5..15], &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x30, 0x30]);
+        assert_eq!(&second[15..17], b"00");
+
+        let card = visa_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.online_response = Some(crate::transaction::OnlineResponse {
+            authorisation_response_code: *b"05",
+            issuer_authentication_data: None,
+            issuer_scripts: vec![],
+        });
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::DeclinedOnline);
+        assert_eq!(transaction.completion().unwrap().cryptogram, crate::transaction::Cryptogram::AAC);
+    }
+
+    #[test]
+    fn handles_voice_referral() {
+        let ca_keys = crate::capk::CapkStore::new();
+        let applications = crate::selection::default_terminal_applications();
+        let (sender, events) = std::sync::mpsc::channel();
+        let run = |card: &MockCard, online_response: Option<crate::transaction::OnlineResponse>, referral: crate::transaction::Referral| {
+            let application = crate::choose_application(card, crate::card::Interface::Contact, &applications, false, Default::default()).unwrap().unwrap();
+            let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+            terminal.amount_authorised = 5000;
+            terminal.events = Some(sender.clone());
+            let mut transaction = crate::transaction::Transaction::new(card, terminal, &ca_keys, application);
+            transaction.online_response = online_response;
+            transaction.referral = referral;
+            let outcome = transaction.process().unwrap();
+            transaction.report(outcome)
+        };
+        let referral = || Some(crate::transaction::OnlineResponse {
+            authorisation_response_code: *b"01",
+            issuer_authentication_data: None,
+            issuer_scripts: vec![],
+        });
+
+        // The issuer asks to be called, and approves over the phone
+        let card = visa_card();
+        let outcome = run(&card, referral(), crate::transaction::Referral::Approve("123456".to_string()));
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(outcome.authorisation_code.as_deref(), Some("123456"));
+        let second = card.transcript().into_iter().filter(|(c, _)| c[1] == 0xae).nth(1).unwrap().0;
+        assert_eq!(second[2], 0x40);
+
+        let card = visa_card();
+        let outcome = run(&card, referral(), crate::transaction::Referral::Decline);
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::DeclinedOnline);
+        assert_eq!(outcome.authorisation_code, None);
+
+        // The card gives an AAR and the terminal can't go online
+        let card = visa_card().expect_first("80AE80*", "8012C00001112233445566778806010A03A00000 9000");
+        let outcome = run(&card, None, crate::transaction::Referral::Approve("A1B2".to_string()));
+        assert_eq!(outcome.authorisation_code.as_deref(), Some("A1B2"));
+        assert_eq!(outcome.authorisation_response_code.as_deref(), Some("Y3"));
+        assert!(outcome.outcome.approved());
+        assert!(outcome.decision_trace.iter().any(|d| d == "Card asks for a referral"));
+        let referrals = events.try_iter().filter(|e| matches!(e, crate::events::Event::ReferralRequired { .. })).count();
+        assert_eq!(referrals, 3);
+
+        // The terminal's handler is asked, given what it needs for the call
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &applications, false, Default::default()).unwrap().unwrap();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        terminal.referral = Some(crate::transaction::ReferralHandler::new(|request| {
+            assert_eq!(request.masked_pan.as_deref(), Some("476173******0010"));
+            assert_eq!((request.amount_authorised, request.currency_code), (5000, 826));
+            Some("654321".to_string())
+        }));
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.online_response = referral();
+        let outcome = transaction.process().unwrap();
+        assert_eq!(transaction.report(outcome).authorisation_code.as_deref(), Some("654321"));
+    }
+
+    #[test]
+    fn builds_authorisation_request_from_card() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        terminal.transaction_sequence_counter = 1234567;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.process().unwrap();
+
+        let request = crate::acquirer::AuthorisationRequest::new(&transaction);
+        assert_eq!(request.pan.as_deref(), Some("4761739001010010"));
+        assert_eq!(request.track2.as_deref(), Some("4761739001010010=30122011143804400000"));
+        assert_eq!(request.expiry.as_deref(), Some("3012"));
+        assert_eq!(request.pan_sequence_number, Some(1));
+        assert_eq!(request.pos_entry_mode, "051");
+        assert_eq!(request.pin_block, None);
+        let chip_data = crate::tlv::TagList::try_from(request.chip_data.as_deref().unwrap()).unwrap();
+        assert_eq!(Vec::<u8>::from(chip_data.get_tag(crate::tlv::TagID::ApplicationCryptogram).unwrap().contents()),
+                   vec![0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]);
+        assert_eq!(Vec::<u8>::from(chip_data.get_tag(crate::tlv::TagID::ApplicationInterchangeProfile).unwrap().contents()), vec![0x1c, 0x00]);
+        assert_eq!(Vec::<u8>::from(chip_data.get_tag(crate::tlv::TagID::DedicatedFileName).unwrap().contents()),
+                   vec![0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10]);
+    }
+
+    #[test]
+    fn sends_merchant_data() {
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.merchant = crate::terminal::MerchantData {
+            category_code: Some(5411),
+            identifier: Some("123456789".to_string()),
+            terminal_identification: Some("T1".to_string()),
+            name_and_location: Some("Corner Shop, Cardiff".to_string()),
+        };
+        let mut dol = crate::tlv::DOL::try_from(crate::hex::decode("9F15029F160F9F1C089F4E14").unwrap().as_slice()).unwrap();
+        dol.fill(&terminal);
+        assert_eq!(Vec::<u8>::from(dol), [&[0x54, 0x11][..], b"123456789      ", b"T1      ", b"Corner Shop, Cardiff"].concat());
+
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        let outcome = transaction.process().unwrap();
+        let request = crate::online::authorisation_message(&crate::acquirer::AuthorisationRequest::new(&transaction), crate::online::MTI_AUTHORISATION_REQUEST);
+        assert_eq!(request.get_str(18), Some("5411"));
+        assert_eq!(request.get_str(41), Some("T1      "));
+        assert_eq!(request.get_str(42), Some("123456789      "));
+        assert_eq!(request.get_str(43).map(|n| n.trim_end()), Some("Corner Shop, Cardiff"));
+        assert_eq!(crate::online::IsoMessage::unpack(&request.pack().unwrap()).unwrap(), request);
+
+        let report = transaction.report(outcome).to_string();
+        assert!(report.starts_with("Corner Shop, Cardiff\nMID: 123456789 TID: T1\nOutcome: "), "{}", report);
+        let json = serde_json::to_value(transaction.report(outcome)).unwrap();
+        assert_eq!(json["merchant"]["category_code"], 5411);
+        assert!(serde_json::to_value(crate::transaction::TransactionOutcome::without_transaction(crate::transaction::Outcome::Error, None)).unwrap().get("merchant").is_none());
+    }
+
+    #[test]
+    fn enciphers_track_2_with_dukpt() {
+        let mut key: crate::dukpt::DukptKey = "bdk:0123456789ABCDEFFEDCBA9876543210:FFFF9876543210E00000".parse().unwrap();
+        key.ksn = crate::state::TerminalState::default().next_ksn(&key.ksn).unwrap();
+        let pin_key = crate::pinblock::PinKey::Dukpt(key);
+
+        // Track 2 goes enciphered in DE48 rather than in the clear
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut transaction = crate::transaction::Transaction::new(&card, crate::terminal::TerminalData::new(crate::card::Interface::Contact), &ca_keys, application);
+        transaction.online_pin_key = Some(pin_key);
+        transaction.process().unwrap();
+        let request = crate::online::authorisation_message(&crate::acquirer::AuthorisationRequest::new(&transaction), crate::online::MTI_AUTHORISATION_REQUEST);
+        assert_eq!(request.get(35), None);
+        assert_eq!(request.get(48).map(crate::hex::encode),
+                   Some("2DA68FDCFD61C04C57CFB20318DCDBB1C2E05D2B79A32F171C14FAD90FFD80557FAB0BEFC256F55B".to_string()));
+        assert_eq!(request.get(53).map(crate::hex::encode), Some("FFFF9876543210E00001".to_string()));
+        assert_eq!(crate::online::IsoMessage::unpack(&request.pack().unwrap()).unwrap(), request);
+    }
+
+    #[test]
+    #[cfg(feature = "online")]
+    fn goes_online_to_host() {
+        use std::io::{Read, Write};
+
+        // A host that accepts sign on and approves the one authorisation, handing
+        // back the PAN it was sent
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut exchange = |response_mti: &str| {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut data = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut data).unwrap();
+                let request = crate::online::IsoMessage::unpack(&data).unwrap();
+                let mut response = crate::online::IsoMessage::new(response_mti);
+                response.set(11, request.get(11).unwrap());
+                response.set(39, "00");
+                let response = response.pack().unwrap();
+                stream.write_all(&(response.len() as u16).to_be_bytes()).unwrap();
+                stream.write_all(&response).unwrap();
+                request
+            };
+            let sign_on = exchange("0810");
+            let authorisation = exchange("0110");
+            (sign_on, authorisation)
+        });
+
+        let mut connection = crate::host::HostConnection::connect(&crate::host::HostConfig::new(&address)).unwrap();
+        connection.sign_on(41).unwrap();
+        let mut card = visa_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        terminal.transaction_sequence_counter = 42;
+        let outcome = crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, Some(&mut connection as &mut dyn crate::acquirer::Acquirer), false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(outcome.authorisation_response_code.as_deref(), Some("00"));
+
+        let (sign_on, authorisation) = host.join().unwrap();
+        assert_eq!(sign_on.mti, "0800");
+        assert_eq!(sign_on.get_str(70), Some("001"));
+        assert_eq!(authorisation.mti, "0100");
+        assert_eq!(authorisation.get_str(2), Some("4761739001010010"));
+        assert_eq!(authorisation.get_str(11), Some("000042"));
+
+        // With the host gone the card is told the terminal couldn't go online
+        let mut card = visa_card();
+        let outcome = crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, Some(&mut connection as &mut dyn crate::acquirer::Acquirer), false).unwrap();
+        assert_eq!(outcome.authorisation_response_code.as_deref(), Some("Z3"));
+        assert!(outcome.decision_trace.iter().any(|d| d.starts_with("No response from the host")));
+    }
+
+    #[test]
+    fn reverses_declined_approval() {
+        // Takes the issuer's part, remembering what it's told
+        #[derive(Default)]
+        struct Recorder {
+            authorisations: Vec<crate::acquirer::AuthorisationRequest>,
+            reversals: Vec<crate::acquirer::AuthorisationRequest>,
+        }
+        impl crate::acquirer::Acquirer for Recorder {
+            fn authorise(&mut self, request: &crate::acquirer::AuthorisationRequest) -> std::io::Result<crate::transaction::OnlineResponse> {
+                self.authorisations.push(request.clone());
+                crate::acquirer::StandIn::default().authorise(request)
+            }
+            fn reverse(&mut self, request: &crate::acquirer::AuthorisationRequest) -> std::io::Result<()> {
+                self.reversals.push(request.clone());
+                Ok(())
+            }
+            fn advice(&mut self, _request: &crate::acquirer::AuthorisationRequest) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let mut recorder = Recorder::default();
+        let outcome = crate::run(&mut visa_card(), &terminal, &crate::capk::CapkStore::new(), None, Some(&mut recorder as &mut dyn crate::acquirer::Acquirer), false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(recorder.authorisations.len(), 1);
+        assert_eq!(recorder.authorisations[0].pan.as_deref(), Some("4761739001010010"));
+        assert!(recorder.reversals.is_empty());
+
+        // The card declines after the issuer approved
+        let mut card = visa_card().expect_first("80AE40*", "8012000002112233445566778806010A03600000 9000");
+        let mut recorder = Recorder::default();
+        let outcome = crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, Some(&mut recorder as &mut dyn crate::acquirer::Acquirer), false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::DeclinedOnline);
+        assert_eq!(recorder.reversals.len(), 1);
+        // Carrying the AAC the card finished with
+        let chip_data = crate::tlv::TagList::try_from(recorder.reversals[0].chip_data.as_deref().unwrap()).unwrap();
+        assert_eq!(Vec::<u8>::from(chip_data.get_tag(crate::tlv::TagID::CryptogramInformationData).unwrap().contents()), vec![0x00]);
+        assert!(outcome.decision_trace.iter().any(|d| d == "Reversal sent to the host"));
+    }
+
+    #[test]
+    fn stands_in_for_issuer() {
+        let stand_in = crate::acquirer::StandIn {
+            limit: Some(10000),
+            issuer_master_key: Some("0123456789ABCDEFFEDCBA9876543210".parse().unwrap()),
+        };
+        let run = |amount: u64, card: &mut MockCard| {
+            let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+            terminal.amount_authorised = amount;
+            let mut stand_in = stand_in.clone();
+            crate::run(card, &terminal, &crate::capk::CapkStore::new(), None, Some(&mut stand_in as &mut dyn crate::acquirer::Acquirer), false).unwrap()
+        };
+
+        // The ARPC over ARQC 1122334455667788 with ATC 1, response code 00
+        let mut card = visa_card().expect_first("008200000A0BECC60331B204A73030 00", "9000");
+        let outcome = run(5000, &mut card);
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(outcome.issuer_authentication, Some(true));
+
+        // Over the limit, declined with its own ARPC
+        let mut card = visa_card().expect_first("008200000A4CA16501940105363531 00", "9000");
+        let outcome = run(20000, &mut card);
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::DeclinedOnline);
+        assert_eq!(outcome.authorisation_response_code.as_deref(), Some("51"));
+        assert_eq!(outcome.issuer_authentication, Some(true));
+
+        // Without the key there's no ARPC to check
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let outcome = crate::run(&mut visa_card(), &terminal, &crate::capk::CapkStore::new(), None, Some(&mut crate::acquirer::StandIn::default() as &mut dyn crate::acquirer::Acquirer), false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(outcome.issuer_authentication, None);
+    }
+
+    #[test]
+    fn authenticates_issuer() {
+        let applications = crate::selection::default_terminal_applications();
+        let ca_keys = crate::capk::CapkStore::new();
+        let run = |iad: Option<Vec<u8>>, mandatory: bool| {
+            let card = visa_card();
+            let application = crate::choose_application(&card, crate::card::Interface::Contact, &applications, false, Default::default()).unwrap().unwrap();
+            let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+            terminal.amount_authorised = 5000;
+            terminal.issuer_authentication_mandatory = mandatory;
+            let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+            transaction.online_response = Some(crate::transaction::OnlineResponse {
+                authorisation_response_code: *b"00",
+                issuer_authentication_data: iad,
+                issuer_scripts: vec![],
+            });
+            transaction.process().unwrap();
+            let external_authenticate = card.transcript().iter().any(|(c, _)| c[1] == 0x82);
+            (transaction.completion().unwrap().issuer_authentication, external_authenticate, transaction.terminal.tvr[4], transaction.terminal.tsi[0])
+        };
+
+        let (passed, sent, tvr, tsi) = run(Some(parse_hex("11223344556677883030").into_iter().map(|b| b.unwrap()).collect()), false);
+        assert_eq!(passed, Some(true));
+        assert!(sent);
+        assert_eq!(tvr & 0x40, 0);
+        assert_eq!(tsi & 0x10, 0x10);
+
+        let (passed, _, tvr, tsi) = run(Some(parse_hex("99999999999999993030").into_iter().map(|b| b.unwrap()).collect()), false);
+        assert_eq!(passed, Some(false));
+        assert_eq!(tvr & 0x40, 0x40);
+        assert_eq!(tsi & 0x10, 0x10);
+
+        let (passed, sent, tvr, _) = run(None, false);
+        assert_eq!(passed, None);
+        assert!(!sent);
+        assert_eq!(tvr & 0x40, 0);
+
+        let (_, _, tvr, tsi) = run(None, true);
+        assert_eq!(tvr & 0x40, 0x40);
+        assert_eq!(tsi & 0x10, 0);
+    }
+
+    #[test]
+    fn runs_issuer_scripts() {
+        // A PIN unblock before the final GENERATE AC, then an application
+        // block that the card rejects and a second command that isn't sent
+        let response = crate::hex::decode("8A023030710E9F18040000000186058424000000720E8605841E00000086058424000000").unwrap();
+        let response = crate::tlv::TagList::try_from(response.as_slice()).unwrap();
+        let response = crate::transaction::OnlineResponse::try_from(&response).unwrap();
+        assert_eq!(response.issuer_scripts.len(), 2);
+        assert!(response.issuer_scripts[0].before_final_ac);
+        assert_eq!(response.issuer_scripts[0].identifier, Some([0x00, 0x00, 0x00, 0x01]));
+        assert_eq!(response.issuer_scripts[1].commands.len(), 2);
+
+        let card = visa_card()
+            .expect("8424000000", "9000")
+            .expect("841E000000", "6985");
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.online_response = Some(response);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOnline);
+
+        assert_eq!(transaction.issuer_script_results(), &[0x20, 0x00, 0x00, 0x00, 0x01, 0x11, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(transaction.terminal.tvr[4] & 0x30, 0x10);
+        assert_eq!(transaction.terminal.tsi[0] & 0x04, 0x04);
+
+        // The unblock goes before the second GENERATE AC, the block after it
+        let instructions: Vec<u8> = card.transcript().iter().map(|(c, _)| c[1]).filter(|i| matches!(i, 0x24 | 0x1e | 0xae)).collect();
+        assert_eq!(instructions, vec![0xae, 0x24, 0xae, 0x1e]);
+    }
+
+    #[test]
+    fn runs_scripts_from_authorisation_response() {
+        let mut message = crate::online::IsoMessage::new("0110");
+        message.set(39, "00");
+        message.set(55, crate::hex::decode("8A023035910A11223344556677883030720E8605841E00000086058424000000").unwrap());
+        let response = crate::online::online_response(&message).unwrap();
+
+        let card = visa_card()
+            .expect("841E000000", "9000");
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.online_response = Some(response);
+        assert_eq!(transaction.process().unwrap(), crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(transaction.completion().unwrap().issuer_authentication, Some(true));
+        let instructions: Vec<u8> = card.transcript().iter().map(|(c, _)| c[1]).filter(|i| matches!(i, 0x82 | 0x1e | 0xae)).collect();
+        assert_eq!(instructions, vec![0xae, 0x82, 0xae, 0x1e]);
+    }
+
+    #[test]
+    fn records_whole_transaction() {
+        let mut card = visa_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let outcome = crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(outcome.apdu_count, card.transcript().len());
+
+        let tags: Vec<String> = outcome.card_data.iter().map(|d| crate::hex::encode(&d.tag)).collect();
+        assert!(tags.contains(&"82".to_string()));
+        assert!(tags.contains(&"5F24".to_string()));
+        assert!(tags.contains(&"8C".to_string()));
+        assert!(!tags.contains(&"5A".to_string()));
+        assert!(!tags.contains(&"57".to_string()));
+
+        let steps: Vec<&str> = outcome.tvr_history.iter().map(|s| s.step.as_str()).collect();
+        assert_eq!(steps, vec!["Read application data", "Offline data authentication", "Processing restrictions",
+                               "Cardholder verification", "Terminal risk management", "First GENERATE AC", "Second GENERATE AC"]);
+        // ODA not performed shows up once ODA has been and gone
+        assert_eq!(outcome.tvr_history[0].tvr[0] & 0x80, 0);
+        assert_eq!(outcome.tvr_history[1].tvr[0] & 0x80, 0x80);
+        assert_eq!(outcome.tvr_history.last().unwrap().tvr, outcome.tvr);
+
+        let json: serde_json::Value = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(json["card_data"][0]["tag"], "82");
+        assert_eq!(json["tvr_history"][6]["step"], "Second GENERATE AC");
+        assert_eq!(json["apdu_count"], card.transcript().len());
+    }
+
+    #[test]
+    #[cfg(feature = "online")]
+    fn posts_outcome_to_webhook() {
+        use std::io::{BufRead, Read, Write};
+
+        // RFC 4231 test case 2
+        assert_eq!(crate::webhook::signature(b"Jefe", b"what do ya want for nothing?"),
+                   "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+
+        // Fails the first delivery, then takes the retry
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/outcomes", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for status in ["500 Internal Server Error", "204 No Content"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream);
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim().to_lowercase());
+                }
+                let length: usize = headers.iter().find_map(|h| h.strip_prefix("content-length: ")).unwrap().parse().unwrap();
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+                requests.push((headers, body));
+            }
+            requests
+        });
+
+        let outcome = crate::transaction::TransactionOutcome::without_transaction(crate::transaction::Outcome::Terminated, None);
+        let mut config = crate::webhook::WebhookConfig::new(&url);
+        config.secret = Some(b"secret".to_vec());
+        config.retry_delay = std::time::Duration::from_millis(10);
+        crate::webhook::send(&config, &outcome).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (headers, body) = &requests[1];
+        assert!(headers[0].starts_with("post /outcomes "));
+        let expected = format!("x-signature: {}", crate::webhook::signature(b"secret", body));
+        assert!(headers.contains(&expected));
+        let json: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(json["outcome"], "Terminated");
+
+        // Nothing listening, so every attempt fails
+        config.url = "http://127.0.0.1:1/outcomes".to_string();
+        config.attempts = 2;
+        assert!(crate::webhook::send(&config, &outcome).is_err());
+    }
+
+    #[test]
+    fn serves_requests() {
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || {
+            let reader = std::io::BufReader::new(server.try_clone().unwrap());
+            crate::serve::handle_connection(reader, &server, |request| match request {
+                crate::serve::Request::Transaction { amount, cashback, transaction_type } => {
+                    let mut card = visa_card();
+                    let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+                    terminal.transaction_type = transaction_type.as_deref().and_then(crate::terminal::transaction_type_from_name).unwrap_or_default();
+                    terminal.amount_authorised = amount + cashback;
+                    crate::serve::Response::Outcome(Box::new(crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap()))
+                }
+                crate::serve::Request::DetectCard => crate::serve::Response::Card {
+                    present: true,
+                    reader: Some("Mock reader".to_string()),
+                    atr: Some(vec![0x3b, 0x00]),
+                    interface: Some(crate::card::Interface::Contact.to_string()),
+                },
+                crate::serve::Request::ReadCard => crate::serve::Response::error("No card present"),
+            })
+        });
+
+        use std::io::{BufRead, Write};
+        let mut responses = std::io::BufReader::new(client.try_clone().unwrap()).lines();
+        let mut ask = |request: &str| {
+            writeln!(&client, "{}", request).unwrap();
+            serde_json::from_str::<serde_json::Value>(&responses.next().unwrap().unwrap()).unwrap()
+        };
+
+        let response = ask(r#"{"command": "detect_card"}"#);
+        assert_eq!(response["status"], "card");
+        assert_eq!(response["present"], true);
+        assert_eq!(response["atr"], "3B00");
+        let response = ask(r#"{"command": "transaction", "amount": 1000, "type": "purchase"}"#);
+        assert_eq!(response["status"], "outcome");
+        assert_eq!(response["masked_pan"], "476173******0010");
+        assert_eq!(response["first_ac"]["application_cryptogram"], "1122334455667788");
+        assert_eq!(ask(r#"{"command": "read_card"}"#)["message"], "No card present");
+        // Bad requests don't end the connection
+        assert_eq!(ask(r#"{"command": "eject"}"#)["status"], "error");
+        assert_eq!(ask(r#"{"command": "transaction"}"#)["status"], "error");
+        assert_eq!(ask(r#"{"command": "detect_card"}"#)["status"], "card");
+
+        client.shutdown(std::net::Shutdown::Both).unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn takes_commands_on_stdin() {
+        let input = concat!(
+            r#"{"command": "start_transaction", "type": "cashback"}"#, "\n",
+            r#"{"command": "provide_amount", "amount": 1000, "cashback": 500}"#, "\n",
+            "\n",
+            r#"{"command": "cancel"}"#, "\n",
+            r#"{"command": "cancel"}"#, "\n",
+            r#"{"command": "start_transaction", "amount": 2000}"#, "\n",
+            r#"{"command": "refund"}"#, "\n",
+        );
+        let commands = crate::stdio::read_commands(std::io::Cursor::new(input));
+        let mut session = crate::stdio::Session::new();
+        let mut events = vec![];
+        for command in commands.iter() {
+            match command {
+                Ok(c) => {
+                    for event in session.command(c) {
+                        crate::stdio::emit(&mut events, &event).unwrap();
+                    }
+                }
+                Err(e) => crate::stdio::emit(&mut events, &crate::stdio::Event::error(e)).unwrap()
+            }
+            if let Some(request) = session.awaiting_card() {
+                assert_eq!(request.transaction_type, match request.amount_other {
+                    0 => crate::terminal::TRANSACTION_TYPE_PURCHASE,
+                    _ => crate::terminal::TRANSACTION_TYPE_CASHBACK
+                });
+            }
+        }
+        let events: Vec<serde_json::Value> = events.split(|b| *b == b'\n')
+            .filter(|l| !l.is_empty())
+            .map(|l| serde_json::from_slice(l).unwrap())
+            .collect();
+        let names: Vec<&str> = events.iter().map(|e| e["event"].as_str().unwrap()).collect();
+        assert_eq!(names, ["amount_required", "waiting_for_card", "cancelled", "error", "waiting_for_card", "error"]);
+        assert_eq!(events[3]["message"], "No transaction to cancel");
+        assert_eq!(session.awaiting_card(), Some(&crate::terminal::TransactionRequest {
+            transaction_type: crate::terminal::TRANSACTION_TYPE_PURCHASE,
+            amount_authorised: 2000,
+            amount_other: 0,
+        }));
+
+        // Only one transaction at a time
+        let mut session = crate::stdio::Session::new();
+        session.command(crate::stdio::Command::StartTransaction { amount: None, cashback: 0, transaction_type: None });
+        assert!(matches!(session.command(crate::stdio::Command::StartTransaction { amount: Some(1), cashback: 0, transaction_type: None })[..], [crate::stdio::Event::Error { .. }]));
+        assert!(matches!(session.command(crate::stdio::Command::StartTransaction { amount: Some(1), cashback: 0, transaction_type: Some("transfer".to_string()) })[..], [crate::stdio::Event::Error { .. }]));
+        session.finish();
+        assert!(matches!(session.command(crate::stdio::Command::ProvideAmount { amount: 1, cashback: 0 })[..], [crate::stdio::Event::Error { .. }]));
+    }
+
+    #[test]
+    fn reports_transaction_outcome() {
+        let card = visa_card();
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        let ca_keys = crate::capk::CapkStore::new();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 5000;
+        let mut transaction = crate::transaction::Transaction::new(&card, terminal, &ca_keys, application);
+        transaction.online_response = Some(crate::transaction::OnlineResponse {
+            authorisation_response_code: *b"00",
+            issuer_authentication_data: None,
+            issuer_scripts: vec![],
+        });
+        let outcome = transaction.process().unwrap();
+        let report = transaction.report(outcome);
+
+        assert_eq!(report.outcome, crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(report.aid, Some(vec![0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10]));
+        assert_eq!(report.tvr, transaction.terminal.tvr.to_vec());
+        assert_eq!(report.authorisation_response_code.as_deref(), Some("00"));
+        assert_eq!(report.first_ac.as_ref().unwrap().cryptogram, crate::transaction::Cryptogram::ARQC);
+        assert_eq!(report.second_ac.as_ref().unwrap().cryptogram, crate::transaction::Cryptogram::TC);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["outcome"], "ApprovedOnline");
+        assert_eq!(json["aid"], "A0000000031010");
+        assert_eq!(json["first_ac"]["application_cryptogram"], "1122334455667788");
+    }
+
+    #[test]
+    fn reads_afl_records() {
+        let card = visa_card();
+        let record = crate::card::card_read_record(&card, 3, 2).unwrap();
+        let template = record.get_tag(crate::tlv::TagID::ReadRecordResponseMessageTemplate).unwrap();
+        assert!(template.get_tag(crate::tlv::TagID::CardRiskManagementDOL1).is_some());
+        assert!(crate::card::card_read_record(&card, 3, 3).is_err());
+
+        let afl = crate::data::parse_afl(&[0x18, 0x01, 0x03, 0x01]).unwrap();
+        let start = card.transcript().len();
+        let records = crate::card::read_records_bulk(&card, &afl).unwrap();
+        assert_eq!(records.iter().map(|r| (r.record, r.for_oda, r.is_ok())).collect::<Vec<_>>(),
+                   [(1, true, true), (2, false, true), (3, false, false)]);
+        assert_eq!(records[1].data, crate::card::card_read_record_raw(&card, 3, 2).unwrap());
+        assert!(records[2].data.is_empty());
+        assert_eq!(card.transcript()[start + 2].0, [0x00, 0xb2, 0x03, 0x1c, 0x00]);
+    }
+
+    #[test]
+    fn collects_oda_records() {
+        let card = visa_card();
+        let afl = crate::data::parse_afl(&[0x10, 0x01, 0x01, 0x00, 0x18, 0x01, 0x02, 0x01]).unwrap();
+        let records = crate::card::card_read_application_records(&card, &afl).unwrap();
+        assert_eq!(records.records.len(), 3);
+        assert!(!records.oda_record_invalid);
+        // Only SFI 3 record 1, minus its 70 4C header
+        assert_eq!(records.oda_data.len(), 0x4c);
+        assert_eq!(&records.oda_data[..2], &[0x5a, 0x08]);
+        assert!(records.get_tag(crate::tlv::TagID::ApplicationExpirationDate).is_some());
+    }
+
+    #[test]
+    fn dumps_card() {
+        let card = visa_card();
+        let card_dump = crate::dump::read_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        assert!(card_dump.atr.is_some());
+        assert_eq!(card_dump.pse.as_ref().unwrap().records.len(), 1);
+        assert_eq!(card_dump.applications.len(), 1);
+        assert_eq!(card_dump.applications[0].records.len(), 3);
+
+        let blob = Vec::<u8>::from(&card_dump);
+        let parsed = crate::tlv::TagList::try_from(blob.as_slice()).unwrap();
+        let application = parsed.get_tag(crate::tlv::TagID::Unknown(0xe0)).unwrap()
+            .get_tag(crate::tlv::TagID::Unknown(0xe2)).unwrap();
+        assert_eq!(application.get_tags(crate::tlv::TagID::Unknown(0xe3)).len(), 3);
+    }
+
+    #[test]
+    fn explores_card() {
+        // Refusing GET PROCESSING OPTIONS leaves no AFL, so the SFIs get scanned
+        let card = visa_card()
+            .expect_first("80A80000*", "6985")
+            .expect_first("80CA9F1400", "9F140105 9000");
+        let card_dump = crate::dump::explore_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        assert_eq!(card_dump.applications.len(), 1);
+        let application = &card_dump.applications[0];
+        assert!(application.processing_options.is_none());
+        let locations: Vec<(u8, u8)> = application.records.iter().map(|r| (r.sfi, r.record)).collect();
+        assert_eq!(locations, vec![(1, 1), (2, 1), (3, 1), (3, 2)]);
+        assert_eq!(application.data_objects.len(), 1);
+        assert_eq!(application.data_objects[0].tag, vec![0x9f, 0x14]);
+        // Mastercard was tried too, but the card doesn't have it
+        assert!(card.transcript().iter().any(|(c, _)| c.starts_with(&[0x00, 0xa4, 0x04, 0x00, 0x07, 0xa0, 0x00, 0x00, 0x00, 0x04])));
+
+        let printed = card_dump.to_string();
+        assert!(printed.contains("Application A0000000031010\n"));
+        assert!(printed.contains("  Record 1 in SFI 3:\n    70 ReadRecordResponseMessageTemplate\n      5A ApplicationPrimaryAccountNumber: 4761739001010010\n"));
+        assert!(printed.contains("  GET PROCESSING OPTIONS refused\n"));
+        assert!(printed.contains("  GET DATA 9F14:\n    9F14"));
+
+        // Reading normally sticks to the AFL and the directory
+        let card = visa_card();
+        let card_dump = crate::dump::read_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        assert_eq!(card_dump.applications[0].records.len(), 3);
+        assert!(!card.transcript().iter().any(|(c, _)| c.starts_with(&[0x00, 0xa4, 0x04, 0x00, 0x07, 0xa0, 0x00, 0x00, 0x00, 0x04])));
+    }
+
+    #[test]
+    fn reads_transaction_log() {
+        let card = visa_card()
+            .expect("80CA9F4F00", "9F4F13 9A03 9F2103 9F0206 5F2A02 9F1A02 9C01 9F3602 9000")
+            .expect_first("00B2015C00", "261014 143005 000000001250 0826 0826 00 0012 9000")
+            .expect_first("00B2025C00", "261012 091500 000000002000 0978 0250 01 0011 9000");
+        let logs = crate::history::read_logs(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact)).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].aid, vec![0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10]);
+        let entries = &logs[0].entries;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].date(), chrono::NaiveDate::from_ymd_opt(2026, 10, 14));
+        assert_eq!(entries[0].time(), chrono::NaiveTime::from_hms_opt(14, 30, 5));
+        assert_eq!(entries[0].amount(), Some(1250));
+        assert_eq!(entries[0].currency().unwrap().code, "GBP");
+        assert_eq!(entries[0].country().unwrap().alpha2, "GB");
+        assert_eq!(entries[0].application_transaction_counter(), Some(0x12));
+        assert_eq!(entries[1].currency().unwrap().code, "EUR");
+        assert_eq!(entries[1].country().unwrap().alpha2, "FR");
+        assert_eq!(entries[1].transaction_type(), Some(crate::terminal::TRANSACTION_TYPE_CASH));
+        // Reading the log is no transaction
+        assert!(!card.transcript().iter().any(|(c, _)| c.starts_with(&[0x80, 0xa8])));
+
+        // A card dump decodes to the same log
+        let card_dump = crate::dump::read_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        assert_eq!(card_dump.applications[0].transaction_log().as_ref(), Some(&logs[0]));
+
+        // Without the Log Format the records can't be made sense of
+        let logs = crate::history::read_logs(&visa_card(), &crate::terminal::TerminalData::new(crate::card::Interface::Contact)).unwrap();
+        assert!(logs.is_empty());
+    }
+
+    #[test]
+    fn walks_directories() {
+        // A second PSE record points at a DDF with an application of its own
+        let card = visa_card()
+            .expect_first("00B2020C00", "700E610C9D0A42414E4B2E4444463031 9000")
+            .expect_first("00A404000A42414E4B2E4444463031 00", "6F11840A42414E4B2E4444463031A503880104 9000")
+            .expect_first("00B2012400", "701561134F07A000000003201050055620504159870182 9000");
+        let directory = crate::selection::read_directory_tree(&card, crate::card::Interface::Contact).unwrap().unwrap();
+        assert_eq!(directory.language_preference.as_ref().map(|l| l.to_string()).as_deref(), Some("en"));
+        assert_eq!(directory.entries.len(), 2);
+        assert!(directory.entries[1].ddf);
+        let nested = directory.entries[1].directory.as_ref().unwrap();
+        assert_eq!(nested.entries[0].name, vec![0xa0, 0x00, 0x00, 0x00, 0x03, 0x20, 0x10]);
+        assert_eq!(nested.entries[0].label.as_deref(), Some("V PAY"));
+        // No application is selected to get there
+        assert!(!card.transcript().iter().any(|(c, _)| c.starts_with(&[0x00, 0xa4, 0x04, 0x00, 0x07])));
+
+        assert_eq!(directory.to_string(), "Directory 1PAY.SYS.DDF01 (315041592E5359532E4444463031)
+  Language Preference: en
+  Application A0000000031010 (Visa)
+    Label: VISA DEBIT
+    Priority: 1
+  Directory BANK.DDF01 (42414E4B2E4444463031)
+    Application A0000000032010 (Visa Electron)
+      Label: V PAY
+      Priority: 2, cardholder confirmation required
+");
+
+        let card = mastercard_contactless_card();
+        let directory = crate::selection::read_directory_tree(&card, crate::card::Interface::Contactless).unwrap().unwrap();
+        assert_eq!(directory.entries[0].kernel_identifier.as_deref(), Some(&[0x02][..]));
+        assert!(crate::selection::read_directory_tree(&card, crate::card::Interface::Contact).unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_application_data() {
+        let card = visa_card();
+        let card_dump = crate::dump::read_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        let data = card_dump.applications[0].application_data();
+        assert_eq!(data.effective_date, chrono::NaiveDate::from_ymd_opt(2020, 1, 1));
+        assert_eq!(data.expiry_date, chrono::NaiveDate::from_ymd_opt(2030, 12, 31));
+        assert!(!data.is_expired(chrono::NaiveDate::from_ymd_opt(2030, 12, 31).unwrap()));
+        assert!(data.is_expired(chrono::NaiveDate::from_ymd_opt(2031, 1, 1).unwrap()));
+        assert!(data.is_not_yet_effective(chrono::NaiveDate::from_ymd_opt(2019, 12, 31).unwrap()));
+        assert!(!crate::data::ApplicationData::default().is_expired(chrono::NaiveDate::MAX));
+    }
+
+    #[test]
+    fn decodes_cardholder_names() {
+        let card = visa_card();
+        let card_dump = crate::dump::read_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        assert_eq!(card_dump.applications[0].application_data().cardholder_name.as_deref(), Some("CARDHOLDER/VISA"));
+
+        assert_eq!(crate::data::decode_cardholder_name(b"M\xdcLLER/J\xd6RG  ").as_deref(), Some("M\u{dc}LLER/J\u{d6}RG"));
+        assert_eq!(crate::data::decode_cardholder_name(b" /"), Some(" /".to_string()));
+        assert_eq!(crate::data::decode_cardholder_name(b"      "), None);
+
+        let records = crate::tlv::TagList::try_from(&b"\x70\x06\x5f\x20\x03A/B"[..]).unwrap();
+        let name = records.get_tag(crate::tlv::TagID::ReadRecordResponseMessageTemplate).unwrap().get_tag(crate::tlv::TagID::CardholderName).unwrap();
+        assert_eq!(name.contents(), &crate::tlv::TagContents::String("A/B".into()));
+    }
+
+    #[test]
+    fn dumps_card_objects() {
+        let card = visa_card();
+        let card_dump = crate::dump::read_card(&card, &crate::terminal::TerminalData::new(crate::card::Interface::Contact), &crate::capk::CapkStore::new()).unwrap();
+        // PSE FCI and record, then the application's FCI, GPO response and three records
+        let objects = card_dump.card_objects();
+        assert_eq!(objects.len(), 7);
+        assert_eq!(objects[0][0], 0x6f);
+        assert_eq!(card_dump.applications[0].application_data().pan.as_ref().map(|p| p.reveal()), Some("4761739001010010"));
+    }
+
+    #[test]
+    fn dumps_icc_public_key() {
+        let card = cda_card();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.transaction_time = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let card_dump = crate::dump::read_card(&card, &terminal, &test_ca_keys()).unwrap();
+        let key = card_dump.applications[0].icc_public_key.as_ref().unwrap();
+        assert_eq!(key.modulus.len(), 96);
+        assert_eq!(key.exponent, vec![0x03]);
+
+        let blob = Vec::<u8>::from(&card_dump);
+        let parsed = crate::tlv::TagList::try_from(blob.as_slice()).unwrap();
+        let application = parsed.get_tag(crate::tlv::TagID::Unknown(0xe0)).unwrap()
+            .get_tag(crate::tlv::TagID::Unknown(0xe2)).unwrap();
+        assert!(application.get_tag(crate::tlv::TagID::Unknown(0xe6)).is_some());
+    }
+
+    #[test]
+    fn runs_transactions_through_terminal() {
+        let mut terminal = crate::Terminal::new();
+        terminal.data.currency_code = 978;
+        let card_data = terminal.read_card_from(&visa_card()).unwrap();
+        assert_eq!(card_data.applications[0].scheme.as_deref(), Some("Visa"));
+
+        let request = crate::TransactionRequest::new(None, 1000, 500);
+        assert_eq!(request, crate::TransactionRequest {
+            transaction_type: crate::terminal::TRANSACTION_TYPE_CASHBACK,
+            amount_authorised: 1500,
+            amount_other: 500,
+        });
+        terminal.acquirer = Some(Box::new(crate::acquirer::StandIn::default()));
+        let outcome = terminal.run_transaction_on(&mut visa_card(), &crate::TransactionRequest::purchase(1000));
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::ApprovedOnline);
+        assert_eq!(terminal.data.transaction_sequence_counter, 1);
+        terminal.run_transaction_on(&mut visa_card(), &request);
+        assert_eq!(terminal.data.transaction_sequence_counter, 2);
+
+        let removed = crate::transaction::TransactionOutcome::card_error(&crate::transport::TransportError::RemovedCard.into(), crate::card::Interface::Contactless);
+        assert_eq!(removed.outcome, crate::transaction::Outcome::TryAgain);
+        assert!(removed.outcome.card_removed());
+        let removed = crate::transaction::TransactionOutcome::card_error(&crate::transport::TransportError::RemovedCard.into(), crate::card::Interface::Contact);
+        assert_eq!(removed.outcome, crate::transaction::Outcome::TornTransaction);
+    }
+
+    #[test]
+    fn reports_progress() {
+        let mut terminal = crate::Terminal::new();
+        terminal.acquirer = Some(Box::new(crate::acquirer::StandIn::default()));
+        let events = terminal.events();
+        let outcome = terminal.run_transaction_on(&mut visa_card(), &crate::TransactionRequest::purchase(1000));
+        let events = events.try_iter().map(|e| serde_json::to_value(&e).unwrap()).collect::<Vec<_>>();
+        let names = events.iter().map(|e| e["event"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, ["card_detected", "application_selected", "gpo_done", "records_read", "cvm_required", "cvm_required", "outcome_ready"]);
+        assert_eq!(events[0]["interface"], "contact");
+        assert_eq!(events[1]["aid"], "A0000000031010");
+        assert_eq!(events[2]["records"], 3);
+        assert_eq!(events[3]["records"], 3);
+        // Online PIN is asked for first but there's no key to encrypt it under
+        assert_eq!(events[4]["cvm"], "online_pin");
+        assert_eq!(events[5]["cvm"], "signature");
+        assert_eq!(events[6]["outcome"], serde_json::to_value(outcome.outcome).unwrap());
+    }
+
+    #[test]
+    fn terminates_on_malformed_card_data() {
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 1000;
+        let run = |card: MockCard| {
+            let mut card = card;
+            crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap()
+        };
+
+        let outcome = run(visa_card().expect_first("80A80000*", "800100 9000"));
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Terminated);
+        assert_eq!(outcome.decision_trace, vec!["Invalid GET PROCESSING OPTIONS response: Format 1 response too short"]);
+
+        let outcome = run(visa_card().expect_first("80AE*", "80028000 9000"));
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Terminated);
+        assert!(outcome.decision_trace.last().unwrap().starts_with("Invalid GENERATE AC response"));
+
+        // Answering the first SELECT but not the one starting the transaction
+        let outcome = run(visa_card().expect_first("00A4040007A0000000031010 00", "6F0484020000 9000"));
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Terminated);
+
+        // A PSE with a malformed SFI is no use, leaving the AIDs to be tried
+        let card = visa_card().expect_first("00A404000E315041592E5359532E4444463031 00",
+                                            "6F19840E315041592E5359532E4444463031A5078800 5F2D02656E 9000");
+        assert_eq!(crate::selection::get_pse_sfi(&card).unwrap(), None);
+        let outcome = run(card);
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::DeclinedOffline);
+        assert!(outcome.aid.is_some());
+    }
+    #[test]
+    fn explains_errors() {
+        use std::error::Error;
+
+        let card = MockCard::new("3B00")
+            .expect("00B2010C00", "6A83")
+            .expect("00B2020C00", "7005 9A0326 9000")
+            .expect("00B2030C00", "90");
+        let err = crate::card::card_read_record(&card, 1, 1).unwrap_err();
+        assert!(matches!(err, crate::card::CardError::Apdu(crate::apdu::ApduError::RecordNotFound)));
+        assert_eq!(err.to_string(), "Record not found (6A83)");
+        assert!(!crate::card::is_card_removed(&err));
+
+        let err = crate::card::card_read_record(&card, 1, 2).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid response from the card: Data ends part way through a data object");
+        assert_eq!(err.source().unwrap().downcast_ref(), Some(&crate::tlv::TlvError::Truncated));
+
+        let err = crate::card::card_read_record(&card, 1, 3).unwrap_err();
+        assert_eq!(err.to_string(), "Response of 1 bytes has no status word");
+
+        let err = crate::transaction::TransactionError::from(crate::transport::TransportError::RemovedCard);
+        assert!(err.is_card_removed());
+        assert!(!err.is_card_reset());
+        let err = crate::transaction::TransactionError::PinEncipherment(crate::auth::OdaError::IccDataMissing);
+        assert_eq!(err.to_string(), "Unable to encipher the PIN: ICC data missing");
+        assert!(err.source().is_some());
+
+        assert_eq!(crate::data::ProcessingOptions::try_from(&crate::tlv::TagList::new()).unwrap_err(),
+                   crate::data::DataError::NoResponseMessageTemplate);
+    }
+
+    #[test]
+    fn restricts_selection_to_aid() {
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 1000;
+        terminal.applications = vec![crate::selection::TerminalApplication::new(&[0xa0, 0x00, 0x00, 0x00, 0x04])];
+        let outcome = crate::run(&mut visa_card(), &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Terminated);
+        assert!(outcome.aid.is_none());
+
+        terminal.applications = vec![crate::selection::TerminalApplication::new(&[0xa0, 0x00, 0x00, 0x00, 0x03])];
+        let outcome = crate::run(&mut visa_card(), &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(outcome.aid.as_deref(), Some(&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10][..]));
+    }
+
+    #[test]
+    fn recognises_other_schemes() {
+        // An Amex card without a PSE or an Application Label
+        let card = MockCard::new("3B 68 00 00 80 66 B0 07 01 01 77 07")
+            .expect("00A4040008A000000025010801 00", "6A82")
+            .expect("00A4040006A00000002501 00", "6F148408A000000025010801A5088701015F2D02656E 9000")
+            .expect("00A4040006A00000002501 02", "6A82");
+        let application = crate::choose_application(&card, crate::card::Interface::Contact, &crate::selection::default_terminal_applications(), false, Default::default()).unwrap().unwrap();
+        assert_eq!(application.aid(), &[0xa0, 0x00, 0x00, 0x00, 0x25, 0x01, 0x08, 0x01]);
+        assert_eq!(application.name(), "American Express");
+        assert_eq!(crate::entry_point::requested_kernel(&application), Some(crate::entry_point::KERNEL_AMEX));
+
+        assert_eq!(crate::selection::application_name(&[0xa0, 0x00, 0x00, 0x03, 0x33, 0x01, 0x01, 0x02]), Some("UnionPay Credit"));
+        assert_eq!(crate::selection::application_name(&[0xa0, 0x00, 0x00, 0x02, 0x77, 0x10, 0x10]), Some("Interac"));
+        assert_eq!(crate::selection::application_name(&[0xa0, 0x00, 0x00, 0x00, 0x65, 0x10, 0x10]), Some("JCB"));
+        assert_eq!(crate::selection::application_name(&[0xa0, 0x00, 0x00, 0x01, 0x52, 0x30, 0x10]), Some("Discover"));
+        assert_eq!(crate::selection::application_name(&[0xa0, 0x00, 0x00, 0x09, 0x99, 0x10, 0x10]), None);
+    }
+
+    #[test]
+    fn uses_card_language() {
+        // The application's Language Preference asks for French
+        let mut card = visa_card()
+            .expect_first("00A4040007A0000000031010 00",
+                          "6F368407A0000000031010A52B500A564953412044454249548701015F2D0266729F380C9F66049F02069F37045F2A02BF0C059F4D020B0A 9000");
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 1000;
+        let outcome = crate::run(&mut card, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(outcome.language, crate::language::Language::French);
+        assert_eq!(outcome.cardholder_message(), "Refusé");
+
+        let outcome = crate::run(&mut visa_card(), &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(outcome.language, crate::language::Language::English);
+    }
+
+    #[test]
+    fn runs_with_loaded_applications() {
+        let applications = crate::selection::parse_applications_json(r#"{"applications": [{"aid": "A000000003", "floor_limit": 2000}]}"#).unwrap();
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.amount_authorised = 1000;
+        terminal.applications = applications;
+        let outcome = crate::run(&mut visa_card(), &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(outcome.aid.as_deref(), Some(&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10][..]));
+    }
+
+    #[test]
+    fn keeps_pins_out_of_logs() {
+        assert_eq!(crate::apdu::loggable_command(&parse_hex("0020008008241234FFFFFFFFFF").into_iter().flatten().collect::<Vec<_>>()),
+                   "0020008008<PIN block>");
+        assert_eq!(crate::apdu::loggable_command(&[0x00, 0xb2, 0x01, 0x0c, 0x00]), "00B2010C00");
+    }
+
+    #[test]
+    fn caches_selections_and_records() {
+        let terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        let reads = |card: &MockCard, from: usize| card.transcript()[from..].iter().filter(|(c, _)| c[1] == 0xb2).count();
+
+        // Reading the card first leaves its records cached for the transaction
+        let mut card = visa_card();
+        let caching = crate::transport::Caching::new(&mut card);
+        crate::dump::read_card(&caching, &terminal, &crate::capk::CapkStore::new()).unwrap();
+        let cache = caching.into_cache();
+        let read = card.transcript().len();
+        assert!(reads(&card, 0) > 0);
+        let mut caching = crate::transport::Caching::with_cache(&mut card, cache);
+        let outcome = crate::run(&mut caching, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        let uncached = crate::run(&mut visa_card(), &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(outcome.outcome, uncached.outcome);
+        assert_eq!(reads(&card, read), 0);
+
+        // GENERATE AC could have added to the log, so nothing is kept past it
+        let mut caching = crate::transport::Caching::with_cache(&mut card, crate::transport::CardCache::new());
+        crate::run(&mut caching, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        let read = card.transcript().len();
+        crate::run(&mut crate::transport::Caching::new(&mut card), &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert!(reads(&card, read) > 0);
+
+        // The final SELECT after going through the list of AIDs is the
+        // candidate's again, with only refused SELECTs in between
+        let mut uncached = cda_card();
+        crate::run(&mut uncached, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        let mut card = cda_card();
+        crate::run(&mut crate::transport::Caching::new(&mut card), &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        assert_eq!(card.transcript().len(), uncached.transcript().len() - 1);
+    }
+
+    #[test]
+    fn caches_selections_answered_in_parts() {
+        let mut card = MockCard::new("3B00")
+            .expect("00A4040007A0000000031010 00", "6108")
+            .expect("00C0000008", "6F0684046104")
+            .expect("00C0000004", "010203049000");
+        let caching = crate::transport::Caching::new(&mut card);
+        let aid = [0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10];
+        let fci = crate::card::card_select_raw(&caching, &aid, false).unwrap();
+        assert_eq!(fci, [0x6f, 0x06, 0x84, 0x04, 0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(crate::card::card_select_raw(&caching, &aid, false).unwrap(), fci);
+        drop(caching);
+        assert_eq!(card.transcript().len(), 3);
+    }
+
+    #[test]
+    fn chains_get_response() {
+        let card = MockCard::new("3B00")
+            .expect("00B2010C00", "6104")
+            .expect("00C0000004", "010203046102")
+            .expect("00C0000002", "05069000")
+            .expect("00B2020C00", "6C03")
+            .expect("00B2020C03", "0708099000");
+        let response = crate::apdu::send_apdu(&card, &crate::apdu::ApduCommand::new(0x00, 0xb2, 0x01, 0x0c, &[], 0)).unwrap();
+        assert_eq!(response.data(), [1, 2, 3, 4, 5, 6]);
+        let response = crate::apdu::send_apdu(&card, &crate::apdu::ApduCommand::new(0x00, 0xb2, 0x02, 0x0c, &[], 0)).unwrap();
+        assert_eq!(response.data(), [7, 8, 9]);
+        assert_eq!(card.transcript().len(), 5);
+
+        // Without buffers of its own the transport gets some for each command
+        let borrowed = card.buffers.borrow_mut();
+        let response = crate::apdu::send_apdu(&card, &crate::apdu::ApduCommand::new(0x00, 0xb2, 0x01, 0x0c, &[], 0)).unwrap();
+        assert_eq!(response.data(), [1, 2, 3, 4, 5, 6]);
+        drop(borrowed);
+    }
+
+    #[test]
+    fn watches_exchanges() {
+        let seen = std::cell::RefCell::new(vec![]);
+        let mut card = visa_card();
+        let mut watched = crate::transport::Watching::new(&mut card, |e: &crate::transport::Exchange| seen.borrow_mut().push(e.clone()));
+        let terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        crate::run(&mut watched, &terminal, &crate::capk::CapkStore::new(), None, None, false).unwrap();
+        let seen = seen.into_inner();
+        assert_eq!(crate::apdu::instruction_name(seen[0].command[1]), Some("SELECT"));
+        assert_eq!(seen[0].response[seen[0].response.len() - 2..], [0x90, 0x00]);
+        assert!(seen.iter().any(|e| crate::apdu::instruction_name(e.command[1]) == Some("GENERATE AC")));
+        assert_eq!(crate::apdu::instruction_name(0x00), None);
+    }
+
+    #[test]
+    fn aborts_when_cancelled() {
+        #[cfg(feature = "pcsc")]
+        {
+            let canceller = crate::card::Canceller::new();
+            assert!(!canceller.is_cancelled());
+            canceller.cancel();
+            assert!(canceller.is_cancelled());
+            canceller.reset();
+            assert!(!canceller.is_cancelled());
+        }
+
+        let err: crate::card::CardError = crate::transport::TransportError::Cancelled.into();
+        assert!(crate::card::is_cancelled(&err));
+        assert!(crate::card::optional(Err::<Vec<u8>, _>(err)).is_err());
+        let err: crate::transaction::TransactionError = crate::transport::TransportError::Cancelled.into();
+        let outcome = crate::transaction::TransactionOutcome::card_error(&err, crate::card::Interface::Contact);
+        assert_eq!(outcome.outcome, crate::transaction::Outcome::Aborted);
+        assert_eq!(outcome.outcome.cardholder_message(), crate::language::Message::Cancelled);
+        assert_eq!(outcome.outcome.to_string(), "Cancelled");
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use crate::transport::CardTransport;
+
+/// Authorisation request, for dual message systems where clearing follows later
+pub const MTI_AUTHORISATION_REQUEST: &str = "0100";
+/// Financial transaction request, for single message systems
+pub const MTI_FINANCIAL_REQUEST: &str = "0200";
+/// Authorisation advice, for a transaction the issuer didn't authorise
+pub const MTI_AUTHORISATION_ADVICE: &str = "0120";
+/// Reversal request, undoing an earlier authorisation
+pub const MTI_REVERSAL_REQUEST: &str = "0400";
+/// Network management request, such as signing on
+pub const MTI_NETWORK_MANAGEMENT_REQUEST: &str = "0800";
+
+/// DE70 network management information code for signing on
+pub const NETWORK_SIGN_ON: &str = "001";
+
+/// What goes in DE55, being the data the issuer needs to check the ARQC and
+/// the terminal's view of the transaction
+pub const CHIP_DATA_TAGS: &[crate::tlv::TagID] = &[
+    crate::tlv::TagID::ApplicationCryptogram,
+    crate::tlv::TagID::CryptogramInformationData,
+    crate::tlv::TagID::IssuerApplicationData,
+    crate::tlv::TagID::UnpredictableNumber,
+    crate::tlv::TagID::ApplicationTransactionCounter,
+    crate::tlv::TagID::TerminalVerificationResults,
+    crate::tlv::TagID::TransactionDate,
+    crate::tlv::TagID::TransactionType,
+    crate::tlv::TagID::AmountAuthorised,
+    crate::tlv::TagID::TransactionCurrencyCode,
+    crate::tlv::TagID::ApplicationInterchangeProfile,
+    crate::tlv::TagID::TerminalCountryCode,
+    crate::tlv::TagID::AmountOther,
+    crate::tlv::TagID::TerminalCapabilities,
+    crate::tlv::TagID::CVMResults,
+    crate::tlv::TagID::TerminalType,
+    crate::tlv::TagID::DedicatedFileName,
+    crate::tlv::TagID::ApplicationVersionNumberTerminal,
+    crate::tlv::TagID::TransactionSequenceCounter,
+];
+
+/// How a data element is laid out. Lengths count digits for numeric
+/// elements, characters for text and bytes for binary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Numeric(usize),
+    Text(usize),
+    Binary(usize),
+    LlNumeric(usize),
+    LlText(usize),
+    LlBinary(usize),
+    LllBinary(usize),
+}
+
+/// ISO 8583:1987 formats for the data elements used here
+fn format(field: u8) -> Option<Format> {
+    Some(match field {
+        2 => Format::LlNumeric(19),
+        3 => Format::Numeric(6),
+        4 => Format::Numeric(12),
+        7 => Format::Numeric(10),
+        11 => Format::Numeric(6),
+        12 => Format::Numeric(6),
+        13 => Format::Numeric(4),
+        14 => Format::Numeric(4),
+        18 => Format::Numeric(4),
+        22 => Format::Numeric(3),
+        23 => Format::Numeric(3),
+        35 => Format::LlText(37),
+        37 => Format::Text(12),
+        38 => Format::Text(6),
+        39 => Format::Text(2),
+        41 => Format::Text(8),
+        42 => Format::Text(15),
+        43 => Format::Text(40),
+        48 => Format::LllBinary(999),
+        49 => Format::Numeric(3),
+        52 => Format::Binary(8),
+        53 => Format::LlBinary(48),
+        55 => Format::LllBinary(255),
+        70 => Format::Numeric(3),
+        _ => return None
+    })
+}
+
+/// An ISO 8583 message, held as its data elements. Numeric and text
+/// elements are kept as their ASCII characters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsoMessage {
+    pub mti: String,
+    fields: BTreeMap<u8, Vec<u8>>,
+}
+
+impl IsoMessage {
+    pub fn new(mti: &str) -> Self {
+        Self {
+            mti: mti.to_string(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, field: u8, value: impl Into<Vec<u8>>) {
+        self.fields.insert(field, value.into());
+    }
+
+    pub fn get(&self, field: u8) -> Option<&[u8]> {
+        self.fields.get(&field).map(|v| v.as_slice())
+    }
+
+    /// The data element as text, for numeric and text elements
+    pub fn get_str(&self, field: u8) -> Option<&str> {
+        self.get(field).and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    /// Encodes the message with an ASCII MTI, binary bitmaps, and ASCII
+    /// numeric data and length prefixes
+    pub fn pack(&self) -> Result<Vec<u8>, &'static str> {
+        if self.mti.len() != 4 || !self.mti.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("Invalid MTI");
+        }
+        let secondary = self.fields.keys().any(|f| *f > 64);
+        let mut bitmap = vec![0u8; if secondary { 16 } else { 8 }];
+        if secondary {
+            bitmap[0] |= 0x80;
+        }
+        let mut data = vec![];
+        for (field, value) in &self.fields {
+            if *field < 2 || *field > 128 {
+                return Err("Invalid data element number");
+            }
+            let index = (*field - 1) as usize;
+            bitmap[index / 8] |= 0x80 >> (index % 8);
+            let numeric = value.iter().all(|b| b.is_ascii_digit());
+            match format(*field).ok_or("Unsupported data element")? {
+                Format::Numeric(l) if value.len() == l && numeric => {}
+                Format::Text(l) | Format::Binary(l) if value.len() == l => {}
+                Format::LlNumeric(l) if value.len() <= l && numeric => data.extend(format!("{:02}", value.len()).bytes()),
+                Format::LlText(l) | Format::LlBinary(l) if value.len() <= l => data.extend(format!("{:02}", value.len()).bytes()),
+                Format::LllBinary(l) if value.len() <= l => data.extend(format!("{:03}", value.len()).bytes()),
+                _ => return Err("Data element doesn't fit its format")
+            }
+            data.extend(value);
+        }
+
+        let mut out = self.mti.as_bytes().to_vec();
+        out.extend(bitmap);
+        out.extend(data);
+        Ok(out)
+    }
+
+    /// Decodes a message encoded as `pack` does it
+    pub fn unpack(data: &[u8]) -> Result<Self, &'static str> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], &'static str> {
+            if data.len() < len {
+                return Err("Message too short");
+            }
+            let (out, rest) = data.split_at(len);
+            *data = rest;
+            Ok(out)
+        }
+        fn length(data: &mut &[u8], digits: usize, max: usize) -> Result<usize, &'static str> {
+            let len = std::str::from_utf8(take(data, digits)?).ok()
+                .and_then(|l| l.parse().ok())
+                .ok_or("Invalid length prefix")?;
+            match len > max {
+                true => Err("Data element too long"),
+                false => Ok(len)
+            }
+        }
+
+        let mut data = data;
+        let mti = std::str::from_utf8(take(&mut data, 4)?).map_err(|_| "Invalid MTI")?;
+        let mut bitmap = take(&mut data, 8)?.to_vec();
+        if bitmap[0] & 0x80 != 0 {
+            bitmap.extend(take(&mut data, 8)?);
+        }
+        let mut message = Self::new(mti);
+        for index in 1..bitmap.len() * 8 {
+            if bitmap[index / 8] & (0x80 >> (index % 8)) == 0 {
+                continue;
+            }
+            let field = (index + 1) as u8;
+            let len = match format(field).ok_or("Unsupported data element")? {
+                Format::Numeric(l) | Format::Text(l) | Format::Binary(l) => l,
+                Format::LlNumeric(l) | Format::LlText(l) | Format::LlBinary(l) => length(&mut data, 2, l)?,
+                Format::LllBinary(l) => length(&mut data, 3, l)?,
+            };
+            message.set(field, take(&mut data, len)?);
+        }
+        Ok(message)
+    }
+}
+
+/// What the issuer answered, from the response code in DE39 and the
+/// Issuer Authentication Data and scripts in DE55. Some hosts only put the
+/// response code in DE55, but DE39 wins where both are there.
+pub fn online_response(message: &IsoMessage) -> Result<crate::transaction::OnlineResponse, crate::data::DataError> {
+    let mut tags = crate::tlv::TagList::new();
+    if let Some(code) = message.get(39) {
+        tags.add_tag(crate::tlv::Tag::new(crate::tlv::TagID::AuthorisationResponseCode, crate::tlv::TagContents::Bytes(code.to_vec().into())));
+    }
+    if let Some(chip_data) = message.get(55) {
+        let chip_data = crate::tlv::TagList::try_from(chip_data).map_err(crate::data::DataError::ChipData)?;
+        for tag in chip_data.tags() {
+            if tag.id() != crate::tlv::TagID::AuthorisationResponseCode || message.get(39).is_none() {
+                tags.add_tag(tag.clone());
+            }
+        }
+    }
+    crate::transaction::OnlineResponse::try_from(&tags)
+}
+
+/// DE22: how the card was read, then whether the terminal can take a PIN
+pub fn pos_entry_mode(terminal: &crate::terminal::TerminalData, mag_stripe: bool) -> String {
+    let mode = match (terminal.interface, mag_stripe) {
+        (crate::card::Interface::Contact, _) => "05",
+        (crate::card::Interface::Contactless, false) => "07",
+        (crate::card::Interface::Contactless, true) => "91",
+    };
+    let pin = if terminal.terminal_capabilities[1] & 0xd0 != 0 { 1 } else { 2 };
+    format!("{}{}", mode, pin)
+}
+
+/// The chip data for DE55, as TLV, leaving out anything not known
+pub fn chip_data<T: CardTransport + ?Sized>(transaction: &crate::transaction::Transaction<T>) -> Vec<u8> {
+    let mut tags = crate::tlv::TagList::new();
+    for tag in CHIP_DATA_TAGS {
+        if let Some(v) = transaction.tag_value(*tag) {
+            tags.add_tag(crate::tlv::Tag::new(*tag, crate::tlv::TagContents::Bytes(v.into())));
+        }
+    }
+    Vec::<u8>::from(&tags)
+}
+
+/// The ISO 8583 message for `request`, `mti` picking between an
+/// authorisation, financial, reversal or advice request
+pub fn authorisation_message(request: &crate::acquirer::AuthorisationRequest, mti: &str) -> IsoMessage {
+    let mut message = IsoMessage::new(mti);
+    if let Some(pan) = &request.pan {
+        message.set(2, pan.as_str());
+    }
+    message.set(3, format!("{:02X}0000", request.transaction_type));
+    message.set(4, format!("{:012}", request.amount_authorised));
+    message.set(11, format!("{:06}", request.transaction_sequence_counter % 1_000_000));
+    message.set(12, request.transaction_time.format("%H%M%S").to_string());
+    message.set(13, request.transaction_date.format("%m%d").to_string());
+    if let Some(expiry) = &request.expiry {
+        message.set(14, expiry.as_str());
+    }
+    if let Some(mcc) = request.merchant.category_code {
+        message.set(18, format!("{:04}", mcc));
+    }
+    message.set(22, request.pos_entry_mode.as_str());
+    if let Some(sequence) = request.pan_sequence_number {
+        message.set(23, format!("{:03}", sequence));
+    }
+    if let Some(tid) = &request.merchant.terminal_identification {
+        message.set(41, format!("{:<8}", tid));
+    }
+    if let Some(mid) = &request.merchant.identifier {
+        message.set(42, format!("{:<15}", mid));
+    }
+    if let Some(name) = &request.merchant.name_and_location {
+        message.set(43, format!("{:<40.40}", name));
+    }
+    // Under DUKPT the track goes enciphered in DE48 instead, with the KSN in DE53
+    match (&request.encrypted_track2, &request.track2) {
+        (Some(track2), _) => message.set(48, track2.as_slice()),
+        (None, Some(track2)) => message.set(35, track2.as_str()),
+        (None, None) => {}
+    }
+    message.set(49, format!("{:03}", request.currency_code));
+    if let Some(pin_block) = &request.pin_block {
+        message.set(52, pin_block.as_slice());
+    }
+    if let Some(ksn) = &request.ksn {
+        message.set(53, ksn.as_slice());
+    }
+    if let Some(chip_data) = &request.chip_data {
+        message.set(55, chip_data.as_slice());
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn rejects_oversized_length_prefixes() {
+        let mut message = super::IsoMessage::new("0100");
+        message.set(2, b"4761739001010010");
+        message.set(3, b"000000");
+        let packed = message.pack().unwrap();
+        assert_eq!(super::IsoMessage::unpack(&packed).unwrap(), message);
+
+        // A PAN claiming 20 digits, one more than DE2 can have
+        let mut oversized = packed[..12].to_vec();
+        oversized.extend(b"2047617390010100100000000000");
+        assert_eq!(super::IsoMessage::unpack(&oversized), Err("Data element too long"));
+    }
+
+    #[test]
+    fn builds_authorisation_request() {
+        let request = crate::acquirer::AuthorisationRequest {
+            pan: Some("4761739001010010".to_string()),
+            track2: Some("4761739001010010=30122011143804400000".to_string()),
+            expiry: Some("3012".to_string()),
+            pan_sequence_number: Some(1),
+            transaction_type: 0x00,
+            amount_authorised: 5000,
+            amount_other: 0,
+            currency_code: 826,
+            transaction_sequence_counter: 1234567,
+            transaction_date: chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            transaction_time: chrono::NaiveTime::from_hms_opt(12, 34, 56).unwrap(),
+            pos_entry_mode: "051".to_string(),
+            pin_block: None,
+            ksn: None,
+            encrypted_track2: None,
+            chip_data: Some(vec![0x82, 0x02, 0x1c, 0x00]),
+            merchant: Default::default(),
+        };
+        let request = super::authorisation_message(&request, super::MTI_AUTHORISATION_REQUEST);
+        assert_eq!(request.get_str(2), Some("4761739001010010"));
+        assert_eq!(request.get_str(3), Some("000000"));
+        assert_eq!(request.get_str(4), Some("000000005000"));
+        assert_eq!(request.get_str(11), Some("234567"));
+        assert_eq!(request.get_str(12), Some("123456"));
+        assert_eq!(request.get_str(13), Some("0301"));
+        assert_eq!(request.get_str(14), Some("3012"));
+        assert_eq!(request.get_str(22), Some("051"));
+        assert_eq!(request.get_str(23), Some("001"));
+        assert_eq!(request.get_str(35), Some("4761739001010010=30122011143804400000"));
+        assert_eq!(request.get_str(49), Some("826"));
+        assert_eq!(request.get(52), None);
+        assert_eq!(request.get(55), Some(&[0x82, 0x02, 0x1c, 0x00][..]));
+
+        let packed = request.pack().unwrap();
+        assert_eq!(&packed[..4], b"0100");
+        // DE2, DE3 and DE4 with no secondary bitmap
+        assert_eq!(packed[4], 0x70);
+        assert_eq!(&packed[12..30], b"164761739001010010");
+
+        let mut invalid = super::IsoMessage::new(super::MTI_FINANCIAL_REQUEST);
+        invalid.set(4, "50");
+        assert!(invalid.pack().is_err());
+    }
+
+    #[test]
+    fn parses_authorisation_response() {
+        let mut message = super::IsoMessage::new("0110");
+        message.set(11, "000001");
+        message.set(39, "00");
+        // An ARPC and a script to run after the final GENERATE AC, along with
+        // a response code that DE39 overrides
+        message.set(55, crate::hex::decode("8A023035910A11223344556677883030720E8605841E00000086058424000000").unwrap());
+        let message = super::IsoMessage::unpack(&message.pack().unwrap()).unwrap();
+        let response = super::online_response(&message).unwrap();
+        assert_eq!(&response.authorisation_response_code, b"00");
+        assert_eq!(response.issuer_authentication_data.as_deref(), Some(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x30, 0x30][..]));
+        assert_eq!(response.issuer_scripts.len(), 1);
+        assert!(!response.issuer_scripts[0].before_final_ac);
+
+        // Only DE55 to go on
+        let mut message = super::IsoMessage::new("0110");
+        message.set(55, crate::hex::decode("8A023035").unwrap());
+        assert_eq!(&super::online_response(&message).unwrap().authorisation_response_code, b"05");
+        assert!(super::online_response(&super::IsoMessage::new("0110")).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_issuer_scripts() {
+        let response = |chip_data: &str| {
+            let mut message = super::IsoMessage::new("0110");
+            message.set(39, "00");
+            message.set(55, crate::hex::decode(chip_data).unwrap());
+            super::online_response(&message).err()
+        };
+        // DE55 cut off part way through the script
+        assert!(matches!(response("720E8605841E000000"), Some(crate::data::DataError::ChipData(_))));
+        // A command too short to have a header
+        assert_eq!(response("7203860184"), Some(crate::data::DataError::InvalidIssuerScriptCommand));
+        // Lc claiming far more data than the command has
+        assert_eq!(response("7209860700840000FF0000"), Some(crate::data::DataError::InvalidIssuerScriptCommand));
+    }
+}
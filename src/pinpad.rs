@@ -0,0 +1,147 @@
+use crate::transport::CardTransport;
+
+const CM_IOCTL_GET_FEATURE_REQUEST: u32 = 3400;
+const FEATURE_VERIFY_PIN_DIRECT: u8 = 0x06;
+
+const PIN_MIN_DIGITS: u8 = 4;
+const PIN_MAX_DIGITS: u8 = 12;
+
+#[cfg(target_os = "windows")]
+fn scard_ctl_code(code: u32) -> u32 {
+    (0x31 << 16) | (code << 2)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn scard_ctl_code(code: u32) -> u32 {
+    0x42000000 + code
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PinVerifyResult {
+    Verified,
+    Incorrect(Option<u8>),
+    Blocked,
+    Cancelled,
+    Timeout,
+    Failed(u8, u8),
+}
+
+impl PinVerifyResult {
+    fn from_status(sw1: u8, sw2: u8) -> Self {
+        match (sw1, sw2) {
+            (0x90, 0x00) => PinVerifyResult::Verified,
+            (0x63, c) if c & 0xf0 == 0xc0 => PinVerifyResult::Incorrect(Some(c & 0x0f)),
+            (0x63, 0x00) => PinVerifyResult::Incorrect(None),
+            (0x69, 0x83) | (0x69, 0x84) => PinVerifyResult::Blocked,
+            (0x64, 0x00) => PinVerifyResult::Timeout,
+            (0x64, 0x01) => PinVerifyResult::Cancelled,
+            (sw1, sw2) => PinVerifyResult::Failed(sw1, sw2),
+        }
+    }
+}
+
+impl std::fmt::Display for PinVerifyResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PinVerifyResult::Verified => write!(f, "PIN OK"),
+            PinVerifyResult::Incorrect(Some(t)) => write!(f, "Incorrect PIN, {} tries remaining", t),
+            PinVerifyResult::Incorrect(None) => write!(f, "Incorrect PIN"),
+            PinVerifyResult::Blocked => write!(f, "PIN blocked"),
+            PinVerifyResult::Cancelled => write!(f, "PIN entry cancelled"),
+            PinVerifyResult::Timeout => write!(f, "PIN entry timed out"),
+            PinVerifyResult::Failed(sw1, sw2) => write!(f, "PIN verification failed ({:02x}{:02x})", sw1, sw2),
+        }
+    }
+}
+
+/// Asks the reader for its CCID feature list and returns the control code for
+/// direct PIN verification, if the reader has a pinpad
+pub fn find_verify_pin_direct<T: CardTransport + ?Sized>(card: &T) -> Option<u32> {
+    let mut buf = [0; 256];
+    let features = card.control(scard_ctl_code(CM_IOCTL_GET_FEATURE_REQUEST), &[], &mut buf).ok()?;
+
+    for feature in features.chunks(6) {
+        if feature.len() == 6 && feature[0] == FEATURE_VERIFY_PIN_DIRECT && feature[1] == 4 {
+            return Some(u32::from_be_bytes([feature[2], feature[3], feature[4], feature[5]]));
+        }
+    }
+    None
+}
+
+/// Builds a PC/SC part 10 PIN_VERIFY_STRUCTURE wrapping an EMV plaintext
+/// offline PIN VERIFY, with the reader filling in a format 2 PIN block
+fn make_verify_structure() -> Vec<u8> {
+    let apdu = [0x00, 0x20, 0x00, 0x80, 0x08, 0x20, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+
+    let mut out = vec![
+        0x00, // bTimerOut
+        0x00, // bTimerOut2
+        0x89, // bmFormatString: byte units, PIN at offset 1, left justified, BCD
+        0x47, // bmPINBlockString: 4 bit length field, 7 byte PIN field after the control byte
+        0x04, // bmPINLengthFormat: length at bit offset 4
+        PIN_MAX_DIGITS, PIN_MIN_DIGITS, // wPINMaxExtraDigit
+        0x02, // bEntryValidationCondition: validation key pressed
+        0x01, // bNumberMessage
+        0x09, 0x04, // wLangId
+        0x00, // bMsgIndex
+        0x00, 0x00, 0x00, // bTeoPrologue
+    ];
+    out.extend(&(apdu.len() as u32).to_le_bytes());
+    out.extend(&apdu);
+    out
+}
+
+/// Formats a PIN as an ISO 9564 format 2 plaintext block for offline verification
+pub fn make_plaintext_pin_block(pin: &str) -> Option<Vec<u8>> {
+    let len = pin.len();
+    if len < PIN_MIN_DIGITS as usize || len > PIN_MAX_DIGITS as usize || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut nibbles: Vec<u8> = pin.bytes().map(|b| b - b'0').collect();
+    nibbles.resize(14, 0xf);
+
+    let mut out = vec![0x20 | len as u8];
+    for pair in nibbles.chunks(2) {
+        out.push(pair[0] << 4 | pair[1]);
+    }
+    Some(out)
+}
+
+fn verify_pin_on_pinpad<T: CardTransport + ?Sized>(card: &T, control_code: u32, terminal: &crate::terminal::TerminalData) -> Result<PinVerifyResult, crate::card::CardError> {
+    crate::events::emit(&terminal.events, crate::events::Event::CardholderMessage {
+        message: terminal.language.message(crate::language::Message::EnterPinOnPinpad).to_string(),
+    });
+    let mut buf = [0; 2];
+    let status = card.control(control_code, &make_verify_structure(), &mut buf)?;
+    if status.len() != 2 {
+        return Err(crate::card::CardError::InvalidPinpadResponse);
+    }
+    Ok(PinVerifyResult::from_status(status[0], status[1]))
+}
+
+fn verify_pin_on_host<T: CardTransport + ?Sized>(card: &T, language: crate::language::Language) -> Result<PinVerifyResult, crate::card::CardError> {
+    let pin_block = loop {
+        let pin = crate::util::get_pin(&format!("{}: ", language.message(crate::language::Message::EnterPin)));
+        if let Some(b) = make_plaintext_pin_block(&pin) {
+            break b;
+        }
+    };
+    verify_pin_block(card, crate::card::VERIFY_PLAINTEXT_PIN, &pin_block)
+}
+
+/// Sends an already formed PIN block to the card for offline verification
+pub fn verify_pin_block<T: CardTransport + ?Sized>(card: &T, qualifier: u8, pin_block: &[u8]) -> Result<PinVerifyResult, crate::card::CardError> {
+    let response = crate::card::card_verify(card, qualifier, pin_block)?;
+    let (sw1, sw2) = response.status();
+    Ok(PinVerifyResult::from_status(*sw1, *sw2))
+}
+
+/// Performs plaintext offline PIN verification, using the reader's pinpad when
+/// it has one so the PIN never passes through the host
+pub fn verify_offline_pin<T: CardTransport + ?Sized>(card: &T, terminal: &crate::terminal::TerminalData) -> Result<PinVerifyResult, crate::card::CardError> {
+    match find_verify_pin_direct(card) {
+        Some(c) => verify_pin_on_pinpad(card, c, terminal),
+        None => verify_pin_on_host(card, terminal.language)
+    }
+}
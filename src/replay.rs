@@ -0,0 +1,267 @@
+use std::convert::TryFrom;
+use serde::{Deserialize, Serialize};
+
+/// A transaction as recorded with `--transcript`: what the terminal was set up
+/// with and every exchange with the card
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transcript {
+    #[serde(serialize_with = "crate::dump::as_optional_hex", deserialize_with = "crate::dump::from_optional_hex")]
+    pub atr: Option<Vec<u8>>,
+    pub contactless: bool,
+    pub transaction_type: u8,
+    /// Amount, Authorised in minor units, including any cashback
+    pub amount_authorised: u64,
+    pub amount_other: u64,
+    pub terminal_type: u8,
+    pub transaction_sequence_counter: u32,
+    pub transaction_date: chrono::NaiveDate,
+    /// The time and unpredictable number the card was given, where the
+    /// transaction got that far
+    pub transaction_time: Option<chrono::NaiveDateTime>,
+    #[serde(serialize_with = "crate::dump::as_optional_hex", deserialize_with = "crate::dump::from_optional_hex")]
+    pub unpredictable_number: Option<Vec<u8>>,
+    pub exchanges: Vec<crate::transport::Exchange>,
+}
+
+impl Transcript {
+    pub fn new(terminal: &crate::terminal::TerminalData, outcome: &crate::transaction::TransactionOutcome, atr: Option<Vec<u8>>, exchanges: Vec<crate::transport::Exchange>) -> Self {
+        Self {
+            atr,
+            contactless: terminal.interface == crate::card::Interface::Contactless,
+            transaction_type: terminal.transaction_type,
+            amount_authorised: terminal.amount_authorised,
+            amount_other: terminal.amount_other,
+            terminal_type: terminal.terminal_type,
+            transaction_sequence_counter: terminal.transaction_sequence_counter,
+            transaction_date: terminal.transaction_date(),
+            transaction_time: outcome.transaction_time,
+            unpredictable_number: outcome.unpredictable_number.clone(),
+            exchanges,
+        }
+    }
+
+    /// The terminal set up as it was, giving the transaction the recorded
+    /// time and unpredictable number so it sends the same commands
+    pub fn terminal(&self) -> crate::terminal::TerminalData {
+        let mut terminal = crate::terminal::TerminalData::new(match self.contactless {
+            true => crate::card::Interface::Contactless,
+            false => crate::card::Interface::Contact
+        });
+        terminal.transaction_type = self.transaction_type;
+        terminal.amount_authorised = self.amount_authorised;
+        terminal.amount_other = self.amount_other;
+        terminal.terminal_type = self.terminal_type;
+        terminal.transaction_sequence_counter = self.transaction_sequence_counter;
+        terminal.date_override = Some(self.transaction_date);
+        terminal.time_override = self.transaction_time;
+        terminal.unpredictable_number_override = self.unpredictable_number.as_deref().and_then(|u| <[u8; 4]>::try_from(u).ok());
+        terminal
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// Something recorded from a card earlier, to analyse without it
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Recording {
+    Transcript(Transcript),
+    Dump(crate::dump::CardDump),
+}
+
+impl Recording {
+    /// Reads a transcript, or a card dump in JSON
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Not a transcript or JSON card dump"))
+    }
+}
+
+fn respond<'buf>(data: &[u8], sw: [u8; 2], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], crate::transport::TransportError> {
+    let len = data.len() + 2;
+    if len > receive_buffer.len() {
+        return Err(crate::transport::TransportError::InsufficientBuffer);
+    }
+    receive_buffer[..data.len()].copy_from_slice(data);
+    receive_buffer[data.len()..len].copy_from_slice(&sw);
+    Ok(&receive_buffer[..len])
+}
+
+fn atr(atr: &Option<Vec<u8>>) -> Result<crate::atr::Atr, crate::transport::TransportError> {
+    atr.as_deref()
+        .and_then(|a| crate::atr::Atr::try_from(a).ok())
+        .ok_or(crate::transport::TransportError::InvalidAtr)
+}
+
+/// Plays back a transcript's responses in order. A command with a different
+/// header to the one recorded gets 6F00, and the first command to differ at
+/// all is noted, as the replay can't be trusted from there on.
+pub struct Replay {
+    atr: Option<Vec<u8>>,
+    exchanges: Vec<crate::transport::Exchange>,
+    next: std::cell::Cell<usize>,
+    divergence: std::cell::RefCell<Option<String>>,
+}
+
+impl Replay {
+    pub fn new(transcript: &Transcript) -> Self {
+        Self {
+            atr: transcript.atr.clone(),
+            exchanges: transcript.exchanges.clone(),
+            next: std::cell::Cell::new(0),
+            divergence: std::cell::RefCell::new(None),
+        }
+    }
+
+    /// Where the replay first parted ways with the recording, if it did
+    pub fn divergence(&self) -> Option<String> {
+        self.divergence.borrow().clone()
+    }
+
+    /// Recorded exchanges the replay never got to
+    pub fn remaining(&self) -> usize {
+        self.exchanges.len() - self.next.get()
+    }
+
+    fn diverge(&self, message: String) {
+        self.divergence.borrow_mut().get_or_insert(message);
+    }
+}
+
+impl crate::transport::CardTransport for Replay {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], crate::transport::TransportError> {
+        let index = self.next.get();
+        let exchange = match self.exchanges.get(index) {
+            Some(e) => e,
+            None => {
+                self.diverge(format!("Command {} ({}) sent after the recording ended", index + 1, crate::hex::Hex(send_buffer)));
+                return respond(&[], [0x6f, 0x00], receive_buffer);
+            }
+        };
+        if exchange.command.get(..4) != send_buffer.get(..4) {
+            self.diverge(format!("Command {} was {} but {} was recorded", index + 1, crate::hex::Hex(send_buffer), crate::hex::Hex(&exchange.command)));
+            return respond(&[], [0x6f, 0x00], receive_buffer);
+        }
+        if exchange.command != send_buffer {
+            self.diverge(format!("Command {} was {} but {} was recorded", index + 1, crate::hex::Hex(send_buffer), crate::hex::Hex(&exchange.command)));
+        }
+        self.next.set(index + 1);
+        if exchange.response.len() > receive_buffer.len() {
+            return Err(crate::transport::TransportError::InsufficientBuffer);
+        }
+        receive_buffer[..exchange.response.len()].copy_from_slice(&exchange.response);
+        Ok(&receive_buffer[..exchange.response.len()])
+    }
+
+    fn reconnect(&mut self) -> Result<(), crate::transport::TransportError> {
+        Ok(())
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, crate::transport::TransportError> {
+        atr(&self.atr)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Selected {
+    Nothing,
+    Directory,
+    Application(usize),
+}
+
+/// Answers SELECT, GET PROCESSING OPTIONS, READ RECORD and GET DATA from a
+/// card dump. There's nothing to answer anything else with, so a transaction
+/// goes as far as the first GENERATE AC.
+pub struct DumpCard<'a> {
+    dump: &'a crate::dump::CardDump,
+    selected: std::cell::Cell<Selected>,
+    unanswered: std::cell::RefCell<Option<Vec<u8>>>,
+}
+
+impl<'a> DumpCard<'a> {
+    pub fn new(dump: &'a crate::dump::CardDump) -> Self {
+        Self {
+            dump,
+            selected: std::cell::Cell::new(Selected::Nothing),
+            unanswered: std::cell::RefCell::new(None),
+        }
+    }
+
+    /// The first command the dump had nothing to answer with, if there was one
+    pub fn unanswered(&self) -> Option<Vec<u8>> {
+        self.unanswered.borrow().clone()
+    }
+
+    fn records(&self) -> Vec<&crate::dump::RecordDump> {
+        match self.selected.get() {
+            Selected::Nothing => vec![],
+            Selected::Directory => self.dump.pse.iter().flat_map(|p| &p.records).collect(),
+            Selected::Application(i) => self.dump.applications[i].records.iter().chain(&self.dump.applications[i].log).collect(),
+        }
+    }
+}
+
+impl crate::transport::CardTransport for DumpCard<'_> {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], crate::transport::TransportError> {
+        if send_buffer.len() < 4 {
+            return respond(&[], [0x67, 0x00], receive_buffer);
+        }
+        let (ins, p1, p2) = (send_buffer[1], send_buffer[2], send_buffer[3]);
+        let data = match send_buffer.get(4) {
+            Some(lc) if send_buffer.len() >= 5 + *lc as usize => &send_buffer[5..5 + *lc as usize],
+            _ => &[]
+        };
+        match ins {
+            // SELECT by name
+            0xa4 => {
+                if let Some(pse) = self.dump.pse.as_ref().filter(|p| p.name == data) {
+                    self.selected.set(Selected::Directory);
+                    return respond(&pse.fci, [0x90, 0x00], receive_buffer);
+                }
+                match self.dump.applications.iter().position(|a| a.aid == data) {
+                    Some(i) => {
+                        self.selected.set(Selected::Application(i));
+                        respond(&self.dump.applications[i].fci, [0x90, 0x00], receive_buffer)
+                    }
+                    None => respond(&[], [0x6a, 0x82], receive_buffer)
+                }
+            }
+            0xa8 => match self.selected.get() {
+                Selected::Application(i) => match &self.dump.applications[i].processing_options {
+                    Some(po) => respond(po, [0x90, 0x00], receive_buffer),
+                    None => respond(&[], [0x69, 0x85], receive_buffer)
+                },
+                _ => respond(&[], [0x69, 0x85], receive_buffer)
+            },
+            0xb2 => match self.records().iter().find(|r| r.record == p1 && r.sfi == p2 >> 3) {
+                Some(r) => respond(&r.data, [0x90, 0x00], receive_buffer),
+                None => respond(&[], [0x6a, 0x83], receive_buffer)
+            },
+            0xca => {
+                let data_object = match self.selected.get() {
+                    Selected::Application(i) => self.dump.applications[i].data_objects.iter().find(|d| d.tag == [p1, p2]),
+                    _ => None
+                };
+                match data_object {
+                    Some(d) => respond(&d.data, [0x90, 0x00], receive_buffer),
+                    None => respond(&[], [0x6a, 0x88], receive_buffer)
+                }
+            }
+            _ => {
+                self.unanswered.borrow_mut().get_or_insert_with(|| send_buffer.to_vec());
+                respond(&[], [0x6d, 0x00], receive_buffer)
+            }
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<(), crate::transport::TransportError> {
+        self.selected.set(Selected::Nothing);
+        Ok(())
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, crate::transport::TransportError> {
+        atr(&self.dump.atr)
+    }
+}
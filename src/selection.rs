@@ -0,0 +1,649 @@
+use std::convert::TryFrom;
+use crate::card;
+use crate::transport::CardTransport;
+
+/// The applications the terminal supports out of the box, with a name for
+/// each
+const KNOWN_AIDS: [(&[u8], &str); 23] = [
+    (&[0xa0, 0x00, 0x00, 0x00, 0x04, 0x10, 0x10], "Mastercard"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x04, 0x30, 0x60], "Maestro"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x04, 0x60, 0x00], "Cirrus"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x04, 0x22, 0x03], "Maestro US Common Debit"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10], "Visa"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x20, 0x10], "Visa Electron"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x20, 0x20], "V PAY"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x30, 0x10], "Visa Interlink"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x80, 0x10], "Visa Plus"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x98, 0x08, 0x40], "Visa US Common Debit"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x25, 0x01], "American Express"),
+    (&[0xa0, 0x00, 0x00, 0x01, 0x52, 0x30, 0x10], "Discover"),
+    (&[0xa0, 0x00, 0x00, 0x01, 0x52, 0x40, 0x10], "Discover US Common Debit"),
+    (&[0xa0, 0x00, 0x00, 0x03, 0x24, 0x10, 0x10], "Discover ZIP"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x65, 0x10, 0x10], "JCB"),
+    (&[0xa0, 0x00, 0x00, 0x03, 0x33, 0x01, 0x01, 0x01], "UnionPay Debit"),
+    (&[0xa0, 0x00, 0x00, 0x03, 0x33, 0x01, 0x01, 0x02], "UnionPay Credit"),
+    (&[0xa0, 0x00, 0x00, 0x03, 0x33, 0x01, 0x01, 0x03], "UnionPay Quasi-credit"),
+    (&[0xa0, 0x00, 0x00, 0x03, 0x33, 0x01, 0x01, 0x06], "UnionPay Electronic Cash"),
+    (&[0xa0, 0x00, 0x00, 0x02, 0x77, 0x10, 0x10], "Interac"),
+    (&[0xa0, 0x00, 0x00, 0x00, 0x42, 0x10, 0x10], "Cartes Bancaires"),
+    (&[0xa0, 0x00, 0x00, 0x03, 0x59, 0x10, 0x10, 0x02, 0x80, 0x01], "girocard"),
+    (&[0xa0, 0x00, 0x00, 0x01, 0x41, 0x00, 0x01], "Pagobancomat"),
+];
+
+/// An entry in the terminal's list of supported applications
+#[derive(Debug, Clone)]
+pub struct TerminalApplication {
+    pub aid: Vec<u8>,
+    /// Application Selection Indicator, set when card AIDs that only start
+    /// with this AID should match too
+    pub partial_selection: bool,
+    /// Terminal Floor Limit, above which transactions should go online
+    pub floor_limit: u64,
+    pub random_selection: crate::terminal::RandomSelection,
+    pub action_codes: crate::terminal::TerminalActionCodes,
+    /// Whether the issuer must always send Issuer Authentication Data online
+    pub issuer_authentication_mandatory: bool,
+    /// Contactless kernel to use with the application, 0 for the default
+    /// kernel for its RID
+    pub kernel_id: u8,
+    pub reader_limits: crate::entry_point::ReaderLimits,
+}
+
+impl TerminalApplication {
+    pub fn new(aid: &[u8]) -> Self {
+        Self {
+            aid: aid.to_vec(),
+            partial_selection: true,
+            floor_limit: 0,
+            random_selection: Default::default(),
+            action_codes: Default::default(),
+            issuer_authentication_mandatory: false,
+            kernel_id: 0,
+            reader_limits: Default::default(),
+        }
+    }
+
+    pub fn matches(&self, card_aid: &[u8]) -> bool {
+        if self.partial_selection {
+            card_aid.starts_with(&self.aid)
+        } else {
+            crate::util::compare_slice(&self.aid, card_aid)
+        }
+    }
+}
+
+pub fn default_terminal_applications() -> Vec<TerminalApplication> {
+    KNOWN_AIDS.iter().map(|(aid, _)| TerminalApplication::new(aid)).collect()
+}
+
+/// The name of a built-in application the card AID falls under, going by the
+/// longest match
+pub fn application_name(aid: &[u8]) -> Option<&'static str> {
+    KNOWN_AIDS.iter()
+        .filter(|(known, _)| aid.starts_with(known))
+        .max_by_key(|(known, _)| known.len())
+        .map(|(_, name)| *name)
+}
+
+/// Narrows the terminal's applications down to just those given, each taking
+/// the parameters of the entry in `applications` it falls under, if any
+pub fn restrict_applications(applications: &[TerminalApplication], only: &[TerminalApplication]) -> Vec<TerminalApplication> {
+    only.iter().map(|o| match applications.iter().find(|a| a.matches(&o.aid)) {
+        Some(a) => TerminalApplication {
+            aid: o.aid.clone(),
+            partial_selection: o.partial_selection,
+            ..a.clone()
+        },
+        None => o.clone()
+    }).collect()
+}
+
+/// Field names are matched ignoring case and any separators, as with CA
+/// public key files
+fn normalise_field(name: &str) -> String {
+    name.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+fn parse_bool_field(name: &str, value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "y" => Ok(true),
+        "0" | "false" | "no" | "n" => Ok(false),
+        v => Err(format!("Invalid {}: {}", name, v))
+    }
+}
+
+fn parse_number_field<N: std::str::FromStr>(name: &str, value: &str) -> Result<N, String> {
+    value.trim().parse().map_err(|_| format!("Invalid {}: {}", name, value))
+}
+
+fn parse_action_code(name: &str, value: &str) -> Result<[u8; 5], String> {
+    crate::hex::decode(value.trim())
+        .and_then(|c| std::convert::TryInto::try_into(c.as_slice()).ok())
+        .ok_or_else(|| format!("{} must be 5 bytes of hex: {}", name, value))
+}
+
+/// Builds an application from named fields, however the file format supplies
+/// them. Only the AID is needed, with the rest as [`TerminalApplication::new`]
+/// has them. Amounts are in minor units.
+fn application_from_fields<F: Fn(&str) -> Option<String>>(field: F) -> Result<TerminalApplication, String> {
+    let any = |names: &[&str]| names.iter().find_map(|n| field(n)).filter(|v| !v.trim().is_empty());
+
+    let aid = any(&["aid"]).ok_or("Application has no AID")?;
+    let mut application = match crate::hex::decode(aid.trim()) {
+        Some(a) if (5..=16).contains(&a.len()) => TerminalApplication::new(&a),
+        _ => return Err(format!("AID must be 5 to 16 bytes of hex: {}", aid))
+    };
+    if let Some(v) = any(&["partialselection", "partial", "asi"]) {
+        application.partial_selection = parse_bool_field("partial selection", &v)?;
+    }
+    if let Some(v) = any(&["floorlimit"]) {
+        application.floor_limit = parse_number_field("floor limit", &v)?;
+    }
+    if let Some(v) = any(&["targetpercentage"]) {
+        application.random_selection.target_percentage = parse_number_field("target percentage", &v)?;
+    }
+    if let Some(v) = any(&["maxtargetpercentage", "maximumtargetpercentage"]) {
+        application.random_selection.max_target_percentage = parse_number_field("maximum target percentage", &v)?;
+    }
+    if let Some(v) = any(&["threshold", "thresholdvalue"]) {
+        application.random_selection.threshold = parse_number_field("threshold", &v)?;
+    }
+    if let Some(v) = any(&["tacdenial"]) {
+        application.action_codes.denial = parse_action_code("TAC denial", &v)?;
+    }
+    if let Some(v) = any(&["taconline"]) {
+        application.action_codes.online = parse_action_code("TAC online", &v)?;
+    }
+    if let Some(v) = any(&["tacdefault"]) {
+        application.action_codes.default = parse_action_code("TAC default", &v)?;
+    }
+    if let Some(v) = any(&["issuerauthenticationmandatory"]) {
+        application.issuer_authentication_mandatory = parse_bool_field("issuer authentication mandatory", &v)?;
+    }
+    if let Some(v) = any(&["kernelid", "kernel"]) {
+        application.kernel_id = parse_number_field("kernel ID", &v)?;
+    }
+    let limits = &mut application.reader_limits;
+    if let Some(v) = any(&["contactlesstransactionlimit", "transactionlimit"]) {
+        limits.transaction_limit = Some(parse_number_field("contactless transaction limit", &v)?);
+    }
+    if let Some(v) = any(&["contactlessfloorlimit"]) {
+        limits.floor_limit = Some(parse_number_field("contactless floor limit", &v)?);
+    }
+    if let Some(v) = any(&["cvmrequiredlimit", "cvmlimit"]) {
+        limits.cvm_required_limit = Some(parse_number_field("CVM required limit", &v)?);
+    }
+    if let Some(v) = any(&["statuschecksupported", "statuscheck"]) {
+        limits.status_check_supported = parse_bool_field("status check", &v)?;
+    }
+    if let Some(v) = any(&["zeroamountallowed", "zeroamount"]) {
+        limits.zero_amount_allowed = parse_bool_field("zero amount allowed", &v)?;
+    }
+    Ok(application)
+}
+
+/// Reads the terminal's applications from JSON: an array of objects, or an
+/// object holding one
+pub fn parse_applications_json(data: &str) -> Result<Vec<TerminalApplication>, String> {
+    let value: serde_json::Value = serde_json::from_str(data).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let applications = match &value {
+        serde_json::Value::Array(a) => a,
+        serde_json::Value::Object(o) => match o.values().find_map(|v| v.as_array()) {
+            Some(a) => a,
+            None => return Err("No array of applications in JSON".to_string())
+        },
+        _ => return Err("No array of applications in JSON".to_string())
+    };
+    applications.iter().map(|a| {
+        let a = a.as_object().ok_or_else(|| "Application isn't a JSON object".to_string())?;
+        application_from_fields(|name| {
+            a.iter().find(|(f, _)| normalise_field(f) == name).and_then(|(_, v)| match v {
+                serde_json::Value::String(s) => Some(s.to_string()),
+                serde_json::Value::Number(n) => Some(n.to_string()),
+                serde_json::Value::Bool(b) => Some(b.to_string()),
+                _ => None
+            })
+        })
+    }).collect()
+}
+
+/// Reads the terminal's applications from CSV, with columns named by a header
+/// row or otherwise just the AID and whether it allows partial selection.
+/// Blank lines and lines starting `#` are skipped.
+pub fn parse_applications_csv(data: &str) -> Result<Vec<TerminalApplication>, String> {
+    let mut columns: Vec<String> = ["aid", "partialselection"].iter().map(|c| c.to_string()).collect();
+    let mut applications = vec![];
+
+    let lines = data.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    for (i, line) in lines.enumerate() {
+        let values: Vec<&str> = line.split([',', ';'])
+            .map(|v| v.trim().trim_matches('"'))
+            .collect();
+        if i == 0 && values.iter().any(|v| normalise_field(v) == "aid") {
+            columns = values.iter().map(|v| normalise_field(v)).collect();
+            continue;
+        }
+        applications.push(application_from_fields(|name| {
+            columns.iter().position(|c| c == name).and_then(|p| values.get(p)).map(|v| v.to_string())
+        })?);
+    }
+
+    Ok(applications)
+}
+
+/// Reads the terminal's applications from a JSON or CSV file, going by its
+/// extension
+pub fn load_applications(path: &std::path::Path) -> Result<Vec<TerminalApplication>, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    match crate::capk::ImportFormat::from_path(path) {
+        Some(crate::capk::ImportFormat::Json) => parse_applications_json(&data),
+        Some(crate::capk::ImportFormat::Csv) => parse_applications_csv(&data),
+        _ => Err(format!("Unknown application file format: {}", path.display()))
+    }
+}
+
+pub fn get_pse_sfi<T: CardTransport + ?Sized>(card: &T) -> Result<Option<u8>, crate::card::CardError> {
+    let select_resp = match card::card_select(card, card::Interface::Contact.directory_name(), false) {
+        Ok(r) => r,
+        Err(e) if card::is_card_removed(&e) || card::is_cancelled(&e) => return Err(e),
+        Err(_) => return Ok(None)
+    };
+    let fcipt = match select_resp.get_tag(crate::tlv::TagID::FileControlInformationTemplate)
+        .and_then(|fci| fci.get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate))
+        .and_then(|fcipt| fcipt.get_tag(crate::tlv::TagID::ShortFileIdentifier)) {
+        Some(t) => t,
+        None => return Ok(None)
+    };
+    // A malformed SFI is as good as no PSE, leaving the list of AIDs to try
+    match fcipt.contents() {
+        crate::tlv::TagContents::Byte(b) => Ok(Some(*b)),
+        _ => Ok(None)
+    }
+}
+
+/// Whether the chip is working, going by whether it understands a SELECT of
+/// the PSE even if it has no PSE. A card without any candidates that passes
+/// this just has nothing the terminal accepts, so there's no fallback.
+pub fn chip_responds<T: CardTransport + ?Sized>(card: &T) -> Result<bool, crate::card::CardError> {
+    match card::card_select_response(card, card::Interface::Contact.directory_name(), false) {
+        Ok(r) => Ok(matches!(r.status(), (0x90, 0x00) | (0x62, 0x83) | (0x6a, 0x81) | (0x6a, 0x82))),
+        Err(e) if card::is_card_removed(&e) || card::is_card_reset(&e) || card::is_cancelled(&e) => Err(e),
+        Err(_) => Ok(false)
+    }
+}
+
+/// Every application template in the PSE directory records
+pub fn read_pse_entries<T: CardTransport + ?Sized>(card: &T, sfi: u8) -> Result<Vec<crate::tlv::Tag>, crate::card::CardError> {
+    let mut entries = vec![];
+
+    let mut i = 1;
+    loop {
+        let record_result = card::card_read_record(card, sfi, i);
+        match record_result {
+            Ok(r) => {
+                if let Some(record) = r.get_tag(crate::tlv::TagID::ReadRecordResponseMessageTemplate) {
+                    entries.extend(record.get_tags(crate::tlv::TagID::ApplicationTemplate).into_iter().cloned());
+                }
+            }
+            Err(e) if card::is_card_removed(&e) || card::is_cancelled(&e) => return Err(e),
+            Err(_) => break
+        }
+        i += 1;
+    }
+
+    Ok(entries)
+}
+
+/// Every application template in the PPSE, which carries its directory
+/// entries in the FCI rather than in records
+pub fn read_ppse_entries<T: CardTransport + ?Sized>(card: &T) -> Result<Option<Vec<crate::tlv::Tag>>, crate::card::CardError> {
+    let select_resp = match card::card_select(card, card::Interface::Contactless.directory_name(), false) {
+        Ok(r) => r,
+        Err(e) if card::is_card_removed(&e) || card::is_cancelled(&e) => return Err(e),
+        Err(_) => return Ok(None)
+    };
+    let directory = match select_resp.get_tag(crate::tlv::TagID::FileControlInformationTemplate)
+        .and_then(|fci| fci.get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate))
+        .and_then(|fcipt| fcipt.get_tag(crate::tlv::TagID::FileControlInformationIssuerDiscretionaryData)) {
+        Some(d) => d,
+        None => return Ok(None)
+    };
+    Ok(Some(directory.get_tags(crate::tlv::TagID::ApplicationTemplate).into_iter().cloned().collect()))
+}
+
+/// How deep DDFs can nest before the rest are left unread, well beyond
+/// anything a real card has
+const MAX_DIRECTORY_DEPTH: usize = 8;
+
+/// An entry in a payment system directory, pointing at an application or at
+/// another directory
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    /// The ADF Name, or DDF Name for a directory
+    pub name: Vec<u8>,
+    pub label: Option<String>,
+    /// The Application Preferred Name, where its code table is one the
+    /// terminal can decode
+    pub preferred_name: Option<String>,
+    pub priority: Option<crate::data::ApplicationPriorityIndicator>,
+    pub language_preference: Option<crate::language::LanguagePreference>,
+    pub kernel_identifier: Option<Vec<u8>>,
+    /// The directory a DDF entry leads to, if it could be selected
+    pub directory: Option<Directory>,
+    /// Whether the entry is for a DDF rather than an application
+    pub ddf: bool,
+}
+
+/// A payment system directory and every entry in it, with any nested DDFs
+/// read in turn
+#[derive(Debug, Clone)]
+pub struct Directory {
+    pub name: Vec<u8>,
+    pub language_preference: Option<crate::language::LanguagePreference>,
+    pub issuer_code_table_index: Option<u8>,
+    pub entries: Vec<DirectoryEntry>,
+}
+
+fn language_tag(tag: &crate::tlv::Tag) -> Option<crate::language::LanguagePreference> {
+    crate::language::LanguagePreference::parse(&bytes_tag(tag, crate::tlv::TagID::LanguagePreference)?)
+}
+
+fn string_tag(tag: &crate::tlv::Tag, id: crate::tlv::TagID) -> Option<String> {
+    match tag.get_tag(id)?.contents() {
+        crate::tlv::TagContents::String(s) => Some(s.to_string()),
+        _ => None
+    }
+}
+
+fn byte_tag(tag: &crate::tlv::Tag, id: crate::tlv::TagID) -> Option<u8> {
+    match tag.get_tag(id)?.contents() {
+        crate::tlv::TagContents::Byte(b) => Some(*b),
+        _ => None
+    }
+}
+
+fn bytes_tag(tag: &crate::tlv::Tag, id: crate::tlv::TagID) -> Option<Vec<u8>> {
+    tag.get_tag(id).map(|t| Vec::<u8>::from(t.contents()))
+}
+
+impl DirectoryEntry {
+    fn parse(entry: &crate::tlv::Tag, issuer_code_table_index: Option<u8>) -> Option<Self> {
+        let (name, ddf) = match bytes_tag(entry, crate::tlv::TagID::ApplicationDedicatedFileName) {
+            Some(n) => (n, false),
+            None => (bytes_tag(entry, crate::tlv::TagID::DirectoryDefinitionFileName)?, true)
+        };
+        let code_table = byte_tag(entry, crate::tlv::TagID::IssuerCodeTableIndex).or(issuer_code_table_index);
+        let preferred_name = bytes_tag(entry, crate::tlv::TagID::ApplicationPreferredName)
+            .zip(code_table)
+            .and_then(|(n, i)| crate::util::code_table_index_decode(&n, i));
+        Some(Self {
+            name,
+            label: string_tag(entry, crate::tlv::TagID::ApplicationLabel),
+            preferred_name,
+            priority: entry.get_tag(crate::tlv::TagID::ApplicationPriorityIndicator)
+                .and_then(|p| crate::data::ApplicationPriorityIndicator::try_from(p).ok()),
+            language_preference: language_tag(entry),
+            kernel_identifier: bytes_tag(entry, crate::tlv::TagID::KernelIdentifier),
+            directory: None,
+            ddf,
+        })
+    }
+}
+
+fn read_directory<T: CardTransport + ?Sized>(card: &T, name: &[u8], depth: usize, seen: &mut Vec<Vec<u8>>) -> Result<Option<Directory>, crate::card::CardError> {
+    if depth > MAX_DIRECTORY_DEPTH || seen.iter().any(|s| crate::util::compare_slice(s, name)) {
+        return Ok(None);
+    }
+    seen.push(name.to_vec());
+    let fci = match card::optional(card::card_select(card, name, false))? {
+        Some(f) => f,
+        None => return Ok(None)
+    };
+    let fcipt = match fci.get_tag(crate::tlv::TagID::FileControlInformationTemplate)
+        .and_then(|fci| fci.get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate)) {
+        Some(f) => f,
+        None => return Ok(None)
+    };
+    let issuer_code_table_index = byte_tag(fcipt, crate::tlv::TagID::IssuerCodeTableIndex);
+
+    // The PPSE lists its entries in the FCI, the PSE and DDFs in records
+    let mut templates: Vec<crate::tlv::Tag> = fcipt.get_tag(crate::tlv::TagID::FileControlInformationIssuerDiscretionaryData)
+        .map(|d| d.get_tags(crate::tlv::TagID::ApplicationTemplate).into_iter().cloned().collect())
+        .unwrap_or_default();
+    if let Some(sfi) = byte_tag(fcipt, crate::tlv::TagID::ShortFileIdentifier) {
+        templates.extend(read_pse_entries(card, sfi)?);
+    }
+
+    let mut entries = vec![];
+    for template in &templates {
+        if let Some(entry) = DirectoryEntry::parse(template, issuer_code_table_index) {
+            entries.push(entry);
+        }
+    }
+    for entry in entries.iter_mut().filter(|e| e.ddf) {
+        entry.directory = read_directory(card, &entry.name, depth + 1, seen)?;
+    }
+
+    Ok(Some(Directory {
+        name: name.to_vec(),
+        language_preference: language_tag(fcipt),
+        issuer_code_table_index,
+        entries,
+    }))
+}
+
+/// Walks the PSE, or PPSE when contactless, and every DDF it leads to,
+/// without selecting any application. None if the card has no directory.
+pub fn read_directory_tree<T: CardTransport + ?Sized>(card: &T, interface: card::Interface) -> Result<Option<Directory>, crate::card::CardError> {
+    read_directory(card, interface.directory_name(), 0, &mut vec![])
+}
+
+/// A file name as ASCII where it's all printable, as DDF names tend to be,
+/// otherwise in hex
+fn file_name(name: &[u8]) -> String {
+    match std::str::from_utf8(name) {
+        Ok(s) if !s.is_empty() && s.chars().all(|c| c.is_ascii_graphic()) => format!("{} ({})", s, crate::hex::Hex(name)),
+        _ => crate::hex::encode(name)
+    }
+}
+
+impl Directory {
+    /// Everything in the directory, indented under its heading
+    fn write_contents(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+        let pad = " ".repeat(indent);
+        if let Some(l) = &self.language_preference {
+            writeln!(f, "{}  Language Preference: {}", pad, l)?;
+        }
+        if let Some(i) = self.issuer_code_table_index {
+            writeln!(f, "{}  Issuer Code Table Index: {}", pad, i)?;
+        }
+        for entry in &self.entries {
+            match entry.ddf {
+                true => writeln!(f, "{}  Directory {}", pad, file_name(&entry.name))?,
+                false => match application_name(&entry.name) {
+                    Some(n) => writeln!(f, "{}  Application {} ({})", pad, crate::hex::Hex(&entry.name), n)?,
+                    None => writeln!(f, "{}  Application {}", pad, crate::hex::Hex(&entry.name))?
+                }
+            }
+            if let Some(l) = &entry.label {
+                writeln!(f, "{}    Label: {}", pad, l)?;
+            }
+            if let Some(n) = &entry.preferred_name {
+                writeln!(f, "{}    Preferred Name: {}", pad, n)?;
+            }
+            if let Some(p) = &entry.priority {
+                match (p.priority(), p.auto_selection_allowed()) {
+                    (None, true) => writeln!(f, "{}    Priority: none", pad)?,
+                    (None, false) => writeln!(f, "{}    Priority: none, cardholder confirmation required", pad)?,
+                    (Some(n), true) => writeln!(f, "{}    Priority: {}", pad, n)?,
+                    (Some(n), false) => writeln!(f, "{}    Priority: {}, cardholder confirmation required", pad, n)?
+                }
+            }
+            if let Some(l) = &entry.language_preference {
+                writeln!(f, "{}    Language Preference: {}", pad, l)?;
+            }
+            if let Some(k) = &entry.kernel_identifier {
+                writeln!(f, "{}    Kernel Identifier: {}", pad, crate::hex::Hex(k))?;
+            }
+            match &entry.directory {
+                Some(d) => d.write_contents(f, indent + 2)?,
+                None if entry.ddf => writeln!(f, "{}    Unable to select the directory", pad)?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Directory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Directory {}", file_name(&self.name))?;
+        self.write_contents(f, 0)
+    }
+}
+
+fn directory_candidates(entries: &[crate::tlv::Tag], terminal_applications: &[TerminalApplication]) -> Vec<crate::data::Application> {
+    entries.iter()
+        .filter_map(|e| crate::data::Application::try_from(e).ok())
+        .filter(|a| terminal_applications.iter().any(|t| t.matches(a.aid())))
+        .collect()
+}
+
+/// Book 1 12.3.3: SELECT each terminal AID in turn, following up partial
+/// matches with SELECT next until the card runs out of matching applications
+fn list_of_aids_candidates<T: CardTransport + ?Sized>(card: &T, terminal_applications: &[TerminalApplication]) -> Result<Vec<crate::data::Application>, crate::card::CardError> {
+    let mut candidates: Vec<crate::data::Application> = vec![];
+
+    for terminal_application in terminal_applications {
+        let mut next = false;
+        loop {
+            let resp = card::card_select_response(card, &terminal_application.aid, next)?;
+            next = true;
+            let blocked = match resp.status() {
+                (0x90, 0x00) => false,
+                (0x62, 0x83) => true,
+                _ => break
+            };
+            let fci = match crate::tlv::TagList::try_from(resp.data()).ok()
+                .and_then(|tl| tl.get_tag(crate::tlv::TagID::FileControlInformationTemplate).cloned()) {
+                Some(f) => f,
+                None => break
+            };
+            let df_name = match fci.get_tag(crate::tlv::TagID::DedicatedFileName).map(|t| t.contents()) {
+                Some(crate::tlv::TagContents::Bytes(b)) => b.to_owned(),
+                _ => break
+            };
+            if candidates.iter().any(|c| crate::util::compare_slice(c.aid(), &df_name)) {
+                break;
+            }
+
+            if !blocked && terminal_application.matches(&df_name) {
+                let fcipt = fci.get_tag(crate::tlv::TagID::FileControlInformationProprietaryTemplate);
+                if let Some(application) = fcipt.and_then(|f| crate::data::Application::from_fci(&df_name, f).ok()) {
+                    candidates.push(application);
+                }
+            }
+            if !terminal_application.partial_selection {
+                break;
+            }
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Builds the list of applications supported by both card and terminal. The
+/// card's directory is used where it has one, falling back to trying each
+/// terminal AID in turn over the contact interface. Blocked applications are
+/// left out.
+pub fn build_candidate_list<T: CardTransport + ?Sized>(card: &T, interface: card::Interface, terminal_applications: &[TerminalApplication]) -> Result<Vec<crate::data::Application>, crate::card::CardError> {
+    let entries = match interface {
+        card::Interface::Contact => match get_pse_sfi(card)? {
+            Some(sfi) => Some(read_pse_entries(card, sfi)?),
+            None => None
+        },
+        card::Interface::Contactless => read_ppse_entries(card)?
+    };
+
+    if let Some(entries) = entries {
+        let candidates = directory_candidates(&entries, terminal_applications);
+        if !candidates.is_empty() {
+            return Ok(candidates);
+        }
+    }
+
+    match interface {
+        card::Interface::Contact => list_of_aids_candidates(card, terminal_applications),
+        card::Interface::Contactless => Ok(vec![])
+    }
+}
+
+/// Orders candidates by priority, highest first and those with no priority
+/// last, keeping the card's own order otherwise
+pub fn sort_candidates(candidates: &mut [crate::data::Application]) {
+    sort_by_priority(candidates, crate::data::Application::priority);
+}
+
+/// Orders anything with an application in it by that application's priority,
+/// as [`sort_candidates`] does
+pub fn sort_by_priority<T>(items: &mut [T], priority: impl Fn(&T) -> &crate::data::ApplicationPriorityIndicator) {
+    items.sort_by(|a, b| priority(b).cmp(priority(a)));
+}
+
+/// The terminal's entry for a card application
+pub fn find_terminal_application<'a>(terminal_applications: &'a [TerminalApplication], card_aid: &[u8]) -> Option<&'a TerminalApplication> {
+    terminal_applications.iter().find(|t| t.matches(card_aid))
+}
+
+/// Lets the cardholder pick from the candidates. Picking an application from
+/// the menu is itself the confirmation any of them might require.
+pub fn choose_from_menu(candidates: &[crate::data::Application], language: crate::language::Language) -> Option<&crate::data::Application> {
+    use crate::language::Message;
+    println!("{}:", language.message(Message::AvailableApplications));
+    for (i, c) in candidates.iter().enumerate() {
+        println!("  [{}] {}", i + 1, c.name());
+    }
+    println!("  [0] {}", language.message(Message::Cancel));
+
+    loop {
+        let i = crate::util::get_input::<usize>(&format!("{}: ", language.message(Message::SelectApplication)));
+        if i == 0 {
+            return None;
+        }
+        if let Some(c) = candidates.get(i - 1) {
+            return Some(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn loads_terminal_applications() {
+        let applications = crate::selection::parse_applications_csv("AID,Partial Selection,Floor Limit,TAC Online,Kernel ID
+# Visa credit and debit only
+A0000000031010,no,5000,DC4004F800,3
+A000000004,yes,,,2
+").unwrap();
+        assert_eq!(applications.len(), 2);
+        assert!(!applications[0].partial_selection);
+        assert_eq!(applications[0].floor_limit, 5000);
+        assert_eq!(applications[0].action_codes.online, [0xdc, 0x40, 0x04, 0xf8, 0x00]);
+        assert_eq!(applications[1].kernel_id, 2);
+        assert!(applications[1].matches(&[0xa0, 0x00, 0x00, 0x00, 0x04, 0x10, 0x10]));
+        assert!(!applications[0].matches(&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10, 0x01]));
+        assert!(crate::selection::parse_applications_csv("A000,yes").is_err());
+
+        let applications = crate::selection::parse_applications_json(r#"{"applications": [
+            {"aid": "A000000003", "floor_limit": 2000, "cvm_required_limit": 3000, "status_check": true}
+        ]}"#).unwrap();
+        assert_eq!(applications[0].floor_limit, 2000);
+        assert_eq!(applications[0].reader_limits.cvm_required_limit, Some(3000));
+        assert!(applications[0].reader_limits.status_check_supported);
+
+        // Narrowing down to one AID keeps the parameters of the entry it's under
+        let only = crate::selection::restrict_applications(&applications, &[crate::selection::TerminalApplication::new(&[0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10])]);
+        assert_eq!(only[0].aid, vec![0xa0, 0x00, 0x00, 0x00, 0x03, 0x10, 0x10]);
+        assert_eq!(only[0].floor_limit, 2000);
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the socket is made when not told otherwise
+pub const DEFAULT_SOCKET: &str = "emv-term.sock";
+
+/// What a point of sale can ask of the terminal, one JSON object per line
+/// tagged by `command`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Whether there's a card in the reader, without waiting for one
+    DetectCard,
+    /// Everything readable from the card, as `--dump` writes it
+    ReadCard,
+    /// A transaction for `amount` in minor units, not including `cashback`
+    Transaction {
+        amount: u64,
+        #[serde(default)]
+        cashback: u64,
+        /// purchase, cash, cashback or refund; purchase if not given
+        #[serde(default, rename = "type")]
+        transaction_type: Option<String>,
+    },
+}
+
+/// The answer to a request, one JSON object per line tagged by `status`
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Card {
+        present: bool,
+        reader: Option<String>,
+        #[serde(serialize_with = "crate::dump::as_optional_hex")]
+        atr: Option<Vec<u8>>,
+        interface: Option<String>,
+    },
+    CardData(crate::dump::CardDump),
+    Outcome(Box<crate::transaction::TransactionOutcome>),
+    Error {
+        message: String,
+    },
+}
+
+impl Response {
+    pub fn error(message: impl std::fmt::Display) -> Self {
+        Response::Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Answers requests from one client until it hangs up. Lines that aren't a
+/// request get an error back rather than ending the connection.
+pub fn handle_connection<R: std::io::BufRead, W: std::io::Write, H: FnMut(Request) -> Response>(reader: R, mut writer: W, mut handler: H) -> std::io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => handler(request),
+            Err(e) => Response::error(format!("Invalid request: {}", e))
+        };
+        let mut out = serde_json::to_vec(&response)?;
+        out.push(b'\n');
+        writer.write_all(&out)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Listens on a Unix socket, serving one client at a time as there's only the
+/// one reader to share
+#[cfg(unix)]
+pub fn serve<H: FnMut(Request) -> Response>(path: &std::path::Path, mut handler: H) -> std::io::Result<()> {
+    // A socket left behind by an earlier run would stop the bind
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e)
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    tracing::info!("Listening on {}", path.display());
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let reader = std::io::BufReader::new(stream.try_clone()?);
+        if let Err(e) = handle_connection(reader, &stream, &mut handler) {
+            tracing::warn!("Connection failed: {}", e);
+        }
+    }
+    Ok(())
+}
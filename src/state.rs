@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the terminal keeps its state when not told otherwise
+pub const DEFAULT_STATE_FILE: &str = "emv-term-state.json";
+
+/// Book 4 says the Transaction Sequence Counter is up to eight digits
+const MAX_SEQUENCE_COUNTER: u32 = 99_999_999;
+
+/// What the terminal remembers from one run to the next
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TerminalState {
+    /// The last Transaction Sequence Counter used
+    pub transaction_sequence_counter: u32,
+    /// The last DUKPT Key Serial Number used, in hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dukpt_ksn: Option<String>,
+}
+
+impl TerminalState {
+    /// Reads the state, starting afresh if there's none saved yet
+    pub fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Takes the next Transaction Sequence Counter, going back to 1 rather
+    /// than 0 after the largest, as issuers expect it never to be zero
+    pub fn next_sequence_counter(&mut self) -> u32 {
+        self.transaction_sequence_counter = match self.transaction_sequence_counter {
+            c if c >= MAX_SEQUENCE_COUNTER => 1,
+            c => c + 1
+        };
+        self.transaction_sequence_counter
+    }
+
+    /// Takes the next DUKPT Key Serial Number for a key injected at
+    /// `injected`, carrying on from the last one used if it was the same key.
+    /// None once the key is used up.
+    pub fn next_ksn(&mut self, injected: &[u8; 10]) -> Option<[u8; 10]> {
+        let last = self.dukpt_ksn.as_deref()
+            .and_then(crate::hex::decode)
+            .and_then(|k| std::convert::TryInto::<[u8; 10]>::try_into(k).ok())
+            .filter(|k| crate::dukpt::initial_ksn(k) == crate::dukpt::initial_ksn(injected) && k > injected)
+            .unwrap_or(*injected);
+        let next = crate::dukpt::next_ksn(&last)?;
+        self.dukpt_ksn = Some(crate::hex::encode(&next));
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn keeps_transaction_sequence_counter() {
+        let path = std::env::temp_dir().join(format!("emv-term-state-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut state = crate::state::TerminalState::load(&path).unwrap();
+        assert_eq!(state.next_sequence_counter(), 1);
+        state.save(&path).unwrap();
+
+        let mut state = crate::state::TerminalState::load(&path).unwrap();
+        assert_eq!(state.next_sequence_counter(), 2);
+        let mut terminal = crate::terminal::TerminalData::new(crate::card::Interface::Contact);
+        terminal.transaction_sequence_counter = state.transaction_sequence_counter;
+        assert_eq!(terminal.next_transaction().value(crate::tlv::TagID::TransactionSequenceCounter).unwrap(), vec![0, 0, 0, 0x02]);
+
+        state.transaction_sequence_counter = 99_999_999;
+        assert_eq!(state.next_sequence_counter(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
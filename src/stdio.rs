@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use crate::terminal::TransactionRequest;
+
+/// How often to look for a card while waiting for one, between checks for a
+/// cancel
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// What the ECR can tell the terminal, one JSON object per line on stdin tagged
+/// by `command`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Starts a transaction, asking for the amount with `amount_required` if
+    /// it isn't given
+    StartTransaction {
+        #[serde(default)]
+        amount: Option<u64>,
+        #[serde(default)]
+        cashback: u64,
+        /// purchase, cash, cashback or refund; purchase if not given
+        #[serde(default, rename = "type")]
+        transaction_type: Option<String>,
+    },
+    /// The amount asked for by `amount_required`, in minor units not including
+    /// `cashback`
+    ProvideAmount {
+        amount: u64,
+        #[serde(default)]
+        cashback: u64,
+    },
+    /// Abandons the transaction, so long as the card hasn't been read yet
+    Cancel,
+}
+
+/// What the terminal tells the ECR, one JSON object per line on stdout tagged
+/// by `event`
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Ready,
+    AmountRequired,
+    WaitingForCard,
+    CardDetected {
+        reader: String,
+        #[serde(serialize_with = "crate::dump::as_optional_hex")]
+        atr: Option<Vec<u8>>,
+        interface: String,
+    },
+    Outcome(Box<crate::transaction::TransactionOutcome>),
+    Cancelled,
+    Error {
+        message: String,
+    },
+}
+
+impl Event {
+    pub fn error(message: impl std::fmt::Display) -> Self {
+        Event::Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Idle,
+    AwaitingAmount {
+        transaction_type: Option<u8>,
+    },
+    AwaitingCard(TransactionRequest),
+}
+
+/// Where the ECR is up to, apart from the card
+#[derive(Debug)]
+pub struct Session {
+    state: State,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            state: State::Idle,
+        }
+    }
+
+    /// The transaction to run on the next card presented, if there's one
+    /// ready to go
+    pub fn awaiting_card(&self) -> Option<&TransactionRequest> {
+        match &self.state {
+            State::AwaitingCard(r) => Some(r),
+            _ => None
+        }
+    }
+
+    /// Goes back to idle once the transaction is over
+    pub fn finish(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Acts on a command, giving the events to send back
+    pub fn command(&mut self, command: Command) -> Vec<Event> {
+        match (&self.state, command) {
+            (State::Idle, Command::StartTransaction { amount, cashback, transaction_type }) => {
+                let transaction_type = match transaction_type.as_deref().map(|n| (n, crate::terminal::transaction_type_from_name(n))) {
+                    Some((_, Some(t))) => Some(t),
+                    Some((name, None)) => return vec![Event::error(format!("Unknown transaction type: {}", name))],
+                    None => None
+                };
+                match amount {
+                    Some(amount) => {
+                        self.state = State::AwaitingCard(TransactionRequest::new(transaction_type, amount, cashback));
+                        vec![Event::WaitingForCard]
+                    }
+                    None => {
+                        self.state = State::AwaitingAmount { transaction_type };
+                        vec![Event::AmountRequired]
+                    }
+                }
+            }
+            (_, Command::StartTransaction { .. }) => vec![Event::error("A transaction is already in progress")],
+            (State::AwaitingAmount { transaction_type }, Command::ProvideAmount { amount, cashback }) => {
+                self.state = State::AwaitingCard(TransactionRequest::new(*transaction_type, amount, cashback));
+                vec![Event::WaitingForCard]
+            }
+            (_, Command::ProvideAmount { .. }) => vec![Event::error("No amount was asked for")],
+            (State::Idle, Command::Cancel) => vec![Event::error("No transaction to cancel")],
+            (_, Command::Cancel) => {
+                self.state = State::Idle;
+                vec![Event::Cancelled]
+            }
+        }
+    }
+}
+
+/// Reads commands from `reader` on a thread of its own, so they can be
+/// acted on while waiting for a card. Lines that aren't a command come through
+/// as an error to report. The channel closes at the end of input.
+pub fn read_commands<R: std::io::BufRead + Send + 'static>(reader: R) -> std::sync::mpsc::Receiver<Result<Command, String>> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let command = serde_json::from_str(&line).map_err(|e| format!("Invalid command: {}", e));
+            if sender.send(command).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Writes an event as a line of its own
+pub fn emit<W: std::io::Write>(writer: &mut W, event: &Event) -> std::io::Result<()> {
+    let mut out = serde_json::to_vec(event)?;
+    out.push(b'\n');
+    writer.write_all(&out)?;
+    writer.flush()
+}
+
+/// Takes stdout for events, pointing file descriptor 1 at stderr so
+/// everything else printed goes there instead
+#[cfg(unix)]
+pub fn take_stdout() -> std::io::Result<std::fs::File> {
+    use std::os::unix::io::FromRawFd;
+
+    let stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if stdout < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { std::fs::File::from_raw_fd(stdout) })
+}
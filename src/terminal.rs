@@ -0,0 +1,493 @@
+use chrono::{Datelike, Timelike};
+use rand::RngCore;
+
+// TVR and TSI bits as (byte index, mask)
+pub const TVR_ODA_NOT_PERFORMED: (usize, u8) = (0, 0x80);
+pub const TVR_SDA_FAILED: (usize, u8) = (0, 0x40);
+pub const TVR_ICC_DATA_MISSING: (usize, u8) = (0, 0x20);
+pub const TVR_DDA_FAILED: (usize, u8) = (0, 0x08);
+pub const TVR_CDA_FAILED: (usize, u8) = (0, 0x04);
+pub const TVR_SDA_SELECTED: (usize, u8) = (0, 0x02);
+pub const TVR_DIFFERENT_APPLICATION_VERSIONS: (usize, u8) = (1, 0x80);
+pub const TVR_EXPIRED_APPLICATION: (usize, u8) = (1, 0x40);
+pub const TVR_APPLICATION_NOT_YET_EFFECTIVE: (usize, u8) = (1, 0x20);
+pub const TVR_SERVICE_NOT_ALLOWED: (usize, u8) = (1, 0x10);
+pub const TVR_NEW_CARD: (usize, u8) = (1, 0x08);
+pub const TVR_CARDHOLDER_VERIFICATION_FAILED: (usize, u8) = (2, 0x80);
+pub const TVR_UNRECOGNISED_CVM: (usize, u8) = (2, 0x40);
+pub const TVR_PIN_TRY_LIMIT_EXCEEDED: (usize, u8) = (2, 0x20);
+pub const TVR_PIN_PAD_NOT_PRESENT: (usize, u8) = (2, 0x10);
+pub const TVR_PIN_NOT_ENTERED: (usize, u8) = (2, 0x08);
+pub const TVR_ONLINE_PIN_ENTERED: (usize, u8) = (2, 0x04);
+pub const TVR_EXCEEDS_FLOOR_LIMIT: (usize, u8) = (3, 0x80);
+pub const TVR_LOWER_OFFLINE_LIMIT_EXCEEDED: (usize, u8) = (3, 0x40);
+pub const TVR_UPPER_OFFLINE_LIMIT_EXCEEDED: (usize, u8) = (3, 0x20);
+pub const TVR_RANDOMLY_SELECTED_ONLINE: (usize, u8) = (3, 0x10);
+pub const TVR_ISSUER_AUTHENTICATION_FAILED: (usize, u8) = (4, 0x40);
+pub const TVR_SCRIPT_FAILED_BEFORE_FINAL_AC: (usize, u8) = (4, 0x20);
+pub const TVR_SCRIPT_FAILED_AFTER_FINAL_AC: (usize, u8) = (4, 0x10);
+pub const TSI_ODA_PERFORMED: (usize, u8) = (0, 0x80);
+pub const TSI_CARDHOLDER_VERIFICATION_PERFORMED: (usize, u8) = (0, 0x40);
+pub const TSI_CARD_RISK_MANAGEMENT_PERFORMED: (usize, u8) = (0, 0x20);
+pub const TSI_ISSUER_AUTHENTICATION_PERFORMED: (usize, u8) = (0, 0x10);
+pub const TSI_TERMINAL_RISK_MANAGEMENT_PERFORMED: (usize, u8) = (0, 0x08);
+pub const TSI_SCRIPT_PROCESSING_PERFORMED: (usize, u8) = (0, 0x04);
+
+/// Every TVR bit defined in Book 3 Annex C5, with a description
+pub const TVR_BITS: [((usize, u8), &str); 31] = [
+    ((0, 0x80), "Offline data authentication was not performed"),
+    ((0, 0x40), "SDA failed"),
+    ((0, 0x20), "ICC data missing"),
+    ((0, 0x10), "Card appears on terminal exception file"),
+    ((0, 0x08), "DDA failed"),
+    ((0, 0x04), "CDA failed"),
+    ((0, 0x02), "SDA selected"),
+    ((1, 0x80), "ICC and terminal have different application versions"),
+    ((1, 0x40), "Expired application"),
+    ((1, 0x20), "Application not yet effective"),
+    ((1, 0x10), "Requested service not allowed for card product"),
+    ((1, 0x08), "New card"),
+    ((2, 0x80), "Cardholder verification was not successful"),
+    ((2, 0x40), "Unrecognised CVM"),
+    ((2, 0x20), "PIN Try Limit exceeded"),
+    ((2, 0x10), "PIN entry required and PIN pad not present or not working"),
+    ((2, 0x08), "PIN entry required, PIN pad present, but PIN was not entered"),
+    ((2, 0x04), "Online PIN entered"),
+    ((3, 0x80), "Transaction exceeds floor limit"),
+    ((3, 0x40), "Lower consecutive offline limit exceeded"),
+    ((3, 0x20), "Upper consecutive offline limit exceeded"),
+    ((3, 0x10), "Transaction selected randomly for online processing"),
+    ((3, 0x08), "Merchant forced transaction online"),
+    ((4, 0x80), "Default TDOL used"),
+    ((4, 0x40), "Issuer authentication failed"),
+    ((4, 0x20), "Script processing failed before final GENERATE AC"),
+    ((4, 0x10), "Script processing failed after final GENERATE AC"),
+    ((4, 0x08), "Relay resistance threshold exceeded"),
+    ((4, 0x04), "Relay resistance time limits exceeded"),
+    ((4, 0x02), "Relay resistance protocol not supported"),
+    ((4, 0x01), "Relay resistance protocol performed"),
+];
+
+/// TSI bits, each with what it means when set
+pub const TSI_BITS: [((usize, u8), &str); 6] = [
+    (TSI_ODA_PERFORMED, "Offline data authentication was performed"),
+    (TSI_CARDHOLDER_VERIFICATION_PERFORMED, "Cardholder verification was performed"),
+    (TSI_CARD_RISK_MANAGEMENT_PERFORMED, "Card risk management was performed"),
+    (TSI_ISSUER_AUTHENTICATION_PERFORMED, "Issuer authentication was performed"),
+    (TSI_TERMINAL_RISK_MANAGEMENT_PERFORMED, "Terminal risk management was performed"),
+    (TSI_SCRIPT_PROCESSING_PERFORMED, "Script processing was performed"),
+];
+
+/// What each set bit in a TVR or TSI means, going by one of the tables above
+pub fn describe_bits(data: &[u8], bits: &[((usize, u8), &'static str)]) -> Vec<&'static str> {
+    bits.iter()
+        .filter(|((byte, mask), _)| data.get(*byte).map(|b| b & mask != 0).unwrap_or(false))
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Terminal Action Codes, the acquirer's counterpart to the card's Issuer
+/// Action Codes
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TerminalActionCodes {
+    pub denial: [u8; 5],
+    pub online: [u8; 5],
+    pub default: [u8; 5],
+}
+
+/// Book 3 10.6.2 parameters for picking transactions below the floor limit to
+/// send online anyway. All zero means no transaction is picked.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RandomSelection {
+    /// Chance, in percent, of picking a transaction below the threshold
+    pub target_percentage: u8,
+    /// Chance of picking a transaction just below the floor limit, with the
+    /// chance rising towards this from the target between threshold and limit
+    pub max_target_percentage: u8,
+    pub threshold: u64,
+}
+
+impl RandomSelection {
+    /// Whether to send a transaction for this amount online, given the floor limit
+    pub fn select(&self, amount: u64, floor_limit: u64) -> bool {
+        if amount >= floor_limit {
+            return false;
+        }
+        let target = if amount < self.threshold {
+            self.target_percentage as u64
+        } else {
+            let target = self.target_percentage as u64;
+            let max = (self.max_target_percentage as u64).max(target);
+            target + (max - target) * (amount - self.threshold) / (floor_limit - self.threshold)
+        };
+        rand::Rng::gen_range(&mut rand::thread_rng(), 1..=99) <= target
+    }
+}
+
+// Transaction Type, as the first two digits of the ISO 8583 processing code
+pub const TRANSACTION_TYPE_PURCHASE: u8 = 0x00;
+pub const TRANSACTION_TYPE_CASH: u8 = 0x01;
+pub const TRANSACTION_TYPE_CASHBACK: u8 = 0x09;
+pub const TRANSACTION_TYPE_REFUND: u8 = 0x20;
+
+/// Book 4 A1: whether a Terminal Type names who operates the terminal and how
+/// it's attended and connected
+pub fn valid_terminal_type(terminal_type: u8) -> bool {
+    matches!(terminal_type >> 4, 1..=3) && matches!(terminal_type & 0x0f, 1..=6)
+}
+
+/// Looks up a Transaction Type by name
+pub fn transaction_type_from_name(name: &str) -> Option<u8> {
+    match name.to_lowercase().as_str() {
+        "purchase" => Some(TRANSACTION_TYPE_PURCHASE),
+        "cash" => Some(TRANSACTION_TYPE_CASH),
+        "cashback" => Some(TRANSACTION_TYPE_CASHBACK),
+        "refund" => Some(TRANSACTION_TYPE_REFUND),
+        _ => None
+    }
+}
+
+/// The name of a Transaction Type, for those [`transaction_type_from_name`]
+/// knows
+pub fn transaction_type_name(transaction_type: u8) -> Option<&'static str> {
+    match transaction_type {
+        TRANSACTION_TYPE_PURCHASE => Some("purchase"),
+        TRANSACTION_TYPE_CASH => Some("cash"),
+        TRANSACTION_TYPE_CASHBACK => Some("cashback"),
+        TRANSACTION_TYPE_REFUND => Some("refund"),
+        _ => None
+    }
+}
+
+/// A transaction with everything needed to start it on a card
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionRequest {
+    pub transaction_type: u8,
+    /// Amount, Authorised in minor units, including any cashback
+    pub amount_authorised: u64,
+    pub amount_other: u64,
+}
+
+impl TransactionRequest {
+    /// A transaction for `amount` plus `cashback`, a purchase or a purchase with
+    /// cashback if no type is given
+    pub fn new(transaction_type: Option<u8>, amount: u64, cashback: u64) -> Self {
+        Self {
+            transaction_type: match transaction_type {
+                Some(t) => t,
+                None if cashback > 0 => TRANSACTION_TYPE_CASHBACK,
+                None => TRANSACTION_TYPE_PURCHASE
+            },
+            amount_authorised: amount + cashback,
+            amount_other: cashback,
+        }
+    }
+
+    pub fn purchase(amount: u64) -> Self {
+        Self::new(None, amount, 0)
+    }
+
+    /// Sets the terminal up to run the transaction
+    pub fn apply(&self, terminal: &mut TerminalData) {
+        terminal.transaction_type = self.transaction_type;
+        terminal.amount_authorised = self.amount_authorised;
+        terminal.amount_other = self.amount_other;
+    }
+}
+
+/// Who the terminal belongs to, as the acquirer set it up
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct MerchantData {
+    /// ISO 18245 Merchant Category Code
+    pub category_code: Option<u16>,
+    /// The acquirer's Merchant Identifier, up to 15 characters
+    pub identifier: Option<String>,
+    /// Terminal Identification, up to 8 characters
+    pub terminal_identification: Option<String>,
+    pub name_and_location: Option<String>,
+}
+
+impl MerchantData {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The terminal's side of the data environment, supplying values for any
+/// terminal sourced data objects a card asks for in a DOL
+#[derive(Debug, Clone)]
+pub struct TerminalData {
+    pub amount_authorised: u64,
+    pub amount_other: u64,
+    pub country_code: u16,
+    pub currency_code: u16,
+    pub currency_exponent: u8,
+    pub merchant: MerchantData,
+    pub transaction_type: u8,
+    pub terminal_type: u8,
+    pub terminal_capabilities: [u8; 3],
+    pub additional_terminal_capabilities: [u8; 5],
+    pub terminal_transaction_qualifiers: [u8; 4],
+    pub transaction_time: chrono::NaiveDateTime,
+    /// Date to run transactions on in place of the clock's
+    pub date_override: Option<chrono::NaiveDate>,
+    /// Time and unpredictable number to give every transaction in place of
+    /// fresh ones, for replaying a recorded transaction exactly
+    pub time_override: Option<chrono::NaiveDateTime>,
+    pub unpredictable_number_override: Option<[u8; 4]>,
+    pub unpredictable_number: [u8; 4],
+    /// The unpredictable number as digits, for Mastercard's mag-stripe mode
+    /// where it has to fit in the track data
+    pub unpredictable_number_numeric: u32,
+    pub transaction_sequence_counter: u32,
+    pub tvr: [u8; 5],
+    pub tsi: [u8; 2],
+    pub cvm_results: [u8; 3],
+    pub authorisation_response_code: [u8; 2],
+    /// The issuer's approval code, given over the phone for a voice referral
+    pub authorisation_code: Option<[u8; 6]>,
+    /// Issuer Authentication Data from the online response, for CDOL2
+    pub issuer_authentication_data: Option<Vec<u8>>,
+    /// Version number the terminal implements for the application, if it checks one
+    pub application_version_number: Option<[u8; 2]>,
+    pub interface: crate::card::Interface,
+    pub contactless_transaction_limit: u64,
+    pub contactless_cvm_limit: u64,
+    /// Terminal Floor Limit for the application in use
+    pub floor_limit: u64,
+    pub random_selection: RandomSelection,
+    pub action_codes: TerminalActionCodes,
+    /// Whether an online response without Issuer Authentication Data counts
+    /// as issuer authentication failing
+    pub issuer_authentication_mandatory: bool,
+    /// The applications the terminal supports, with the parameters for each
+    pub applications: Vec<crate::selection::TerminalApplication>,
+    /// Language to speak to the cardholder in, the terminal's own until the
+    /// card's Language Preference picks one
+    pub language: crate::language::Language,
+    /// Where to send events as the transaction goes along, if anywhere
+    pub events: Option<crate::events::EventSender>,
+    /// Who decides voice referrals, referrals being declined without one
+    pub referral: Option<crate::transaction::ReferralHandler>,
+}
+
+impl TerminalData {
+    /// Sets up the data for a new transaction, taking the date from the clock
+    /// and generating a fresh unpredictable number
+    pub fn new(interface: crate::card::Interface) -> Self {
+        let mut unpredictable_number = [0; 4];
+        rand::thread_rng().fill_bytes(&mut unpredictable_number);
+
+        Self {
+            amount_authorised: 0,
+            amount_other: 0,
+            country_code: 826,
+            currency_code: 826,
+            currency_exponent: 2,
+            merchant: Default::default(),
+            transaction_type: 0x00,
+            terminal_type: 0x22,
+            terminal_capabilities: [0xe0, 0xf8, 0xc8],
+            additional_terminal_capabilities: [0xf0, 0x00, 0xf0, 0xa0, 0x01],
+            terminal_transaction_qualifiers: [0x36, 0x00, 0x40, 0x00],
+            transaction_time: chrono::Local::now().naive_local(),
+            date_override: None,
+            time_override: None,
+            unpredictable_number_override: None,
+            unpredictable_number,
+            unpredictable_number_numeric: 0,
+            transaction_sequence_counter: 0,
+            tvr: [0; 5],
+            tsi: [0; 2],
+            cvm_results: [0x3f, 0x00, 0x00],
+            authorisation_response_code: [0; 2],
+            authorisation_code: None,
+            issuer_authentication_data: None,
+            application_version_number: None,
+            interface,
+            contactless_transaction_limit: 100000,
+            contactless_cvm_limit: 10000,
+            floor_limit: 0,
+            random_selection: Default::default(),
+            action_codes: Default::default(),
+            issuer_authentication_mandatory: false,
+            applications: crate::selection::default_terminal_applications(),
+            language: Default::default(),
+            events: None,
+            referral: None,
+        }
+    }
+
+    /// The same terminal set up for another transaction, with the time, a fresh
+    /// unpredictable number and none of the last transaction's results
+    pub fn next_transaction(&self) -> Self {
+        let mut next = Self::new(self.interface);
+        next.amount_authorised = self.amount_authorised;
+        next.amount_other = self.amount_other;
+        next.country_code = self.country_code;
+        next.currency_code = self.currency_code;
+        next.currency_exponent = self.currency_exponent;
+        next.merchant = self.merchant.clone();
+        next.transaction_type = self.transaction_type;
+        next.transaction_sequence_counter = self.transaction_sequence_counter;
+        next.terminal_type = self.terminal_type;
+        next.terminal_capabilities = self.terminal_capabilities;
+        next.additional_terminal_capabilities = self.additional_terminal_capabilities;
+        next.terminal_transaction_qualifiers = self.terminal_transaction_qualifiers;
+        next.date_override = self.date_override;
+        next.time_override = self.time_override;
+        next.unpredictable_number_override = self.unpredictable_number_override;
+        if let Some(time) = self.time_override {
+            next.transaction_time = time;
+        }
+        if let Some(un) = self.unpredictable_number_override {
+            next.unpredictable_number = un;
+        }
+        next.application_version_number = self.application_version_number;
+        next.contactless_transaction_limit = self.contactless_transaction_limit;
+        next.contactless_cvm_limit = self.contactless_cvm_limit;
+        next.floor_limit = self.floor_limit;
+        next.random_selection = self.random_selection;
+        next.action_codes = self.action_codes;
+        next.issuer_authentication_mandatory = self.issuer_authentication_mandatory;
+        next.applications = self.applications.clone();
+        next.language = self.language;
+        next.events = self.events.clone();
+        next.referral = self.referral.clone();
+        next
+    }
+
+    /// Takes on the terminal's settings for the application about to be used
+    pub fn configure_for(&mut self, application: &crate::selection::TerminalApplication) {
+        self.floor_limit = application.floor_limit;
+        self.random_selection = application.random_selection;
+        self.action_codes = application.action_codes;
+        self.issuer_authentication_mandatory = application.issuer_authentication_mandatory;
+        if self.interface == crate::card::Interface::Contactless {
+            let limits = &application.reader_limits;
+            self.contactless_transaction_limit = limits.transaction_limit.unwrap_or(self.contactless_transaction_limit);
+            self.contactless_cvm_limit = limits.cvm_required_limit.unwrap_or(self.contactless_cvm_limit);
+            self.floor_limit = limits.floor_limit.unwrap_or(self.floor_limit);
+        }
+    }
+
+    pub fn transaction_date(&self) -> chrono::NaiveDate {
+        self.date_override.unwrap_or_else(|| self.transaction_time.date())
+    }
+
+    pub fn set_tvr(&mut self, (byte, mask): (usize, u8)) {
+        self.tvr[byte] |= mask;
+    }
+
+    pub fn set_tsi(&mut self, (byte, mask): (usize, u8)) {
+        self.tsi[byte] |= mask;
+    }
+
+    /// Whether the terminal can send transactions online, going by its type
+    pub fn online_capable(&self) -> bool {
+        matches!(self.terminal_type & 0x0f, 1 | 2 | 4 | 5)
+    }
+
+    /// Whether there's no attendant at the terminal, going by its type
+    pub fn is_unattended(&self) -> bool {
+        matches!(self.terminal_type & 0x0f, 4..=6)
+    }
+
+    /// Whether the terminal is an ATM: unattended or attended by the financial
+    /// institution itself, and able to dispense cash
+    pub fn is_atm(&self) -> bool {
+        matches!(self.terminal_type, 0x14..=0x16) && self.additional_terminal_capabilities[0] & 0x80 != 0
+    }
+
+    /// Whether the transaction gives out cash, on its own or as cashback
+    pub fn is_cash(&self) -> bool {
+        matches!(self.transaction_type, TRANSACTION_TYPE_CASH | 0x17)
+    }
+
+    /// Whether money goes back to the cardholder, which the card has no say in
+    pub fn is_refund(&self) -> bool {
+        self.transaction_type == TRANSACTION_TYPE_REFUND
+    }
+
+    pub fn is_cashback(&self) -> bool {
+        self.transaction_type == TRANSACTION_TYPE_CASHBACK || self.amount_other > 0
+    }
+
+    /// Whether a contactless transaction for this amount needs a CVM
+    pub fn contactless_cvm_required(&self) -> bool {
+        self.interface == crate::card::Interface::Contactless && self.amount_authorised > self.contactless_cvm_limit
+    }
+
+    /// Whether this amount can be taken over the current interface at all
+    pub fn within_interface_limits(&self) -> bool {
+        self.interface == crate::card::Interface::Contact || self.amount_authorised <= self.contactless_transaction_limit
+    }
+
+    fn ttq(&self) -> Vec<u8> {
+        let mut ttq = self.terminal_transaction_qualifiers;
+        if self.contactless_cvm_required() {
+            ttq[1] |= 0b01000000;
+        }
+        ttq.to_vec()
+    }
+
+    fn date_bcd(&self) -> Vec<u8> {
+        let date = self.transaction_date();
+        vec![
+            crate::util::to_bcd(date.year() as u64 % 100, 1)[0],
+            crate::util::to_bcd(date.month() as u64, 1)[0],
+            crate::util::to_bcd(date.day() as u64, 1)[0],
+        ]
+    }
+
+    fn time_bcd(&self) -> Vec<u8> {
+        let time = self.transaction_time.time();
+        vec![
+            crate::util::to_bcd(time.hour() as u64, 1)[0],
+            crate::util::to_bcd(time.minute() as u64, 1)[0],
+            crate::util::to_bcd(time.second() as u64, 1)[0],
+        ]
+    }
+
+    /// The encoded value of a terminal data object, if the terminal has one
+    pub fn value(&self, tag: crate::tlv::TagID) -> Option<Vec<u8>> {
+        use crate::tlv::TagID;
+        Some(match tag {
+            TagID::AmountAuthorised => crate::util::to_bcd(self.amount_authorised, 6),
+            TagID::AmountOther => crate::util::to_bcd(self.amount_other, 6),
+            TagID::TerminalCountryCode => crate::util::to_bcd(self.country_code as u64, 2),
+            TagID::TransactionCurrencyCode => crate::util::to_bcd(self.currency_code as u64, 2),
+            TagID::TransactionCurrencyExponent => crate::util::to_bcd(self.currency_exponent as u64, 1),
+            TagID::MerchantCategoryCode => crate::util::to_bcd(self.merchant.category_code? as u64, 2),
+            // Fixed length, so padded out with spaces
+            TagID::MerchantIdentifier => format!("{:<15}", self.merchant.identifier.as_ref()?).into_bytes(),
+            TagID::TerminalIdentification => format!("{:<8}", self.merchant.terminal_identification.as_ref()?).into_bytes(),
+            TagID::MerchantNameAndLocation => self.merchant.name_and_location.clone()?.into_bytes(),
+            TagID::TransactionDate => self.date_bcd(),
+            TagID::TransactionTime => self.time_bcd(),
+            TagID::TransactionType => vec![self.transaction_type],
+            TagID::TerminalType => vec![self.terminal_type],
+            TagID::TerminalCapabilities => self.terminal_capabilities.to_vec(),
+            TagID::AdditionalTerminalCapabilities => self.additional_terminal_capabilities.to_vec(),
+            TagID::TerminalTransactionQualifiers => self.ttq(),
+            TagID::UnpredictableNumber => self.unpredictable_number.to_vec(),
+            TagID::TransactionSequenceCounter => crate::util::to_bcd(self.transaction_sequence_counter as u64, 4),
+            TagID::UnpredictableNumberNumeric => crate::util::to_bcd(self.unpredictable_number_numeric as u64, 4),
+            TagID::TerminalVerificationResults => self.tvr.to_vec(),
+            TagID::TransactionStatusInformation => self.tsi.to_vec(),
+            TagID::CVMResults => self.cvm_results.to_vec(),
+            TagID::AuthorisationResponseCode => self.authorisation_response_code.to_vec(),
+            TagID::AuthorisationCode => self.authorisation_code?.to_vec(),
+            TagID::IssuerAuthenticationData => self.issuer_authentication_data.clone()?,
+            TagID::TerminalFloorLimit => (self.floor_limit.min(u32::MAX as u64) as u32).to_be_bytes().to_vec(),
+            TagID::ApplicationVersionNumberTerminal => self.application_version_number?.to_vec(),
+            _ => return None
+        })
+    }
+}
+
+impl crate::tlv::DataSource for TerminalData {
+    fn value(&self, tag: crate::tlv::TagID) -> Option<Vec<u8>> {
+        TerminalData::value(self, tag)
+    }
+}
@@ -1,5 +1,28 @@
-use std::collections::VecDeque;
-use std::convert::TryFrom;
+//! BER-TLV data objects as cards send them, and the DOLs cards use to ask
+//! for data from the terminal. Nothing here, or in [`hex`](crate::hex), needs
+//! more than `core` and `alloc`, so the parser can be taken as it is to
+//! terminals without `std`.
+
+use core::convert::TryFrom;
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+/// Why BER-TLV data couldn't be parsed
+#[derive(Debug, PartialEq, Copy, Clone, thiserror::Error)]
+pub enum TlvError {
+    #[error("Data ends part way through a data object")]
+    Truncated,
+    #[error("Length of {0} bytes is longer than any data object could be")]
+    LengthTooLong(u8),
+    #[error("Data objects nested more than {} deep", MAX_DEPTH)]
+    TooDeep,
+    #[error("Tag longer than 4 bytes")]
+    TagTooLong,
+}
+
+/// How deep constructed data objects may be nested. EMV's own templates go a
+/// few levels at most, but anything from the card could go deep enough to
+/// run out of stack parsing it.
+pub const MAX_DEPTH: usize = 32;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum TagID {
@@ -28,6 +51,87 @@ pub enum TagID {
     LogEntry,
     FileControlInformationProprietaryTemplate,
     FileControlInformationIssuerDiscretionaryData,
+    ResponseMessageTemplateFormat1,
+    ResponseMessageTemplateFormat2,
+    ApplicationInterchangeProfile,
+    ApplicationFileLocator,
+    ApplicationTransactionCounter,
+    LastOnlineApplicationTransactionCounter,
+    PINTryCounter,
+    LogFormat,
+    AmountAuthorised,
+    AmountOther,
+    TerminalCountryCode,
+    MerchantCategoryCode,
+    MerchantIdentifier,
+    TerminalIdentification,
+    MerchantNameAndLocation,
+    TransactionCurrencyCode,
+    TransactionCurrencyExponent,
+    TransactionDate,
+    TransactionTime,
+    TransactionType,
+    UnpredictableNumber,
+    TerminalType,
+    TerminalCapabilities,
+    AdditionalTerminalCapabilities,
+    TerminalTransactionQualifiers,
+    TerminalVerificationResults,
+    ApplicationPrimaryAccountNumber,
+    ApplicationExpirationDate,
+    ApplicationEffectiveDate,
+    CardRiskManagementDOL1,
+    CardRiskManagementDOL2,
+    CardholderVerificationMethodList,
+    AuthorisationResponseCode,
+    TransactionStatusInformation,
+    ApplicationVersionNumberCard,
+    ApplicationVersionNumberTerminal,
+    IssuerActionCodeDefault,
+    IssuerActionCodeDenial,
+    IssuerActionCodeOnline,
+    IssuerApplicationData,
+    ApplicationCryptogram,
+    CryptogramInformationData,
+    CVMResults,
+    CertificationAuthorityPublicKeyIndex,
+    IssuerPublicKeyCertificate,
+    IssuerPublicKeyRemainder,
+    IssuerPublicKeyExponent,
+    SignedStaticApplicationData,
+    StaticDataAuthenticationTagList,
+    DataAuthenticationCode,
+    IccPublicKeyCertificate,
+    IccPublicKeyExponent,
+    IccPublicKeyRemainder,
+    SignedDynamicApplicationData,
+    ApplicationUsageControl,
+    IssuerCountryCode,
+    ApplicationCurrencyCode,
+    TerminalFloorLimit,
+    LowerConsecutiveOfflineLimit,
+    UpperConsecutiveOfflineLimit,
+    IssuerAuthenticationData,
+    IssuerScriptTemplate1,
+    IssuerScriptTemplate2,
+    IssuerScriptCommand,
+    IssuerScriptIdentifier,
+    KernelIdentifier,
+    CardTransactionQualifiers,
+    CardAuthenticationRelatedData,
+    PosCardholderInteractionInformation,
+    Track2EquivalentData,
+    Track2Data,
+    Pcvc3Track2,
+    NatcTrack2,
+    Cvc3Track2,
+    UnpredictableNumberNumeric,
+    TransactionSequenceCounter,
+    AuthorisationCode,
+    ApplicationPrimaryAccountNumberSequenceNumber,
+    Track1Data,
+    CardholderName,
+    CardholderNameExtended,
     Unknown(u32),
 }
 
@@ -47,6 +151,7 @@ impl From<u32> for TagID {
             0x6f => TagID::FileControlInformationTemplate,
             0x70 => TagID::ReadRecordResponseMessageTemplate,
             0x73 => TagID::DirectoryDiscretionaryTemplate,
+            0x83 => TagID::CommandTemplate,
             0x84 => TagID::DedicatedFileName,
             0x87 => TagID::ApplicationPriorityIndicator,
             0x88 => TagID::ShortFileIdentifier,
@@ -58,6 +163,87 @@ impl From<u32> for TagID {
             0x9f4d => TagID::LogEntry,
             0xa5 => TagID::FileControlInformationProprietaryTemplate,
             0xbf0c => TagID::FileControlInformationIssuerDiscretionaryData,
+            0x80 => TagID::ResponseMessageTemplateFormat1,
+            0x77 => TagID::ResponseMessageTemplateFormat2,
+            0x82 => TagID::ApplicationInterchangeProfile,
+            0x94 => TagID::ApplicationFileLocator,
+            0x9f36 => TagID::ApplicationTransactionCounter,
+            0x9f13 => TagID::LastOnlineApplicationTransactionCounter,
+            0x9f17 => TagID::PINTryCounter,
+            0x9f4f => TagID::LogFormat,
+            0x9f02 => TagID::AmountAuthorised,
+            0x9f03 => TagID::AmountOther,
+            0x9f1a => TagID::TerminalCountryCode,
+            0x9f15 => TagID::MerchantCategoryCode,
+            0x9f16 => TagID::MerchantIdentifier,
+            0x9f1c => TagID::TerminalIdentification,
+            0x9f4e => TagID::MerchantNameAndLocation,
+            0x5f2a => TagID::TransactionCurrencyCode,
+            0x5f36 => TagID::TransactionCurrencyExponent,
+            0x9a => TagID::TransactionDate,
+            0x9f21 => TagID::TransactionTime,
+            0x9c => TagID::TransactionType,
+            0x9f37 => TagID::UnpredictableNumber,
+            0x9f35 => TagID::TerminalType,
+            0x9f33 => TagID::TerminalCapabilities,
+            0x9f40 => TagID::AdditionalTerminalCapabilities,
+            0x9f66 => TagID::TerminalTransactionQualifiers,
+            0x95 => TagID::TerminalVerificationResults,
+            0x5a => TagID::ApplicationPrimaryAccountNumber,
+            0x5f24 => TagID::ApplicationExpirationDate,
+            0x5f25 => TagID::ApplicationEffectiveDate,
+            0x8c => TagID::CardRiskManagementDOL1,
+            0x8d => TagID::CardRiskManagementDOL2,
+            0x8e => TagID::CardholderVerificationMethodList,
+            0x8a => TagID::AuthorisationResponseCode,
+            0x9b => TagID::TransactionStatusInformation,
+            0x9f08 => TagID::ApplicationVersionNumberCard,
+            0x9f09 => TagID::ApplicationVersionNumberTerminal,
+            0x9f0d => TagID::IssuerActionCodeDefault,
+            0x9f0e => TagID::IssuerActionCodeDenial,
+            0x9f0f => TagID::IssuerActionCodeOnline,
+            0x9f10 => TagID::IssuerApplicationData,
+            0x9f26 => TagID::ApplicationCryptogram,
+            0x9f27 => TagID::CryptogramInformationData,
+            0x9f34 => TagID::CVMResults,
+            0x8f => TagID::CertificationAuthorityPublicKeyIndex,
+            0x90 => TagID::IssuerPublicKeyCertificate,
+            0x92 => TagID::IssuerPublicKeyRemainder,
+            0x9f32 => TagID::IssuerPublicKeyExponent,
+            0x93 => TagID::SignedStaticApplicationData,
+            0x9f4a => TagID::StaticDataAuthenticationTagList,
+            0x9f45 => TagID::DataAuthenticationCode,
+            0x9f46 => TagID::IccPublicKeyCertificate,
+            0x9f47 => TagID::IccPublicKeyExponent,
+            0x9f48 => TagID::IccPublicKeyRemainder,
+            0x9f4b => TagID::SignedDynamicApplicationData,
+            0x9f07 => TagID::ApplicationUsageControl,
+            0x5f28 => TagID::IssuerCountryCode,
+            0x9f42 => TagID::ApplicationCurrencyCode,
+            0x9f1b => TagID::TerminalFloorLimit,
+            0x9f14 => TagID::LowerConsecutiveOfflineLimit,
+            0x9f23 => TagID::UpperConsecutiveOfflineLimit,
+            0x91 => TagID::IssuerAuthenticationData,
+            0x71 => TagID::IssuerScriptTemplate1,
+            0x72 => TagID::IssuerScriptTemplate2,
+            0x86 => TagID::IssuerScriptCommand,
+            0x9f18 => TagID::IssuerScriptIdentifier,
+            0x9f2a => TagID::KernelIdentifier,
+            0x9f6c => TagID::CardTransactionQualifiers,
+            0x9f69 => TagID::CardAuthenticationRelatedData,
+            0xdf4b => TagID::PosCardholderInteractionInformation,
+            0x57 => TagID::Track2EquivalentData,
+            0x9f6b => TagID::Track2Data,
+            0x9f65 => TagID::Pcvc3Track2,
+            0x9f67 => TagID::NatcTrack2,
+            0x9f61 => TagID::Cvc3Track2,
+            0x9f6a => TagID::UnpredictableNumberNumeric,
+            0x9f41 => TagID::TransactionSequenceCounter,
+            0x89 => TagID::AuthorisationCode,
+            0x5f34 => TagID::ApplicationPrimaryAccountNumberSequenceNumber,
+            0x56 => TagID::Track1Data,
+            0x5f20 => TagID::CardholderName,
+            0x9f0b => TagID::CardholderNameExtended,
             u => TagID::Unknown(u)
         }
     }
@@ -66,19 +252,131 @@ impl From<u32> for TagID {
 impl From<TagID> for u32 {
     fn from(value: TagID) -> Self {
         match value {
+            TagID::IssuerIdentificationNumber => 0x42,
+            TagID::ApplicationDedicatedFileName => 0x4F,
+            TagID::ApplicationLabel => 0x50,
+            TagID::LanguagePreference => 0x5f2d,
+            TagID::IssuerURL => 0x5f50,
+            TagID::InternationalBankAccountNumber => 0x5f53,
+            TagID::BankIdentifierCode => 0x5f54,
+            TagID::IssuerCountryCodeAlpha2 => 0x5f55,
+            TagID::IssuerCountryCodeAlpha6 => 0x5f56,
+            TagID::ApplicationTemplate => 0x61,
+            TagID::FileControlInformationTemplate => 0x6f,
+            TagID::ReadRecordResponseMessageTemplate => 0x70,
+            TagID::DirectoryDiscretionaryTemplate => 0x73,
             TagID::CommandTemplate => 0x83,
+            TagID::DedicatedFileName => 0x84,
+            TagID::ApplicationPriorityIndicator => 0x87,
+            TagID::ShortFileIdentifier => 0x88,
+            TagID::DirectoryDefinitionFileName => 0x9d,
+            TagID::ApplicationIdentifier => 0x9f06,
+            TagID::IssuerCodeTableIndex => 0x9f11,
+            TagID::ApplicationPreferredName => 0x9f12,
+            TagID::ProcessingOptionsDataObjectList => 0x9f38,
+            TagID::LogEntry => 0x9f4d,
+            TagID::FileControlInformationProprietaryTemplate => 0xa5,
+            TagID::FileControlInformationIssuerDiscretionaryData => 0xbf0c,
+            TagID::ResponseMessageTemplateFormat1 => 0x80,
+            TagID::ResponseMessageTemplateFormat2 => 0x77,
+            TagID::ApplicationInterchangeProfile => 0x82,
+            TagID::ApplicationFileLocator => 0x94,
+            TagID::ApplicationTransactionCounter => 0x9f36,
+            TagID::LastOnlineApplicationTransactionCounter => 0x9f13,
+            TagID::PINTryCounter => 0x9f17,
+            TagID::LogFormat => 0x9f4f,
+            TagID::AmountAuthorised => 0x9f02,
+            TagID::AmountOther => 0x9f03,
+            TagID::TerminalCountryCode => 0x9f1a,
+            TagID::MerchantCategoryCode => 0x9f15,
+            TagID::MerchantIdentifier => 0x9f16,
+            TagID::TerminalIdentification => 0x9f1c,
+            TagID::MerchantNameAndLocation => 0x9f4e,
+            TagID::TransactionCurrencyCode => 0x5f2a,
+            TagID::TransactionCurrencyExponent => 0x5f36,
+            TagID::TransactionDate => 0x9a,
+            TagID::TransactionTime => 0x9f21,
+            TagID::TransactionType => 0x9c,
+            TagID::UnpredictableNumber => 0x9f37,
+            TagID::TerminalType => 0x9f35,
+            TagID::TerminalCapabilities => 0x9f33,
+            TagID::AdditionalTerminalCapabilities => 0x9f40,
+            TagID::TerminalTransactionQualifiers => 0x9f66,
+            TagID::TerminalVerificationResults => 0x95,
+            TagID::ApplicationPrimaryAccountNumber => 0x5a,
+            TagID::ApplicationExpirationDate => 0x5f24,
+            TagID::ApplicationEffectiveDate => 0x5f25,
+            TagID::CardRiskManagementDOL1 => 0x8c,
+            TagID::CardRiskManagementDOL2 => 0x8d,
+            TagID::CardholderVerificationMethodList => 0x8e,
+            TagID::AuthorisationResponseCode => 0x8a,
+            TagID::TransactionStatusInformation => 0x9b,
+            TagID::ApplicationVersionNumberCard => 0x9f08,
+            TagID::ApplicationVersionNumberTerminal => 0x9f09,
+            TagID::IssuerActionCodeDefault => 0x9f0d,
+            TagID::IssuerActionCodeDenial => 0x9f0e,
+            TagID::IssuerActionCodeOnline => 0x9f0f,
+            TagID::IssuerApplicationData => 0x9f10,
+            TagID::ApplicationCryptogram => 0x9f26,
+            TagID::CryptogramInformationData => 0x9f27,
+            TagID::CVMResults => 0x9f34,
+            TagID::CertificationAuthorityPublicKeyIndex => 0x8f,
+            TagID::IssuerPublicKeyCertificate => 0x90,
+            TagID::IssuerPublicKeyRemainder => 0x92,
+            TagID::IssuerPublicKeyExponent => 0x9f32,
+            TagID::SignedStaticApplicationData => 0x93,
+            TagID::StaticDataAuthenticationTagList => 0x9f4a,
+            TagID::DataAuthenticationCode => 0x9f45,
+            TagID::IccPublicKeyCertificate => 0x9f46,
+            TagID::IccPublicKeyExponent => 0x9f47,
+            TagID::IccPublicKeyRemainder => 0x9f48,
+            TagID::SignedDynamicApplicationData => 0x9f4b,
+            TagID::ApplicationUsageControl => 0x9f07,
+            TagID::IssuerCountryCode => 0x5f28,
+            TagID::ApplicationCurrencyCode => 0x9f42,
+            TagID::TerminalFloorLimit => 0x9f1b,
+            TagID::LowerConsecutiveOfflineLimit => 0x9f14,
+            TagID::UpperConsecutiveOfflineLimit => 0x9f23,
+            TagID::IssuerAuthenticationData => 0x91,
+            TagID::IssuerScriptTemplate1 => 0x71,
+            TagID::IssuerScriptTemplate2 => 0x72,
+            TagID::IssuerScriptCommand => 0x86,
+            TagID::IssuerScriptIdentifier => 0x9f18,
+            TagID::KernelIdentifier => 0x9f2a,
+            TagID::CardTransactionQualifiers => 0x9f6c,
+            TagID::CardAuthenticationRelatedData => 0x9f69,
+            TagID::PosCardholderInteractionInformation => 0xdf4b,
+            TagID::Track2EquivalentData => 0x57,
+            TagID::Track2Data => 0x9f6b,
+            TagID::Pcvc3Track2 => 0x9f65,
+            TagID::NatcTrack2 => 0x9f67,
+            TagID::Cvc3Track2 => 0x9f61,
+            TagID::UnpredictableNumberNumeric => 0x9f6a,
+            TagID::TransactionSequenceCounter => 0x9f41,
+            TagID::AuthorisationCode => 0x89,
+            TagID::ApplicationPrimaryAccountNumberSequenceNumber => 0x5f34,
+            TagID::Track1Data => 0x56,
+            TagID::CardholderName => 0x5f20,
+            TagID::CardholderNameExtended => 0x9f0b,
             TagID::Unknown(u) => u,
-            _ => unimplemented!()
         }
     }
 }
 
-fn int_to_least_bytes(value: u64) -> Vec<u8> {
-    let mut bytes = VecDeque::from(value.to_be_bytes().to_vec());
-    while bytes[0] == 0 && bytes.len() > 1 {
-        bytes.pop_front();
+impl TagID {
+    /// Whether the data object has format n, meaning it is right justified and
+    /// padded with leading zeros when fitted into a DOL
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, TagID::AmountAuthorised | TagID::AmountOther | TagID::TerminalCountryCode | TagID::MerchantCategoryCode
+            | TagID::TransactionCurrencyCode | TagID::TransactionCurrencyExponent | TagID::TransactionDate
+            | TagID::TransactionTime | TagID::TransactionType | TagID::TerminalType)
     }
-    bytes.into()
+}
+
+fn int_to_least_bytes(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let zeros = bytes.iter().take_while(|b| **b == 0).count().min(bytes.len() - 1);
+    bytes[zeros..].to_vec()
 }
 
 impl From<TagID> for Vec<u8> {
@@ -87,27 +385,101 @@ impl From<TagID> for Vec<u8> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// A value borrowed from the data it was parsed out of. Everything parsed
+/// together shares one copy of that data, so parsing a whole card dump copies
+/// it once rather than once for every data object, and cloning is cheap.
+#[derive(Clone)]
+pub struct SharedBytes {
+    data: Arc<[u8]>,
+    start: usize,
+    end: usize,
+}
+
+impl SharedBytes {
+    fn slice(data: &Arc<[u8]>, start: usize, end: usize) -> Self {
+        Self {
+            data: data.clone(),
+            start,
+            end,
+        }
+    }
+}
+
+impl core::ops::Deref for SharedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for SharedBytes {
+    fn from(value: Vec<u8>) -> Self {
+        let end = value.len();
+        Self {
+            data: value.into(),
+            start: 0,
+            end,
+        }
+    }
+}
+
+impl From<&[u8]> for SharedBytes {
+    fn from(value: &[u8]) -> Self {
+        Self {
+            data: value.into(),
+            start: 0,
+            end: value.len(),
+        }
+    }
+}
+
+impl PartialEq for SharedBytes {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for SharedBytes {}
+
+impl core::fmt::Debug for SharedBytes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&crate::hex::Hex(self), f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TagContents {
     Invalid,
     String(String),
-    Bytes(Vec<u8>),
+    Bytes(SharedBytes),
     Byte(u8),
     Number(u32),
     Constructed(TagList),
 }
 
 impl TagContents {
-    fn make_primitive(bytes: &[u8], tag: &TagID) -> Self {
+    /// Values that don't fit the type their tag should have are kept as bytes,
+    /// so nothing is lost encoding them again
+    fn make_primitive(bytes: SharedBytes, tag: &TagID) -> Self {
         match tag {
-            TagID::LanguagePreference | TagID::ApplicationLabel => {
-                match String::from_utf8(bytes.to_vec()) {
-                    Ok(s) => TagContents::String(s),
-                    Err(_) => TagContents::Invalid,
+            TagID::LanguagePreference | TagID::ApplicationLabel | TagID::CardholderName | TagID::CardholderNameExtended => {
+                match core::str::from_utf8(&bytes) {
+                    Ok(s) => TagContents::String(s.into()),
+                    Err(_) => TagContents::Bytes(bytes),
                 }
             }
-            TagID::ShortFileIdentifier | TagID::ApplicationPriorityIndicator | TagID::IssuerCodeTableIndex => TagContents::Byte(bytes[0]),
-            _ => TagContents::Bytes(bytes.to_vec())
+            TagID::ShortFileIdentifier | TagID::ApplicationPriorityIndicator | TagID::IssuerCodeTableIndex => match *bytes {
+                [b] => TagContents::Byte(b),
+                _ => TagContents::Bytes(bytes)
+            },
+            _ => TagContents::Bytes(bytes)
         }
     }
 }
@@ -116,8 +488,8 @@ impl From<&TagContents> for Vec<u8> {
     fn from(value: &TagContents) -> Self {
         match value {
             TagContents::Invalid => vec![],
-            TagContents::String(s) => s.to_owned().into_bytes(),
-            TagContents::Bytes(b) => b.to_owned(),
+            TagContents::String(s) => s.as_bytes().to_vec(),
+            TagContents::Bytes(b) => b.to_vec(),
             TagContents::Byte(b) => vec![*b],
             TagContents::Number(n) => n.to_be_bytes().to_vec(),
             TagContents::Constructed(t) => t.into()
@@ -125,7 +497,7 @@ impl From<&TagContents> for Vec<u8> {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Tag {
     id: TagID,
     contents: TagContents,
@@ -154,53 +526,83 @@ impl Tag {
         }
     }
 
+    pub fn id(&self) -> TagID {
+        self.id
+    }
+
     pub fn contents(&self) -> &TagContents {
         &self.contents
     }
 }
 
-impl std::fmt::Debug for Tag {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let alternate = f.alternate();
-        let mut d = f.debug_struct("Tag");
-        d.field("id", &format_args!("{:02x?}", self.id));
-        if alternate {
-            d.field("contents", &format_args!("{:#02x?}", self.contents));
-        } else {
-            d.field("contents", &format_args!("{:02x?}", self.contents));
+impl From<&Tag> for Vec<u8> {
+    fn from(value: &Tag) -> Self {
+        let mut tl = TagList::new();
+        tl.add_tag(value.clone());
+        Vec::<u8>::from(&tl)
+    }
+}
+
+/// A tag by name, or by number in hex when it has none
+struct TagName(TagID);
+
+impl core::fmt::Debug for TagName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            TagID::Unknown(u) => write!(f, "Unknown({:X})", u),
+            id => core::fmt::Debug::fmt(&id, f)
         }
+    }
+}
+
+impl core::fmt::Debug for Tag {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut d = f.debug_struct("Tag");
+        d.field("id", &TagName(self.id));
+        d.field("contents", &self.contents);
         d.finish()
     }
 }
 
-#[derive(Debug, Clone)]
+/// Data objects one after another. Those parsed from the card are only split
+/// up when first looked at, a level at a time, so reading a few tags out of a
+/// large dump doesn't mean building all of it.
+#[derive(Clone, Default)]
 pub struct TagList {
-    tags: Vec<Tag>
+    tags: core::cell::OnceCell<Vec<Tag>>,
+    /// The data objects as they were parsed from, already known to be well
+    /// formed, until they're split up
+    raw: Option<SharedBytes>,
 }
 
 impl TagList {
     pub fn new() -> Self {
         Self {
-            tags: vec![]
+            tags: core::cell::OnceCell::from(vec![]),
+            raw: None,
         }
     }
 
+    pub fn tags(&self) -> &[Tag] {
+        self.tags.get_or_init(|| match &self.raw {
+            Some(raw) => Self::split(raw),
+            None => vec![]
+        })
+    }
+
     pub fn add_tag(&mut self, tag: Tag){
-        self.tags.push(tag);
+        self.tags();
+        self.raw = None;
+        self.tags.get_mut().unwrap().push(tag);
     }
 
     pub fn get_tag(&self, tag_id: TagID) -> Option<&Tag> {
-        for tag in &self.tags {
-            if tag.id == tag_id {
-                return Some(tag);
-            }
-        }
-        None
+        self.tags().iter().find(|t| t.id == tag_id)
     }
 
     pub fn get_tags(&self, tag_id: TagID) -> Vec<&Tag> {
         let mut tags: Vec<&Tag> = vec![];
-        for tag in &self.tags {
+        for tag in self.tags() {
             if tag.id == tag_id {
                 tags.push(tag)
             }
@@ -208,23 +610,58 @@ impl TagList {
         tags
     }
 
-    fn read_byte(vec: &mut VecDeque<u8>) -> Result<u8, pcsc::Error> {
-        match vec.pop_front() {
-            Some(i) => Ok(i),
-            None => Err(pcsc::Error::Eof)
+    /// The tags as a tree, one to a line with the contents of constructed
+    /// tags indented under them, each line starting with `indent` spaces
+    pub fn pretty(&self, indent: usize) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, indent);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        use core::fmt::Write;
+        // Writing to a string can't fail
+        for tag in self.tags() {
+            let _ = write!(out, "{:indent$}{}", "", crate::hex::Hex(&Vec::<u8>::from(tag.id)), indent = indent);
+            if !matches!(tag.id, TagID::Unknown(_)) {
+                let _ = write!(out, " {:?}", tag.id);
+            }
+            let _ = match &tag.contents {
+                TagContents::Constructed(_) => writeln!(out),
+                TagContents::String(value) => writeln!(out, ": \"{}\"", value),
+                TagContents::Invalid => writeln!(out, ": invalid"),
+                TagContents::Bytes(b) => writeln!(out, ": {}", crate::hex::Hex(b)),
+                contents => writeln!(out, ": {}", crate::hex::Hex(&Vec::<u8>::from(contents)))
+            };
+            if let TagContents::Constructed(tl) = &tag.contents {
+                tl.write_pretty(out, indent + 2);
+            }
         }
     }
 
-    fn read_id(vec: &mut VecDeque<u8>) -> Result<u32, pcsc::Error> {
+    /// Takes the next byte off the front of the data
+    fn read_byte(data: &mut &[u8]) -> Result<u8, TlvError> {
+        let (first, rest) = data.split_first().ok_or(TlvError::Truncated)?;
+        *data = rest;
+        Ok(*first)
+    }
+
+    fn read_id(vec: &mut &[u8]) -> Result<u32, TlvError> {
         let mut id = Self::read_byte(vec)? as u32;
 
         if (id & 0b11111) == 0b11111 {
-            let next_id = Self::read_byte(vec)?;
+            let mut next_id = Self::read_byte(vec)?;
             id <<= 8;
             id |= next_id as u32;
 
+            let mut id_len = 2;
             while next_id & 0b10000000 == 0b10000000 {
-                let next_id = Self::read_byte(vec)?;
+                // Any more would no longer fit in a u32
+                if id_len == 4 {
+                    return Err(TlvError::TagTooLong);
+                }
+                id_len += 1;
+                next_id = Self::read_byte(vec)?;
                 id <<= 8;
                 id |= next_id as u32;
             }
@@ -241,16 +678,20 @@ impl TagList {
         data & 0b00100000 != 0b00100000
     }
 
-    fn read_length(vec: &mut VecDeque<u8>) -> Result<u64, pcsc::Error> {
+    fn read_length(vec: &mut &[u8]) -> Result<u64, TlvError> {
         let mut length = Self::read_byte(vec)? as u64;
 
         if (length & 0b10000000) == 0b10000000 {
-            let num_octets = length & 0b01111111;
+            let mut num_octets = length & 0b01111111;
+            if num_octets > 4 {
+                return Err(TlvError::LengthTooLong(num_octets as u8));
+            }
             length = 0;
             while num_octets > 0 {
                 let octet = Self::read_byte(vec)? as u64;
                 length <<= 8;
                 length |= octet;
+                num_octets -= 1;
             }
         }
 
@@ -265,7 +706,7 @@ impl TagList {
         } else {
             let bytes = int_to_least_bytes(len);
             let num_octets = bytes.len() as u8 & 0b01111111;
-            out.push(num_octets);
+            out.push(0b10000000 | num_octets);
             for b in &bytes {
                 out.push(*b);
             }
@@ -273,56 +714,117 @@ impl TagList {
         out
     }
 
-    fn read_content(vec: &mut VecDeque<u8>, length: u64) -> Result<Vec<u8>, pcsc::Error> {
-        let mut out = vec![];
-        while out.len() != length as usize {
-            out.push(Self::read_byte(vec)?);
+    fn read_content<'a>(data: &mut &'a [u8], length: u64) -> Result<&'a [u8], TlvError> {
+        if length > data.len() as u64 {
+            return Err(TlvError::Truncated);
         }
-        Ok(out)
+        let (content, rest) = data.split_at(length as usize);
+        *data = rest;
+        Ok(content)
     }
-}
 
-impl TryFrom<&[u8]> for TagList {
-    type Error = pcsc::Error;
-
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        TagList::try_from(&VecDeque::<u8>::from(value.to_vec()))
+    /// Checks the data objects are well formed all the way down, found
+    /// `depth` constructed data objects down, without building anything,
+    /// giving how many there are at the top level
+    fn validate(mut data: &[u8], depth: usize) -> Result<usize, TlvError> {
+        if depth > MAX_DEPTH {
+            return Err(TlvError::TooDeep);
+        }
+        let mut count = 0;
+        while !data.is_empty() {
+            let id = Self::read_id(&mut data)?;
+            let length = Self::read_length(&mut data)?;
+            let contents = Self::read_content(&mut data, length)?;
+            if !Self::is_id_primitive(id) {
+                Self::validate(contents, depth + 1)?;
+            }
+            count += 1;
+        }
+        Ok(count)
     }
-}
 
-impl TryFrom<&VecDeque<u8>> for TagList {
-    type Error = pcsc::Error;
+    /// How many data objects there are at the top level of data already
+    /// known to be well formed, so each list is allocated only once
+    fn count(mut data: &[u8]) -> usize {
+        let mut count = 0;
+        while let (Ok(_), Ok(length)) = (Self::read_id(&mut data), Self::read_length(&mut data)) {
+            if Self::read_content(&mut data, length).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
 
-    fn try_from(value: &VecDeque<u8>) -> Result<Self, Self::Error> {
-        let mut data = value.clone();
-        let mut out = TagList {
-            tags: vec![]
-        };
+    /// Splits up data objects already known to be well formed, keeping the
+    /// values as offsets into the data and leaving constructed data objects
+    /// to be split up in turn when they're looked at
+    fn split(raw: &SharedBytes) -> Vec<Tag> {
+        let mut data: &[u8] = raw;
+        let mut out = Vec::with_capacity(Self::count(data));
 
-        while data.len() != 0 {
-            let id = Self::read_id(&mut data)?;
+        while !data.is_empty() {
+            let (id, length) = match (Self::read_id(&mut data), Self::read_length(&mut data)) {
+                (Ok(id), Ok(length)) => (id, length),
+                _ => break
+            };
+            let start = raw.start + raw.len() - data.len();
+            let contents = match Self::read_content(&mut data, length) {
+                Ok(c) => c,
+                Err(_) => break
+            };
+            let value = SharedBytes::slice(&raw.data, start, start + contents.len());
             let tag_id = TagID::from(id);
-            let length = Self::read_length(&mut data)?;
-            let contents = Self::read_content(&mut data, length)?;
 
-            if Self::is_id_primitive(id) {
-                let contents = TagContents::make_primitive(&contents, &tag_id);
-                let tag = Tag {
-                    id: tag_id,
-                    contents,
-                };
-                out.tags.push(tag);
-            } else {
-                let tag_list = TagList::try_from(&VecDeque::from(contents))?;
-                let tag = Tag {
-                    id: tag_id,
-                    contents: TagContents::Constructed(tag_list),
-                };
-                out.tags.push(tag);
-            }
+            out.push(Tag {
+                id: tag_id,
+                contents: match Self::is_id_primitive(id) {
+                    true => TagContents::make_primitive(value, &tag_id),
+                    false => TagContents::Constructed(TagList {
+                        tags: core::cell::OnceCell::new(),
+                        raw: Some(value),
+                    })
+                },
+            });
         }
 
-        Ok(out)
+        out
+    }
+}
+
+/// Returns the value field of `data` if it is a single BER-TLV object with the
+/// given tag, without parsing anything inside it
+pub fn template_value(data: &[u8], tag: TagID) -> Option<&[u8]> {
+    let mut rest = data;
+    let id = TagList::read_id(&mut rest).ok()?;
+    let length = TagList::read_length(&mut rest).ok()?;
+    if TagID::from(id) != tag || rest.len() as u64 != length {
+        return None;
+    }
+    Some(rest)
+}
+
+impl TryFrom<&[u8]> for TagList {
+    type Error = TlvError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        TagList::validate(value, 0)?;
+        Ok(TagList {
+            tags: core::cell::OnceCell::new(),
+            raw: Some(SharedBytes::from(value)),
+        })
+    }
+}
+
+impl PartialEq for TagList {
+    fn eq(&self, other: &Self) -> bool {
+        self.tags() == other.tags()
+    }
+}
+
+impl core::fmt::Debug for TagList {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TagList").field("tags", &self.tags()).finish()
     }
 }
 
@@ -330,7 +832,7 @@ impl From<&TagList> for Vec<u8> {
     fn from(value: &TagList) -> Self {
         let mut out = vec![];
 
-        for tag in &value.tags {
+        for tag in value.tags() {
             let data = Vec::<u8>::from(tag.contents());
             let len = TagList::make_length(data.len() as u64);
             out.extend(Vec::<u8>::from(tag.id));
@@ -349,22 +851,22 @@ pub struct DOLTag {
     exp_len: u8,
 }
 
-impl std::fmt::Debug for DOLTag {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let alternate = f.alternate();
+impl core::fmt::Debug for DOLTag {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut d = f.debug_struct("DOLTag");
-        d.field("id", &format_args!("{:02x?}", self.id));
-        if alternate {
-            d.field("contents", &format_args!("{:#02x?}", self.contents));
-        } else {
-            d.field("contents", &format_args!("{:02x?}", self.contents));
-        }
+        d.field("id", &TagName(self.id));
+        d.field("contents", &self.contents);
         d.field("exp_len", &format_args!("{:?}", self.exp_len));
         d.finish()
     }
 }
 
-#[derive(Debug, Clone)]
+/// Where a DOL's fields get their values from, such as the terminal's data
+pub trait DataSource {
+    fn value(&self, tag: TagID) -> Option<Vec<u8>>;
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct DOL {
     fields: Vec<DOLTag>
 }
@@ -376,6 +878,33 @@ impl DOL {
         }
     }
 
+    /// Fills in every field the source has a value for, leaving the rest zeroed
+    pub fn fill<S: DataSource + ?Sized>(&mut self, source: &S) {
+        for field in &mut self.fields {
+            if let Some(v) = source.value(field.id) {
+                field.contents = TagContents::Bytes(v.into());
+            }
+        }
+    }
+
+    /// Splits data laid out as the DOL lists it, such as a log record written
+    /// to the card's Log Format, into each field's value. None if there isn't
+    /// enough data for every field.
+    pub fn split(&self, data: &[u8]) -> Option<Vec<(TagID, Vec<u8>)>> {
+        let mut out = vec![];
+        let mut rest = data;
+        for field in &self.fields {
+            let len = field.exp_len as usize;
+            if rest.len() < len {
+                return None;
+            }
+            let (value, remaining) = rest.split_at(len);
+            out.push((field.id, value.to_vec()));
+            rest = remaining;
+        }
+        Some(out)
+    }
+
     fn fit_bytes(value: &[u8], exp_len: u8, numeric: bool) -> Vec<u8> {
         let exp_len = exp_len as usize;
         let mut data = value.to_vec();
@@ -393,33 +922,25 @@ impl DOL {
             }
         } else {
             if !numeric {
-                data.split_off(exp_len);
+                data.truncate(exp_len);
                 data
             } else {
-                data.split_off(exp_len)
+                data.split_off(len - exp_len)
             }
         }
     }
 }
 
 impl TryFrom<&[u8]> for DOL {
-    type Error = pcsc::Error;
+    type Error = TlvError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        DOL::try_from(&VecDeque::<u8>::from(value.to_vec()))
-    }
-}
-
-impl TryFrom<&VecDeque<u8>> for DOL {
-    type Error = pcsc::Error;
-
-    fn try_from(value: &VecDeque<u8>) -> Result<Self, Self::Error> {
-        let mut data = value.clone();
+        let mut data = value;
         let mut out = DOL {
             fields: vec![]
         };
 
-        while data.len() != 0 {
+        while !data.is_empty() {
             let id = TagList::read_id(&mut data)?;
             let tag_id = TagID::from(id);
             let length = TagList::read_byte(&mut data)?;
@@ -445,13 +966,127 @@ impl From<DOL> for Vec<u8> {
         for tag in &value.fields {
             out.extend(match &tag.contents {
                 TagContents::Invalid | TagContents::Constructed(_) => vec![0; tag.exp_len as usize],
-                TagContents::String(s) => DOL::fit_bytes(&s.to_owned().into_bytes(), tag.exp_len, false),
-                TagContents::Bytes(b) => DOL::fit_bytes(&b, tag.exp_len, false),
-                TagContents::Byte(b) => DOL::fit_bytes(&[*b], tag.exp_len, false),
-                TagContents::Number(n) => DOL::fit_bytes(&n.to_be_bytes(), tag.exp_len, false),
+                TagContents::String(s) => DOL::fit_bytes(s.as_bytes(), tag.exp_len, false),
+                TagContents::Bytes(b) => DOL::fit_bytes(b, tag.exp_len, tag.id.is_numeric()),
+                TagContents::Byte(b) => DOL::fit_bytes(&[*b], tag.exp_len, tag.id.is_numeric()),
+                TagContents::Number(n) => DOL::fit_bytes(&n.to_be_bytes(), tag.exp_len, true),
             })
         }
 
         out
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::TryFrom;
+
+    #[test]
+    fn parses_tlv_from_slices() {
+        use crate::tlv::{TagID, TagList, TlvError, DOL};
+
+        let mut data = vec![0x70, 0x81, 0x83, 0x57, 0x81, 0x80];
+        data.extend([0x11; 0x80]);
+        let tags = TagList::try_from(data.as_slice()).unwrap();
+        let track2 = tags.get_tag(TagID::ReadRecordResponseMessageTemplate).unwrap().get_tag(TagID::Track2EquivalentData).unwrap();
+        assert_eq!(Vec::<u8>::from(track2.contents()), [0x11; 0x80]);
+        match track2.contents() {
+            crate::tlv::TagContents::Bytes(b) => assert_eq!(*b, crate::tlv::SharedBytes::from(vec![0x11; 0x80])),
+            c => panic!("Track 2 parsed as {:?}", c)
+        }
+        assert_eq!(Vec::<u8>::from(&tags), data);
+        assert_eq!(crate::tlv::template_value(&data, TagID::ReadRecordResponseMessageTemplate), Some(&data[3..]));
+        assert_eq!(crate::tlv::template_value(&data[..10], TagID::ReadRecordResponseMessageTemplate), None);
+
+        assert_eq!(TagList::try_from(&data[..10]).unwrap_err(), TlvError::Truncated);
+        assert_eq!(TagList::try_from(&[0x9f][..]).unwrap_err(), TlvError::Truncated);
+        // Constructed data objects are only split up when looked at, but
+        // anything malformed inside them is still found parsing
+        assert_eq!(TagList::try_from(&[0x70, 0x04, 0x77, 0x02, 0x57, 0x05][..]).unwrap_err(), TlvError::Truncated);
+        assert_eq!(TagList::try_from(&[0x57, 0x85, 0, 0, 0, 0, 1][..]).unwrap_err(), TlvError::LengthTooLong(5));
+
+        let nested = |depth: usize| (0..depth).fold(vec![], |inner, _| {
+            let mut outer = vec![0x70, 0x84];
+            outer.extend((inner.len() as u32).to_be_bytes());
+            outer.extend(inner);
+            outer
+        });
+        assert!(TagList::try_from(nested(crate::tlv::MAX_DEPTH).as_slice()).is_ok());
+        assert_eq!(TagList::try_from(nested(crate::tlv::MAX_DEPTH + 1).as_slice()).unwrap_err(), TlvError::TooDeep);
+        assert_eq!(TagList::try_from(nested(10_000).as_slice()).unwrap_err(), TlvError::TooDeep);
+
+        struct Amount;
+        impl crate::tlv::DataSource for Amount {
+            fn value(&self, tag: TagID) -> Option<Vec<u8>> {
+                match tag {
+                    TagID::AmountAuthorised => Some(vec![0x10, 0x00]),
+                    _ => None
+                }
+            }
+        }
+        let mut dol = DOL::try_from(&[0x9f, 0x02, 0x06, 0x9a, 0x03][..]).unwrap();
+        dol.fill(&Amount);
+        assert_eq!(Vec::<u8>::from(dol), [0, 0, 0, 0, 0x10, 0x00, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_tags_over_four_bytes() {
+        use crate::tlv::{TagList, TlvError};
+
+        let four_byte_tag = [0x9f, 0x81, 0x82, 0x03, 0x01, 0xaa];
+        assert_eq!(Vec::<u8>::from(&TagList::try_from(&four_byte_tag[..]).unwrap()), four_byte_tag);
+        assert_eq!(TagList::try_from(&[0x9f, 0x81, 0x82, 0x83, 0x04, 0x00][..]).unwrap_err(), TlvError::TagTooLong);
+    }
+
+    /// Tag numbers of one to three bytes as BER-TLV lays them out, with the
+    /// constructed bit as asked, mixed with tags the terminal knows so that
+    /// their values get parsed as it would
+    fn tag_id(constructed: bool) -> impl proptest::strategy::Strategy<Value = crate::tlv::TagID> + Clone {
+        use proptest::prelude::*;
+        use crate::tlv::TagID;
+
+        let flag = if constructed { 0x20 } else { 0 };
+        let known = (0..=0xffff).map(TagID::from)
+            .filter(|id| !matches!(id, TagID::Unknown(_)))
+            .filter(|id| (Vec::<u8>::from(*id)[0] & 0x20 != 0) == constructed)
+            .collect::<Vec<_>>();
+        prop_oneof![
+            proptest::sample::select(known),
+            (0..4u32, 0..0x1fu32).prop_map(move |(class, number)| TagID::from(class << 6 | flag | number)),
+            (0..4u32, 0x1f..0x80u32).prop_map(move |(class, number)| TagID::from((class << 6 | flag | 0x1f) << 8 | number)),
+            (0..4u32, 0x81..0x100u32, 0..0x80u32).prop_map(move |(class, high, low)| TagID::from((class << 6 | flag | 0x1f) << 16 | high << 8 | low)),
+        ]
+    }
+
+    fn tag_list() -> impl proptest::strategy::Strategy<Value = crate::tlv::TagList> {
+        use proptest::prelude::*;
+        use crate::tlv::{Tag, TagContents, TagList};
+
+        fn list(tags: Vec<Tag>) -> TagList {
+            let mut list = TagList::new();
+            for tag in tags {
+                list.add_tag(tag);
+            }
+            list
+        }
+        let primitive = (tag_id(false), proptest::collection::vec(any::<u8>(), 0..300))
+            .prop_map(|(id, value)| Tag::new(id, TagContents::Bytes(value.into())));
+        let constructed = tag_id(true);
+        let tag = primitive.prop_recursive(3, 24, 4, move |inner| {
+            (constructed.clone(), proptest::collection::vec(inner, 0..4))
+                .prop_map(|(id, tags)| Tag::new(id, TagContents::Constructed(list(tags))))
+        });
+        proptest::collection::vec(tag, 0..4).prop_map(list)
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn tlv_round_trips(tags in tag_list()) {
+            let encoded = Vec::<u8>::from(&tags);
+            let parsed = crate::tlv::TagList::try_from(encoded.as_slice()).unwrap();
+            proptest::prop_assert_eq!(Vec::<u8>::from(&parsed), encoded);
+            let reparsed = crate::tlv::TagList::try_from(Vec::<u8>::from(&parsed).as_slice()).unwrap();
+            proptest::prop_assert_eq!(reparsed, parsed);
+        }
+    }
+}
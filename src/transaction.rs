@@ -0,0 +1,1680 @@
+use std::convert::TryFrom;
+use serde::{Deserialize, Serialize};
+use crate::terminal::{self, TerminalData};
+use crate::transport::CardTransport;
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Outcome {
+    /// The card gave a TC without the issuer being asked, either straight
+    /// away or after the terminal was unable to go online
+    ApprovedOffline,
+    /// The card gave an AAC without the issuer being asked
+    DeclinedOffline,
+    /// The issuer answered and the card finished with a TC
+    ApprovedOnline,
+    /// The issuer answered and either it or the card declined
+    DeclinedOnline,
+    /// Processing stopped before a cryptogram was requested, such as when no
+    /// application could be selected
+    Terminated,
+    /// The card was removed part way through an exchange
+    TornTransaction,
+    /// A refund was taken, the card having been asked for the AAC that goes
+    /// in clearing
+    Refunded,
+    /// The card has asked the cardholder to do something on their phone, such
+    /// as verify themselves, before presenting it again
+    SeePhone,
+    /// The card was taken away too soon and should be presented again
+    TryAgain,
+    /// The card can't be used contactless this time, but might work over
+    /// contact
+    TryAnotherInterface,
+    /// The chip couldn't be read, so the magnetic stripe should be swiped
+    /// instead and the transaction sent as a fallback
+    Fallback,
+    /// The transaction was cancelled before it finished, and the card reset
+    Aborted,
+    /// Communication with the card failed
+    Error,
+}
+
+impl Outcome {
+    pub fn approved(&self) -> bool {
+        matches!(self, Outcome::ApprovedOffline | Outcome::ApprovedOnline)
+    }
+
+    /// Whether the card was taken away before the transaction finished
+    pub fn card_removed(&self) -> bool {
+        matches!(self, Outcome::TornTransaction | Outcome::TryAgain)
+    }
+
+    /// The standard message to show the cardholder for the outcome
+    pub fn cardholder_message(&self) -> crate::language::Message {
+        use crate::language::Message;
+        match self {
+            Outcome::ApprovedOffline | Outcome::ApprovedOnline | Outcome::Refunded => Message::Approved,
+            Outcome::DeclinedOffline | Outcome::DeclinedOnline => Message::Declined,
+            Outcome::Terminated => Message::NotAccepted,
+            Outcome::TornTransaction => Message::CardError,
+            Outcome::Error => Message::ProcessingError,
+            Outcome::TryAgain => Message::TryAgain,
+            Outcome::TryAnotherInterface => Message::InsertCard,
+            Outcome::Fallback => Message::UseMagStripe,
+            Outcome::Aborted => Message::Cancelled,
+            Outcome::SeePhone => Message::SeePhone,
+        }
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::ApprovedOffline => write!(f, "Approved offline"),
+            Outcome::DeclinedOffline => write!(f, "Declined offline"),
+            Outcome::ApprovedOnline => write!(f, "Approved online"),
+            Outcome::DeclinedOnline => write!(f, "Declined online"),
+            Outcome::Terminated => write!(f, "Terminated"),
+            Outcome::TornTransaction => write!(f, "Torn transaction, card removed"),
+            Outcome::Refunded => write!(f, "Refund completed"),
+            Outcome::SeePhone => write!(f, "See phone for instructions, then present it again"),
+            Outcome::TryAgain => write!(f, "Present card again"),
+            Outcome::TryAnotherInterface => write!(f, "Try another interface"),
+            Outcome::Fallback => write!(f, "Chip unreadable, swipe the card"),
+            Outcome::Aborted => write!(f, "Cancelled"),
+            Outcome::Error => write!(f, "Error"),
+        }
+    }
+}
+
+/// CID bit asking for an advice to be sent
+const CID_ADVICE_REQUIRED: u8 = 0x08;
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Cryptogram {
+    AAC,
+    TC,
+    ARQC,
+}
+
+impl Cryptogram {
+    /// Decodes the cryptogram type from the Cryptogram Information Data
+    pub fn from_cid(cid: u8) -> Option<Self> {
+        match cid & 0xc0 {
+            0x00 => Some(Cryptogram::AAC),
+            0x40 => Some(Cryptogram::TC),
+            0x80 => Some(Cryptogram::ARQC),
+            _ => None
+        }
+    }
+
+    /// P1 of GENERATE AC requesting this cryptogram type
+    pub fn reference_control(&self) -> u8 {
+        match self {
+            Cryptogram::AAC => 0x00,
+            Cryptogram::TC => 0x40,
+            Cryptogram::ARQC => 0x80,
+        }
+    }
+}
+
+/// What the first GENERATE AC asked for and what the card gave back
+#[derive(Debug, Clone)]
+pub struct FirstAcResult {
+    /// The cryptogram terminal action analysis asked for
+    pub requested: Cryptogram,
+    pub cryptogram: Cryptogram,
+    pub response: crate::data::GenerateAcResponse,
+}
+
+/// Why a transaction couldn't be carried on with
+#[derive(Debug, thiserror::Error)]
+pub enum TransactionError {
+    #[error(transparent)]
+    Card(#[from] crate::card::CardError),
+    #[error("Unable to encipher the PIN: {0}")]
+    PinEncipherment(#[source] crate::auth::OdaError),
+}
+
+impl From<crate::tlv::TlvError> for TransactionError {
+    fn from(value: crate::tlv::TlvError) -> Self {
+        TransactionError::Card(value.into())
+    }
+}
+
+impl From<crate::transport::TransportError> for TransactionError {
+    fn from(value: crate::transport::TransportError) -> Self {
+        TransactionError::Card(value.into())
+    }
+}
+
+#[cfg(feature = "pcsc")]
+impl From<pcsc::Error> for TransactionError {
+    fn from(value: pcsc::Error) -> Self {
+        TransactionError::Card(value.into())
+    }
+}
+
+impl TransactionError {
+    /// Whether the card went away part way through
+    pub fn is_card_removed(&self) -> bool {
+        matches!(self, TransactionError::Card(e) if crate::card::is_card_removed(e))
+    }
+
+    /// Whether the card was reset by something else part way through
+    pub fn is_card_reset(&self) -> bool {
+        matches!(self, TransactionError::Card(e) if crate::card::is_card_reset(e))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, TransactionError::Card(e) if crate::card::is_cancelled(e))
+    }
+}
+
+/// What the issuer sent back for an online request
+#[derive(Debug, Clone)]
+pub struct OnlineResponse {
+    pub authorisation_response_code: [u8; 2],
+    pub issuer_authentication_data: Option<Vec<u8>>,
+    pub issuer_scripts: Vec<crate::data::IssuerScript>,
+}
+
+impl TryFrom<&crate::tlv::TagList> for OnlineResponse {
+    type Error = crate::data::DataError;
+
+    /// Reads the EMV data from an authorisation response
+    fn try_from(value: &crate::tlv::TagList) -> Result<Self, Self::Error> {
+        let authorisation_response_code = match value.get_tag(crate::tlv::TagID::AuthorisationResponseCode)
+            .map(|t| Vec::<u8>::from(t.contents())) {
+            Some(c) if c.len() == 2 => [c[0], c[1]],
+            _ => return Err(crate::data::DataError::NoAuthorisationResponseCode)
+        };
+        let issuer_scripts = value.tags().iter()
+            .filter(|t| matches!(t.id(), crate::tlv::TagID::IssuerScriptTemplate1 | crate::tlv::TagID::IssuerScriptTemplate2))
+            .map(crate::data::IssuerScript::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            authorisation_response_code,
+            issuer_authentication_data: value.get_tag(crate::tlv::TagID::IssuerAuthenticationData).map(|t| Vec::<u8>::from(t.contents())),
+            issuer_scripts,
+        })
+    }
+}
+
+impl OnlineResponse {
+    /// Whether the issuer approved the transaction, going by the ISO 8583
+    /// response codes that mean an approval
+    pub fn approved(&self) -> bool {
+        matches!(&self.authorisation_response_code, b"00" | b"08" | b"10" | b"11")
+    }
+
+    /// Whether the issuer wants the merchant to call them
+    pub fn referral(&self) -> bool {
+        matches!(&self.authorisation_response_code, b"01" | b"02")
+    }
+}
+
+/// What the operator needs to call the issuer about a voice referral
+#[derive(Debug, Clone)]
+pub struct ReferralRequest {
+    pub masked_pan: Option<String>,
+    pub amount_authorised: u64,
+    pub currency_code: u16,
+}
+
+/// Decides voice referrals, giving the approval code the issuer read out over
+/// the phone or nothing if the issuer declined. This is where an interface
+/// asks the operator to make the call.
+#[derive(Clone)]
+pub struct ReferralHandler(std::sync::Arc<ReferralFn>);
+
+type ReferralFn = dyn Fn(&ReferralRequest) -> Option<String> + Send + Sync;
+
+impl ReferralHandler {
+    pub fn new(handler: impl Fn(&ReferralRequest) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self(std::sync::Arc::new(handler))
+    }
+}
+
+impl std::fmt::Debug for ReferralHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReferralHandler")
+    }
+}
+
+/// How a voice referral gets decided
+#[derive(Debug, Clone)]
+pub enum Referral {
+    /// Hand it to the terminal's referral handler
+    Ask(ReferralHandler),
+    /// Approve with this authorisation code, for scripted runs
+    Approve(String),
+    /// Nobody is there to make the call, so referrals are declined
+    Decline,
+}
+
+/// How the second GENERATE AC finished the transaction, with the data that
+/// goes along with it for clearing
+#[derive(Debug, Clone)]
+pub struct CompletionResult {
+    /// Whether the issuer answered, rather than the terminal being unable to go online
+    pub online: bool,
+    pub authorisation_response_code: [u8; 2],
+    /// Whether EXTERNAL AUTHENTICATE accepted the Issuer Authentication Data,
+    /// if it was sent
+    pub issuer_authentication: Option<bool>,
+    pub requested: Cryptogram,
+    pub cryptogram: Cryptogram,
+    pub response: crate::data::GenerateAcResponse,
+}
+
+/// A cryptogram the card generated, as recorded in a `TransactionOutcome`
+#[derive(Debug, Clone, Serialize)]
+pub struct CryptogramEvidence {
+    pub requested: Cryptogram,
+    pub cryptogram: Cryptogram,
+    pub cid: u8,
+    pub atc: u16,
+    #[serde(serialize_with = "crate::dump::as_hex")]
+    pub application_cryptogram: Vec<u8>,
+    #[serde(serialize_with = "crate::dump::as_optional_hex")]
+    pub issuer_application_data: Option<Vec<u8>>,
+}
+
+impl CryptogramEvidence {
+    fn new(requested: Cryptogram, cryptogram: Cryptogram, response: &crate::data::GenerateAcResponse) -> Self {
+        Self {
+            requested,
+            cryptogram,
+            cid: response.cid,
+            atc: response.atc,
+            application_cryptogram: response.application_cryptogram.to_vec(),
+            issuer_application_data: response.issuer_application_data.clone(),
+        }
+    }
+}
+
+/// The TVR and TSI as they stood after a step of processing
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessingStep {
+    pub step: String,
+    #[serde(serialize_with = "crate::dump::as_hex")]
+    pub tvr: Vec<u8>,
+    #[serde(serialize_with = "crate::dump::as_hex")]
+    pub tsi: Vec<u8>,
+}
+
+/// Tags carrying the PAN, left out of the card data in a `TransactionOutcome`
+const PAN_TAGS: [crate::tlv::TagID; 4] = [
+    crate::tlv::TagID::ApplicationPrimaryAccountNumber,
+    crate::tlv::TagID::Track2EquivalentData,
+    crate::tlv::TagID::Track2Data,
+    crate::tlv::TagID::Track1Data,
+];
+
+/// How a transaction ended, along with everything that went into the decision
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionOutcome {
+    pub outcome: Outcome,
+    /// What went wrong, for `Outcome::Error` and `Outcome::Fallback`
+    pub error: Option<String>,
+    #[serde(serialize_with = "crate::dump::as_optional_hex")]
+    pub aid: Option<Vec<u8>>,
+    /// The PAN with all but the first six and last four digits hidden
+    pub masked_pan: Option<String>,
+    #[serde(serialize_with = "crate::dump::as_hex")]
+    pub tvr: Vec<u8>,
+    #[serde(serialize_with = "crate::dump::as_hex")]
+    pub tsi: Vec<u8>,
+    #[serde(serialize_with = "crate::dump::as_hex")]
+    pub cvm_results: Vec<u8>,
+    pub signature_required: bool,
+    /// The CVM a contactless kernel settled on
+    pub contactless_cvm: Option<crate::kernel3::Cvm>,
+    pub authorisation_response_code: Option<String>,
+    /// The issuer's approval code from a voice referral
+    pub authorisation_code: Option<String>,
+    pub issuer_authentication: Option<bool>,
+    pub first_ac: Option<CryptogramEvidence>,
+    pub second_ac: Option<CryptogramEvidence>,
+    #[serde(serialize_with = "crate::dump::as_hex")]
+    pub issuer_script_results: Vec<u8>,
+    /// Track 2 data sent online in place of a cryptogram by the mag-stripe
+    /// paths, with the PAN masked and the discretionary data dropped
+    pub masked_track2: Option<String>,
+    pub decision_trace: Vec<String>,
+    /// Every data element the card gave up in its GPO response and records,
+    /// bar those carrying the PAN
+    pub card_data: Vec<crate::dump::DataObjectDump>,
+    /// How the TVR and TSI got to where they ended up
+    pub tvr_history: Vec<ProcessingStep>,
+    /// Commands sent to the card, selection included
+    pub apdu_count: usize,
+    pub transaction_time: Option<chrono::NaiveDateTime>,
+    #[serde(serialize_with = "crate::dump::as_optional_hex")]
+    pub unpredictable_number: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "crate::terminal::MerchantData::is_empty")]
+    pub merchant: crate::terminal::MerchantData,
+    /// The language the cardholder was spoken to in, for their receipt
+    pub language: crate::language::Language,
+}
+
+impl TransactionOutcome {
+    /// An outcome reached without getting as far as processing a transaction
+    pub fn without_transaction(outcome: Outcome, error: Option<String>) -> Self {
+        Self {
+            outcome,
+            error,
+            aid: None,
+            masked_pan: None,
+            tvr: vec![],
+            tsi: vec![],
+            cvm_results: vec![],
+            signature_required: false,
+            contactless_cvm: None,
+            authorisation_response_code: None,
+            authorisation_code: None,
+            issuer_authentication: None,
+            first_ac: None,
+            second_ac: None,
+            issuer_script_results: vec![],
+            masked_track2: None,
+            decision_trace: vec![],
+            card_data: vec![],
+            tvr_history: vec![],
+            apdu_count: 0,
+            transaction_time: None,
+            unpredictable_number: None,
+            merchant: Default::default(),
+            language: Default::default(),
+        }
+    }
+
+    /// The outcome of communication with the card failing part way through,
+    /// with the card either taken away or not answering, or the transaction
+    /// being cancelled
+    pub fn card_error(error: &TransactionError, interface: crate::card::Interface) -> Self {
+        match error {
+            e if e.is_cancelled() => Self::without_transaction(Outcome::Aborted, None),
+            e if e.is_card_removed() && interface == crate::card::Interface::Contactless => Self::without_transaction(Outcome::TryAgain, None),
+            e if e.is_card_removed() => Self::without_transaction(Outcome::TornTransaction, None),
+            e => Self::without_transaction(Outcome::Error, Some(format!("Card communication failed: {}", e)))
+        }
+    }
+}
+
+impl TransactionOutcome {
+    /// What to tell the cardholder, in their language
+    pub fn cardholder_message(&self) -> &'static str {
+        self.language.message(self.outcome.cardholder_message())
+    }
+
+    /// Spells out what went into the outcome: each TVR and TSI bit set, the
+    /// CVM results, and the decisions made along the way
+    pub fn explain(&self) -> String {
+        let mut out = format!("Why {}:", self.outcome.to_string().to_lowercase());
+        if self.tvr.is_empty() {
+            out.push_str("\n  No transaction was processed");
+            return out;
+        }
+        for (name, data, bits) in [("TVR", &self.tvr, &terminal::TVR_BITS[..]), ("TSI", &self.tsi, &terminal::TSI_BITS[..])].iter() {
+            out.push_str(&format!("\n  {} {}:", name, crate::hex::Hex(data)));
+            let set = terminal::describe_bits(data, bits);
+            if set.is_empty() {
+                out.push_str(" nothing set");
+            }
+            for bit in set {
+                out.push_str(&format!("\n    - {}", bit));
+            }
+        }
+        out.push_str(&format!("\n  CVM results {}: {}", crate::hex::Hex(&self.cvm_results),
+                              crate::cvm::describe_cvm_results(&self.cvm_results)));
+        if !self.decision_trace.is_empty() {
+            out.push_str("\n  Decisions:");
+            for line in &self.decision_trace {
+                out.push_str(&format!("\n    - {}", line));
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for TransactionOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = &self.merchant.name_and_location {
+            writeln!(f, "{}", name)?;
+        }
+        match (&self.merchant.identifier, &self.merchant.terminal_identification) {
+            (Some(mid), Some(tid)) => writeln!(f, "MID: {} TID: {}", mid, tid)?,
+            (Some(mid), None) => writeln!(f, "MID: {}", mid)?,
+            (None, Some(tid)) => writeln!(f, "TID: {}", tid)?,
+            (None, None) => {}
+        }
+        write!(f, "Outcome: {}", self.outcome)?;
+        if let Some(e) = &self.error {
+            write!(f, " ({})", e)?;
+        }
+        if let Some(aid) = &self.aid {
+            write!(f, "\n  AID: {}", crate::hex::Hex(aid))?;
+        }
+        if let Some(pan) = &self.masked_pan {
+            write!(f, "\n  PAN: {}", pan)?;
+        }
+        if !self.tvr.is_empty() {
+            write!(f, "\n  TVR: {}, TSI: {}, CVM results: {}", crate::hex::Hex(&self.tvr),
+                   crate::hex::encode(&self.tsi), crate::hex::encode(&self.cvm_results))?;
+        }
+        for (name, ac) in [("First", &self.first_ac), ("Second", &self.second_ac)].iter() {
+            if let Some(ac) = ac {
+                write!(f, "\n  {} GENERATE AC: {:?} (asked for {:?}), ATC {}, cryptogram {}",
+                       name, ac.cryptogram, ac.requested, ac.atc, crate::hex::encode(&ac.application_cryptogram))?;
+            }
+        }
+        if let Some(cvm) = &self.contactless_cvm {
+            write!(f, "\n  Contactless CVM: {:?}", cvm)?;
+        }
+        if let Some(code) = &self.authorisation_response_code {
+            write!(f, "\n  Authorisation response code: {}", code)?;
+        }
+        if let Some(code) = &self.authorisation_code {
+            write!(f, "\n  Authorisation code: {}", code)?;
+        }
+        if !self.issuer_script_results.is_empty() {
+            write!(f, "\n  Issuer script results: {}", crate::hex::Hex(&self.issuer_script_results))?;
+        }
+        if let Some(track2) = &self.masked_track2 {
+            write!(f, "\n  Track 2: {}", track2)?;
+        }
+        if self.signature_required {
+            write!(f, "\n  Cardholder signature required")?;
+        }
+        Ok(())
+    }
+}
+
+// Application Usage Control, byte 1
+const AUC_DOMESTIC_CASH: u8 = 0x80;
+const AUC_INTERNATIONAL_CASH: u8 = 0x40;
+const AUC_DOMESTIC_GOODS: u8 = 0x20;
+const AUC_INTERNATIONAL_GOODS: u8 = 0x10;
+const AUC_DOMESTIC_SERVICES: u8 = 0x08;
+const AUC_INTERNATIONAL_SERVICES: u8 = 0x04;
+const AUC_ATMS: u8 = 0x02;
+const AUC_OTHER_TERMINALS: u8 = 0x01;
+// Application Usage Control, byte 2
+const AUC_DOMESTIC_CASHBACK: u8 = 0x80;
+const AUC_INTERNATIONAL_CASHBACK: u8 = 0x40;
+
+/// Runs one contact transaction with an already chosen application, following
+/// the EMV Book 3 sequence from GET PROCESSING OPTIONS through to completion
+pub struct Transaction<'a, T: CardTransport + ?Sized> {
+    card: &'a T,
+    pub terminal: TerminalData,
+    ca_keys: &'a crate::capk::CapkStore,
+    application: crate::data::Application,
+    processing_options: Option<crate::data::ProcessingOptions>,
+    gpo_response: Option<crate::tlv::TagList>,
+    records: crate::data::ApplicationRecords,
+    /// Set once the ICC key has been recovered for CDA
+    icc_key: Option<crate::auth::IccPublicKey>,
+    /// How to get a PIN from the cardholder, PIN CVMs being bypassed unless set
+    pub pin_entry: crate::cvm::PinEntry,
+    /// Key for encrypting online PINs, online PIN being unavailable without one
+    pub online_pin_key: Option<crate::pinblock::PinKey>,
+    /// Contactless kernel the Entry Point activated, None over contact
+    pub kernel_id: Option<u8>,
+    /// The issuer's answer should the card ask to go online. Without one the
+    /// transaction is completed as unable to go online
+    pub online_response: Option<OnlineResponse>,
+    /// Asked for `online_response` when there isn't one, and told of
+    /// reversals and advices
+    pub acquirer: Option<&'a mut dyn crate::acquirer::Acquirer>,
+    /// How to decide a voice referral, by default asking the terminal's
+    /// referral handler or declining if it has none
+    pub referral: Referral,
+    /// The card gave an AAR, asking for a referral
+    card_referral: bool,
+    cvm_outcome: crate::cvm::CvmOutcome,
+    decision_trace: Vec<String>,
+    first_ac: Option<FirstAcResult>,
+    completion: Option<CompletionResult>,
+    /// Issuer Script Results, five bytes for each script run
+    issuer_script_results: Vec<u8>,
+    track2: Option<Vec<u8>>,
+    contactless_cvm: Option<crate::kernel3::Cvm>,
+    /// PDOL and CDOL data sent so far, as covered by the CDA transaction data hash
+    transaction_data: Vec<u8>,
+    tvr_history: Vec<ProcessingStep>,
+}
+
+impl<'a, T: CardTransport + ?Sized> Transaction<'a, T> {
+    pub fn new(card: &'a T, terminal: TerminalData, ca_keys: &'a crate::capk::CapkStore, application: crate::data::Application) -> Self {
+        let referral = terminal.referral.clone().map_or(Referral::Decline, Referral::Ask);
+        Self {
+            card,
+            terminal,
+            ca_keys,
+            application,
+            processing_options: None,
+            gpo_response: None,
+            records: Default::default(),
+            icc_key: None,
+            pin_entry: crate::cvm::PinEntry::Bypass,
+            online_pin_key: None,
+            kernel_id: None,
+            online_response: None,
+            acquirer: None,
+            referral,
+            card_referral: false,
+            cvm_outcome: Default::default(),
+            decision_trace: vec![],
+            first_ac: None,
+            completion: None,
+            issuer_script_results: vec![],
+            track2: None,
+            contactless_cvm: None,
+            transaction_data: vec![],
+            tvr_history: vec![],
+        }
+    }
+
+    pub fn process(&mut self) -> Result<Outcome, TransactionError> {
+        let _span = tracing::info_span!("application", aid = %crate::hex::Hex(self.application.aid())).entered();
+        tracing::info!("Using application: {}", self.application.name());
+        if !self.terminal.within_interface_limits() {
+            tracing::info!("Amount exceeds the {} transaction limit", self.terminal.interface);
+            return Ok(Outcome::Terminated);
+        }
+
+        match self.kernel_id {
+            Some(crate::entry_point::KERNEL_MASTERCARD) => return self.process_kernel2(),
+            Some(crate::entry_point::KERNEL_VISA) => return self.process_qvsdc(),
+            Some(k) => tracing::warn!("No implementation of kernel {}, following the contact flow", k),
+            None => {}
+        }
+
+        if !self.initiate_application_processing()? {
+            return Ok(Outcome::Terminated);
+        }
+        self.read_application_data()?;
+        self.checkpoint("Read application data");
+        self.offline_data_authentication();
+        self.checkpoint("Offline data authentication");
+        self.processing_restrictions();
+        self.checkpoint("Processing restrictions");
+        self.cardholder_verification()?;
+        self.checkpoint("Cardholder verification");
+        self.terminal_risk_management()?;
+        self.checkpoint("Terminal risk management");
+        let requested = self.terminal_action_analysis();
+
+        let (first, response) = match self.generate_ac(requested, crate::tlv::TagID::CardRiskManagementDOL1)? {
+            Some(r) => r,
+            None => return Ok(Outcome::Terminated)
+        };
+        self.terminal.set_tsi(terminal::TSI_CARD_RISK_MANAGEMENT_PERFORMED);
+        self.checkpoint("First GENERATE AC");
+        tracing::info!("First GENERATE AC: {:?}, ATC {}, cryptogram {}", first, response.atc, crate::hex::Hex(&response.application_cryptogram));
+        self.first_ac = Some(FirstAcResult {
+            requested,
+            cryptogram: first,
+            response,
+        });
+        match first {
+            Cryptogram::AAC if self.terminal.is_refund() => Ok(Outcome::Refunded),
+            Cryptogram::AAC => Ok(Outcome::DeclinedOffline),
+            Cryptogram::TC if self.cda_failed() => Ok(Outcome::DeclinedOffline),
+            Cryptogram::TC => Ok(self.approved(Outcome::ApprovedOffline)),
+            Cryptogram::ARQC => self.complete(),
+        }
+    }
+
+    /// The encrypted PIN block captured for an online PIN CVM
+    pub fn online_pin_block(&self) -> Option<&[u8]> {
+        self.cvm_outcome.online_pin_block.as_deref()
+    }
+
+    fn approved(&self, outcome: Outcome) -> Outcome {
+        if self.cvm_outcome.signature_required {
+            tracing::info!("Approved subject to the cardholder's signature");
+        }
+        outcome
+    }
+
+    fn checkpoint(&mut self, step: &str) {
+        self.tvr_history.push(ProcessingStep {
+            step: step.to_string(),
+            tvr: self.terminal.tvr.to_vec(),
+            tsi: self.terminal.tsi.to_vec(),
+        });
+    }
+
+    /// The primitive data elements in the GPO response and records
+    fn card_data(&self) -> Vec<crate::dump::DataObjectDump> {
+        fn collect(tags: &[crate::tlv::Tag], out: &mut Vec<crate::dump::DataObjectDump>) {
+            for tag in tags {
+                match tag.contents() {
+                    crate::tlv::TagContents::Constructed(c) => collect(c.tags(), out),
+                    _ if PAN_TAGS.contains(&tag.id()) => {}
+                    c => out.push(crate::dump::DataObjectDump {
+                        tag: Vec::<u8>::from(tag.id()),
+                        data: Vec::<u8>::from(c),
+                    })
+                }
+            }
+        }
+        let mut out = vec![];
+        for tags in self.gpo_response.iter().chain(&self.records.records) {
+            collect(tags.tags(), &mut out);
+        }
+        out
+    }
+
+    /// Gathers up the results of processing for reporting `outcome`
+    pub fn report(&self, outcome: Outcome) -> TransactionOutcome {
+        // The contactless kernels don't record steps, so there's always at
+        // least where things ended up
+        let mut tvr_history = self.tvr_history.clone();
+        if tvr_history.last().is_none_or(|s| s.tvr != self.terminal.tvr || s.tsi != self.terminal.tsi) {
+            tvr_history.push(ProcessingStep {
+                step: "Outcome".to_string(),
+                tvr: self.terminal.tvr.to_vec(),
+                tsi: self.terminal.tsi.to_vec(),
+            });
+        }
+        let authorisation_response_code = match self.terminal.authorisation_response_code {
+            [0, 0] => None,
+            code => Some(String::from_utf8_lossy(&code).into_owned())
+        };
+        TransactionOutcome {
+            outcome,
+            error: None,
+            aid: Some(self.application.aid().to_vec()),
+            masked_pan: self.pan().map(|p| p.masked()),
+            tvr: self.terminal.tvr.to_vec(),
+            tsi: self.terminal.tsi.to_vec(),
+            cvm_results: self.terminal.cvm_results.to_vec(),
+            signature_required: self.cvm_outcome.signature_required,
+            contactless_cvm: self.contactless_cvm,
+            authorisation_response_code,
+            authorisation_code: self.terminal.authorisation_code.map(|c| String::from_utf8_lossy(&c).trim_end().to_string()),
+            issuer_authentication: self.completion.as_ref().and_then(|c| c.issuer_authentication),
+            first_ac: self.first_ac.as_ref().map(|ac| CryptogramEvidence::new(ac.requested, ac.cryptogram, &ac.response)),
+            second_ac: self.completion.as_ref().map(|c| CryptogramEvidence::new(c.requested, c.cryptogram, &c.response)),
+            issuer_script_results: self.issuer_script_results.clone(),
+            masked_track2: self.track2.as_deref().and_then(crate::data::mask_track2),
+            decision_trace: self.decision_trace.clone(),
+            card_data: self.card_data(),
+            tvr_history,
+            apdu_count: 0,
+            transaction_time: Some(self.terminal.transaction_time),
+            unpredictable_number: Some(self.terminal.unpredictable_number.to_vec()),
+            merchant: self.terminal.merchant.clone(),
+            language: self.terminal.language,
+        }
+    }
+
+    fn aip(&self) -> [u8; 2] {
+        self.processing_options.as_ref().map(|po| po.aip).unwrap_or_default()
+    }
+
+    fn cda_failed(&self) -> bool {
+        let (byte, mask) = terminal::TVR_CDA_FAILED;
+        self.terminal.tvr[byte] & mask != 0
+    }
+
+    fn record_bytes(&self, tag: crate::tlv::TagID) -> Option<Vec<u8>> {
+        self.records.get_bytes(tag)
+    }
+
+    fn pan(&self) -> Option<crate::data::Pan> {
+        self.records.pan()
+    }
+
+    /// A data element as things stand, from the terminal, the card's latest
+    /// GENERATE AC response, or what the card has given up so far
+    pub fn tag_value(&self, tag: crate::tlv::TagID) -> Option<Vec<u8>> {
+        use crate::tlv::TagID;
+        if let Some(v) = self.terminal.value(tag) {
+            return Some(v);
+        }
+        let ac = self.completion.as_ref().map(|c| &c.response)
+            .or_else(|| self.first_ac.as_ref().map(|f| &f.response));
+        match tag {
+            TagID::ApplicationCryptogram => ac.map(|r| r.application_cryptogram.to_vec()),
+            TagID::CryptogramInformationData => ac.map(|r| vec![r.cid]),
+            TagID::IssuerApplicationData => ac.and_then(|r| r.issuer_application_data.clone()),
+            TagID::ApplicationTransactionCounter => ac.map(|r| r.atc.to_be_bytes().to_vec())
+                .or_else(|| self.record_bytes(tag)),
+            TagID::ApplicationInterchangeProfile => self.processing_options.as_ref().map(|po| po.aip.to_vec()),
+            TagID::DedicatedFileName => Some(self.application.aid().to_vec()),
+            TagID::Track2EquivalentData => self.track2.clone().or_else(|| self.record_bytes(tag)),
+            _ => self.record_bytes(tag)
+        }
+    }
+
+    /// Whether the card was read as a magnetic stripe over contactless, so
+    /// there's a track to send but no chip data
+    pub fn mag_stripe_mode(&self) -> bool {
+        self.track2.is_some()
+    }
+
+    /// Selects the application and gets its processing options, giving false
+    /// with the reason traced where the card's answers don't make sense
+    fn initiate_application_processing(&mut self) -> Result<bool, TransactionError> {
+        let _span = tracing::info_span!("gpo").entered();
+        let fcipt = match crate::select_aid(self.card, self.application.aid())? {
+            Some((_df_name, fcipt)) => fcipt,
+            None => {
+                self.trace(format!("Unable to select {} again", self.application.name()));
+                return Ok(false);
+            }
+        };
+        let preference = fcipt.get_tag(crate::tlv::TagID::LanguagePreference)
+            .and_then(|t| crate::language::LanguagePreference::parse(&Vec::<u8>::from(t.contents())));
+        if let Some(preference) = preference {
+            self.terminal.language = crate::language::select(&preference, self.terminal.language);
+            tracing::debug!(language = self.terminal.language.code(), "Language chosen from the card's preference");
+        }
+        let pdol = fcipt.get_tag(crate::tlv::TagID::ProcessingOptionsDataObjectList)
+            .map(|d| crate::tlv::DOL::try_from(Vec::<u8>::from(d.contents()).as_slice()));
+        let mut pdol = match pdol {
+            Some(Ok(d)) => d,
+            Some(Err(_)) => {
+                self.trace("Card gave an invalid PDOL".to_string());
+                return Ok(false);
+            }
+            None => crate::tlv::DOL::new()
+        };
+
+        pdol.fill(&self.terminal);
+        self.transaction_data = pdol.clone().into();
+        let gpo_data = crate::card::gpo_command_data(pdol);
+        let gpo_resp = crate::card::card_get_processing_options(self.card, &gpo_data)?;
+        let processing_options = match crate::data::ProcessingOptions::try_from(&gpo_resp) {
+            Ok(p) => p,
+            Err(e) => {
+                self.trace(format!("Invalid GET PROCESSING OPTIONS response: {}", e));
+                return Ok(false);
+            }
+        };
+        tracing::debug!("AIP: {}, AFL: {:?}", crate::hex::Hex(&processing_options.aip), processing_options.afl);
+        crate::events::emit(&self.terminal.events, crate::events::Event::GpoDone {
+            aip: processing_options.aip.to_vec(),
+            records: processing_options.afl.iter().map(|a| a.records().count()).sum(),
+        });
+        self.processing_options = Some(processing_options);
+        self.gpo_response = Some(gpo_resp);
+        Ok(true)
+    }
+
+    fn read_application_data(&mut self) -> Result<(), TransactionError> {
+        let _span = tracing::info_span!("read").entered();
+        let afl = match &self.processing_options {
+            Some(po) => &po.afl,
+            None => return Ok(())
+        };
+        self.records = crate::card::card_read_application_records(self.card, afl)?;
+        tracing::debug!("Read {} records, {} bytes of ODA data", self.records.records.len(), self.records.oda_data.len());
+        crate::events::emit(&self.terminal.events, crate::events::Event::RecordsRead {
+            records: self.records.records.len(),
+        });
+        Ok(())
+    }
+
+    /// Performs the best offline data authentication method both card and
+    /// terminal support. For CDA this only recovers the ICC key, the signature
+    /// itself being checked on GENERATE AC.
+    fn offline_data_authentication(&mut self) {
+        let _span = tracing::info_span!("oda").entered();
+        let aip = self.aip();
+        let capabilities = self.terminal.terminal_capabilities[2];
+        let today = self.terminal.transaction_date();
+        if aip[0] & 0x01 != 0 && capabilities & 0x08 != 0 {
+            self.terminal.set_tsi(terminal::TSI_ODA_PERFORMED);
+            let recovered = crate::auth::recover_icc_key(self.ca_keys, self.application.aid(), &self.records, aip, today);
+            self.oda_result(crate::events::OdaMethod::Cda, recovered.is_ok());
+            match recovered {
+                Ok(key) => self.icc_key = Some(key),
+                Err(e) => {
+                    tracing::warn!("CDA failed: {}", e);
+                    if e == crate::auth::OdaError::IccDataMissing {
+                        self.terminal.set_tvr(terminal::TVR_ICC_DATA_MISSING);
+                    }
+                    self.terminal.set_tvr(terminal::TVR_CDA_FAILED);
+                }
+            }
+            return;
+        }
+        if aip[0] & 0x40 == 0 || capabilities & 0x80 == 0 {
+            self.terminal.set_tvr(terminal::TVR_ODA_NOT_PERFORMED);
+            return;
+        }
+
+        self.terminal.set_tvr(terminal::TVR_SDA_SELECTED);
+        self.terminal.set_tsi(terminal::TSI_ODA_PERFORMED);
+        let verified = crate::auth::verify_sda(self.ca_keys, self.application.aid(), &self.records, aip, today);
+        self.oda_result(crate::events::OdaMethod::Sda, verified.is_ok());
+        match verified {
+            Ok(dac) => tracing::info!("SDA succeeded, data authentication code {}", crate::hex::Hex(&dac)),
+            Err(e) => {
+                tracing::warn!("SDA failed: {}", e);
+                if e == crate::auth::OdaError::IccDataMissing {
+                    self.terminal.set_tvr(terminal::TVR_ICC_DATA_MISSING);
+                }
+                self.terminal.set_tvr(terminal::TVR_SDA_FAILED);
+            }
+        }
+    }
+
+    fn oda_result(&self, method: crate::events::OdaMethod, success: bool) {
+        crate::events::emit(&self.terminal.events, crate::events::Event::OdaResult {
+            method,
+            success,
+        });
+    }
+
+    /// Checks the fDDA signature on a qVSDC offline approval
+    fn fast_dda(&mut self, response: &crate::kernel3::QvsdcResponse) -> bool {
+        let _span = tracing::info_span!("oda").entered();
+        self.terminal.set_tsi(terminal::TSI_ODA_PERFORMED);
+        let today = self.terminal.transaction_date();
+        let result = crate::auth::recover_icc_key(self.ca_keys, self.application.aid(), &self.records, self.aip(), today)
+            .and_then(|key| match &response.cryptogram.signed_dynamic_application_data {
+                Some(signed_data) => crate::auth::verify_fdda(&key, signed_data, &response.terminal_dynamic_data(&self.terminal)),
+                None => Err(crate::auth::OdaError::IccDataMissing)
+            });
+        self.oda_result(crate::events::OdaMethod::Fdda, result.is_ok());
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("fDDA failed: {}", e);
+                if e == crate::auth::OdaError::IccDataMissing {
+                    self.terminal.set_tvr(terminal::TVR_ICC_DATA_MISSING);
+                }
+                self.terminal.set_tvr(terminal::TVR_DDA_FAILED);
+                false
+            }
+        }
+    }
+
+    /// Book C-3: the qVSDC path, where GET PROCESSING OPTIONS returns the
+    /// cryptogram and there's no GENERATE AC. When fDDA fails on an offline
+    /// approval the CTQ decides whether it goes online instead.
+    fn process_qvsdc(&mut self) -> Result<Outcome, TransactionError> {
+        if !self.initiate_application_processing()? {
+            return Ok(Outcome::Terminated);
+        }
+        let response = match self.gpo_response.as_ref().map(crate::kernel3::QvsdcResponse::try_from) {
+            Some(Ok(r)) => r,
+            _ if crate::kernel3::msd_supported(&self.terminal.terminal_transaction_qualifiers) => return self.process_msd(),
+            _ => {
+                tracing::info!("Card didn't take the qVSDC path");
+                return Ok(Outcome::Terminated);
+            }
+        };
+        self.read_application_data()?;
+
+        let ttq = self.terminal.terminal_transaction_qualifiers;
+        let cryptogram = Cryptogram::from_cid(response.cryptogram.cid).unwrap_or(Cryptogram::AAC);
+        tracing::info!("qVSDC cryptogram: {:?}, ATC {}, cryptogram {}", cryptogram, response.cryptogram.atc,
+                 crate::hex::encode(&response.cryptogram.application_cryptogram));
+        self.first_ac = Some(FirstAcResult {
+            requested: crate::kernel3::requested_cryptogram(&ttq),
+            cryptogram,
+            response: response.cryptogram.clone(),
+        });
+
+        let online = match cryptogram {
+            Cryptogram::AAC if self.terminal.is_refund() => return Ok(Outcome::Refunded),
+            Cryptogram::AAC => return Ok(Outcome::DeclinedOffline),
+            Cryptogram::ARQC => true,
+            Cryptogram::TC if self.fast_dda(&response) => false,
+            Cryptogram::TC => match crate::kernel3::oda_failure(&ttq, response.ctq) {
+                crate::kernel3::OdaFailure::GoOnline => {
+                    self.trace("fDDA failed, so going online as the card asks".to_string());
+                    true
+                }
+                crate::kernel3::OdaFailure::SwitchInterface => {
+                    self.trace("fDDA failed, and the card asks for another interface".to_string());
+                    return Ok(Outcome::TryAnotherInterface);
+                }
+                crate::kernel3::OdaFailure::Decline => {
+                    self.trace("fDDA failed".to_string());
+                    return Ok(Outcome::DeclinedOffline);
+                }
+            }
+        };
+
+        let cvm = crate::kernel3::cvm(&ttq, response.ctq, online);
+        if !self.contactless_cardholder_verification(cvm) {
+            return Ok(Outcome::DeclinedOffline);
+        }
+        // Offline, only the signed copy of the CTQ can be trusted
+        if cvm == crate::kernel3::Cvm::ConsumerDevice && !online && !response.cdcvm_confirmed() {
+            self.trace("CDCVM not confirmed by the Card Authentication Related Data".to_string());
+            return Ok(Outcome::DeclinedOffline);
+        }
+        if !online {
+            return Ok(self.approved(Outcome::ApprovedOffline));
+        }
+        Ok(self.online_outcome())
+    }
+
+    /// Visa's legacy MSD path, for cards that didn't take qVSDC. The card puts
+    /// its dCVV in the Track 2 Equivalent Data, which always goes online.
+    fn process_msd(&mut self) -> Result<Outcome, TransactionError> {
+        self.read_application_data()?;
+        let track2 = match self.record_bytes(crate::tlv::TagID::Track2EquivalentData) {
+            Some(t) => t,
+            None => {
+                tracing::warn!("Card has no Track 2 Equivalent Data for MSD");
+                return Ok(Outcome::Terminated);
+            }
+        };
+        tracing::debug!("MSD track 2: {}", crate::hex::Hex(&track2));
+        self.track2 = Some(track2);
+
+        let ttq = self.terminal.terminal_transaction_qualifiers;
+        if !self.contactless_cardholder_verification(crate::kernel3::cvm(&ttq, None, true)) {
+            return Ok(Outcome::DeclinedOffline);
+        }
+        Ok(self.online_outcome())
+    }
+
+    /// Records the CVM a contactless kernel settled on, getting the PIN block
+    /// for online PIN. Returns whether the cardholder can be verified at all.
+    fn contactless_cardholder_verification(&mut self, cvm: crate::kernel3::Cvm) -> bool {
+        let required = match cvm {
+            crate::kernel3::Cvm::OnlinePin => Some(crate::events::Cvm::OnlinePin),
+            crate::kernel3::Cvm::Signature => Some(crate::events::Cvm::Signature),
+            crate::kernel3::Cvm::ConsumerDevice => Some(crate::events::Cvm::ConsumerDevice),
+            crate::kernel3::Cvm::NotRequired | crate::kernel3::Cvm::Failed => None
+        };
+        if let Some(cvm) = required {
+            crate::events::emit(&self.terminal.events, crate::events::Event::CvmRequired { cvm });
+        }
+        let mut cvm = cvm;
+        if cvm == crate::kernel3::Cvm::OnlinePin {
+            let block = match (&self.online_pin_key, self.pan()) {
+                (Some(key), Some(pan)) => crate::cvm::online_pin(&self.pin_entry, pan.reveal(), key, &self.terminal),
+                _ => None
+            };
+            match block {
+                Some(b) => self.cvm_outcome.online_pin_block = Some(b),
+                None => cvm = crate::kernel3::Cvm::Failed
+            }
+        }
+        self.trace(format!("Contactless CVM: {:?}", cvm));
+        self.contactless_cvm = Some(cvm);
+        self.cvm_outcome.signature_required = cvm == crate::kernel3::Cvm::Signature;
+        self.terminal.cvm_results = cvm.results();
+        self.terminal.set_tsi(terminal::TSI_CARDHOLDER_VERIFICATION_PERFORMED);
+        if cvm == crate::kernel3::Cvm::Failed {
+            self.trace("No CVM the card and reader both support".to_string());
+            return false;
+        }
+        true
+    }
+
+    /// The outcome of a contactless transaction that went online, there being
+    /// no second GENERATE AC to finish it with
+    fn online_outcome(&mut self) -> Outcome {
+        if self.online_response.is_none() {
+            self.online_response = self.request_authorisation();
+        }
+        match self.online_response.clone() {
+            Some(r) => {
+                self.terminal.authorisation_response_code = r.authorisation_response_code;
+                if r.approved() { self.approved(Outcome::ApprovedOnline) } else { Outcome::DeclinedOnline }
+            }
+            None => {
+                self.trace("Unable to go online".to_string());
+                Outcome::DeclinedOffline
+            }
+        }
+    }
+
+    /// Book C-2: Mastercard's EMV mode, following the contact flow as far as
+    /// the one GENERATE AC with CDA. An ARQC is only completed by the issuer's
+    /// response, as there's no second GENERATE AC over contactless.
+    fn process_kernel2(&mut self) -> Result<Outcome, TransactionError> {
+        if !self.initiate_application_processing()? {
+            return Ok(Outcome::Terminated);
+        }
+        if !crate::kernel2::emv_mode_supported(self.aip()) {
+            tracing::info!("Card only supports mag-stripe mode");
+            return self.process_mag_stripe();
+        }
+        self.read_application_data()?;
+        self.offline_data_authentication();
+        self.processing_restrictions();
+        match crate::kernel2::cvm_results_without_list(&self.terminal, self.aip()) {
+            Some(results) => {
+                self.terminal.cvm_results = results;
+                self.terminal.set_tsi(terminal::TSI_CARDHOLDER_VERIFICATION_PERFORMED);
+            }
+            None => self.cardholder_verification()?
+        }
+        self.terminal_risk_management()?;
+        let requested = self.terminal_action_analysis();
+
+        let (cryptogram, response) = match self.generate_ac(requested, crate::tlv::TagID::CardRiskManagementDOL1)? {
+            Some(r) => r,
+            None => return Ok(Outcome::Terminated)
+        };
+        self.terminal.set_tsi(terminal::TSI_CARD_RISK_MANAGEMENT_PERFORMED);
+        tracing::info!("GENERATE AC: {:?}, ATC {}, cryptogram {}", cryptogram, response.atc, crate::hex::Hex(&response.application_cryptogram));
+        let see_phone = crate::kernel2::see_phone(&response);
+        self.first_ac = Some(FirstAcResult {
+            requested,
+            cryptogram,
+            response,
+        });
+
+        Ok(match cryptogram {
+            Cryptogram::AAC if self.terminal.is_refund() => Outcome::Refunded,
+            Cryptogram::AAC if see_phone => {
+                self.trace("Card is waiting on the cardholder's phone".to_string());
+                Outcome::SeePhone
+            }
+            Cryptogram::AAC => Outcome::DeclinedOffline,
+            _ if self.cda_failed() => Outcome::DeclinedOffline,
+            Cryptogram::TC => self.approved(Outcome::ApprovedOffline),
+            Cryptogram::ARQC => self.online_outcome(),
+        })
+    }
+
+    /// Book C-2: Mastercard's mag-stripe mode, for cards without EMV mode.
+    /// The card makes a CVC3 over the unpredictable number, which goes online
+    /// in dynamic track 2 data in place of a cryptogram.
+    fn process_mag_stripe(&mut self) -> Result<Outcome, TransactionError> {
+        self.read_application_data()?;
+        let data = match crate::kernel2::MagStripeData::try_from(&self.records) {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::warn!("Can't use mag-stripe mode: {}", e);
+                return Ok(Outcome::Terminated);
+            }
+        };
+        let un = u32::from_be_bytes(self.terminal.unpredictable_number) % 10u32.pow(data.un_digits());
+        self.terminal.unpredictable_number_numeric = un;
+
+        // The default UDOL is just the numeric unpredictable number
+        let udol = crate::tlv::DOL::try_from(data.udol.as_deref().unwrap_or(&[0x9f, 0x6a, 0x04]));
+        let mut udol = match udol {
+            Ok(d) => d,
+            Err(_) => {
+                tracing::warn!("Can't use mag-stripe mode: invalid UDOL");
+                return Ok(Outcome::Terminated);
+            }
+        };
+        udol.fill(&self.terminal);
+        let response = crate::card::card_compute_cryptographic_checksum(self.card, &Vec::<u8>::from(udol))?;
+        let track2 = match crate::kernel2::cryptographic_checksum(&response)
+            .and_then(|(cvc3, atc)| data.dynamic_track2(un, atc, cvc3)) {
+            Ok(t) => t,
+            Err(e) => {
+                tracing::warn!("Invalid COMPUTE CRYPTOGRAPHIC CHECKSUM response: {}", e);
+                return Ok(Outcome::Terminated);
+            }
+        };
+        tracing::debug!("Mag-stripe mode track 2: {}", crate::hex::Hex(&track2));
+        self.track2 = Some(track2);
+
+        if !self.contactless_cardholder_verification(crate::kernel2::mag_stripe_cvm(&self.terminal, self.aip())) {
+            return Ok(Outcome::DeclinedOffline);
+        }
+        Ok(self.online_outcome())
+    }
+
+    fn processing_restrictions(&mut self) {
+        if let (Some(card), Some(terminal)) = (self.record_bytes(crate::tlv::TagID::ApplicationVersionNumberCard), self.terminal.application_version_number) {
+            if card != terminal {
+                self.terminal.set_tvr(terminal::TVR_DIFFERENT_APPLICATION_VERSIONS);
+            }
+        }
+        let today = self.terminal.transaction_date();
+        let application_data = crate::data::ApplicationData::from(&self.records);
+        if application_data.is_not_yet_effective(today) {
+            tracing::info!("Application not yet effective");
+            self.terminal.set_tvr(terminal::TVR_APPLICATION_NOT_YET_EFFECTIVE);
+        }
+        if application_data.is_expired(today) {
+            tracing::info!("Application expired");
+            self.terminal.set_tvr(terminal::TVR_EXPIRED_APPLICATION);
+        }
+        if !self.usage_allowed() {
+            tracing::info!("Application usage control doesn't allow this transaction");
+            self.terminal.set_tvr(terminal::TVR_SERVICE_NOT_ALLOWED);
+        }
+    }
+
+    /// Book 3 10.4.2: checks the Application Usage Control allows this kind of
+    /// transaction at this kind of terminal, in the issuer's country or abroad
+    fn usage_allowed(&self) -> bool {
+        let auc = match self.record_bytes(crate::tlv::TagID::ApplicationUsageControl) {
+            Some(a) if a.len() == 2 => a,
+            _ => return true
+        };
+
+        let terminal_bit = if self.terminal.is_atm() { AUC_ATMS } else { AUC_OTHER_TERMINALS };
+        if auc[0] & terminal_bit == 0 {
+            return false;
+        }
+
+        let issuer_country = match self.record_bytes(crate::tlv::TagID::IssuerCountryCode) {
+            Some(c) => c,
+            None => return true
+        };
+        let domestic = issuer_country == crate::util::to_bcd(self.terminal.country_code as u64, 2);
+        // Refunds are checked as for the goods or services being refunded
+        let required = if self.terminal.is_cash() {
+            if domestic { AUC_DOMESTIC_CASH } else { AUC_INTERNATIONAL_CASH }
+        } else if domestic {
+            AUC_DOMESTIC_GOODS | AUC_DOMESTIC_SERVICES
+        } else {
+            AUC_INTERNATIONAL_GOODS | AUC_INTERNATIONAL_SERVICES
+        };
+        if auc[0] & required == 0 {
+            return false;
+        }
+        if self.terminal.is_cashback() {
+            let cashback = if domestic { AUC_DOMESTIC_CASHBACK } else { AUC_INTERNATIONAL_CASHBACK };
+            if auc[1] & cashback == 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Runs cardholder verification if the card supports it, recovering the ICC
+    /// key first if the CVM List may need it to encipher a PIN
+    fn cardholder_verification(&mut self) -> Result<(), TransactionError> {
+        let _span = tracing::info_span!("cvm").entered();
+        if self.aip()[0] & 0x10 == 0 {
+            return Ok(());
+        }
+        let cvm_list = self.record_bytes(crate::tlv::TagID::CardholderVerificationMethodList);
+        let application_currency = self.record_bytes(crate::tlv::TagID::ApplicationCurrencyCode);
+
+        let enciphered_pin = cvm_list.as_ref().and_then(|l| crate::cvm::CvmList::try_from(l.as_slice()).ok())
+            .map(|l| l.rules.iter().any(|r| matches!(r.method(), crate::cvm::METHOD_ENCIPHERED_PIN | crate::cvm::METHOD_ENCIPHERED_PIN_AND_SIGNATURE)))
+            .unwrap_or(false);
+        let pin_key = match &self.icc_key {
+            Some(k) => Some(k.clone()),
+            None if enciphered_pin => crate::auth::recover_icc_key(self.ca_keys, self.application.aid(), &self.records, self.aip(), self.terminal.transaction_date()).ok(),
+            None => None
+        };
+
+        let context = crate::cvm::CvmContext {
+            cvm_list: cvm_list.as_deref(),
+            application_currency: application_currency.as_deref(),
+            pin_entry: &self.pin_entry,
+            pin_key: pin_key.as_ref(),
+            online_pin_key: self.online_pin_key.as_ref(),
+            pan: self.pan(),
+        };
+        self.cvm_outcome = crate::cvm::perform(self.card, &mut self.terminal, &context)?;
+        tracing::info!("CVM results: {}", crate::hex::Hex(&self.terminal.cvm_results));
+        Ok(())
+    }
+
+    /// Reads a two byte counter with GET DATA, giving None if the card won't
+    fn get_counter(&self, tag: crate::tlv::TagID) -> Result<Option<u16>, TransactionError> {
+        let data = match crate::card::optional(crate::card::card_get_data_raw(self.card, u32::from(tag) as u16))? {
+            Some(d) => d,
+            None => return Ok(None)
+        };
+        Ok(match crate::tlv::template_value(&data, tag) {
+            Some([high, low]) => Some(u16::from_be_bytes([*high, *low])),
+            _ => None
+        })
+    }
+
+    /// Book 3 10.6.3: counts the transactions since the card last went online
+    /// against the card's consecutive offline limits. Cards without the limits
+    /// don't want this done.
+    fn velocity_checking(&mut self) -> Result<(), TransactionError> {
+        let limit = |tag| self.record_bytes(tag).and_then(|l| l.first().copied());
+        let (lower, upper) = match (limit(crate::tlv::TagID::LowerConsecutiveOfflineLimit), limit(crate::tlv::TagID::UpperConsecutiveOfflineLimit)) {
+            (Some(l), Some(u)) => (l, u),
+            _ => return Ok(())
+        };
+
+        let atc = self.get_counter(crate::tlv::TagID::ApplicationTransactionCounter)?;
+        let last_online_atc = self.get_counter(crate::tlv::TagID::LastOnlineApplicationTransactionCounter)?;
+        let (atc, last_online_atc) = match (atc, last_online_atc) {
+            (Some(a), Some(l)) => (a, l),
+            _ => {
+                tracing::warn!("Card didn't give its transaction counters for velocity checking");
+                self.terminal.set_tvr(terminal::TVR_LOWER_OFFLINE_LIMIT_EXCEEDED);
+                self.terminal.set_tvr(terminal::TVR_UPPER_OFFLINE_LIMIT_EXCEEDED);
+                return Ok(());
+            }
+        };
+
+        let offline = atc.saturating_sub(last_online_atc);
+        if offline > lower as u16 {
+            self.terminal.set_tvr(terminal::TVR_LOWER_OFFLINE_LIMIT_EXCEEDED);
+        }
+        if offline > upper as u16 {
+            self.terminal.set_tvr(terminal::TVR_UPPER_OFFLINE_LIMIT_EXCEEDED);
+        }
+        if last_online_atc == 0 {
+            self.terminal.set_tvr(terminal::TVR_NEW_CARD);
+        }
+        Ok(())
+    }
+
+    /// Book 3 10.6, if the card asks for it
+    fn terminal_risk_management(&mut self) -> Result<(), TransactionError> {
+        if self.aip()[0] & 0x08 == 0 {
+            return Ok(());
+        }
+        if self.terminal.is_refund() {
+            tracing::info!("Refunds carry no risk, skipping terminal risk management");
+            return Ok(());
+        }
+        // Cash is never given out without asking the issuer
+        let floor_limit = if self.terminal.is_cash() { 0 } else { self.terminal.floor_limit };
+        if self.terminal.amount_authorised > floor_limit {
+            tracing::info!("Amount exceeds the floor limit");
+            self.terminal.set_tvr(terminal::TVR_EXCEEDS_FLOOR_LIMIT);
+        } else if self.terminal.online_capable() && self.terminal.random_selection.select(self.terminal.amount_authorised, self.terminal.floor_limit) {
+            tracing::info!("Transaction randomly selected to go online");
+            self.terminal.set_tvr(terminal::TVR_RANDOMLY_SELECTED_ONLINE);
+        }
+        self.velocity_checking()?;
+        self.terminal.set_tsi(terminal::TSI_TERMINAL_RISK_MANAGEMENT_PERFORMED);
+        Ok(())
+    }
+
+    fn action_code(&self, tag: crate::tlv::TagID, default: u8) -> [u8; 5] {
+        match self.record_bytes(tag) {
+            Some(b) if b.len() == 5 => [b[0], b[1], b[2], b[3], b[4]],
+            _ => [default; 5]
+        }
+    }
+
+    /// The TVR bits picked out by either the Terminal or Issuer Action Code of
+    /// one kind, each described for the decision trace
+    fn action_code_matches(&self, kind: &str, iac: crate::tlv::TagID, iac_absent: u8, tac: [u8; 5]) -> Vec<String> {
+        let iac = self.action_code(iac, iac_absent);
+        terminal::TVR_BITS.iter()
+            .filter(|((byte, mask), _)| self.terminal.tvr[*byte] & mask != 0)
+            .filter_map(|((byte, mask), name)| {
+                let by = match (tac[*byte] & mask != 0, iac[*byte] & mask != 0) {
+                    (true, true) => "TAC and IAC",
+                    (true, false) => "TAC",
+                    (false, true) => "IAC",
+                    (false, false) => return None
+                };
+                Some(format!("{} matches {}-{}", name, by, kind))
+            })
+            .collect()
+    }
+
+    fn trace(&mut self, line: String) {
+        tracing::info!("{}", line);
+        self.decision_trace.push(line);
+    }
+
+    /// Records why the decision was made, or that nothing in the TVR called for it
+    fn trace_matches(&mut self, matches: Vec<String>, otherwise: &str) -> bool {
+        let matched = !matches.is_empty();
+        if matched {
+            for m in matches {
+                self.trace(m);
+            }
+        } else {
+            self.trace(otherwise.to_string());
+        }
+        matched
+    }
+
+    /// Book 3 10.7: decides what to ask for in the first GENERATE AC by checking
+    /// the TVR against the denial, then online or default, action codes.
+    /// Refunds always ask for an AAC.
+    fn terminal_action_analysis(&mut self) -> Cryptogram {
+        if self.terminal.is_refund() {
+            self.trace("Refunds only ask for an AAC".to_string());
+            return Cryptogram::AAC;
+        }
+        let codes = self.terminal.action_codes;
+        let denial = self.action_code_matches("Denial", crate::tlv::TagID::IssuerActionCodeDenial, 0x00, codes.denial);
+        let requested = if self.trace_matches(denial, "Nothing in the TVR calls for denial") {
+            Cryptogram::AAC
+        } else if self.terminal.online_capable() {
+            let online = self.action_code_matches("Online", crate::tlv::TagID::IssuerActionCodeOnline, 0xff, codes.online);
+            if self.trace_matches(online, "Nothing in the TVR calls for going online") { Cryptogram::ARQC } else { Cryptogram::TC }
+        } else {
+            let default = self.action_code_matches("Default", crate::tlv::TagID::IssuerActionCodeDefault, 0xff, codes.default);
+            if self.trace_matches(default, "Nothing in the TVR calls for declining offline") { Cryptogram::AAC } else { Cryptogram::TC }
+        };
+        self.trace(format!("Requesting {:?}", requested));
+        requested
+    }
+
+    pub fn first_ac(&self) -> Option<&FirstAcResult> {
+        self.first_ac.as_ref()
+    }
+
+    pub fn completion(&self) -> Option<&CompletionResult> {
+        self.completion.as_ref()
+    }
+
+    pub fn issuer_script_results(&self) -> &[u8] {
+        &self.issuer_script_results
+    }
+
+    /// Why terminal action analysis asked for the cryptograms it did
+    pub fn decision_trace(&self) -> &[String] {
+        &self.decision_trace
+    }
+
+    fn cdol(&self, tag: crate::tlv::TagID) -> Result<crate::tlv::DOL, TransactionError> {
+        let mut cdol = match self.record_bytes(tag) {
+            Some(b) => crate::tlv::DOL::try_from(b.as_slice())?,
+            None => crate::tlv::DOL::new()
+        };
+        cdol.fill(&self.terminal);
+        Ok(cdol)
+    }
+
+    /// Issues GENERATE AC with the data asked for by the CDOL in `cdol_tag`,
+    /// asking for a CDA signature on anything but an AAC when the ICC key is
+    /// available and checking it when it comes back. Gives nothing, with the
+    /// reason traced, where the card's data doesn't make sense.
+    fn generate_ac(&mut self, requested: Cryptogram, cdol_tag: crate::tlv::TagID) -> Result<Option<(Cryptogram, crate::data::GenerateAcResponse)>, TransactionError> {
+        let _span = tracing::info_span!("ac", requested = ?requested).entered();
+        let cdol_data = match self.cdol(cdol_tag) {
+            Ok(c) => Vec::<u8>::from(c),
+            Err(_) => {
+                self.trace(format!("Card gave an invalid {:?}", cdol_tag));
+                return Ok(None);
+            }
+        };
+        let cda = self.icc_key.is_some() && requested != Cryptogram::AAC;
+        let reference_control = requested.reference_control() | if cda { 0x10 } else { 0x00 };
+        let resp = crate::card::card_generate_ac(self.card, reference_control, &cdol_data)?;
+        self.transaction_data.extend(cdol_data);
+        let response = match crate::data::GenerateAcResponse::try_from(&resp) {
+            Ok(r) => r,
+            Err(e) => {
+                self.trace(format!("Invalid GENERATE AC response: {}", e));
+                return Ok(None);
+            }
+        };
+        // An AAR, left over from earlier versions of EMV, goes online like an
+        // ARQC but has the issuer called should that not be possible
+        let cryptogram = match Cryptogram::from_cid(response.cid) {
+            Some(c) => c,
+            None => {
+                self.trace("Card asks for a referral".to_string());
+                self.card_referral = true;
+                Cryptogram::ARQC
+            }
+        };
+
+        if let (true, Some(icc_key)) = (cda && cryptogram != Cryptogram::AAC, &self.icc_key) {
+            if let Err(e) = crate::auth::verify_cda(icc_key, &resp, &self.terminal.unpredictable_number, &self.transaction_data) {
+                tracing::warn!("CDA failed: {}", e);
+                self.terminal.set_tvr(terminal::TVR_CDA_FAILED);
+            }
+        }
+        Ok(Some((cryptogram, response)))
+    }
+
+    /// Book 3 10.9: hands the issuer's ARPC to the card with EXTERNAL
+    /// AUTHENTICATE when the AIP says it supports that, otherwise leaving it
+    /// for the card to pick up from CDOL2. Returns whether the card accepted
+    /// it, if it was sent at all.
+    fn issuer_authentication(&mut self, response: &OnlineResponse) -> Result<Option<bool>, TransactionError> {
+        let iad = match &response.issuer_authentication_data {
+            Some(d) => d,
+            None => {
+                if self.terminal.issuer_authentication_mandatory {
+                    self.trace("Issuer authentication is mandatory but the response had no Issuer Authentication Data".to_string());
+                    self.terminal.set_tvr(terminal::TVR_ISSUER_AUTHENTICATION_FAILED);
+                }
+                return Ok(None);
+            }
+        };
+        if self.aip()[0] & 0x04 == 0 {
+            return Ok(None);
+        }
+
+        let resp = crate::card::card_external_authenticate(self.card, iad)?;
+        self.terminal.set_tsi(terminal::TSI_ISSUER_AUTHENTICATION_PERFORMED);
+        let (&sw1, &sw2) = resp.status();
+        let passed = (sw1, sw2) == (0x90, 0x00);
+        if !passed {
+            self.trace(format!("Issuer authentication failed with status {:02X}{:02X}", sw1, sw2));
+            self.terminal.set_tvr(terminal::TVR_ISSUER_AUTHENTICATION_FAILED);
+        }
+        Ok(Some(passed))
+    }
+
+    /// Book 3 10.10: runs the scripts from either before or after the final
+    /// GENERATE AC, stopping each at the first command the card rejects
+    fn run_issuer_scripts(&mut self, response: &OnlineResponse, before_final_ac: bool) -> Result<(), TransactionError> {
+        for script in response.issuer_scripts.iter().filter(|s| s.before_final_ac == before_final_ac) {
+            let mut failed_at = None;
+            for (i, command) in script.commands.iter().enumerate() {
+                let resp = crate::card::card_issuer_script_command(self.card, command)?;
+                if !matches!(resp.status(), (&0x90, _) | (&0x62, _) | (&0x63, _)) {
+                    failed_at = Some(i + 1);
+                    break;
+                }
+            }
+            self.terminal.set_tsi(terminal::TSI_SCRIPT_PROCESSING_PERFORMED);
+
+            // Result in the high nibble, failing command's sequence number in the low
+            let result = match failed_at {
+                Some(i) => {
+                    self.trace(format!("Issuer script failed at command {}", i));
+                    self.terminal.set_tvr(if before_final_ac {
+                        terminal::TVR_SCRIPT_FAILED_BEFORE_FINAL_AC
+                    } else {
+                        terminal::TVR_SCRIPT_FAILED_AFTER_FINAL_AC
+                    });
+                    0x10 | i.min(0x0f) as u8
+                }
+                None => 0x20
+            };
+            self.issuer_script_results.push(result);
+            self.issuer_script_results.extend(script.identifier.unwrap_or([0; 4]));
+        }
+        Ok(())
+    }
+
+    /// Book 4 6.3.6: the operator calls the issuer, approving with the
+    /// authorisation code they're given or declining
+    fn voice_referral(&mut self) -> Cryptogram {
+        let masked_pan = self.pan().map(|p| p.masked());
+        tracing::info!(pan = masked_pan.as_deref().unwrap_or_default(), "Card asked for a voice referral");
+        crate::events::emit(&self.terminal.events, crate::events::Event::ReferralRequired { masked_pan: masked_pan.clone() });
+        let code = match &self.referral {
+            Referral::Ask(handler) => (handler.0)(&ReferralRequest {
+                masked_pan,
+                amount_authorised: self.terminal.amount_authorised,
+                currency_code: self.terminal.currency_code,
+            }),
+            Referral::Approve(c) => Some(c.clone()),
+            Referral::Decline => None
+        };
+        match code {
+            Some(c) => {
+                self.trace(format!("Referral approved with authorisation code {}", c.trim()));
+                let mut code = [b' '; 6];
+                for (d, s) in code.iter_mut().zip(c.trim().bytes()) {
+                    *d = s;
+                }
+                self.terminal.authorisation_code = Some(code);
+                Cryptogram::TC
+            }
+            None => {
+                self.trace("Referral declined".to_string());
+                Cryptogram::AAC
+            }
+        }
+    }
+
+    /// Sends the authorisation request to the acquirer, if there is one.
+    /// Should it not answer the transaction carries on as unable to go online.
+    fn request_authorisation(&mut self) -> Option<OnlineResponse> {
+        self.acquirer.as_ref()?;
+        let request = crate::acquirer::AuthorisationRequest::new(self);
+        match self.acquirer.as_mut()?.authorise(&request) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                tracing::warn!("Unable to get a response from the host: {}", e);
+                self.trace(format!("No response from the host: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Book 4 12.2: reverses an approval the card went on to decline, or sends
+    /// the advice the card asked for
+    fn notify_acquirer(&mut self, issuer_approved: bool, cryptogram: Cryptogram, cid: u8) {
+        let reversal = issuer_approved && cryptogram == Cryptogram::AAC;
+        if self.acquirer.is_none() || !(reversal || cid & CID_ADVICE_REQUIRED != 0) {
+            return;
+        }
+        let request = crate::acquirer::AuthorisationRequest::new(self);
+        let acquirer = self.acquirer.as_mut().unwrap();
+        let (kind, result) = if reversal {
+            ("Reversal", acquirer.reverse(&request))
+        } else {
+            ("Advice", acquirer.advice(&request))
+        };
+        match result {
+            Ok(()) => self.trace(format!("{} sent to the host", kind)),
+            Err(e) => {
+                tracing::warn!("Unable to send {} to the host: {}", kind.to_lowercase(), e);
+                self.trace(format!("{} not sent: {}", kind, e));
+            }
+        }
+    }
+
+    /// Finishes a transaction the card wants to take online, asking for a TC
+    /// or AAC depending on the issuer's response. Without one it's completed
+    /// as unable to go online, deciding between approval and decline from the
+    /// Issuer Action Code - Default. A failed CDA signature on the ARQC means
+    /// an offline decline instead.
+    fn complete(&mut self) -> Result<Outcome, TransactionError> {
+        if self.online_response.is_none() && !self.cda_failed() {
+            self.online_response = self.request_authorisation();
+        }
+        let online = !self.cda_failed() && self.online_response.is_some();
+        let mut issuer_authentication = None;
+        let requested = if self.cda_failed() {
+            self.trace("CDA failed, so declining offline".to_string());
+            self.terminal.authorisation_response_code = *b"Z1";
+            Cryptogram::AAC
+        } else if let Some(response) = self.online_response.clone() {
+            self.terminal.authorisation_response_code = response.authorisation_response_code;
+            self.terminal.issuer_authentication_data = response.issuer_authentication_data.clone();
+            issuer_authentication = self.issuer_authentication(&response)?;
+            self.run_issuer_scripts(&response, true)?;
+            let code = String::from_utf8_lossy(&response.authorisation_response_code).into_owned();
+            if response.approved() {
+                self.trace(format!("Issuer approved with response code {}", code));
+                Cryptogram::TC
+            } else if response.referral() {
+                self.trace(format!("Issuer asks for a referral with response code {}", code));
+                self.voice_referral()
+            } else {
+                self.trace(format!("Issuer declined with response code {}", code));
+                Cryptogram::AAC
+            }
+        } else if self.card_referral {
+            match self.voice_referral() {
+                Cryptogram::TC => {
+                    self.terminal.authorisation_response_code = *b"Y3";
+                    Cryptogram::TC
+                }
+                c => {
+                    self.terminal.authorisation_response_code = *b"Z3";
+                    c
+                }
+            }
+        } else {
+            let default = self.action_code_matches("Default", crate::tlv::TagID::IssuerActionCodeDefault, 0xff, self.terminal.action_codes.default);
+            if self.trace_matches(default, "Unable to go online, and nothing in the TVR calls for declining") {
+                self.terminal.authorisation_response_code = *b"Z3";
+                Cryptogram::AAC
+            } else {
+                self.terminal.authorisation_response_code = *b"Y3";
+                Cryptogram::TC
+            }
+        };
+
+        let (second, response) = match self.generate_ac(requested, crate::tlv::TagID::CardRiskManagementDOL2)? {
+            Some(r) => r,
+            None => return Ok(Outcome::Terminated)
+        };
+        self.checkpoint("Second GENERATE AC");
+        tracing::info!("Second GENERATE AC: {:?}, ATC {}, cryptogram {}, response code {}",
+                 second, response.atc, crate::hex::encode(&response.application_cryptogram),
+                 String::from_utf8_lossy(&self.terminal.authorisation_response_code));
+        if let Some(iad) = &response.issuer_application_data {
+            tracing::debug!("Issuer application data: {}", crate::hex::Hex(iad));
+        }
+        self.completion = Some(CompletionResult {
+            online,
+            authorisation_response_code: self.terminal.authorisation_response_code,
+            issuer_authentication,
+            requested,
+            cryptogram: second,
+            response,
+        });
+
+        if let (true, Some(response)) = (online, self.online_response.clone()) {
+            self.run_issuer_scripts(&response, false)?;
+        }
+        let issuer_approved = online && self.online_response.as_ref().is_some_and(|r| r.approved());
+        let cid = self.completion.as_ref().map(|c| c.response.cid).unwrap_or_default();
+        self.notify_acquirer(issuer_approved, second, cid);
+        if !self.issuer_script_results.is_empty() {
+            tracing::info!("Issuer script results: {}", crate::hex::Hex(&self.issuer_script_results));
+        }
+        Ok(match (second, online) {
+            (Cryptogram::TC, _) if self.cda_failed() => Outcome::DeclinedOffline,
+            (Cryptogram::TC, true) => self.approved(Outcome::ApprovedOnline),
+            (Cryptogram::TC, false) => self.approved(Outcome::ApprovedOffline),
+            (_, true) => Outcome::DeclinedOnline,
+            (_, false) => Outcome::DeclinedOffline,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn explains_decline() {
+        let mut outcome = crate::transaction::TransactionOutcome::without_transaction(crate::transaction::Outcome::DeclinedOffline, None);
+        assert!(outcome.explain().contains("No transaction was processed"));
+        outcome.tvr = vec![0x80, 0x00, 0x00, 0x80, 0x00];
+        outcome.tsi = vec![0x68, 0x00];
+        outcome.cvm_results = vec![0x42, 0x03, 0x00];
+        outcome.decision_trace = vec!["Transaction exceeds floor limit matches TAC-Denial".to_string()];
+        let explanation = outcome.explain();
+        let lines: Vec<_> = explanation.lines().collect();
+        assert_eq!(lines, vec![
+            "Why declined offline:",
+            "  TVR 8000008000:",
+            "    - Offline data authentication was not performed",
+            "    - Transaction exceeds floor limit",
+            "  TSI 6800:",
+            "    - Cardholder verification was performed",
+            "    - Card risk management was performed",
+            "    - Terminal risk management was performed",
+            "  CVM results 420300: Enciphered PIN verified online (if the terminal supports the CVM), result unknown",
+            "  Decisions:",
+            "    - Transaction exceeds floor limit matches TAC-Denial",
+        ]);
+    }
+}
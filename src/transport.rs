@@ -0,0 +1,392 @@
+/// Why an exchange with the card couldn't be had. Those the terminal acts on
+/// are picked out, anything else from the reader is kept as it came.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TransportError {
+    #[error("The smart card has been removed, so further communication is not possible")]
+    RemovedCard,
+    #[error("The smart card has been reset, so any shared state information is invalid")]
+    ResetCard,
+    #[error("The action was cancelled")]
+    Cancelled,
+    #[error("The data buffer to receive returned data is too small for the returned data")]
+    InsufficientBuffer,
+    #[error("Invalid ATR")]
+    InvalidAtr,
+    #[error("This smart card does not support the requested feature")]
+    UnsupportedFeature,
+    #[cfg(feature = "pcsc")]
+    #[error(transparent)]
+    Reader(pcsc::Error),
+}
+
+#[cfg(feature = "pcsc")]
+impl From<pcsc::Error> for TransportError {
+    fn from(value: pcsc::Error) -> Self {
+        match value {
+            pcsc::Error::RemovedCard | pcsc::Error::NoSmartcard => TransportError::RemovedCard,
+            pcsc::Error::ResetCard => TransportError::ResetCard,
+            pcsc::Error::Cancelled => TransportError::Cancelled,
+            pcsc::Error::InsufficientBuffer => TransportError::InsufficientBuffer,
+            pcsc::Error::InvalidAtr => TransportError::InvalidAtr,
+            pcsc::Error::UnsupportedFeature => TransportError::UnsupportedFeature,
+            e => TransportError::Reader(e)
+        }
+    }
+}
+
+/// Anything able to exchange raw APDUs with a card
+pub trait CardTransport {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError>;
+
+    /// Re-establishes communication after the card has been reset
+    fn reconnect(&mut self) -> Result<(), TransportError>;
+
+    fn atr(&self) -> Result<crate::atr::Atr, TransportError>;
+
+    /// Name of the reader the card is in, where there is one
+    fn reader_name(&self) -> Option<String> {
+        None
+    }
+
+    /// Sends a reader control command; transports without a reader behind them
+    /// don't support any
+    fn control<'buf>(&self, _control_code: u32, _send_buffer: &[u8], _receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        Err(TransportError::UnsupportedFeature)
+    }
+
+    /// Buffers kept for sending commands with, so that each command doesn't
+    /// need its own. None if the transport doesn't keep any, or they're in use.
+    fn buffers(&self) -> Option<std::cell::RefMut<'_, crate::apdu::ApduBuffers>> {
+        None
+    }
+}
+
+/// Passes everything through to another transport, counting the APDUs sent
+pub struct Counting<'a, T: CardTransport + ?Sized> {
+    inner: &'a mut T,
+    count: std::cell::Cell<usize>,
+}
+
+impl<'a, T: CardTransport + ?Sized> Counting<'a, T> {
+    pub fn new(inner: &'a mut T) -> Self {
+        Self {
+            inner,
+            count: std::cell::Cell::new(0),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.get()
+    }
+}
+
+impl<T: CardTransport + ?Sized> CardTransport for Counting<'_, T> {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        self.count.set(self.count.get() + 1);
+        self.inner.transmit(send_buffer, receive_buffer)
+    }
+
+    fn reconnect(&mut self) -> Result<(), TransportError> {
+        self.inner.reconnect()
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, TransportError> {
+        self.inner.atr()
+    }
+
+    fn reader_name(&self) -> Option<String> {
+        self.inner.reader_name()
+    }
+
+    fn control<'buf>(&self, control_code: u32, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        self.inner.control(control_code, send_buffer, receive_buffer)
+    }
+
+    fn buffers(&self) -> Option<std::cell::RefMut<'_, crate::apdu::ApduBuffers>> {
+        self.inner.buffers()
+    }
+}
+
+/// A command sent to the card and the response it gave, status word included
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Exchange {
+    #[serde(serialize_with = "crate::dump::as_hex", deserialize_with = "crate::dump::from_hex")]
+    pub command: Vec<u8>,
+    #[serde(serialize_with = "crate::dump::as_hex", deserialize_with = "crate::dump::from_hex")]
+    pub response: Vec<u8>,
+}
+
+/// Passes everything through to another transport, keeping a transcript of
+/// the exchanges with the card
+pub struct Recording<'a, T: CardTransport + ?Sized> {
+    inner: &'a mut T,
+    exchanges: std::cell::RefCell<Vec<Exchange>>,
+}
+
+impl<'a, T: CardTransport + ?Sized> Recording<'a, T> {
+    pub fn new(inner: &'a mut T) -> Self {
+        Self {
+            inner,
+            exchanges: std::cell::RefCell::new(vec![]),
+        }
+    }
+
+    pub fn into_exchanges(self) -> Vec<Exchange> {
+        self.exchanges.into_inner()
+    }
+}
+
+impl<T: CardTransport + ?Sized> CardTransport for Recording<'_, T> {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        let response = self.inner.transmit(send_buffer, receive_buffer)?;
+        self.exchanges.borrow_mut().push(Exchange {
+            command: send_buffer.to_vec(),
+            response: response.to_vec(),
+        });
+        Ok(response)
+    }
+
+    fn reconnect(&mut self) -> Result<(), TransportError> {
+        self.inner.reconnect()
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, TransportError> {
+        self.inner.atr()
+    }
+
+    fn reader_name(&self) -> Option<String> {
+        self.inner.reader_name()
+    }
+
+    fn control<'buf>(&self, control_code: u32, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        self.inner.control(control_code, send_buffer, receive_buffer)
+    }
+
+    fn buffers(&self) -> Option<std::cell::RefMut<'_, crate::apdu::ApduBuffers>> {
+        self.inner.buffers()
+    }
+}
+
+/// Passes everything through to another transport, handing each exchange with
+/// the card to a watcher as it happens
+pub struct Watching<'a, T: CardTransport + ?Sized, F: Fn(&Exchange)> {
+    inner: &'a mut T,
+    watcher: F,
+}
+
+impl<'a, T: CardTransport + ?Sized, F: Fn(&Exchange)> Watching<'a, T, F> {
+    pub fn new(inner: &'a mut T, watcher: F) -> Self {
+        Self {
+            inner,
+            watcher,
+        }
+    }
+}
+
+impl<T: CardTransport + ?Sized, F: Fn(&Exchange)> CardTransport for Watching<'_, T, F> {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        let response = self.inner.transmit(send_buffer, receive_buffer)?;
+        (self.watcher)(&Exchange {
+            command: send_buffer.to_vec(),
+            response: response.to_vec(),
+        });
+        Ok(response)
+    }
+
+    fn reconnect(&mut self) -> Result<(), TransportError> {
+        self.inner.reconnect()
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, TransportError> {
+        self.inner.atr()
+    }
+
+    fn reader_name(&self) -> Option<String> {
+        self.inner.reader_name()
+    }
+
+    fn control<'buf>(&self, control_code: u32, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        self.inner.control(control_code, send_buffer, receive_buffer)
+    }
+
+    fn buffers(&self) -> Option<std::cell::RefMut<'_, crate::apdu::ApduBuffers>> {
+        self.inner.buffers()
+    }
+}
+
+/// What the card has already answered for an application, or a directory
+#[derive(Debug, Clone, Default)]
+struct CachedFile {
+    fci: Option<Vec<u8>>,
+    /// Responses to READ RECORD by SFI and record number
+    records: std::collections::HashMap<(u8, u8), Vec<u8>>,
+}
+
+/// What a card has already answered to SELECT and READ RECORD, so going back
+/// to an application or record doesn't mean going back to the card.
+///
+/// A SELECT is only answered from here while the application is still as the
+/// last SELECT left it, nothing but reads having been sent since; anything
+/// else and it's sent so the card can start the application afresh. Records
+/// are kept until something is sent that could change them, such as GENERATE
+/// AC adding to the transaction log or an issuer script updating them.
+#[derive(Debug, Clone, Default)]
+pub struct CardCache {
+    files: std::collections::HashMap<Vec<u8>, CachedFile>,
+    selected: Option<Vec<u8>>,
+    fresh: bool,
+    /// A SELECT the card answered with 61xx, and the FCI so far from the GET
+    /// RESPONSEs after it
+    pending: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl CardCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets which application is selected, as once the card has been reset
+    pub fn deselect(&mut self) {
+        self.selected = None;
+        self.fresh = false;
+        self.pending = None;
+    }
+
+    fn cached(&self, command: &[u8]) -> Option<&[u8]> {
+        match command {
+            [0x00, 0xa4, 0x04, 0x00, len, rest @ ..] if self.fresh => {
+                let name = rest.get(..*len as usize)?;
+                match &self.selected {
+                    Some(selected) if selected == name => self.files.get(name)?.fci.as_deref(),
+                    _ => None
+                }
+            }
+            [0x00, 0xb2, record, reference, ..] if reference & 0b111 == 0b100 => {
+                self.files.get(self.selected.as_ref()?)?.records.get(&(reference >> 3, *record)).map(|r| r.as_slice())
+            }
+            _ => None
+        }
+    }
+
+    fn selected(&mut self, name: &[u8], fci: Vec<u8>) {
+        self.files.entry(name.to_vec()).or_default().fci = Some(fci);
+        self.selected = Some(name.to_vec());
+        self.fresh = true;
+    }
+
+    fn update(&mut self, command: &[u8], response: &[u8]) {
+        let success = response.ends_with(&[0x90, 0x00]);
+        let more = matches!(response, [.., 0x61, _]);
+        let pending = self.pending.take();
+        match command {
+            [0x00, 0xa4, 0x04, 0x00, len, rest @ ..] if success || more => match rest.get(..*len as usize) {
+                Some(name) if more => {
+                    self.deselect();
+                    self.pending = Some((name.to_vec(), vec![]));
+                }
+                Some(name) => self.selected(name, response.to_vec()),
+                None => self.deselect()
+            },
+            // The rest of an FCI, cached once the card has given all of it
+            [0x00, 0xc0, ..] => if let Some((name, mut fci)) = pending {
+                fci.extend_from_slice(&response[..response.len().saturating_sub(2)]);
+                if success {
+                    fci.extend_from_slice(&[0x90, 0x00]);
+                    self.selected(&name, fci);
+                } else if more {
+                    self.pending = Some((name, fci));
+                }
+            },
+            // A SELECT the card refuses leaves whatever was selected before as
+            // it was
+            [_, 0xa4, ..] if matches!(response, [.., 0x6a, _] | [.., 0x6d, 0x00] | [.., 0x6e, 0x00]) => {}
+            [_, 0xa4, ..] => self.deselect(),
+            // Records that aren't there are as much worth remembering
+            [0x00, 0xb2, record, reference, ..] if (success || response.ends_with(&[0x6a, 0x83])) && reference & 0b111 == 0b100 => {
+                let files = &mut self.files;
+                if let Some(file) = self.selected.as_ref().and_then(|s| files.get_mut(s)) {
+                    file.records.insert((reference >> 3, *record), response.to_vec());
+                }
+            }
+            // Reads, which leave the application as it is
+            [_, 0xb2 | 0xca | 0xc0, ..] => {}
+            // Commands moving the transaction on, without changing any records
+            [_, 0xa8 | 0x20 | 0x84 | 0x88 | 0x2a, ..] => self.fresh = false,
+            _ => {
+                self.fresh = false;
+                self.files.clear();
+            }
+        }
+    }
+
+    /// Exchanges a command with the card by `transmit`, unless the card has
+    /// already answered it
+    pub fn exchange<'buf, F>(&mut self, send_buffer: &[u8], receive_buffer: &'buf mut [u8], transmit: F) -> Result<&'buf [u8], TransportError>
+        where F: FnOnce(&[u8], &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        if let Some(response) = self.cached(send_buffer) {
+            if response.len() > receive_buffer.len() {
+                return Err(TransportError::InsufficientBuffer);
+            }
+            tracing::trace!(command = %crate::hex::Hex(send_buffer), "Answered from the card cache");
+            receive_buffer[..response.len()].copy_from_slice(response);
+            return Ok(&receive_buffer[..response.len()]);
+        }
+
+        let response = transmit(send_buffer, receive_buffer)?;
+        self.update(send_buffer, response);
+        Ok(response)
+    }
+}
+
+/// Passes everything through to another transport, answering from a
+/// [`CardCache`] what the card has already answered
+pub struct Caching<'a, T: CardTransport + ?Sized> {
+    inner: &'a mut T,
+    cache: std::cell::RefCell<CardCache>,
+}
+
+impl<'a, T: CardTransport + ?Sized> Caching<'a, T> {
+    pub fn new(inner: &'a mut T) -> Self {
+        Self::with_cache(inner, CardCache::new())
+    }
+
+    /// Carries on with what was cached from the same card before
+    pub fn with_cache(inner: &'a mut T, cache: CardCache) -> Self {
+        Self {
+            inner,
+            cache: std::cell::RefCell::new(cache),
+        }
+    }
+
+    pub fn into_cache(self) -> CardCache {
+        self.cache.into_inner()
+    }
+}
+
+impl<T: CardTransport + ?Sized> CardTransport for Caching<'_, T> {
+    fn transmit<'buf>(&self, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        let inner = &*self.inner;
+        self.cache.borrow_mut().exchange(send_buffer, receive_buffer, |s, r| inner.transmit(s, r))
+    }
+
+    fn reconnect(&mut self) -> Result<(), TransportError> {
+        self.cache.get_mut().deselect();
+        self.inner.reconnect()
+    }
+
+    fn atr(&self) -> Result<crate::atr::Atr, TransportError> {
+        self.inner.atr()
+    }
+
+    fn reader_name(&self) -> Option<String> {
+        self.inner.reader_name()
+    }
+
+    fn control<'buf>(&self, control_code: u32, send_buffer: &[u8], receive_buffer: &'buf mut [u8]) -> Result<&'buf [u8], TransportError> {
+        self.inner.control(control_code, send_buffer, receive_buffer)
+    }
+
+    fn buffers(&self) -> Option<std::cell::RefMut<'_, crate::apdu::ApduBuffers>> {
+        self.inner.buffers()
+    }
+}
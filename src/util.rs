@@ -1,37 +1,116 @@
 use encoding::Encoding;
+use std::io::Write;
 
 pub fn compare_slice<T: PartialEq>(p1: &[T], p2: &[T]) -> bool {
-    if p1.len() != p1.len() {
+    if p1.len() != p2.len() {
         return false;
     }
-    let mut i = 0;
-    for v1 in p1 {
-        if &p2[i] != v1 {
-            return false;
+    p1.iter().zip(p2).all(|(v1, v2)| v1 == v2)
+}
+
+/// Encodes a number as packed BCD, right justified in `len` bytes
+pub fn to_bcd(value: u64, len: usize) -> Vec<u8> {
+    let mut out = vec![0; len];
+    let mut value = value;
+    for b in out.iter_mut().rev() {
+        *b = (((value / 10 % 10) << 4) | (value % 10)) as u8;
+        value /= 100;
+    }
+    out
+}
+
+/// Decodes packed BCD, giving None if any nibble isn't a decimal digit
+pub fn from_bcd(data: &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for b in data {
+        let (high, low) = (b >> 4, b & 0x0f);
+        if high > 9 || low > 9 {
+            return None;
         }
-        i += 1;
+        value = value * 100 + (high * 10 + low) as u64;
+    }
+    Some(value)
+}
+
+/// Decodes a YYMMDD BCD date, taking years from 50 on as 19xx as Book 4 has it
+pub fn from_bcd_date(data: &[u8]) -> Option<chrono::NaiveDate> {
+    if data.len() != 3 {
+        return None;
+    }
+    let year = from_bcd(&data[..1])? as i32;
+    let year = if year < 50 { 2000 + year } else { 1900 + year };
+    chrono::NaiveDate::from_ymd_opt(year, from_bcd(&data[1..2])? as u32, from_bcd(&data[2..3])? as u32)
+}
+
+/// Parses an amount written in major units, such as "12.34", into minor units
+/// of a currency with `exponent` decimal places. Amounts have to fit in the
+/// twelve digits of Amount, Authorised.
+pub fn parse_amount(value: &str, exponent: u8) -> Option<u64> {
+    let (whole, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+    if (whole.is_empty() && fraction.is_empty()) || fraction.len() > exponent as usize {
+        return None;
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = format!("{}{:0<width$}", whole, fraction, width = exponent as usize);
+    match digits.trim_start_matches('0') {
+        "" => Some(0),
+        d if d.len() <= 12 => d.parse().ok(),
+        _ => None
     }
-    true
+}
+
+/// Writes an amount in minor units as major units, such as 1234 as "12.34"
+pub fn format_amount(value: u64, exponent: u8) -> String {
+    let scale = 10u64.pow(exponent as u32);
+    match exponent {
+        0 => value.to_string(),
+        e => format!("{}.{:0width$}", value / scale, value % scale, width = e as usize)
+    }
+}
+
+/// Hides all but the first six and last four digits of a PAN
+pub fn mask_pan(pan: &str) -> String {
+    pan.chars().enumerate()
+        .map(|(i, c)| if i < 6 || i + 4 >= pan.len() { c } else { '*' })
+        .collect()
 }
 
 pub fn get_input<T: std::str::FromStr>(question: &str) -> T {
     loop {
         print!("{}", question);
+        std::io::stdout().flush().expect("Unable to write output");
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).expect("Unable to read input");
-        match input.parse::<T>() {
+        match input.trim().parse::<T>() {
             Ok(v) => return v,
             Err(_) => continue
         }
     }
 }
 
+pub fn get_amount(question: &str, exponent: u8) -> u64 {
+    loop {
+        let input: String = get_input(question);
+        if let Some(amount) = parse_amount(&input, exponent) {
+            return amount;
+        }
+    }
+}
+
+/// Reads a PIN without echoing it
+pub fn get_pin(question: &str) -> String {
+    rpassword::prompt_password(question).expect("Unable to read input").trim().to_string()
+}
+
 pub fn get_input_bool(question: &str) -> bool {
     loop {
-        print!("{} [Y/N]", question);
+        print!("{} [Y/N] ", question);
+        std::io::stdout().flush().expect("Unable to write output");
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).expect("Unable to read input");
-        match input.to_lowercase().as_str() {
+        match input.trim().to_lowercase().as_str() {
             "y" => return true,
             "n" => return false,
             _ => continue
@@ -57,8 +136,5 @@ pub fn code_table_index_decode(data: &[u8], index: u8) -> Option<String> {
         16 => encoding::all::ISO_8859_16,
         _ => return None
     };
-    match encoder.decode(data, encoding::DecoderTrap::Ignore) {
-        Ok(s) => Some(s),
-        Err(_) => None
-    }
+    encoder.decode(data, encoding::DecoderTrap::Ignore).ok()
 }
\ No newline at end of file
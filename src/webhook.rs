@@ -0,0 +1,64 @@
+use hmac::Mac;
+
+/// How many times to try delivering an outcome when not told otherwise
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// An HTTP endpoint each transaction outcome is POSTed to as JSON
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key the body is signed under with HMAC-SHA256, the signature going in
+    /// the `X-Signature` header
+    pub secret: Option<Vec<u8>>,
+    pub attempts: u32,
+    /// Wait before the first retry, doubling after each one
+    pub retry_delay: std::time::Duration,
+    pub timeout: std::time::Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            secret: None,
+            attempts: DEFAULT_ATTEMPTS,
+            retry_delay: std::time::Duration::from_secs(1),
+            timeout: std::time::Duration::from_secs(10),
+        }
+    }
+}
+
+/// `sha256=` then the hex HMAC-SHA256 of `body`
+pub fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret).expect("HMAC takes any key length");
+    mac.update(body);
+    format!("sha256={}", crate::hex::encode(&mac.finalize().into_bytes()).to_lowercase())
+}
+
+/// POSTs the outcome, retrying when the endpoint can't be reached or has a
+/// server error. Client errors aren't retried, as they'd only happen again.
+pub fn send(config: &WebhookConfig, outcome: &crate::transaction::TransactionOutcome) -> Result<(), String> {
+    let body = serde_json::to_vec(outcome).map_err(|e| e.to_string())?;
+    let agent = ureq::AgentBuilder::new().timeout(config.timeout).build();
+    let mut delay = config.retry_delay;
+    let mut attempt = 1;
+    loop {
+        let mut request = agent.post(&config.url).set("Content-Type", "application/json");
+        if let Some(secret) = &config.secret {
+            request = request.set("X-Signature", &signature(secret, &body));
+        }
+        let error = match request.send_bytes(&body) {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(code, _)) if code < 500 => return Err(format!("Endpoint refused the outcome with status {}", code)),
+            Err(ureq::Error::Status(code, _)) => format!("Endpoint failed with status {}", code),
+            Err(e) => e.to_string()
+        };
+        if attempt >= config.attempts {
+            return Err(error);
+        }
+        tracing::warn!("Unable to send outcome to webhook, retrying: {}", error);
+        std::thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}